@@ -3,7 +3,7 @@
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate diesel;
 extern crate rocket_config;
-extern crate rocket_diesel;
+#[macro_use] extern crate rocket_diesel;
 
 use rocket::State;
 use rocket_config::Factory as ConfigurationsFairing;
@@ -19,38 +19,15 @@ use schema::users::dsl::{users as users_table};
 fn hello<'r>(database: State<'r, DieselDatabase>, _name: String, _age: u8) -> String {
     let database: &'r DieselDatabase = database.inner();
 
-    println!("Interaction returned: {:?}", database.interact::<(), rocket_diesel::error::Error, _, _, _>(
-        |mysql_conn| {
-            let users = users_table
-                .select(schema::users::all_columns)
-                .limit(5)
-                .load::<UserModel>(mysql_conn)
-                .expect("Error loading users");
+    println!("Interaction returned: {:?}", interact_all!(database, rocket_diesel::error::Error, |conn| {
+        let users = users_table
+            .select(schema::users::all_columns)
+            .limit(5)
+            .load::<UserModel>(conn)?;
 
-            println!("{:?}", users);
-            Ok(())
-        },
-        |pg_conn| {
-            let users = users_table
-                .select(schema::users::all_columns)
-                .limit(5)
-                .load::<UserModel>(pg_conn)
-                .expect("Error loading users");
-
-            println!("{:?}", users);
-            Ok(())
-        },
-        |sqlite_conn| {
-            let users = users_table
-                .select(schema::users::all_columns)
-                .limit(5)
-                .load::<UserModel>(sqlite_conn)
-                .expect("Error loading users");
-
-            println!("{:?}", users);
-            Ok(())
-        }
-    ));
+        println!("{:?}", users);
+        Ok(())
+    }));
 
     format!("Hello!")
 }
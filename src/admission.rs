@@ -0,0 +1,357 @@
+//! Admission of writes while the database is degraded.
+//!
+//! ```rust,ignore
+//! rocket::ignite()
+//!     .attach(ConfigurationsFairing::new())
+//!     .attach(DieselDatabase::new())
+//!     .attach(WriteAdmission::new()
+//!         .with_route("/uploads", Admission::Reject)
+//!         .with_route("/webhooks", Admission::Defer(Duration::from_secs(2))))
+//! ```
+//!
+//! The `WriteAdmission` fairing answers `503 Service Unavailable` (with a
+//! `Retry-After` header) to the writes (`POST`, `PUT`, `PATCH`, `DELETE`)
+//! received while the database is degraded (`Database::degraded`): the
+//! circuit breaker is open or the connection could not be initialized.
+//! Handlers then only see writes the database is expected to serve.
+//!
+//! Each path prefix is admitted with its own `Admission`, the longest
+//! matching prefix applying (whole segments: `/users` matches `/users/1`,
+//! not `/usersettings`); other writes follow the default one. Deferred
+//! writes wait for the database to recover before being rejected, unless
+//! their body is larger than `with_defer_limit`: holding a large upload
+//! while waiting costs more than having the client retry it.
+//!
+//! A deferred write holds a Rocket worker while it waits: beyond
+//! `with_max_deferred` writes waiting at once (4 by default), writes are
+//! rejected rather than deferred, so that the workers keep serving reads.
+//!
+//! Rocket fairings can't answer requests: rejected writes are routed to
+//! `UNAVAILABLE_PATH`, mounted by the fairing, which answers 503. The
+//! fairing must be attached after the database fairing.
+
+use rocket::{
+    data::Data,
+    fairing::{
+        Fairing,
+        Info,
+        Kind
+    },
+    handler,
+    http::{
+        uri::Origin,
+        Method,
+        Status
+    },
+    Outcome,
+    request::Request,
+    response::Response,
+    Rocket,
+    Route,
+    State
+};
+
+use std::{
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering
+        },
+        Arc
+    },
+    thread,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::redaction::log_redacted;
+use crate::Database;
+
+/// Path of the route answering the rejected writes.
+pub const UNAVAILABLE_PATH: &str = "/__rocket_diesel/unavailable";
+
+/// Seconds announced by the `Retry-After` header of rejected writes.
+const RETRY_AFTER: u64 = 5;
+
+/// Interval at which deferred writes check whether the database recovered.
+const DEFER_POLL: Duration = Duration::from_millis(50);
+
+/// Writes deferred at once by default.
+const DEFAULT_MAX_DEFERRED: usize = 4;
+
+/// What happens to a write received while the database is degraded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Admission {
+    /// The handler runs anyway.
+    Allow,
+    /// The write is answered with 503.
+    Reject,
+    /// The write waits up to the duration for the database to recover,
+    /// then is answered with 503.
+    Defer(Duration),
+}
+
+/// Fairing answering 503 to the writes received while the database is
+/// degraded.
+#[derive(Clone, Debug)]
+pub struct WriteAdmission {
+    _default: Admission,
+    _defer_limit: u64,
+    // Writes waiting, shared by the clones of the fairing
+    _deferred: Arc<AtomicUsize>,
+    _max_deferred: usize,
+    _routes: Vec<(String, Admission)>,
+}
+
+impl WriteAdmission {
+    /// Fairing rejecting every write while the database is degraded, with
+    /// a defer limit of 1 MiB and up to 4 writes deferred at once.
+    pub fn new() -> Self {
+        Self {
+            _default: Admission::Reject,
+            _defer_limit: 1024 * 1024,
+            _deferred: Arc::new(AtomicUsize::new(0)),
+            _max_deferred: DEFAULT_MAX_DEFERRED,
+            _routes: Vec::new()
+        }
+    }
+
+    /// Admits the writes of paths without a specific admission with
+    /// `admission`.
+    pub fn with_default(mut self, admission: Admission) -> Self {
+        self._default = admission;
+        self
+    }
+
+    /// Admits the writes of paths starting with `prefix` with `admission`.
+    pub fn with_route(mut self, prefix: &str, admission: Admission) -> Self {
+        self._routes.push((prefix.to_owned(), admission));
+        self
+    }
+
+    /// Rejects rather than defers the writes whose `Content-Length` is
+    /// larger than `bytes`.
+    pub fn with_defer_limit(mut self, bytes: u64) -> Self {
+        self._defer_limit = bytes;
+        self
+    }
+
+    /// Rejects rather than defers the writes received while `count` writes
+    /// are already waiting.
+    pub fn with_max_deferred(mut self, count: usize) -> Self {
+        self._max_deferred = count;
+        self
+    }
+
+    /// Returns the admission of the writes of `path`.
+    pub fn admission(&self, path: &str) -> Admission {
+        self._routes.iter()
+            .filter(|(prefix, _admission)| has_prefix(path, prefix))
+            .max_by_key(|(prefix, _admission)| prefix.len())
+            .map_or(self._default, |(_prefix, admission)| *admission)
+    }
+
+    /// Returns whether a write of `path` with a body of `length` bytes runs
+    /// while `degraded` tells whether the database is still degraded,
+    /// waiting for it to recover when deferred.
+    fn admits<F>(&self, path: &str, length: Option<u64>, degraded: F) -> bool
+        where F: Fn() -> bool
+    {
+        if !degraded() {
+            return true;
+        }
+
+        match self.admission(path) {
+            Admission::Allow => true,
+            Admission::Reject => false,
+            Admission::Defer(_) if length.map_or(false, |length| length > self._defer_limit) => false,
+            Admission::Defer(timeout) => {
+                let _slot = match DeferSlot::take(&self._deferred, self._max_deferred) {
+                    Some(slot) => slot,
+                    None => return false
+                };
+                let deadline = Instant::now() + timeout;
+
+                while Instant::now() < deadline {
+                    thread::sleep(DEFER_POLL);
+                    if !degraded() {
+                        return true;
+                    }
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Place of a deferred write among the `with_max_deferred` ones, freed
+/// when dropped.
+struct DeferSlot<'a>(&'a AtomicUsize);
+
+impl<'a> DeferSlot<'a> {
+    fn take(deferred: &'a AtomicUsize, max: usize) -> Option<Self> {
+        let mut current = deferred.load(Ordering::Acquire);
+
+        loop {
+            if current >= max {
+                return None;
+            }
+            match deferred.compare_exchange(current, current + 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some(DeferSlot(deferred)),
+                Err(actual) => current = actual
+            }
+        }
+    }
+}
+
+impl<'a> Drop for DeferSlot<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Returns whether `path` starts with the segments of `prefix`.
+fn has_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+
+    match path.get(..prefix.len()) {
+        Some(start) if start == prefix => {
+            path.len() == prefix.len() || path[prefix.len()..].starts_with('/')
+        },
+        _ => false
+    }
+}
+
+impl Default for WriteAdmission {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns whether requests of `method` write.
+fn is_write(method: Method) -> bool {
+    match method {
+        Method::Post | Method::Put | Method::Patch | Method::Delete => true,
+        _ => false
+    }
+}
+
+fn unavailable<'r>(_request: &'r Request<'_>, _data: Data) -> handler::Outcome<'r> {
+    Outcome::Success(Response::build()
+        .status(Status::ServiceUnavailable)
+        .raw_header("Retry-After", RETRY_AFTER.to_string())
+        .finalize())
+}
+
+impl Fairing for WriteAdmission {
+    fn info(&self) -> Info {
+        Info {
+            name: "Diesel write admission",
+            kind: Kind::Attach | Kind::Request
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> std::result::Result<Rocket, Rocket> {
+        Ok(rocket.mount("/", vec![Route::new(Method::Get, UNAVAILABLE_PATH, unavailable)]))
+    }
+
+    fn on_request(&self, request: &mut Request<'_>, _data: &Data) {
+        if !is_write(request.method()) {
+            return ;
+        }
+        let database = match request.guard::<State<'_, Database>>() {
+            Outcome::Success(database) => database.inner().clone(),
+            _ => return
+        };
+        let path = request.uri().path().to_owned();
+        let length = request.headers().get_one("Content-Length").and_then(|length| length.parse().ok());
+
+        if self.admits(&path, length, || database.degraded()) {
+            return ;
+        }
+
+        log_redacted!(log::Level::Warn, "Rejected {} {}: the database is degraded.", request.method(), path);
+        request.set_method(Method::Get);
+        request.set_uri(Origin::parse(UNAVAILABLE_PATH).expect("valid path").into_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Method;
+    use std::{
+        cell::Cell,
+        time::Duration
+    };
+    use super::{is_write, Admission, WriteAdmission};
+
+    #[test]
+    fn longest_prefix_applies() {
+        let admission = WriteAdmission::new()
+            .with_default(Admission::Allow)
+            .with_route("/reports", Admission::Reject)
+            .with_route("/reports/drafts", Admission::Defer(Duration::from_secs(1)));
+
+        assert_eq!(admission.admission("/users"), Admission::Allow);
+        assert_eq!(admission.admission("/reports/1"), Admission::Reject);
+        assert_eq!(admission.admission("/reports/drafts/1"), Admission::Defer(Duration::from_secs(1)));
+        // Whole segments only
+        assert_eq!(admission.admission("/reports"), Admission::Reject);
+        assert_eq!(admission.admission("/reportsarchive"), Admission::Allow);
+        assert_eq!(admission.admission("/reports/draftsarchive"), Admission::Reject);
+    }
+
+    #[test]
+    fn admits_writes() {
+        let admission = WriteAdmission::new()
+            .with_route("/webhooks", Admission::Defer(Duration::from_secs(1)))
+            .with_defer_limit(1024);
+
+        assert!(admission.admits("/users", None, || false));
+        assert!(!admission.admits("/users", None, || true));
+        assert!(!admission.admits("/webhooks", Some(4096), || true));
+
+        // Recovers while deferred
+        let polls = Cell::new(0);
+        assert!(admission.admits("/webhooks", Some(512), || {
+            polls.set(polls.get() + 1);
+            polls.get() < 3
+        }));
+    }
+
+    #[test]
+    fn caps_deferred_writes() {
+        let admission = WriteAdmission::new()
+            .with_default(Admission::Defer(Duration::from_secs(1)))
+            .with_max_deferred(1);
+
+        // Rejected while another write waits
+        let polls = Cell::new(0);
+        assert!(admission.admits("/webhooks", None, || {
+            polls.set(polls.get() + 1);
+            match polls.get() {
+                1 => true,
+                2 => !admission.admits("/webhooks", None, || true),
+                _ => false
+            }
+        }));
+        assert_eq!(polls.get(), 3);
+
+        // The slot is freed once the write stops waiting
+        polls.set(0);
+        assert!(admission.admits("/webhooks", None, || {
+            polls.set(polls.get() + 1);
+            polls.get() < 2
+        }));
+    }
+
+    #[test]
+    fn writes() {
+        assert!(is_write(Method::Post));
+        assert!(is_write(Method::Delete));
+        assert!(!is_write(Method::Get));
+        assert!(!is_write(Method::Head));
+    }
+}
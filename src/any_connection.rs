@@ -0,0 +1,138 @@
+use diesel::{
+    connection::{
+        Connection,
+        SimpleConnection
+    },
+    query_dsl::RunQueryDsl,
+    result::QueryResult
+};
+
+use std::fmt;
+
+use crate::Backend;
+
+/// Connection of whichever backend the database is connected to, for
+/// backend-generic code taking a single concrete type.
+///
+/// Diesel's `Connection` trait fixes a single `Backend` type, which a
+/// connection to any of the three backends can't implement: the wrapper
+/// implements `SimpleConnection`, delegates what does not depend on the
+/// backend (raw statements, transactions), and gives access to the
+/// concrete connection for typed queries.
+///
+/// ```rust,ignore
+/// fn purge<C: SimpleConnection>(conn: &C) -> QueryResult<()> {
+///     conn.batch_execute("DELETE FROM tokens WHERE expired")
+/// }
+///
+/// database.interact_any::<_, diesel::result::Error, _>(|conn| purge(&*conn))?;
+/// ```
+pub enum AnyConnection<'a> {
+    Mysql(&'a mut diesel::MysqlConnection),
+    Pg(&'a mut diesel::PgConnection),
+    Sqlite(&'a mut diesel::SqliteConnection),
+}
+
+impl AnyConnection<'_> {
+    pub fn backend(&self) -> Backend {
+        match *self {
+            AnyConnection::Mysql(_)     => Backend::Mysql,
+            AnyConnection::Pg(_)        => Backend::Pg,
+            AnyConnection::Sqlite(_)    => Backend::Sqlite,
+        }
+    }
+
+    pub fn as_mysql(&mut self) -> Option<&mut diesel::MysqlConnection> {
+        match self {
+            AnyConnection::Mysql(conn) => Some(&mut **conn),
+            _ => None
+        }
+    }
+
+    pub fn as_pg(&mut self) -> Option<&mut diesel::PgConnection> {
+        match self {
+            AnyConnection::Pg(conn) => Some(&mut **conn),
+            _ => None
+        }
+    }
+
+    pub fn as_sqlite(&mut self) -> Option<&mut diesel::SqliteConnection> {
+        match self {
+            AnyConnection::Sqlite(conn) => Some(&mut **conn),
+            _ => None
+        }
+    }
+
+    /// Executes the statement `sql`, returning the number of affected rows.
+    pub fn execute(&self, sql: &str) -> QueryResult<usize> {
+        match self {
+            AnyConnection::Mysql(conn)  => diesel::sql_query(sql).execute(&**conn),
+            AnyConnection::Pg(conn)     => diesel::sql_query(sql).execute(&**conn),
+            AnyConnection::Sqlite(conn) => diesel::sql_query(sql).execute(&**conn),
+        }
+    }
+
+    /// Runs `f` in a transaction, as `diesel::Connection::transaction`.
+    pub fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
+        where F: FnOnce() -> Result<T, E>,
+              E: From<diesel::result::Error>
+    {
+        match self {
+            AnyConnection::Mysql(conn)  => conn.transaction(f),
+            AnyConnection::Pg(conn)     => conn.transaction(f),
+            AnyConnection::Sqlite(conn) => conn.transaction(f),
+        }
+    }
+}
+
+impl SimpleConnection for AnyConnection<'_> {
+    fn batch_execute(&self, query: &str) -> QueryResult<()> {
+        match self {
+            AnyConnection::Mysql(conn)  => conn.batch_execute(query),
+            AnyConnection::Pg(conn)     => conn.batch_execute(query),
+            AnyConnection::Sqlite(conn) => conn.batch_execute(query),
+        }
+    }
+}
+
+impl fmt::Debug for AnyConnection<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "AnyConnection({})", self.backend())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        result::QueryResult,
+        sqlite::SqliteConnection
+    };
+    use crate::{Backend, Database};
+    use super::AnyConnection;
+
+    fn create_users<C: SimpleConnection>(conn: &C) -> QueryResult<()> {
+        conn.batch_execute("CREATE TABLE users (id INTEGER); INSERT INTO users VALUES (1);")
+    }
+
+    #[test]
+    fn delegates_to_the_backend() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+
+        let deleted = database.interact_any::<_, diesel::result::Error, _>(|conn| {
+            assert_eq!(conn.backend(), Backend::Sqlite);
+            assert!(conn.as_pg().is_none());
+
+            create_users(&*conn)?;
+            conn.transaction(|| conn.execute("DELETE FROM users"))
+        }).unwrap();
+        assert_eq!(deleted, 1);
+    }
+
+    #[test]
+    fn debug_names_the_backend() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+
+        assert_eq!(format!("{:?}", AnyConnection::Sqlite(&mut conn)), "AnyConnection(sqlite)");
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    fmt,
+    thread,
+    time::Duration
+};
+
+use crate::settings::PoolSettings;
+
+/// Workers per connection above which interactions queue for the
+/// connection most of the time.
+const CONTENTION_RATIO: usize = 4;
+
+/// Checkout timeout recommended when the connection is contended.
+pub const RECOMMENDED_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sizing of a database compared to the concurrency of the server, with
+/// the pool settings recommended for it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Recommendation
+{
+    /// CPUs available to the process.
+    pub cpus: usize,
+    /// Rocket workers, the default of Rocket (twice the CPUs) when the
+    /// database was not attached.
+    pub workers: usize,
+    /// Connections held by the database: per shard, the shared connection
+    /// and the connections of its pool.
+    pub connections: usize,
+    /// Recommended `pool.fair`.
+    pub fair: bool,
+    /// Recommended `pool.checkout_timeout`.
+    pub checkout_timeout: Option<Duration>,
+    /// Pathological combinations found, empty when the sizing is sound.
+    pub warnings: Vec<String>,
+}
+
+impl Recommendation
+{
+    /// Returns whether interactions queue for the connections most of the
+    /// time.
+    pub fn contended(&self) -> bool
+    {
+        self.workers > self.connections * CONTENTION_RATIO
+    }
+}
+
+impl fmt::Display for Recommendation
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            fmt, "{} CPU(s), {} worker(s), {} connection(s): `pool.fair` {}, `pool.checkout_timeout` {}",
+            self.cpus, self.workers, self.connections, self.fair,
+            self.checkout_timeout.map_or("none".to_owned(), |timeout| format!("{}ms", timeout.as_millis()))
+        )
+    }
+}
+
+/// CPUs available to the process.
+pub(crate) fn cpus() -> usize
+{
+    thread::available_parallelism().map(|cpus| cpus.get()).unwrap_or(1)
+}
+
+/// Connections held by a database with `shards` shards besides its primary
+/// database, each of them completed by a pool of `pooled` connections (0
+/// when not pooled).
+pub(crate) fn connections(shards: usize, pooled: usize) -> usize
+{
+    (1 + shards) * (1 + pooled)
+}
+
+/// Compares `connections` to the concurrency of the server, given its
+/// `cpus` and `workers` (`None` when unknown), and recommends pool settings
+/// from the configured `pool`.
+pub(crate) fn recommend(cpus: usize, workers: Option<usize>, connections: usize, pool: &PoolSettings)
+    -> Recommendation
+{
+    let mut recommendation = Recommendation {
+        cpus,
+        // Default of Rocket 0.4
+        workers: workers.unwrap_or(cpus * 2),
+        connections,
+        fair: pool.fair,
+        checkout_timeout: pool.checkout_timeout,
+        warnings: Vec::new()
+    };
+    let workers = recommendation.workers;
+
+    if connections > workers {
+        recommendation.warnings.push(format!(
+            "{} connections for {} Rocket worker(s): at most {} of them are used at once.",
+            connections, workers, workers
+        ));
+    }
+    if recommendation.contended() {
+        recommendation.warnings.push(format!(
+            "{} Rocket workers share {} connection(s): interactions are serialized and queue for them.",
+            workers, connections
+        ));
+
+        if !pool.fair {
+            recommendation.fair = true;
+            recommendation.warnings.push(
+                "`pool.fair` is disabled: a busy route can starve the others.".to_owned()
+            );
+        }
+        if pool.checkout_timeout.is_none() {
+            recommendation.checkout_timeout = Some(RECOMMENDED_CHECKOUT_TIMEOUT);
+            recommendation.warnings.push(
+                "`pool.checkout_timeout` is not set: requests wait for the connection indefinitely.".to_owned()
+            );
+        }
+    }
+
+    recommendation
+}
+
+/// Applies the recommended pool settings to `pool` for the keys the
+/// configuration leaves unset.
+pub(crate) fn apply(pool: PoolSettings, fair_set: bool, checkout_timeout_set: bool, recommendation: &Recommendation)
+    -> PoolSettings
+{
+    PoolSettings {
+        fair: match fair_set {
+            true => pool.fair,
+            false => recommendation.fair
+        },
+        checkout_timeout: match checkout_timeout_set {
+            true => pool.checkout_timeout,
+            false => recommendation.checkout_timeout
+        },
+        ..pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::settings::PoolSettings;
+    use super::{apply, connections, recommend, RECOMMENDED_CHECKOUT_TIMEOUT};
+
+    #[test]
+    fn sound_sizing() {
+        let recommendation = recommend(2, Some(4), 1, &PoolSettings::default());
+
+        assert!(!recommendation.contended());
+        assert!(recommendation.warnings.is_empty());
+        assert_eq!(recommendation.checkout_timeout, None);
+    }
+
+    #[test]
+    fn single_connection_with_many_workers() {
+        let recommendation = recommend(8, None, 1, &PoolSettings::default());
+
+        assert_eq!(recommendation.workers, 16);
+        assert!(recommendation.contended());
+        assert!(recommendation.fair);
+        assert_eq!(recommendation.checkout_timeout, Some(RECOMMENDED_CHECKOUT_TIMEOUT));
+        assert_eq!(recommendation.warnings.len(), 3);
+
+        let configured = PoolSettings { checkout_timeout: Some(Duration::from_secs(1)), fair: false, ..PoolSettings::default() };
+        let applied = apply(configured, true, true, &recommendation);
+        assert_eq!(applied.checkout_timeout, Some(Duration::from_secs(1)));
+        assert!(!applied.fair);
+    }
+
+    #[test]
+    fn more_connections_than_workers() {
+        let recommendation = recommend(1, Some(2), connections(4, 0), &PoolSettings::default());
+
+        // The primary database and its 4 shards
+        assert_eq!(recommendation.connections, 5);
+        assert_eq!(recommendation.warnings.len(), 1);
+
+        // Pools of 8 connections behind the shared one
+        assert_eq!(connections(0, 8), 9);
+        assert_eq!(connections(2, 8), 27);
+    }
+}
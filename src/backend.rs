@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    str::FromStr
+};
+
+use crate::error;
+use crate::Result;
+
+/// Database backends supported by rocket-diesel.
+///
+/// Also exported as `ConnectionKind`: the canonical name of a kind of
+/// connection in configuration, logs, metrics labels and tests.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Backend {
+    Mysql,
+    Pg,
+    Sqlite,
+}
+
+impl Backend {
+    /// Returns the backend handling URLs with the given scheme.
+    pub fn from_scheme(scheme: &str) -> Option<Backend> {
+        match scheme {
+            "mysql"                     => Some(Backend::Mysql),
+            "postgres" | "postgresql"   => Some(Backend::Pg),
+            "sqlite"                    => Some(Backend::Sqlite),
+            _                           => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Mysql  => "mysql",
+            Backend::Pg     => "pg",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Diesel connections of the supported backends.
+///
+/// This trait is sealed: it is only implemented for `MysqlConnection`,
+/// `PgConnection` and `SqliteConnection`.
+pub trait BackendConnection: diesel::Connection + sealed::Sealed + 'static {
+    const BACKEND: Backend;
+}
+
+macro_rules! impl_backend_connection {
+    ($connection:ty, $backend:expr) => {
+        impl sealed::Sealed for $connection {}
+
+        impl BackendConnection for $connection {
+            const BACKEND: Backend = $backend;
+        }
+    };
+}
+
+impl_backend_connection!(diesel::MysqlConnection, Backend::Mysql);
+impl_backend_connection!(diesel::PgConnection, Backend::Pg);
+impl_backend_connection!(diesel::SqliteConnection, Backend::Sqlite);
+
+/// Parses the names returned by `as_str`, as well as URL schemes.
+impl FromStr for Backend {
+    type Err = error::Error;
+
+    fn from_str(backend: &str) -> Result<Self> {
+        match backend {
+            "pg" => Ok(Backend::Pg),
+            _ => Backend::from_scheme(backend).ok_or_else(|| error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("unknown backend `{}`", backend)
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Backend {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Backend {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let backend = String::deserialize(deserializer)?;
+
+        backend.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backend;
+
+    #[test]
+    fn from_scheme() {
+        assert_eq!(Backend::from_scheme("mysql"), Some(Backend::Mysql));
+        assert_eq!(Backend::from_scheme("postgres"), Some(Backend::Pg));
+        assert_eq!(Backend::from_scheme("postgresql"), Some(Backend::Pg));
+        assert_eq!(Backend::from_scheme("sqlite"), Some(Backend::Sqlite));
+        assert_eq!(Backend::from_scheme("oracle"), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(format!("{}", Backend::Mysql), "mysql");
+        assert_eq!(format!("{}", Backend::Pg), "pg");
+        assert_eq!(format!("{}", Backend::Sqlite), "sqlite");
+    }
+
+    #[test]
+    fn from_str() {
+        for backend in &[Backend::Mysql, Backend::Pg, Backend::Sqlite] {
+            assert_eq!(backend.as_str().parse::<Backend>().unwrap(), *backend);
+        }
+        assert_eq!("postgres".parse::<Backend>().unwrap(), Backend::Pg);
+        assert!("oracle".parse::<Backend>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+#![allow(dead_code)]
+
+use std::time::{
+    Duration,
+    Instant
+};
+
+use crate::error;
+
+/// Delay applied after the first failure.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound of the delay between two attempts.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Failure cache rate-limiting the initialization attempts.
+///
+/// Every recorded failure doubles the delay before the next attempt is
+/// allowed, up to `MAX_DELAY`. A successful attempt resets the cache.
+#[derive(Debug, Default)]
+pub(crate) struct Backoff
+{
+    _failures: u32,
+    _last_failure: Option<Instant>,
+    _last_error: Option<(error::ErrorKind, String)>,
+}
+
+impl Backoff
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Number of consecutive failures recorded since the last reset.
+    pub fn failures(&self) -> u32
+    {
+        self._failures
+    }
+
+    /// Delay to wait after the last failure before attempting again.
+    pub fn delay(&self) -> Duration
+    {
+        if self._failures == 0 {
+            return Duration::from_secs(0);
+        }
+
+        let exponent = (self._failures - 1).min(16);
+        let delay = BASE_DELAY * 2u32.pow(exponent);
+
+        delay.min(MAX_DELAY)
+    }
+
+    /// Returns whether a new attempt is allowed at `now`.
+    pub fn ready(&self, now: Instant) -> bool
+    {
+        match self._last_failure {
+            None => true,
+            Some(last_failure) => now.duration_since(last_failure) >= self.delay()
+        }
+    }
+
+    pub fn record_failure(&mut self, err: &error::Error, now: Instant)
+    {
+        self._failures = self._failures.saturating_add(1);
+        self._last_failure = Some(now);
+        self._last_error = Some((err.kind(), err.to_string()));
+    }
+
+    pub fn reset(&mut self)
+    {
+        *self = Self::default();
+    }
+
+    /// Rebuilds the last recorded error, if any.
+    pub fn last_error(&self) -> Option<error::Error>
+    {
+        self._last_error.as_ref().map(|(kind, message)| {
+            error::Error::new(*kind, message.as_str())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::super::error::{Error, ErrorKind};
+    use super::{Backoff, MAX_DELAY};
+
+    #[test]
+    fn fresh_is_ready() {
+        let backoff = Backoff::new();
+
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.delay(), Duration::from_secs(0));
+        assert!(backoff.ready(Instant::now()));
+        assert!(backoff.last_error().is_none());
+    }
+
+    #[test]
+    fn delay_doubles() {
+        let mut backoff = Backoff::new();
+        let now = Instant::now();
+        let error = Error::from(ErrorKind::Other);
+
+        backoff.record_failure(&error, now);
+        let first = backoff.delay();
+        backoff.record_failure(&error, now);
+
+        assert_eq!(backoff.delay(), first * 2);
+        assert!(!backoff.ready(now));
+        assert!(backoff.ready(now + first * 2));
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let mut backoff = Backoff::new();
+        let error = Error::from(ErrorKind::Other);
+
+        for _ in 0..100 {
+            backoff.record_failure(&error, Instant::now());
+        }
+
+        assert_eq!(backoff.delay(), MAX_DELAY);
+    }
+
+    #[test]
+    fn last_error_and_reset() {
+        let mut backoff = Backoff::new();
+        let error = Error::new(ErrorKind::MissingValue, "test error");
+
+        backoff.record_failure(&error, Instant::now());
+        let last_error = backoff.last_error().unwrap();
+        assert_eq!(last_error.kind(), ErrorKind::MissingValue);
+        assert_eq!(last_error.to_string(), "test error");
+
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        assert!(backoff.last_error().is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Creation of the tables used by the crate itself.
+//!
+//! Every internal table is prefixed with `_rocket_diesel_` and created with
+//! `CREATE TABLE IF NOT EXISTS`, so bootstrapping is idempotent.
+
+use diesel::connection::SimpleConnection;
+
+use crate::redaction::log_redacted;
+use crate::Backend;
+use crate::Result;
+
+const MYSQL: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_outbox (
+        id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+        topic VARCHAR(255) NOT NULL,
+        payload TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        dispatched_at TIMESTAMP NULL DEFAULT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_idempotency (
+        idempotency_key VARCHAR(255) NOT NULL PRIMARY KEY,
+        response TEXT NOT NULL,
+        expires_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_schedules (
+        name VARCHAR(255) NOT NULL PRIMARY KEY,
+        last_period BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_table_versions (
+        table_name VARCHAR(255) NOT NULL PRIMARY KEY,
+        version BIGINT NOT NULL,
+        modified_at BIGINT NOT NULL
+    )",
+];
+
+const PG: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_outbox (
+        id BIGSERIAL PRIMARY KEY,
+        topic VARCHAR(255) NOT NULL,
+        payload TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        dispatched_at TIMESTAMP NULL DEFAULT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_idempotency (
+        idempotency_key VARCHAR(255) NOT NULL PRIMARY KEY,
+        response TEXT NOT NULL,
+        expires_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_schedules (
+        name VARCHAR(255) NOT NULL PRIMARY KEY,
+        last_period BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_table_versions (
+        table_name VARCHAR(255) NOT NULL PRIMARY KEY,
+        version BIGINT NOT NULL,
+        modified_at BIGINT NOT NULL
+    )",
+];
+
+const SQLITE: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_outbox (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        topic VARCHAR NOT NULL,
+        payload TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        dispatched_at TIMESTAMP NULL DEFAULT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_idempotency (
+        idempotency_key VARCHAR NOT NULL PRIMARY KEY,
+        response TEXT NOT NULL,
+        expires_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_schedules (
+        name VARCHAR NOT NULL PRIMARY KEY,
+        last_period BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS _rocket_diesel_table_versions (
+        table_name VARCHAR NOT NULL PRIMARY KEY,
+        version BIGINT NOT NULL,
+        modified_at BIGINT NOT NULL
+    )",
+];
+
+/// Statements creating the internal tables for `backend`.
+pub(crate) fn statements(backend: Backend) -> &'static [&'static str] {
+    match backend {
+        Backend::Mysql  => MYSQL,
+        Backend::Pg     => PG,
+        Backend::Sqlite => SQLITE,
+    }
+}
+
+/// Creates the missing internal tables using `conn`.
+pub(crate) fn run<C>(conn: &C, backend: Backend) -> Result<()>
+    where C: SimpleConnection
+{
+    log_redacted!(log::Level::Debug, "Bootstrapping internal tables...");
+
+    for statement in statements(backend) {
+        conn.batch_execute(statement)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Backend;
+    use super::statements;
+
+    #[test]
+    fn statements_are_idempotent_and_prefixed() {
+        for backend in &[Backend::Mysql, Backend::Pg, Backend::Sqlite] {
+            let statements = statements(*backend);
+
+            assert!(!statements.is_empty());
+            for statement in statements {
+                assert!(statement.starts_with("CREATE TABLE IF NOT EXISTS _rocket_diesel_"));
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fmt,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::redaction::log_redacted;
+use crate::error;
+use crate::Result;
+
+/// State of the circuit breaker protecting the database.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CircuitState {
+    /// Interactions go through.
+    Closed,
+    /// Interactions fail fast with `ErrorKind::CircuitOpen`.
+    Open,
+    /// A single probe interaction is let through to test the database.
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(match self {
+            CircuitState::Closed    => "closed",
+            CircuitState::Open      => "open",
+            CircuitState::HalfOpen  => "half_open",
+        })
+    }
+}
+
+/// Returns whether `err` reveals a problem with the database, rather than
+/// with the data (e.g. a missing row).
+pub(crate) fn is_failure(err: &error::Error) -> bool {
+    err.kind() != error::ErrorKind::CircuitOpen &&
+    err.downcast_ref::<diesel::result::Error>() != Some(&diesel::result::Error::NotFound)
+}
+
+/// Circuit breaker settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CircuitBreakerSettings {
+    /// Failure ratio (between 0 and 1) opening the circuit.
+    pub error_rate: f64,
+    /// Duration of the window the failure ratio is computed over.
+    pub window: Duration,
+    /// Minimum number of interactions in the window before it can open.
+    pub min_interactions: u32,
+    /// Duration the circuit stays open before probing.
+    pub cool_down: Duration,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            error_rate: 0.5,
+            window: Duration::from_secs(10),
+            min_interactions: 20,
+            cool_down: Duration::from_secs(30)
+        }
+    }
+}
+
+/// State machine of the circuit breaker.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    _state: CircuitState,
+    _window_start: Option<Instant>,
+    _interactions: u32,
+    _failures: u32,
+    _opened_at: Option<Instant>,
+    // Start of the probe in flight
+    _probing: Option<Instant>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            _state: CircuitState::Closed,
+            _window_start: None,
+            _interactions: 0,
+            _failures: 0,
+            _opened_at: None,
+            _probing: None
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self._state
+    }
+
+    /// Asks for the permission to interact with the database at `now`.
+    pub fn acquire(&mut self, settings: &CircuitBreakerSettings, now: Instant) -> Result<()> {
+        if self._state == CircuitState::Open {
+            let cooled_down = self._opened_at
+                .map_or(true, |opened_at| now.duration_since(opened_at) >= settings.cool_down);
+
+            if cooled_down {
+                log_redacted!(log::Level::Info, "Circuit breaker half-open, probing the database.");
+                self._state = CircuitState::HalfOpen;
+                self._probing = None;
+            }
+        }
+
+        // A probe which never reported back (e.g. hung) is given up on once
+        // the cool-down expired again
+        let probe_free = self._probing
+            .map_or(true, |started| now.duration_since(started) >= settings.cool_down);
+
+        match self._state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::HalfOpen if probe_free => {
+                self._probing = Some(now);
+                Ok(())
+            },
+            _ => Err(error::Error::new(
+                error::ErrorKind::CircuitOpen, "circuit breaker is open"
+            ))
+        }
+    }
+
+    /// Records the outcome of an interaction allowed by `acquire`.
+    pub fn record(&mut self, settings: &CircuitBreakerSettings, success: bool, now: Instant) {
+        match self._state {
+            CircuitState::HalfOpen => {
+                if success {
+                    log_redacted!(log::Level::Info, "Circuit breaker closed.");
+                    *self = Self::default();
+                } else {
+                    self.open(now);
+                }
+            },
+            CircuitState::Closed => {
+                let expired = self._window_start
+                    .map_or(true, |start| now.duration_since(start) >= settings.window);
+
+                if expired {
+                    self._window_start = Some(now);
+                    self._interactions = 0;
+                    self._failures = 0;
+                }
+
+                self._interactions += 1;
+                if !success {
+                    self._failures += 1;
+                }
+
+                let rate = f64::from(self._failures) / f64::from(self._interactions);
+                if self._interactions >= settings.min_interactions && rate >= settings.error_rate {
+                    self.open(now);
+                }
+            },
+            CircuitState::Open => {}
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        log_redacted!(log::Level::Warn, "Circuit breaker opened.");
+        self._state = CircuitState::Open;
+        self._opened_at = Some(now);
+        self._probing = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::error::ErrorKind;
+    use super::{CircuitBreaker, CircuitBreakerSettings, CircuitState};
+
+    fn settings() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            error_rate: 0.5,
+            window: Duration::from_secs(10),
+            min_interactions: 4,
+            cool_down: Duration::from_secs(30)
+        }
+    }
+
+    #[test]
+    fn opens_on_error_rate() {
+        let settings = settings();
+        let mut breaker = CircuitBreaker::new();
+        let now = Instant::now();
+
+        breaker.record(&settings, true, now);
+        breaker.record(&settings, false, now);
+        breaker.record(&settings, true, now);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record(&settings, false, now);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(
+            breaker.acquire(&settings, now).unwrap_err().kind(),
+            ErrorKind::CircuitOpen
+        );
+    }
+
+    #[test]
+    fn window_expires() {
+        let settings = settings();
+        let mut breaker = CircuitBreaker::new();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            breaker.record(&settings, false, now);
+        }
+        breaker.record(&settings, false, now + Duration::from_secs(11));
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn half_open_probe() {
+        let settings = settings();
+        let mut breaker = CircuitBreaker::new();
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            breaker.record(&settings, false, now);
+        }
+
+        let later = now + Duration::from_secs(30);
+        assert!(breaker.acquire(&settings, later).is_ok());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        // A single probe at a time
+        assert!(breaker.acquire(&settings, later).is_err());
+
+        breaker.record(&settings, false, later);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let later = later + Duration::from_secs(30);
+        assert!(breaker.acquire(&settings, later).is_ok());
+        breaker.record(&settings, true, later);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn lost_probe_expires() {
+        let settings = settings();
+        let mut breaker = CircuitBreaker::new();
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            breaker.record(&settings, false, now);
+        }
+
+        // The probe never records its outcome
+        let later = now + Duration::from_secs(30);
+        assert!(breaker.acquire(&settings, later).is_ok());
+        assert!(breaker.acquire(&settings, later + Duration::from_secs(29)).is_err());
+
+        let later = later + Duration::from_secs(30);
+        assert!(breaker.acquire(&settings, later).is_ok());
+        assert!(breaker.acquire(&settings, later).is_err());
+        breaker.record(&settings, true, later);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
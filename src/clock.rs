@@ -0,0 +1,166 @@
+//! Source of time of the database.
+//!
+//! The circuit breaker, the initialization backoff, the idle connection
+//! reaper and the idempotency keys' TTL read the time from the clock of the
+//! database, so that tests can control it. With the `testing` feature,
+//! `Database::with_clock(TestClock::new())` replaces the system clock by one
+//! advancing only when told to.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{
+        Instant,
+        SystemTime
+    }
+};
+
+#[cfg(any(test, feature = "testing"))]
+use std::{
+    sync::Mutex,
+    time::Duration
+};
+
+/// Source of time.
+pub trait Clock: Send + Sync + 'static
+{
+    /// Monotonic time, measuring delays.
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, stored in the database.
+    fn system_now(&self) -> SystemTime;
+}
+
+/// Time of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock
+{
+    fn now(&self) -> Instant
+    {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime
+    {
+        SystemTime::now()
+    }
+}
+
+/// Clock frozen at its creation, advancing only with `advance`.
+///
+/// Clones share their time.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone, Debug)]
+pub struct TestClock
+{
+    _instant: Instant,
+    _system: SystemTime,
+    _elapsed: Arc<Mutex<Duration>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl TestClock
+{
+    pub fn new() -> Self
+    {
+        Self {
+            _instant: Instant::now(),
+            _system: SystemTime::now(),
+            _elapsed: Arc::new(Mutex::new(Duration::from_secs(0)))
+        }
+    }
+
+    /// Moves the time forward by `duration`.
+    pub fn advance(&self, duration: Duration)
+    {
+        if let Ok(mut elapsed) = self._elapsed.lock() {
+            *elapsed += duration;
+        }
+    }
+
+    fn elapsed(&self) -> Duration
+    {
+        self._elapsed.lock().map(|elapsed| *elapsed).unwrap_or_default()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Default for TestClock
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for TestClock
+{
+    fn now(&self) -> Instant
+    {
+        self._instant + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime
+    {
+        self._system + self.elapsed()
+    }
+}
+
+/// Clock of a database.
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock
+{
+    pub fn new<C: Clock>(clock: C) -> Self
+    {
+        SharedClock(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> Instant
+    {
+        self.0.now()
+    }
+
+    pub fn system_now(&self) -> SystemTime
+    {
+        self.0.system_now()
+    }
+}
+
+impl Default for SharedClock
+{
+    fn default() -> Self
+    {
+        SharedClock::new(SystemClock)
+    }
+}
+
+impl fmt::Debug for SharedClock
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.write_str("SharedClock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Clock, TestClock};
+
+    #[test]
+    fn test_clock_advances_on_demand() {
+        let clock = TestClock::new();
+        let (instant, system) = (clock.now(), clock.system_now());
+
+        assert_eq!(clock.now(), instant);
+
+        clock.clone().advance(Duration::from_secs(30));
+        assert_eq!(clock.now() - instant, Duration::from_secs(30));
+        assert_eq!(clock.system_now().duration_since(system).unwrap(), Duration::from_secs(30));
+    }
+}
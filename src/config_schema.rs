@@ -0,0 +1,417 @@
+//! Machine-readable description of the `diesel` configuration, for
+//! deployment tooling checking configuration files before a rollout.
+
+use std::fmt;
+
+use crate::environment;
+
+/// Type of the value of a setting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SettingType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    /// Array of strings.
+    Array,
+    /// Table of arbitrary keys.
+    Table,
+}
+
+impl SettingType {
+    /// Name of the type in JSON Schema.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SettingType::String     => "string",
+            SettingType::Integer    => "integer",
+            SettingType::Float      => "number",
+            SettingType::Boolean    => "boolean",
+            SettingType::Array      => "array",
+            SettingType::Table      => "object",
+        }
+    }
+}
+
+impl fmt::Display for SettingType {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Setting recognized in the `diesel` configuration.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Setting {
+    /// Key, nested keys being joined with `.` (e.g. `migrations.policy`).
+    pub key: &'static str,
+    pub kind: SettingType,
+    /// Default value as a JSON literal, `None` when unset by default.
+    pub default: Option<&'static str>,
+    /// Accepted values of string settings, empty when free.
+    pub values: &'static [&'static str],
+    /// Unit of integer durations (`ms` or `s`).
+    pub unit: Option<&'static str>,
+    /// Feature of the crate reading the setting, `None` when always read.
+    pub feature: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl Setting {
+    /// Base of the settings of `config_schema`.
+    const DEFAULT: Setting = Setting {
+        key: "",
+        kind: SettingType::String,
+        default: None,
+        values: &[],
+        unit: None,
+        feature: None,
+        description: "",
+    };
+
+    /// Environment variable overriding the setting, `None` for tables which
+    /// can't be overridden.
+    pub fn variable(&self) -> Option<String> {
+        match self.kind {
+            SettingType::Table => None,
+            _ => Some(environment::variable_name(self.key))
+        }
+    }
+}
+
+/// Settings recognized in the `diesel` configuration, returned by
+/// `config_schema`.
+#[derive(Clone, Debug)]
+pub struct ConfigSchema {
+    _settings: Vec<Setting>,
+}
+
+impl ConfigSchema {
+    pub fn settings(&self) -> &[Setting] {
+        &self._settings
+    }
+
+    /// Returns the setting of `key` (e.g. `pool.fair`).
+    pub fn get(&self, key: &str) -> Option<&Setting> {
+        self._settings.iter().find(|setting| setting.key == key)
+    }
+
+    /// Returns the keys of `keys`, nested keys being joined with `.`, that
+    /// are not recognized. Keys within tables of arbitrary keys (e.g.
+    /// `params.connect_timeout`) are recognized.
+    pub fn unknown_keys<'k, I>(&self, keys: I) -> Vec<&'k str>
+        where I: IntoIterator<Item = &'k str>
+    {
+        keys.into_iter().filter(|key| !self._settings.iter().any(|setting| {
+            setting.key == *key || (
+                setting.kind == SettingType::Table
+                && key.starts_with(setting.key)
+                && key[setting.key.len()..].starts_with('.')
+            )
+        })).collect()
+    }
+
+    /// Renders the schema as a JSON Schema (draft 7) of the configuration
+    /// file, nested keys being rendered as nested objects.
+    pub fn to_json_schema(&self) -> String {
+        let mut json = String::from(
+            "{\"$schema\":\"http://json-schema.org/draft-07/schema#\",\"type\":\"object\",\"properties\":{"
+        );
+        let mut sections: Vec<&str> = Vec::new();
+
+        for setting in self._settings.iter() {
+            let section = setting.key.split('.').next().unwrap_or(setting.key);
+
+            if sections.contains(&section) {
+                continue;
+            }
+            if !sections.is_empty() {
+                json.push(',');
+            }
+            sections.push(section);
+
+            if section == setting.key {
+                json.push_str(&format!("{}:{}", quote(section), property(setting)));
+                continue;
+            }
+
+            let nested = self._settings.iter()
+                .filter(|nested| nested.key.split('.').next() == Some(section))
+                .map(|nested| format!(
+                    "{}:{}", quote(&nested.key[section.len() + 1..]), property(nested)
+                ))
+                .collect::<Vec<String>>();
+            json.push_str(&format!(
+                "{}:{{\"type\":\"object\",\"additionalProperties\":false,\"properties\":{{{}}}}}",
+                quote(section), nested.join(",")
+            ));
+        }
+
+        json.push_str("},\"required\":[\"url\"],\"additionalProperties\":false}");
+        json
+    }
+}
+
+/// JSON Schema of the value of `setting`.
+fn property(setting: &Setting) -> String {
+    let mut property = format!("{{\"type\":{}", quote(setting.kind.as_str()));
+
+    if setting.kind == SettingType::Array {
+        property.push_str(",\"items\":{\"type\":\"string\"}");
+    }
+    if !setting.values.is_empty() {
+        let values = setting.values.iter().map(|value| quote(value)).collect::<Vec<String>>();
+        property.push_str(&format!(",\"enum\":[{}]", values.join(",")));
+    }
+    if let Some(default) = setting.default {
+        property.push_str(&format!(",\"default\":{}", default));
+    }
+
+    let mut description = setting.description.to_owned();
+    if let Some(unit) = setting.unit {
+        description.push_str(&format!(" In {}.", unit));
+    }
+    if let Some(feature) = setting.feature {
+        description.push_str(&format!(" With the `{}` feature.", feature));
+    }
+    property.push_str(&format!(",\"description\":{}}}", quote(&description)));
+
+    property
+}
+
+/// JSON string of `value`.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+macro_rules! setting {
+    ($key:expr, $kind:ident, $description:expr $(, $field:ident: $value:expr)* $(,)?) => {
+        Setting {
+            key: $key,
+            kind: SettingType::$kind,
+            description: $description,
+            $($field: $value,)*
+            ..Setting::DEFAULT
+        }
+    };
+}
+
+/// Returns the settings recognized in the `diesel` configuration, with
+/// their types and defaults.
+///
+/// ```rust
+/// let schema = rocket_diesel::config_schema();
+///
+/// assert_eq!(schema.get("pool.fair").and_then(|setting| setting.default), Some("false"));
+/// assert_eq!(schema.unknown_keys(vec!["url", "pool.sise"]), vec!["pool.sise"]);
+/// ```
+pub fn config_schema() -> ConfigSchema {
+    ConfigSchema {
+        _settings: vec![
+            setting!("url", String, "Connection URL (`mysql://`, `postgres://` or `sqlite://`). Required."),
+            setting!("fallback_url", String, "URL tried when the credentials of `url` are refused."),
+            setting!("username", String, "Username, for a `url` without credentials."),
+            setting!("password", String, "Password, for a `url` without credentials."),
+            setting!(
+                "credentials_encoding", String, "How the credentials of the `url` are written.",
+                default: Some("\"encoded\""), values: &["encoded", "raw"]
+            ),
+            setting!("application_name", String, "Identifier sent to Postgres as `application_name` (not to MySQL)."),
+            setting!("stats_interval", Integer, "Interval between two statistics log lines.", unit: Some("s")),
+            setting!(
+                "migrations.policy", String, "What the initialization does with pending migrations.",
+                default: Some("\"ignore\""), values: &["run", "check", "ignore"]
+            ),
+            setting!(
+                "migrations.directory", String, "Directory holding the diesel migrations.",
+                default: Some("\"migrations\"")
+            ),
+            setting!(
+                "migrations.dry_run", Boolean, "Logs the pending migrations instead of applying the policy.",
+                default: Some("false")
+            ),
+            setting!(
+                "pool.checkout_timeout", Integer, "Time an interaction waits for the connection.",
+                unit: Some("ms")
+            ),
+            setting!(
+                "pool.fair", Boolean, "Serves waiting interactions in arrival order.",
+                default: Some("false")
+            ),
+            setting!("pool.size", Integer, "Number of pooled connections, above 1 to pool."),
+            setting!("pool.max", Integer, "Same as `pool.size`."),
+            setting!("pool.min", Integer, "Pooled connections established on initialization."),
+            setting!(
+                "pool.implementation", String, "Implementation of the pool.",
+                values: &["r2d2", "deadpool"]
+            ),
+            setting!("redaction.keys", Array, "Keys whose values are masked in logs and errors."),
+            setting!("redaction.patterns", Array, "Regular expressions masked in logs and errors."),
+            setting!(
+                "sessions.max", Integer, "Maximum number of sessions open at once.",
+                default: Some("4"), feature: Some("sessions")
+            ),
+            setting!(
+                "sessions.max_ttl", Integer, "Maximum time to live of a session.",
+                default: Some("300"), unit: Some("s"), feature: Some("sessions")
+            ),
+            setting!(
+                "tls.mode", String, "Encryption of the connection (Postgres `sslmode`).",
+                values: &["disable", "prefer", "require", "verify-ca", "verify-full"]
+            ),
+            setting!("tls.root_cert", String, "Path of the certificate authority verifying the server."),
+            setting!("tls.client_cert", String, "Path of the client certificate."),
+            setting!("tls.client_key", String, "Path of the key of the client certificate."),
+            setting!("replicas.urls", Array, "URLs of the read-only replicas of the database."),
+            setting!("profiling.capacity", Integer, "Number of interaction samples kept."),
+            setting!(
+                "profiling.sample_every", Integer, "Samples one interaction out of `sample_every`.",
+                default: Some("1")
+            ),
+            setting!("min_version.mysql", String, "Oldest MySQL server version supported."),
+            setting!("min_version.pg", String, "Oldest Postgres server version supported."),
+            setting!("min_version.sqlite", String, "Oldest SQLite version supported."),
+            setting!("metrics.token", String, "Bearer token required by the `/metrics` route."),
+            setting!(
+                "crypto.primary", Integer, "Id of the key encrypting values.",
+                feature: Some("crypto")
+            ),
+            setting!(
+                "crypto.keys", Array, "`<id>:<64 hex digits>` AES-256 keys.",
+                feature: Some("crypto")
+            ),
+            setting!(
+                "slow_query_threshold", Integer, "Duration above which an interaction is logged as slow.",
+                unit: Some("ms")
+            ),
+            setting!(
+                "serverless_idle_timeout", Integer, "Idle time after which the connection is closed.",
+                unit: Some("s")
+            ),
+            setting!(
+                "idle_timeout", Integer, "Idle time after which the connection is replaced on its next use.",
+                unit: Some("s")
+            ),
+            setting!(
+                "max_lifetime", Integer, "Age after which the connection is replaced on its next use.",
+                unit: Some("s")
+            ),
+            setting!(
+                "bootstrap", Boolean, "Creates the internal tables on initialization.",
+                default: Some("false")
+            ),
+            setting!(
+                "warm_up_parallelism", Integer, "Connections established at a time by `Database::warm_up`.",
+                default: Some("4")
+            ),
+            setting!(
+                "sqlite_max_page_count", Integer, "Maximum number of pages of SQLite databases.",
+                unit: Some("pages")
+            ),
+            setting!(
+                "integrity_checks.fail_on_critical", Boolean, "Fails the initialization on failed critical checks.",
+                default: Some("true")
+            ),
+            setting!("max_result_rows", Integer, "Maximum number of rows a helper may load."),
+            setting!("max_result_bytes", Integer, "Maximum estimated size in bytes of a loaded result, heap data included."),
+            setting!(
+                "compression", Boolean, "Requests wire protocol compression (ignored by libpq 14 or later).",
+                default: Some("false")
+            ),
+            setting!("pg_options", String, "Postgres `options` connection parameter."),
+            setting!("params", Table, "Parameters added to the query of connection URLs."),
+            setting!("route_quotas", Table, "Maximum number of interactions in flight per path prefix."),
+            setting!(
+                "circuit_breaker.error_rate", Float, "Failure ratio opening the circuit breaker.",
+                default: Some("0.5")
+            ),
+            setting!(
+                "circuit_breaker.window", Integer, "Window over which the failure ratio is computed.",
+                default: Some("10"), unit: Some("s")
+            ),
+            setting!(
+                "circuit_breaker.min_interactions", Integer, "Interactions required in the window before opening.",
+                default: Some("20")
+            ),
+            setting!(
+                "circuit_breaker.cool_down", Integer, "Time the circuit stays open before a probe.",
+                default: Some("30"), unit: Some("s")
+            ),
+            setting!(
+                "query_tagging", Boolean, "Prefixes statements with route and request tags.",
+                default: Some("false")
+            ),
+            setting!(
+                "query_tagging_client_ip", Boolean, "Adds the client IP address to the tags.",
+                default: Some("false")
+            ),
+            setting!(
+                "retry_on_disconnect", Boolean, "Retries idempotent interactions once after a disconnection.",
+                default: Some("true")
+            ),
+            setting!(
+                "retry_budget", Integer, "Time the retries of a request may take in total.",
+                unit: Some("ms")
+            ),
+            setting!("shards", Array, "URLs of the shards, in shard order."),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config_schema, SettingType};
+
+    #[test]
+    fn documents_every_key() {
+        let schema = config_schema();
+        let readme = include_str!("../README.md");
+        let table = readme.split("## Configuration").nth(1).unwrap().split("###").next().unwrap();
+
+        for row in table.lines().filter(|line| line.starts_with("| `")) {
+            for key in row.split(" | ").next().unwrap().trim_start_matches("| ").split(", ") {
+                assert!(schema.get(key.trim_matches('`')).is_some(), "{} is not in the schema", key);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let schema = config_schema();
+
+        assert_eq!(
+            schema.unknown_keys(vec!["url", "params.connect_timeout", "pool.sise", "paramsx"]),
+            vec!["pool.sise", "paramsx"]
+        );
+        assert_eq!(schema.get("params").unwrap().variable(), None);
+        assert_eq!(
+            schema.get("pool.fair").unwrap().variable(),
+            Some("ROCKET_DIESEL_POOL_FAIR".to_owned())
+        );
+    }
+
+    #[test]
+    fn renders_json_schema() {
+        let json = config_schema().to_json_schema();
+
+        assert!(json.starts_with("{\"$schema\""));
+        assert!(json.contains(
+            "\"pool\":{\"type\":\"object\",\"additionalProperties\":false,\"properties\":{\"checkout_timeout\":"
+        ));
+        assert!(json.contains("\"enum\":[\"run\",\"check\",\"ignore\"],\"default\":\"ignore\""));
+        assert_eq!(config_schema().get("circuit_breaker.error_rate").unwrap().kind, SettingType::Float);
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+    }
+}
@@ -7,9 +7,11 @@ use std::{
         LockResult,
         Mutex,
         MutexGuard,
+        TryLockResult,
     }
 };
 
+use crate::redaction::log_redacted;
 use crate::error;
 use crate::Result;
 
@@ -30,19 +32,24 @@ impl Connection
 
     pub fn lock(&self) -> LockResult<MutexGuard<'_, Option<Box<dyn Any>>>>
     {
-        log::debug!("Locking connection...");
+        log_redacted!(log::Level::Debug, "Locking connection...");
         
         let lock = self._connection.lock();
 
         if lock.is_ok() {
-            log::debug!("Successfully locked connection!");
+            log_redacted!(log::Level::Debug, "Successfully locked connection!");
         } else {
-            log::debug!("Failed to lock connection.");
+            log_redacted!(log::Level::Debug, "Failed to lock connection.");
         }
 
         lock
     }
 
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, Option<Box<dyn Any>>>>
+    {
+        self._connection.try_lock()
+    }
+
     pub fn initialized(&self) -> Result<bool>
     {
         let guard = self.lock().map_err(|err| error::Error::new(
@@ -0,0 +1,8 @@
+//! Optional companion modules built on the database, enabled by their
+//! feature.
+
+#[cfg(feature = "oauth")]
+pub mod oauth;
+
+#[cfg(feature = "pools")]
+pub mod pools;
@@ -0,0 +1,462 @@
+//! Repositories of the OAuth schema of the example application: clients,
+//! access tokens, refresh tokens and authorization codes.
+//!
+//! ```rust,ignore
+//! #[post("/token", data = "<form>")]
+//! fn token(database: State<'_, Database>, form: Form<TokenRequest>) -> Result<String> {
+//!     let client = Clients::new(&database).find_by_random_id(&form.client_id)?
+//!         .filter(|client| client.check_secret(&form.client_secret))
+//!         .ok_or(/* invalid_client */)?;
+//!     // ...
+//!     AccessTokens::new(&database).insert(&NewToken {
+//!         client_id: client.id.unwrap(), user_id: None, token, expires_at, scope: None
+//!     })
+//! }
+//! ```
+//!
+//! The tables are those of the example's `create_oauth` migration. Expiry
+//! dates are UNIX timestamps in seconds, compared with the clock of the
+//! database.
+
+use diesel::{
+    ExpressionMethods,
+    OptionalExtension,
+    QueryDsl,
+    RunQueryDsl
+};
+
+use std::time::UNIX_EPOCH;
+
+use crate::Database;
+use crate::error;
+use crate::Result;
+
+/// Diesel tables of the OAuth schema.
+pub mod schema {
+    table! {
+        access_token (id) {
+            id -> Nullable<Integer>,
+            client_id -> Integer,
+            user_id -> Nullable<Integer>,
+            token -> Text,
+            expires_at -> Nullable<Integer>,
+            scope -> Nullable<Text>,
+        }
+    }
+
+    table! {
+        auth_code (id) {
+            id -> Nullable<Integer>,
+            client_id -> Integer,
+            user_id -> Nullable<Integer>,
+            token -> Text,
+            redirect_uri -> Text,
+            expires_at -> Nullable<Integer>,
+            scope -> Nullable<Text>,
+        }
+    }
+
+    table! {
+        client (id) {
+            id -> Nullable<Integer>,
+            random_id -> Text,
+            redirect_uris -> Text,
+            secret -> Text,
+            allowed_grant_types -> Text,
+        }
+    }
+
+    table! {
+        refresh_token (id) {
+            id -> Nullable<Integer>,
+            client_id -> Integer,
+            user_id -> Nullable<Integer>,
+            token -> Text,
+            expires_at -> Nullable<Integer>,
+            scope -> Nullable<Text>,
+        }
+    }
+
+    allow_tables_to_appear_in_same_query!(access_token, auth_code, client, refresh_token);
+}
+
+use self::schema::{
+    access_token,
+    auth_code,
+    client,
+    refresh_token
+};
+
+/// Registered client application.
+#[derive(Clone, Debug, Eq, PartialEq, Queryable)]
+pub struct Client
+{
+    pub id: Option<i32>,
+    /// Public identifier of the client (`client_id` of the requests).
+    pub random_id: String,
+    /// Space-separated redirection URIs.
+    pub redirect_uris: String,
+    pub secret: String,
+    /// Space-separated grant types (e.g. `authorization_code refresh_token`).
+    pub allowed_grant_types: String,
+}
+
+impl Client
+{
+    /// Compares `secret` with the secret of the client in constant time.
+    pub fn check_secret(&self, secret: &str) -> bool
+    {
+        let (expected, candidate) = (self.secret.as_bytes(), secret.as_bytes());
+
+        expected.len() == candidate.len() &&
+            expected.iter().zip(candidate).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    pub fn allows_redirect_uri(&self, redirect_uri: &str) -> bool
+    {
+        self.redirect_uris.split_whitespace().any(|allowed| allowed == redirect_uri)
+    }
+
+    pub fn allows_grant_type(&self, grant_type: &str) -> bool
+    {
+        self.allowed_grant_types.split_whitespace().any(|allowed| allowed == grant_type)
+    }
+}
+
+/// Client to register.
+#[derive(Clone, Debug, Eq, PartialEq, Insertable)]
+#[table_name = "client"]
+pub struct NewClient
+{
+    pub random_id: String,
+    pub redirect_uris: String,
+    pub secret: String,
+    pub allowed_grant_types: String,
+}
+
+/// Access or refresh token.
+#[derive(Clone, Debug, Eq, PartialEq, Queryable)]
+pub struct Token
+{
+    pub id: Option<i32>,
+    pub client_id: i32,
+    pub user_id: Option<i32>,
+    pub token: String,
+    /// UNIX timestamp, `None` for tokens which do not expire.
+    pub expires_at: Option<i32>,
+    pub scope: Option<String>,
+}
+
+/// Token to issue.
+#[derive(Clone, Debug, Eq, PartialEq, Insertable)]
+#[table_name = "access_token"]
+pub struct NewToken
+{
+    pub client_id: i32,
+    pub user_id: Option<i32>,
+    pub token: String,
+    pub expires_at: Option<i32>,
+    pub scope: Option<String>,
+}
+
+/// Refresh tokens share the columns of access tokens.
+#[derive(Insertable)]
+#[table_name = "refresh_token"]
+struct NewRefreshToken<'a>
+{
+    client_id: i32,
+    user_id: Option<i32>,
+    token: &'a str,
+    expires_at: Option<i32>,
+    scope: Option<&'a str>,
+}
+
+impl<'a> From<&'a NewToken> for NewRefreshToken<'a>
+{
+    fn from(token: &'a NewToken) -> Self
+    {
+        NewRefreshToken {
+            client_id: token.client_id,
+            user_id: token.user_id,
+            token: &token.token,
+            expires_at: token.expires_at,
+            scope: token.scope.as_ref().map(String::as_str)
+        }
+    }
+}
+
+/// Authorization code, exchanged for an access token.
+#[derive(Clone, Debug, Eq, PartialEq, Queryable)]
+pub struct AuthCode
+{
+    pub id: Option<i32>,
+    pub client_id: i32,
+    pub user_id: Option<i32>,
+    pub token: String,
+    pub redirect_uri: String,
+    pub expires_at: Option<i32>,
+    pub scope: Option<String>,
+}
+
+/// Authorization code to issue.
+#[derive(Clone, Debug, Eq, PartialEq, Insertable)]
+#[table_name = "auth_code"]
+pub struct NewAuthCode
+{
+    pub client_id: i32,
+    pub user_id: Option<i32>,
+    pub token: String,
+    pub redirect_uri: String,
+    pub expires_at: Option<i32>,
+    pub scope: Option<String>,
+}
+
+/// Current UNIX timestamp of the clock of `database`.
+fn now(database: &Database) -> i32
+{
+    database.system_now().duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i32)
+        .unwrap_or(0)
+}
+
+/// Returns whether a value expiring at `expires_at` is expired at `now`.
+fn expired(expires_at: Option<i32>, now: i32) -> bool
+{
+    expires_at.map_or(false, |expires_at| expires_at <= now)
+}
+
+/// Repository of the clients.
+#[derive(Clone, Copy, Debug)]
+pub struct Clients<'a>
+{
+    _database: &'a Database,
+}
+
+impl<'a> Clients<'a>
+{
+    pub fn new(database: &'a Database) -> Self
+    {
+        Self {
+            _database: database
+        }
+    }
+
+    pub fn find(&self, id: i32) -> Result<Option<Client>>
+    {
+        crate::interact_all!(self._database, error::Error, |conn| {
+            Ok(client::table.filter(client::id.eq(id)).first::<Client>(&*conn).optional()?)
+        })
+    }
+
+    pub fn find_by_random_id(&self, random_id: &str) -> Result<Option<Client>>
+    {
+        crate::interact_all!(self._database, error::Error, |conn| {
+            Ok(client::table.filter(client::random_id.eq(random_id)).first::<Client>(&*conn).optional()?)
+        })
+    }
+
+    /// Registers `new_client`, returning it with its id.
+    pub fn insert(&self, new_client: &NewClient) -> Result<Client>
+    {
+        crate::interact_all!(self._database, error::Error, |conn| {
+            diesel::insert_into(client::table).values(new_client).execute(&*conn)?;
+
+            Ok(client::table.filter(client::random_id.eq(&new_client.random_id))
+                .order(client::id.desc())
+                .first::<Client>(&*conn)?)
+        })
+    }
+
+    /// Deletes the client `id`, returning whether it existed. Its tokens are
+    /// left to expire.
+    pub fn delete(&self, id: i32) -> Result<bool>
+    {
+        crate::interact_all!(self._database, error::Error, |conn| {
+            Ok(diesel::delete(client::table.filter(client::id.eq(id))).execute(&*conn)? > 0)
+        })
+    }
+}
+
+/// Generates the repository of a table of tokens: find, insert, revoke and
+/// purge.
+macro_rules! token_repository {
+    (
+        $(#[$meta:meta])*
+        $name:ident, $table:ident, $row:ty, $new:ty, |$new_value:ident| $insertable:expr
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug)]
+        pub struct $name<'a>
+        {
+            _database: &'a Database,
+        }
+
+        impl<'a> $name<'a>
+        {
+            pub fn new(database: &'a Database) -> Self
+            {
+                Self {
+                    _database: database
+                }
+            }
+
+            /// Finds `token`, expired or not.
+            pub fn find_by_token(&self, token: &str) -> Result<Option<$row>>
+            {
+                crate::interact_all!(self._database, error::Error, |conn| {
+                    Ok($table::table.filter($table::token.eq(token)).first::<$row>(&*conn).optional()?)
+                })
+            }
+
+            /// Finds `token` unless it expired.
+            pub fn find_valid(&self, token: &str) -> Result<Option<$row>>
+            {
+                let now = now(self._database);
+
+                Ok(self.find_by_token(token)?.filter(|row| !expired(row.expires_at, now)))
+            }
+
+            /// Issues `new`, returning it with its id.
+            pub fn insert(&self, $new_value: &$new) -> Result<$row>
+            {
+                crate::interact_all!(self._database, error::Error, |conn| {
+                    diesel::insert_into($table::table).values($insertable).execute(&*conn)?;
+
+                    Ok($table::table.filter($table::token.eq(&$new_value.token)).first::<$row>(&*conn)?)
+                })
+            }
+
+            /// Revokes `token`, returning whether it existed.
+            pub fn revoke(&self, token: &str) -> Result<bool>
+            {
+                crate::interact_all!(self._database, error::Error, |conn| {
+                    Ok(diesel::delete($table::table.filter($table::token.eq(token))).execute(&*conn)? > 0)
+                })
+            }
+
+            /// Deletes the expired tokens, returning how many were deleted.
+            pub fn delete_expired(&self) -> Result<usize>
+            {
+                let now = now(self._database);
+
+                crate::interact_all!(self._database, error::Error, |conn| {
+                    Ok(diesel::delete($table::table.filter($table::expires_at.le(now))).execute(&*conn)?)
+                })
+            }
+        }
+    };
+}
+
+token_repository!(
+    /// Repository of the access tokens.
+    AccessTokens, access_token, Token, NewToken, |new| new
+);
+token_repository!(
+    /// Repository of the refresh tokens.
+    RefreshTokens, refresh_token, Token, NewToken, |new| NewRefreshToken::from(new)
+);
+token_repository!(
+    /// Repository of the authorization codes.
+    AuthCodes, auth_code, AuthCode, NewAuthCode, |new| new
+);
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use std::time::Duration;
+    use crate::clock::TestClock;
+    use crate::Database;
+    use super::{now, AccessTokens, AuthCodes, Clients, NewAuthCode, NewClient, NewToken, RefreshTokens};
+
+    fn database() -> (Database, TestClock) {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(include_str!(
+            "../../examples/database/migrations/2019-10-07-065135_create_oauth/up.sql"
+        )).unwrap();
+
+        let clock = TestClock::new();
+        (Database::from_connection(conn).with_clock(clock.clone()), clock)
+    }
+
+    #[test]
+    fn clients() {
+        let (database, _clock) = database();
+        let clients = Clients::new(&database);
+
+        let client = clients.insert(&NewClient {
+            random_id: "app".to_owned(),
+            redirect_uris: "https://app.example/callback https://localhost/callback".to_owned(),
+            secret: "s3cret".to_owned(),
+            allowed_grant_types: "authorization_code refresh_token".to_owned()
+        }).unwrap();
+
+        assert!(client.id.is_some());
+        assert!(client.check_secret("s3cret"));
+        assert!(!client.check_secret("s3cre"));
+        assert!(client.allows_redirect_uri("https://localhost/callback"));
+        assert!(!client.allows_redirect_uri("https://evil.example/callback"));
+        assert!(client.allows_grant_type("refresh_token"));
+        assert!(!client.allows_grant_type("password"));
+
+        assert_eq!(clients.find_by_random_id("app").unwrap(), Some(client.clone()));
+        assert_eq!(clients.find(client.id.unwrap()).unwrap(), Some(client.clone()));
+        assert!(clients.delete(client.id.unwrap()).unwrap());
+        assert_eq!(clients.find_by_random_id("app").unwrap(), None);
+    }
+
+    #[test]
+    fn tokens_expire() {
+        let (database, clock) = database();
+        let tokens = AccessTokens::new(&database);
+        let new = |token: &str, lifetime: Option<i32>| NewToken {
+            client_id: 1,
+            user_id: Some(7),
+            token: token.to_owned(),
+            expires_at: lifetime.map(|lifetime| now(&database) + lifetime),
+            scope: Some("read".to_owned())
+        };
+
+        let token = tokens.insert(&new("short", Some(60))).unwrap();
+        tokens.insert(&new("forever", None)).unwrap();
+        assert_eq!(token.user_id, Some(7));
+        assert_eq!(tokens.find_valid("short").unwrap(), Some(token.clone()));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(tokens.find_valid("short").unwrap(), None);
+        assert_eq!(tokens.find_by_token("short").unwrap(), Some(token));
+        assert_eq!(tokens.delete_expired().unwrap(), 1);
+        assert!(tokens.find_valid("forever").unwrap().is_some());
+
+        assert!(tokens.revoke("forever").unwrap());
+        assert!(!tokens.revoke("forever").unwrap());
+    }
+
+    #[test]
+    fn refresh_tokens_and_codes() {
+        let (database, _clock) = database();
+
+        let refresh = RefreshTokens::new(&database).insert(&NewToken {
+            client_id: 1,
+            user_id: None,
+            token: "refresh".to_owned(),
+            expires_at: None,
+            scope: None
+        }).unwrap();
+        assert_eq!(refresh.token, "refresh");
+        assert_eq!(AccessTokens::new(&database).find_by_token("refresh").unwrap(), None);
+
+        let codes = AuthCodes::new(&database);
+        let code = codes.insert(&NewAuthCode {
+            client_id: 1,
+            user_id: Some(7),
+            token: "code".to_owned(),
+            redirect_uri: "https://localhost/callback".to_owned(),
+            expires_at: None,
+            scope: None
+        }).unwrap();
+        assert_eq!(codes.find_valid("code").unwrap(), Some(code));
+    }
+}
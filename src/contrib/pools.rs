@@ -0,0 +1,137 @@
+//! Coexistence with the database pools of `rocket_contrib`, to migrate an
+//! application one route at a time.
+//!
+//! Routes still using a `#[database("app")]` pool read the `databases.app`
+//! table of `Rocket.toml`. A `Database` can adopt that configuration, so
+//! that both point at the same database while routes move over:
+//!
+//! ```rust,ignore
+//! #[database("app")]
+//! struct AppDb(diesel::PgConnection);
+//!
+//! rocket::ignite()
+//!     .attach(AppDb::fairing())
+//!     .attach(AdHoc::on_attach("Diesel", |rocket| {
+//!         match pools::adopt::<diesel::PgConnection>(&rocket, "app") {
+//!             Ok(database) => Ok(rocket.manage(database)),
+//!             Err(_) => Err(rocket)
+//!         }
+//!     }))
+//! ```
+//!
+//! The other way around, `databases_table` renders the URL of a configured
+//! `Database` as the `databases` table `rocket_contrib` reads, to give to
+//! `rocket::Config::extra` while some routes still use the pool.
+//!
+//! An adopted database holds a connection of its own, established once:
+//! unlike a configured one, it is not re-established after being lost.
+
+use rocket::{
+    config::{
+        Table,
+        Value
+    },
+    Rocket
+};
+use rocket_contrib::databases::{
+    database_config,
+    DatabaseConfig
+};
+
+use crate::Backend;
+use crate::BackendConnection;
+use crate::Database;
+use crate::error;
+use crate::Result;
+
+/// Backend of a `rocket_contrib` database configuration, from the scheme
+/// of its URL. URLs without scheme are SQLite paths, as for
+/// `rocket_contrib`.
+pub fn backend_of(config: &DatabaseConfig<'_>) -> Option<Backend> {
+    match config.url.find("://") {
+        Some(end) => Backend::from_scheme(&config.url[..end]),
+        None => Some(Backend::Sqlite)
+    }
+}
+
+/// Database connected with a connection of `C` to the database of the
+/// `databases.<name>` table, as configured for `rocket_contrib`.
+pub fn adopt<C: BackendConnection>(rocket: &Rocket, name: &str) -> Result<Database> {
+    let config = database_config(name, rocket.config()).map_err(|err| error::Error::new(
+        error::ErrorKind::MissingValue,
+        format!("invalid `databases.{}` configuration: {}", name, err)
+    ))?;
+
+    match backend_of(&config) {
+        Some(backend) if backend == C::BACKEND => {},
+        backend => return Err(error::Error::new(
+            error::ErrorKind::UnsupportedScheme,
+            format!(
+                "`databases.{}` is a {} database, not a {} one",
+                name, backend.map_or("unknown", Backend::as_str), C::BACKEND
+            )
+        ))
+    }
+
+    Ok(Database::from_connection(C::establish(config.url)?))
+}
+
+/// `databases` table of `rocket_contrib` declaring the database of
+/// `database` as `name`, with pools of `pool_size` connections.
+pub fn databases_table(database: &Database, name: &str, pool_size: u32) -> Result<Table> {
+    let settings = database.settings()?;
+    let url = match settings.url().scheme() {
+        // `rocket_contrib` takes SQLite paths
+        "sqlite" => settings.url().path().to_owned(),
+        _ => settings.url().as_str().to_owned()
+    };
+
+    let mut pool = Table::new();
+    pool.insert("url".to_owned(), Value::String(url));
+    pool.insert("pool_size".to_owned(), Value::Integer(i64::from(pool_size)));
+
+    let mut databases = Table::new();
+    databases.insert(name.to_owned(), Value::Table(pool));
+
+    Ok(databases)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::config::{Config, Environment, Table, Value};
+    use rocket_contrib::databases::DatabaseConfig;
+    use std::collections::BTreeMap;
+    use crate::Backend;
+    use super::{adopt, backend_of};
+
+    fn config(url: &str) -> DatabaseConfig<'_> {
+        DatabaseConfig { url, pool_size: 1, extras: BTreeMap::new() }
+    }
+
+    #[test]
+    fn backends_of_urls() {
+        assert_eq!(backend_of(&config("postgres://localhost/app")), Some(Backend::Pg));
+        assert_eq!(backend_of(&config("mysql://localhost/app")), Some(Backend::Mysql));
+        assert_eq!(backend_of(&config("db/app.sqlite")), Some(Backend::Sqlite));
+        assert_eq!(backend_of(&config("redis://localhost")), None);
+    }
+
+    #[test]
+    fn adopts_the_contrib_configuration() {
+        let mut pool = Table::new();
+        pool.insert("url".to_owned(), Value::String(":memory:".to_owned()));
+        let mut databases = Table::new();
+        databases.insert("app".to_owned(), Value::Table(pool));
+
+        let config = Config::build(Environment::Development)
+            .extra("databases", databases)
+            .finalize()
+            .unwrap();
+        let rocket = rocket::custom(config);
+
+        let database = adopt::<diesel::SqliteConnection>(&rocket, "app").unwrap();
+        assert_eq!(database.backend(), Some(Backend::Sqlite));
+        assert!(adopt::<diesel::PgConnection>(&rocket, "app").is_err());
+        assert!(adopt::<diesel::SqliteConnection>(&rocket, "missing").is_err());
+    }
+}
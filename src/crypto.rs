@@ -0,0 +1,303 @@
+//! Encryption of sensitive column values.
+//!
+//! A `KeyRing` encrypts values with AES-256-GCM under its primary key, and
+//! decrypts values encrypted under any of its keys, so that keys can be
+//! rotated: add the new key as primary, keep the old ones until every value
+//! has been re-encrypted with `KeyRing::rotate`.
+//!
+//! ```rust,ignore
+//! let keys = database.key_ring()?;
+//!
+//! impl User {
+//!     fn from_row(row: UserRow, keys: &KeyRing) -> Result<Self> {
+//!         Ok(Self { email: keys.decrypt_str(&row.email)?, .. })
+//!     }
+//! }
+//! ```
+//!
+//! Keys are read from the `crypto.keys` configuration key (`"<id>:<64 hex
+//! digits>"` entries) and `crypto.primary`, or supplied by a `KeyProvider`
+//! (e.g. a secrets manager) given to `Database::with_key_provider`.
+//!
+//! Encrypted values are laid out as the format version, the key id, the
+//! 96-bit random nonce, then the ciphertext and its tag. The version and key
+//! id are authenticated as associated data.
+
+use aes_gcm_crate::{
+    aead::{
+        generic_array::GenericArray,
+        Aead,
+        NewAead,
+        Payload
+    },
+    Aes256Gcm
+};
+
+use rand_crate::{
+    rngs::OsRng,
+    RngCore
+};
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Arc
+};
+
+use crate::error;
+use crate::Result;
+
+/// Version of the layout of encrypted values.
+const VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 12;
+
+const HEADER_LEN: usize = 2 + NONCE_LEN;
+
+/// Length of the keys, in bytes.
+pub const KEY_LEN: usize = 32;
+
+fn crypto_error<E>(message: E) -> error::Error
+    where E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+    error::Error::new(error::ErrorKind::Crypto, message)
+}
+
+/// Source of the keys, e.g. a secrets manager.
+pub trait KeyProvider: Send + Sync + 'static
+{
+    fn key_ring(&self) -> Result<KeyRing>;
+}
+
+/// Keys encrypting and decrypting values.
+#[derive(Clone)]
+pub struct KeyRing
+{
+    _primary: u8,
+    _keys: BTreeMap<u8, Aes256Gcm>,
+}
+
+impl KeyRing
+{
+    /// Key ring encrypting with the key `primary` of `keys`.
+    pub fn new(primary: u8, keys: Vec<(u8, [u8; KEY_LEN])>) -> Result<Self>
+    {
+        let keys: BTreeMap<u8, Aes256Gcm> = keys.iter()
+            .map(|(id, key)| (*id, Aes256Gcm::new(GenericArray::from_slice(key))))
+            .collect();
+
+        if !keys.contains_key(&primary) {
+            return Err(crypto_error(format!("no key with the primary id {}", primary)));
+        }
+
+        Ok(Self {
+            _primary: primary,
+            _keys: keys
+        })
+    }
+
+    /// Parses `"<id>:<hex key>"` entries, as in the `crypto.keys`
+    /// configuration key.
+    pub fn parse(primary: u8, entries: &[String]) -> Result<Self>
+    {
+        let keys = entries.iter().enumerate().map(|(index, entry)| {
+            let invalid = || error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("invalid `crypto.keys[{}]`: expected `<id>:<{} hex digits>`", index, KEY_LEN * 2)
+            );
+            let mut parts = entry.splitn(2, ':');
+            let id = parts.next().and_then(|id| id.trim().parse::<u8>().ok()).ok_or_else(invalid)?;
+            let key = parts.next().and_then(decode_hex).ok_or_else(invalid)?;
+
+            if key.len() != KEY_LEN {
+                return Err(invalid());
+            }
+
+            let mut bytes = [0; KEY_LEN];
+            bytes.copy_from_slice(&key);
+            Ok((id, bytes))
+        }).collect::<Result<Vec<_>>>()?;
+
+        KeyRing::new(primary, keys)
+    }
+
+    /// Id of the key encrypting values.
+    pub fn primary(&self) -> u8
+    {
+        self._primary
+    }
+
+    /// Encrypts `plaintext` with the primary key.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>>
+    {
+        let mut data = vec![VERSION, self._primary];
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        data.extend_from_slice(&nonce);
+
+        let ciphertext = self._keys[&self._primary].encrypt(
+            GenericArray::from_slice(&nonce),
+            Payload { msg: plaintext, aad: &data[..2] }
+        ).map_err(|_err| crypto_error("encryption failed"))?;
+
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    /// Decrypts `data`, with the key it was encrypted with.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>>
+    {
+        let (id, nonce, ciphertext) = split(data)?;
+        let key = self._keys.get(&id)
+            .ok_or_else(|| crypto_error(format!("unknown key id {}", id)))?;
+
+        key.decrypt(
+            GenericArray::from_slice(nonce),
+            Payload { msg: ciphertext, aad: &data[..2] }
+        ).map_err(|_err| crypto_error("decryption failed: wrong key or tampered value"))
+    }
+
+    /// Same as `encrypt`, hex-encoded for text columns.
+    pub fn encrypt_str(&self, plaintext: &str) -> Result<String>
+    {
+        self.encrypt(plaintext.as_bytes()).map(|data| encode_hex(&data))
+    }
+
+    /// Same as `decrypt`, for values encrypted with `encrypt_str`.
+    pub fn decrypt_str(&self, data: &str) -> Result<String>
+    {
+        let data = decode_hex(data).ok_or_else(|| crypto_error("encrypted value is not hex-encoded"))?;
+
+        String::from_utf8(self.decrypt(&data)?).map_err(crypto_error)
+    }
+
+    /// Returns whether `data` was encrypted with another key than the
+    /// primary one.
+    pub fn needs_rotation(&self, data: &[u8]) -> Result<bool>
+    {
+        split(data).map(|(id, _, _)| id != self._primary)
+    }
+
+    /// Re-encrypts `data` with the primary key.
+    pub fn rotate(&self, data: &[u8]) -> Result<Vec<u8>>
+    {
+        self.encrypt(&self.decrypt(data)?)
+    }
+}
+
+impl fmt::Debug for KeyRing
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        // Never prints the keys
+        fmt.debug_struct("KeyRing")
+            .field("primary", &self._primary)
+            .field("ids", &self._keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Key provider of a database.
+#[derive(Clone)]
+pub(crate) struct SharedKeyProvider(Arc<dyn KeyProvider>);
+
+impl SharedKeyProvider
+{
+    pub fn new<P: KeyProvider>(provider: P) -> Self
+    {
+        SharedKeyProvider(Arc::new(provider))
+    }
+
+    pub fn key_ring(&self) -> Result<KeyRing>
+    {
+        self.0.key_ring()
+    }
+}
+
+impl fmt::Debug for SharedKeyProvider
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.write_str("SharedKeyProvider")
+    }
+}
+
+/// Splits encrypted `data` in key id, nonce and ciphertext.
+fn split(data: &[u8]) -> Result<(u8, &[u8], &[u8])>
+{
+    if data.len() < HEADER_LEN {
+        return Err(crypto_error("encrypted value is truncated"));
+    }
+    if data[0] != VERSION {
+        return Err(crypto_error(format!("unsupported encrypted value version {}", data[0])));
+    }
+
+    Ok((data[1], &data[2..HEADER_LEN], &data[HEADER_LEN..]))
+}
+
+fn encode_hex(data: &[u8]) -> String
+{
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>>
+{
+    let hex = hex.trim();
+
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyRing, KEY_LEN};
+
+    fn key_ring(primary: u8) -> KeyRing {
+        KeyRing::new(primary, vec![(1, [1; KEY_LEN]), (2, [2; KEY_LEN])]).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let keys = key_ring(1);
+        let encrypted = keys.encrypt_str("jane@example.com").unwrap();
+
+        assert_ne!(encrypted, keys.encrypt_str("jane@example.com").unwrap());
+        assert_eq!(keys.decrypt_str(&encrypted).unwrap(), "jane@example.com");
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let keys = key_ring(1);
+        let mut encrypted = keys.encrypt(b"secret").unwrap();
+
+        // Claims the value was encrypted with the other key
+        encrypted[1] = 2;
+        assert!(keys.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn rotation() {
+        let old = key_ring(1).encrypt(b"secret").unwrap();
+        let keys = key_ring(2);
+
+        assert!(keys.needs_rotation(&old).unwrap());
+
+        let rotated = keys.rotate(&old).unwrap();
+        assert!(!keys.needs_rotation(&rotated).unwrap());
+        assert_eq!(keys.decrypt(&rotated).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn parses_configured_keys() {
+        let keys = KeyRing::parse(3, &[format!("3:{}", "ab".repeat(KEY_LEN))]).unwrap();
+        assert_eq!(keys.primary(), 3);
+
+        let err = KeyRing::parse(3, &["3:abcd".to_owned()]).unwrap_err();
+        assert!(err.to_string().contains("`crypto.keys[0]`"));
+    }
+}
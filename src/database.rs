@@ -1,7 +1,15 @@
 #![allow(dead_code)]
 
 use diesel::{
-    connection::Connection as _
+    connection::{
+        Connection as _,
+        SimpleConnection
+    },
+    deserialize::QueryableByName,
+    mysql::Mysql,
+    pg::Pg,
+    query_dsl::RunQueryDsl,
+    sqlite::Sqlite
 };
 
 use rocket::{
@@ -11,40 +19,550 @@ use rocket::{
         Info,
         Kind
     },
+    http::Status,
     Outcome,
     request::Request,
+    response::Response,
     Rocket
 };
 
 use std::{
     any::Any,
+    cell::Cell,
     error::Error,
+    panic::Location,
+    path::PathBuf,
     sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering
+        },
         Arc,
-        RwLock
+        LockResult,
+        Mutex,
+        MutexGuard,
+        Once,
+        RwLock,
+        TryLockError
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+        SystemTime
     }
 };
 
+use crate::any_connection::AnyConnection;
+use crate::autotune::{
+    self,
+    Recommendation
+};
+use crate::backoff::Backoff;
+use crate::bootstrap;
+use crate::circuit::{
+    CircuitBreaker,
+    CircuitBreakerSettings
+};
+#[cfg(any(test, feature = "testing"))]
+use crate::clock::Clock;
+use crate::clock::SharedClock;
+#[cfg(feature = "crypto")]
+use crate::crypto::{
+    KeyProvider,
+    KeyRing,
+    SharedKeyProvider
+};
+use crate::fairness::{
+    self,
+    FairQueue
+};
+use crate::flight::SingleFlight;
+use crate::export::{
+    self,
+    Chunk,
+    CsvExport,
+    CsvRow
+};
+use crate::stream::StreamLease;
+use crate::prepared::{
+    Prepared,
+    PreparedSql
+};
+use crate::redaction::{
+    self,
+    log_redacted,
+    Redaction
+};
+use crate::layer::{
+    InteractContext,
+    InteractLayer,
+    Layers,
+    Next
+};
+use crate::CircuitState;
+use crate::Backend;
+use crate::BackendConnection;
 use crate::Connection;
 use crate::LockedConnection;
 use crate::Configuration;
 use crate::Settings;
+use crate::settings::{
+    self,
+    ActiveCredentials,
+    CredentialEncoding,
+    ConnectionLifetime,
+    PoolSettings,
+    ReplicaSettings,
+    SessionSettings,
+    TlsSettings
+};
+use crate::tagging::{
+    self,
+    QueryTag
+};
+use crate::tx;
+use crate::schema::{
+    Schema,
+    SchemaConnection
+};
+use crate::resolver::{
+    AddressResolver,
+    Resolver
+};
+use crate::sharding::{
+    Router,
+    ShardRouter
+};
+use crate::sqlite_file::{
+    self,
+    WatchedFile
+};
+use crate::stats::{
+    Operation,
+    OperationStats,
+    Stats
+};
+use crate::HistogramSnapshot;
+use crate::StatsSnapshot;
+use crate::support::{
+    MigrationStatus,
+    SupportBundle
+};
+use crate::status;
+use crate::ConfigurationStatus;
+use crate::environment;
 use crate::error;
+use crate::helpers;
+use crate::idempotency;
+use crate::InteractOptions;
+use crate::InteractOutcome;
+use crate::integrity::{
+    self,
+    CheckResult,
+    Checks,
+    Severity
+};
+use crate::metrics::MetricsToken;
+use crate::migrations::{
+    MigrationEngine,
+    MigrationPolicy,
+    MigrationTarget,
+    Migrations,
+    PlannedMigration,
+    SharedEngine
+};
+use crate::outbox;
+use crate::profile::{
+    ProfileSample,
+    ProfileSettings,
+    Profiler
+};
+use crate::quota::Quotas;
+use crate::QuotaUsage;
+use crate::HeapSize;
+use crate::ResultLimit;
+use crate::retry;
+use crate::schedule::{
+    self,
+    Claim
+};
+use crate::ServerVersion;
+use crate::Strictness;
+#[cfg(any(test, feature = "testing"))]
+use crate::simulation::{
+    self,
+    Simulation
+};
+#[cfg(feature = "sessions")]
+use crate::session::{
+    self,
+    Session,
+    Sessions
+};
+#[cfg(any(feature = "r2d2", feature = "deadpool"))]
+use crate::pool::{
+    self,
+    ConnectionSetup,
+    Pool,
+    PoolState
+};
+use crate::storage::StorageFullHook;
+use crate::versions::{
+    self,
+    TableVersion,
+    VersionRow
+};
+use crate::views::{
+    View,
+    Views,
+    ViewStatus
+};
+use crate::warmup::{
+    self,
+    WarmUp
+};
+use crate::version::{
+    self,
+    VersionConnection
+};
 use crate::Result;
 
+/// Reads an optional value, converting it with `$convert` and failing with
+/// `FormatError` when the conversion fails.
+///
+/// The environment variable named after `$key` takes precedence over the
+/// configuration.
+macro_rules! optional_value {
+    ($configuration:expr, $key:expr, $convert:expr) => {
+        match environment::lookup($key) {
+            Some(value) => Some(environment::convert(&value, $convert).ok_or(error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("invalid format for `{}` in `{}`.", $key, environment::variable_name($key))
+            ))?),
+            None => match optional_table!($configuration, $key) {
+                None => None,
+                Some(value) => Some(environment::convert(value, $convert).ok_or(error::Error::new(
+                    error::ErrorKind::FormatError,
+                    format!("invalid format for `{}` in configuration.", $key)
+                ))?)
+            }
+        }
+    };
+}
+
+/// Reads an optional table (e.g. `migrations`) from the configuration.
+macro_rules! optional_table {
+    ($configuration:expr, $key:expr) => {
+        $configuration.get($key).map_err(|err| error::Error::new(
+            error::ErrorKind::Other,
+            err.description()
+        ))?
+    };
+}
+
+/// Reads an optional value of the `$table` table (e.g. `policy` in
+/// `migrations`), like `optional_value`.
+macro_rules! nested_value {
+    ($table:expr, $key:expr, $convert:expr) => {{
+        let key = format!("{}.{}", $table.0, $key);
+
+        match environment::lookup(&key) {
+            Some(value) => Some(environment::convert(&value, $convert).ok_or(error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("invalid format for `{}` in `{}`.", key, environment::variable_name(&key))
+            ))?),
+            None => match $table.1.as_ref().and_then(|table| table.get($key)) {
+                None => None,
+                Some(value) => Some(environment::convert(value, $convert).ok_or(error::Error::new(
+                    error::ErrorKind::FormatError,
+                    format!("invalid format for `{}` in configuration.", key)
+                ))?)
+            }
+        }
+    }};
+}
+
+/// Executes the closure matching the backend of the locked connection,
+/// recording the backend and the execution time in `context`.
+pub(crate) fn run_on<T, E, MysqlF, PgF, SqliteF>(
+    guard: &mut LockedConnection<'_>,
+    context: &mut InteractContext,
+    mysql_f: MysqlF,
+    pg_f: PgF,
+    sqlite_f: SqliteF
+) -> Result<T>
+    where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+          MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+          PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+          SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+{
+    let start = Instant::now();
+
+    let (value, backend) = match guard.conn_mut() {
+        crate::locked_connection::ConnectionMut::Unknown => {
+            return Err(error::Error::new(
+                error::ErrorKind::UnsupportedScheme, "connection of an unsupported backend"
+            ));
+        },
+        crate::locked_connection::ConnectionMut::Mysql(conn) => {
+            (mysql_f(conn), Backend::Mysql)
+        },
+        crate::locked_connection::ConnectionMut::Pg(conn) => {
+            (pg_f(conn), Backend::Pg)
+        },
+        crate::locked_connection::ConnectionMut::Sqlite(conn) => {
+            (sqlite_f(conn), Backend::Sqlite)
+        },
+    };
+
+    context.executed(backend, start.elapsed());
+
+    value.map_err(error::Error::from_interaction)
+}
+
+/// Runs `f` on `conn` with the `options` of an interaction.
+fn with_options<C, T, E, F>(conn: &mut C, backend: Backend, options: InteractOptions, f: F)
+    -> std::result::Result<T, E>
+    where C: diesel::Connection,
+          E: From<diesel::result::Error>,
+          F: FnOnce(&mut C) -> std::result::Result<T, E>
+{
+    match (options.statement_timeout, options.dry_run) {
+        (None, false) => f(conn),
+        (None, true) => helpers::dry_run(conn, f),
+        (Some(timeout), false) => helpers::with_statement_timeout(conn, backend, timeout, f),
+        (Some(timeout), true) => helpers::with_statement_timeout(
+            conn, backend, timeout, |conn| helpers::dry_run(conn, f)
+        ),
+    }
+}
+
+/// Settings of a support bundle: passwords are masked, tokens and keys
+/// only reported as set.
+fn support_settings(settings: &Settings) -> Vec<(&'static str, String)>
+{
+    let limit = settings.result_limit();
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+
+    vec![
+        ("url", settings.redacted_url()),
+        ("fallback_url", optional(settings.redacted_fallback_url())),
+        ("application_name", optional(settings.application_name().map(str::to_owned))),
+        ("bootstrap", settings.bootstrap().to_string()),
+        ("idle_timeout", optional(settings.connection_lifetime().idle_timeout.map(|timeout| format!("{:?}", timeout)))),
+        ("max_lifetime", optional(settings.connection_lifetime().max_lifetime.map(|lifetime| format!("{:?}", lifetime)))),
+        ("warm_up_parallelism", settings.warm_up_parallelism().to_string()),
+        ("sqlite_max_page_count", optional(settings.sqlite_max_page_count().map(|pages| pages.to_string()))),
+        ("integrity_checks.fail_on_critical", settings.fail_on_critical_checks().to_string()),
+        ("max_result_rows", optional(limit.max_rows.map(|rows| rows.to_string()))),
+        ("max_result_bytes", optional(limit.max_bytes.map(|bytes| bytes.to_string()))),
+        ("circuit_breaker", settings.circuit_breaker().is_some().to_string()),
+        ("query_tagging", settings.query_tagging().to_string()),
+        ("query_tagging_client_ip", settings.query_tagging_client_ip().to_string()),
+        ("retry_on_disconnect", settings.retry_on_disconnect().to_string()),
+        ("retry_budget", format!("{:?}", settings.retry_budget())),
+        ("shards", settings.shard_urls().len().to_string()),
+        ("params", settings.params().iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>().join(",")),
+        ("pool.checkout_timeout", optional(settings.pool().checkout_timeout.map(|timeout| format!("{:?}", timeout)))),
+        ("pool.fair", settings.pool().fair.to_string()),
+        ("pool.size", optional(settings.pool().size.map(|size| size.to_string()))),
+        ("pool.min", optional(settings.pool().min.map(|min| min.to_string()))),
+        ("pool.implementation", optional(settings.pool().implementation.map(|implementation| implementation.as_str().to_owned()))),
+        ("tls.mode", optional(settings.tls().mode.map(|mode| mode.as_str().to_owned()))),
+        ("replicas", settings.replicas().urls.len().to_string()),
+        ("min_version", optional(settings.min_version().map(|version| version.to_string()))),
+        ("metrics.token", if settings.metrics_token().is_some() { "set" } else { "-" }.to_owned()),
+    ]
+}
+
+/// Error of a URL whose scheme names no supported backend.
+fn unsupported_scheme(scheme: &str) -> error::Error
+{
+    error::Error::new(
+        error::ErrorKind::UnsupportedScheme,
+        format!("unsupported database URL scheme `{}` (expected `mysql`, `postgres` or `sqlite`)", scheme)
+    )
+}
+
+/// Converts a failure to establish the connection, naming the host when it
+/// could not be resolved.
+fn establish_error(err: diesel::result::ConnectionError, settings: &Settings) -> error::Error
+{
+    let err = error::Error::from(err);
+
+    match (err.kind(), settings.url().host_str()) {
+        (error::ErrorKind::HostResolution, Some(host)) => error::Error::new(
+            error::ErrorKind::HostResolution,
+            format!("failed to resolve database host `{}`: {}", host, err)
+        ),
+        _ => err
+    }
+}
+
+/// Connections of the pool completing the shared connection to `url`, 0
+/// when it is not pooled (see `pool.size`).
+fn pooled_connections(connection_url: &str, pool: &PoolSettings) -> usize
+{
+    let in_memory = url::Url::parse(connection_url).map_or(false, |url| {
+        url.scheme() == "sqlite" && sqlite_file::in_memory(url.path())
+    });
+
+    match pool.size {
+        Some(size) if size > 1 && cfg!(any(feature = "r2d2", feature = "deadpool")) && !in_memory => size as usize,
+        _ => 0
+    }
+}
+
+/// Statements run on each SQLite connection of `settings` once
+/// established: the cap of `sqlite_max_page_count` pages.
+fn sqlite_setup_sql(settings: &Settings) -> Option<String>
+{
+    settings.sqlite_max_page_count().map(|pages| format!("PRAGMA max_page_count = {}", pages))
+}
+
+/// Opens the SQLite database of `settings`, set up with `sqlite_setup_sql`.
+fn establish_sqlite(settings: &Settings) -> Result<diesel::SqliteConnection>
+{
+    let sqlite = diesel::SqliteConnection::establish(settings.url().path())?;
+
+    if let Some(sql) = sqlite_setup_sql(settings) {
+        sqlite.batch_execute(&sql)?;
+    }
+    Ok(sqlite)
+}
+
 #[derive(Clone, Debug)]
 pub struct Database
 {
+    // Set once attached to Rocket
+    _application: Option<String>,
+    _attached: Arc<AtomicBool>,
+    _autotune: bool,
+    _backoff: Arc<Mutex<Backoff>>,
+    _checks: Checks,
+    _circuit: Arc<Mutex<CircuitBreaker>>,
+    _clock: SharedClock,
     _configuration: Arc<RwLock<Option<Configuration>>>,
-    _database: Arc<Connection>
+    _configuration_backoff: Arc<Mutex<Backoff>>,
+    // Bumped whenever the configuration or the workers change
+    _configuration_version: Arc<AtomicUsize>,
+    _credentials: Arc<RwLock<Option<ActiveCredentials>>>,
+    _database: Arc<Connection>,
+    _established: Arc<Mutex<Option<Instant>>>,
+    _idle_reaper: Arc<Once>,
+    _initialization: Arc<SingleFlight>,
+    #[cfg(feature = "crypto")]
+    _key_provider: Option<SharedKeyProvider>,
+    _last_used: Arc<Mutex<Option<Instant>>>,
+    _layers: Layers,
+    _migration_engine: Option<SharedEngine>,
+    #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+    _pool: Arc<RwLock<Option<Arc<Pool>>>>,
+    _profiler: Arc<Profiler>,
+    _queue: Arc<FairQueue>,
+    _quotas: Arc<Quotas>,
+    // Set once initialized, so that requests skip the fairing's checks
+    _ready: Arc<AtomicBool>,
+    _refresh_after: Option<u32>,
+    _resolver: Resolver,
+    _router: Router,
+    _schema: Option<Arc<Schema>>,
+    _server_version: Arc<RwLock<Option<ServerVersion>>>,
+    #[cfg(feature = "sessions")]
+    _session_reaper: Arc<Once>,
+    #[cfg(feature = "sessions")]
+    _sessions: Arc<Sessions>,
+    // Parsed settings, with the configuration version they were parsed from
+    _settings: Arc<RwLock<Option<(usize, Settings)>>>,
+    _shard: Option<usize>,
+    _shards: Arc<RwLock<Vec<Database>>>,
+    #[cfg(any(test, feature = "testing"))]
+    _simulation: Option<Arc<Simulation>>,
+    _sqlite_file: Arc<Mutex<Option<WatchedFile>>>,
+    _stats: Arc<Stats>,
+    _stats_logger: Arc<Once>,
+    _storage_full: StorageFullHook,
+    _stream_leases: Arc<AtomicUsize>,
+    _strictness: Strictness,
+    _tag_client_ip: Arc<AtomicBool>,
+    _views: Views,
+    // Rocket workers, set once attached
+    _workers: Arc<AtomicUsize>
+}
+
+/// Turns `NotFound` into `None`.
+fn optional<T, E>(result: std::result::Result<T, E>) -> Result<Option<T>>
+    where E: Error + Send + Sync + 'static
+{
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => match error::Error::from_interaction(err) {
+            ref err if err.is_not_found() => Ok(None),
+            err => Err(err)
+        }
+    }
 }
 
 impl Default for Database {
     fn default() -> Self
     {
+        let circuit = Arc::new(Mutex::new(CircuitBreaker::new()));
+        let stats = Arc::new(Stats::new());
+        let profiler = Arc::new(Profiler::default());
+        let quotas = Arc::new(Quotas::default());
+
         Self {
+            _application: None,
+            _attached: Arc::new(AtomicBool::new(false)),
+            _autotune: false,
+            _backoff: Arc::new(Mutex::new(Backoff::new())),
+            _checks: Checks::default(),
+            _circuit: circuit.clone(),
+            _clock: SharedClock::default(),
             _database: Arc::new(Connection::default()),
-            _configuration: Arc::new(RwLock::new(None))
+            _established: Arc::new(Mutex::new(None)),
+            _configuration: Arc::new(RwLock::new(None)),
+            _configuration_backoff: Arc::new(Mutex::new(Backoff::new())),
+            _configuration_version: Arc::new(AtomicUsize::new(0)),
+            _credentials: Arc::new(RwLock::new(None)),
+            _idle_reaper: Arc::new(Once::new()),
+            _initialization: Arc::new(SingleFlight::default()),
+            #[cfg(feature = "crypto")]
+            _key_provider: None,
+            _last_used: Arc::new(Mutex::new(None)),
+            _layers: Layers::new(circuit, stats.clone(), profiler.clone(), quotas.clone()),
+            _migration_engine: None,
+            #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+            _pool: Arc::new(RwLock::new(None)),
+            _profiler: profiler,
+            _queue: Arc::new(FairQueue::default()),
+            _quotas: quotas,
+            _ready: Arc::new(AtomicBool::new(false)),
+            _refresh_after: None,
+            _resolver: Resolver::default(),
+            _router: Router::default(),
+            _schema: None,
+            _server_version: Arc::new(RwLock::new(None)),
+            #[cfg(feature = "sessions")]
+            _session_reaper: Arc::new(Once::new()),
+            #[cfg(feature = "sessions")]
+            _sessions: Arc::new(Sessions::default()),
+            _settings: Arc::new(RwLock::new(None)),
+            _shard: None,
+            _shards: Arc::new(RwLock::new(Vec::new())),
+            #[cfg(any(test, feature = "testing"))]
+            _simulation: None,
+            _sqlite_file: Arc::new(Mutex::new(None)),
+            _stats: stats,
+            _stats_logger: Arc::new(Once::new()),
+            _storage_full: StorageFullHook::default(),
+            _stream_leases: Arc::new(AtomicUsize::new(0)),
+            _strictness: Strictness::default(),
+            _tag_client_ip: Arc::new(AtomicBool::new(false)),
+            _views: Views::default(),
+            _workers: Arc::new(AtomicUsize::new(0))
         }
     }
 }
@@ -56,6 +574,186 @@ impl Database
         Self::default()
     }
 
+    /// Creates a database holding an already established connection, e.g.
+    /// one wrapped in a test transaction.
+    ///
+    /// No configuration is needed: the connection is used as is, without
+    /// applying migrations nor bootstrapping. If it gets closed (idle
+    /// timeout, lost connection), it is re-established from the
+    /// configuration, if any.
+    pub fn from_connection<C: BackendConnection>(connection: C) -> Self
+    {
+        let database = Self::default();
+        let boxed: Box<dyn Any> = Box::new(connection);
+
+        Self {
+            _database: Arc::new(Connection::new(Some(boxed))),
+            _ready: Arc::new(AtomicBool::new(true)),
+            ..database
+        }
+    }
+
+    /// Database without connection recording the statements it executes,
+    /// translated for `backend`. See `rocket_diesel::simulation`.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn simulated(backend: Backend) -> Self
+    {
+        Self {
+            _ready: Arc::new(AtomicBool::new(true)),
+            _simulation: Some(Arc::new(Simulation::new(backend))),
+            ..Self::default()
+        }
+    }
+
+    /// Simulation of a database created with `simulated`.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn simulation(&self) -> Option<&Simulation>
+    {
+        self._simulation.as_ref().map(|simulation| &**simulation)
+    }
+
+    /// Applies the pool settings recommended by `autotune` for the keys of
+    /// the `pool` section the configuration leaves unset, and logs the
+    /// pathological combinations found once initialized.
+    pub fn autotuned(mut self) -> Self
+    {
+        self._autotune = true;
+        self.configuration_changed();
+        self
+    }
+
+    /// Identifies the connections as the application `name` at `version`
+    /// (`name/version`), e.g. `env!("CARGO_PKG_NAME")` and
+    /// `env!("CARGO_PKG_VERSION")`, unless the configuration sets
+    /// `application_name`.
+    pub fn with_application(mut self, name: &str, version: &str) -> Self
+    {
+        self._application = Some(settings::application_name(name, version));
+        self.configuration_changed();
+        self
+    }
+
+    /// Sets how misuse is reported: with typed errors (`Lenient`, the
+    /// default), or with panics in debug builds (`Strict`).
+    pub fn strictness(mut self, strictness: Strictness) -> Self
+    {
+        self._strictness = strictness;
+        self
+    }
+
+    /// Re-reads the configuration once `failures` consecutive
+    /// initialization attempts failed, so that a fixed configuration file is
+    /// picked up without restarting the server.
+    pub fn with_configuration_refresh(mut self, failures: u32) -> Self
+    {
+        self._refresh_after = Some(failures);
+        self
+    }
+
+    /// Reads the time from `clock` rather than from the system.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_clock<C: Clock>(mut self, clock: C) -> Self
+    {
+        self._clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Compares, in debug builds, the columns of `schema` with the database
+    /// once connected, logging a warning for each divergence.
+    pub fn with_schema(mut self, schema: Schema) -> Self
+    {
+        self._schema = Some(Arc::new(schema));
+        self
+    }
+
+    /// Reads the encryption keys from `provider` rather than from the
+    /// `crypto` section of the configuration.
+    #[cfg(feature = "crypto")]
+    pub fn with_key_provider<P: KeyProvider>(mut self, provider: P) -> Self
+    {
+        self._key_provider = Some(SharedKeyProvider::new(provider));
+        self
+    }
+
+    /// Finds and applies migrations with `engine` rather than with the diesel
+    /// migrations of `migrations.directory`. The `migrations.policy` still
+    /// decides what happens to pending migrations.
+    pub fn with_migration_engine<E: MigrationEngine>(mut self, engine: E) -> Self
+    {
+        self._migration_engine = Some(SharedEngine::new(engine));
+        self
+    }
+
+    /// Routes `interact_on_shard` with `router` rather than with the
+    /// default `HashRouter`.
+    pub fn with_shard_router<R: ShardRouter>(mut self, router: R) -> Self
+    {
+        self._router = Router::new(router);
+        self
+    }
+
+    /// Connects to the URL `resolver` returns for the configured one each
+    /// time the connection is established, e.g. to go through a tunnel
+    /// whose local address is only known at runtime.
+    pub fn with_address_resolver<R: AddressResolver>(mut self, resolver: R) -> Self
+    {
+        self._resolver = Resolver::new(resolver);
+        self
+    }
+
+    /// Wraps the execution of interactions with `layer`.
+    ///
+    /// Layers run in the order they are added, inside the statistics and the
+    /// circuit breaker, outside the slow interaction log.
+    pub fn layer<L: InteractLayer>(mut self, layer: L) -> Self
+    {
+        self._layers.push(layer);
+        self
+    }
+
+    /// Drops the stored configuration and the initialization failures: the
+    /// configuration is read again on the next request.
+    ///
+    /// An already initialized connection is kept as is.
+    pub fn refresh_configuration(&self)
+    {
+        if let Ok(mut configuration) = self._configuration.write() {
+            *configuration = None;
+        }
+        self.configuration_changed();
+        if let Ok(mut backoff) = self._backoff.lock() {
+            backoff.reset();
+        }
+    }
+
+    /// Returns whether the stored configuration should be read again before
+    /// the next initialization attempt.
+    fn should_refresh_configuration(&self) -> bool
+    {
+        match (self._refresh_after, self._backoff.lock()) {
+            (Some(failures), Ok(backoff)) => {
+                backoff.failures() >= failures && backoff.ready(self._clock.now())
+            },
+            _ => false
+        }
+    }
+
+    /// Drops the stored configuration when initialization keeps failing,
+    /// returning whether it did.
+    fn forget_failing_configuration(&self) -> bool
+    {
+        if !self.should_refresh_configuration() {
+            return false;
+        }
+
+        log_redacted!(log::Level::Info, "Initialization keeps failing, reading configuration again.");
+        if let Ok(mut lock) = self._configuration.write() {
+            *lock = None;
+        }
+        self.configuration_changed();
+        true
+    }
+
     pub fn has_configuration(&self) -> bool
     {
         if let Ok(configuration) = self._configuration.read() {
@@ -65,7 +763,93 @@ impl Database
         false
     }
 
-    fn settings(&self) -> Result<Settings> {
+    /// Returns whether the configuration was read, and whether it is
+    /// valid, so that a misconfigured application can be told from an
+    /// unreachable database.
+    pub fn configuration_status(&self) -> ConfigurationStatus
+    {
+        if self.has_configuration() {
+            return match self.settings() {
+                Ok(_) => ConfigurationStatus::Loaded,
+                Err(err) => ConfigurationStatus::Invalid { error: err.to_string() }
+            };
+        }
+
+        match self._configuration_backoff.lock() {
+            Ok(ref backoff) if backoff.failures() > 0 => ConfigurationStatus::Missing {
+                failures: backoff.failures(),
+                error: backoff.last_error().map(|err| err.to_string()).unwrap_or_default()
+            },
+            _ => ConfigurationStatus::Pending
+        }
+    }
+
+    /// Returns whether reading the configuration may be attempted again,
+    /// after previous failures.
+    fn configuration_ready(&self) -> bool
+    {
+        self._configuration_backoff.lock()
+            .map(|backoff| backoff.ready(self._clock.now()))
+            .unwrap_or(true)
+    }
+
+    /// Records a failure to read the configuration, logged louder as they
+    /// repeat.
+    fn configuration_failed(&self, status: Status)
+    {
+        let err = error::Error::new(
+            error::ErrorKind::MissingValue,
+            format!("failed to read the `diesel` configuration ({})", status)
+        );
+
+        if let Ok(mut backoff) = self._configuration_backoff.lock() {
+            backoff.record_failure(&err, self._clock.now());
+            log_redacted!(
+                status::log_level(backoff.failures()),
+                "{} ({} attempt(s)). Retrying in {:?}.",
+                err, backoff.failures(), backoff.delay()
+            );
+        }
+    }
+
+    /// Makes the next `settings` call parse the configuration again.
+    fn configuration_changed(&self)
+    {
+        self._configuration_version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Settings parsed from the stored configuration, parsed again only once
+    /// the configuration (or the number of workers) changed.
+    pub(crate) fn settings(&self) -> Result<Settings> {
+        let version = self._configuration_version.load(Ordering::Acquire);
+
+        if let Ok(cached) = self._settings.read() {
+            if let Some((cached_version, settings)) = cached.as_ref() {
+                if *cached_version == version {
+                    return Ok(settings.clone());
+                }
+            }
+        }
+
+        let settings = self.parse_settings()?;
+
+        if let Ok(mut cached) = self._settings.write() {
+            *cached = Some((version, settings.clone()));
+        }
+        Ok(settings)
+    }
+
+    /// Uses `settings` as if parsed from the configuration, until it changes.
+    #[cfg(test)]
+    fn with_settings(self, settings: Settings) -> Self
+    {
+        if let Ok(mut cached) = self._settings.write() {
+            *cached = Some((self._configuration_version.load(Ordering::Acquire), settings));
+        }
+        self
+    }
+
+    fn parse_settings(&self) -> Result<Settings> {
         let guard = self._configuration.read();
 
         if guard.is_err() {
@@ -82,19 +866,406 @@ impl Database
         }
         let configuration = guard.as_ref().unwrap();
 
-        let url_value = configuration.get("url").map_err(|err| error::Error::new(
-            error::ErrorKind::Other,
-            err.description()
-        ))?.ok_or(error::Error::new(
+        let url = optional_value!(
+            configuration, "url", |value| value.as_str().map(str::to_owned)
+        ).ok_or(error::Error::new(
             error::ErrorKind::MissingValue, "no `url` value in configuration"
         ))?;
 
-        let url = url_value.as_str().ok_or(error::Error::new(
-            error::ErrorKind::FormatError,
-            "invalid format for `url` in configuration."
-        ))?.to_owned();
-        
-        Settings::new(url)
+        let shard_urls = optional_value!(
+            configuration, "shards", |value| value.as_array().and_then(|urls| {
+                urls.iter()
+                    .map(|url| url.as_str().map(str::to_owned))
+                    .collect::<Option<Vec<String>>>()
+            })
+        ).unwrap_or_default();
+
+        // Shards share the configuration, only their URL differs
+        let url = match self._shard {
+            None => url,
+            Some(index) => shard_urls.get(index).cloned().ok_or(error::Error::new(
+                error::ErrorKind::MissingValue,
+                format!("no shard #{} in `shards` configuration", index)
+            ))?
+        };
+
+        let credentials_encoding = match optional_value!(
+            configuration, "credentials_encoding", |value| value.as_str().map(str::to_owned)
+        ) {
+            None => CredentialEncoding::default(),
+            Some(encoding) => encoding.parse()?
+        };
+        let username = optional_value!(
+            configuration, "username", |value| value.as_str().map(str::to_owned)
+        );
+        let password = optional_value!(
+            configuration, "password", |value| value.as_str().map(str::to_owned)
+        );
+        // Shards have their own URL
+        let fallback_url = match self._shard {
+            None => optional_value!(
+                configuration, "fallback_url", |value| value.as_str().map(str::to_owned)
+            ).map(|url| settings::encode_credentials(&url, credentials_encoding)),
+            Some(_) => None
+        };
+        let params = match optional_table!(configuration, "params") {
+            None => Vec::new(),
+            Some(table) => table.as_object().ok_or(error::Error::new(
+                error::ErrorKind::FormatError, "`params` must be a table"
+            ))?.iter().map(|(key, value)| {
+                let value = value.as_str().map(str::to_owned)
+                    .or_else(|| value.as_bool().map(|value| value.to_string()))
+                    .or_else(|| value.as_u64().map(|value| value.to_string()))
+                    .or_else(|| value.as_i64().map(|value| value.to_string()))
+                    .or_else(|| value.as_f64().map(|value| value.to_string()))
+                    .ok_or(error::Error::new(
+                        error::ErrorKind::FormatError,
+                        format!("invalid format for `params.{}` in configuration.", key)
+                    ))?;
+
+                Ok((key.clone(), value))
+            }).collect::<Result<Vec<(String, String)>>>()?
+        };
+        let route_quotas = match optional_table!(configuration, "route_quotas") {
+            None => Vec::new(),
+            Some(table) => table.as_object().ok_or(error::Error::new(
+                error::ErrorKind::FormatError, "`route_quotas` must be a table"
+            ))?.iter().map(|(prefix, limit)| {
+                let limit = limit.as_u64().ok_or(error::Error::new(
+                    error::ErrorKind::FormatError,
+                    format!("invalid format for `route_quotas.{}` in configuration.", prefix)
+                ))?;
+
+                Ok((prefix.clone(), limit as usize))
+            }).collect::<Result<Vec<(String, usize)>>>()?
+        };
+        let application_name = optional_value!(
+            configuration, "application_name", |value| value.as_str().map(str::to_owned)
+        );
+        let stats_interval = optional_value!(
+            configuration, "stats_interval", |value| value.as_u64().map(Duration::from_secs)
+        );
+        let bootstrap = optional_value!(
+            configuration, "bootstrap", |value| value.as_bool()
+        );
+        let warm_up_parallelism = optional_value!(
+            configuration, "warm_up_parallelism", |value| value.as_u64().map(|parallelism| parallelism as usize)
+        );
+        let sqlite_max_page_count = optional_value!(
+            configuration, "sqlite_max_page_count", |value| value.as_u64()
+        );
+        let max_result_rows = optional_value!(
+            configuration, "max_result_rows", |value| value.as_u64().map(|rows| rows as usize)
+        );
+        let max_result_bytes = optional_value!(
+            configuration, "max_result_bytes", |value| value.as_u64().map(|bytes| bytes as usize)
+        );
+        let compression = optional_value!(
+            configuration, "compression", |value| value.as_bool()
+        );
+        let pg_options = optional_value!(
+            configuration, "pg_options", |value| value.as_str().map(str::to_owned)
+        );
+        let circuit_breaker = {
+            let table = ("circuit_breaker", optional_table!(configuration, "circuit_breaker"));
+            let defaults = CircuitBreakerSettings::default();
+
+            let error_rate = nested_value!(table, "error_rate", |value| value.as_f64());
+            let window = nested_value!(
+                table, "window", |value| value.as_u64().map(Duration::from_secs)
+            );
+            let min_interactions = nested_value!(
+                table, "min_interactions", |value| value.as_u64().map(|count| count as u32)
+            );
+            let cool_down = nested_value!(
+                table, "cool_down", |value| value.as_u64().map(Duration::from_secs)
+            );
+
+            // Enabled by the table or by any of its overrides
+            let enabled = table.1.is_some() || error_rate.is_some() || window.is_some() ||
+                min_interactions.is_some() || cool_down.is_some();
+
+            if enabled {
+                Some(CircuitBreakerSettings {
+                    error_rate: error_rate.unwrap_or(defaults.error_rate),
+                    window: window.unwrap_or(defaults.window),
+                    min_interactions: min_interactions.unwrap_or(defaults.min_interactions),
+                    cool_down: cool_down.unwrap_or(defaults.cool_down)
+                })
+            } else {
+                None
+            }
+        };
+        let query_tagging = optional_value!(
+            configuration, "query_tagging", |value| value.as_bool()
+        );
+        let query_tagging_client_ip = optional_value!(
+            configuration, "query_tagging_client_ip", |value| value.as_bool()
+        );
+        let retry_on_disconnect = optional_value!(
+            configuration, "retry_on_disconnect", |value| value.as_bool()
+        );
+        let retry_budget = optional_value!(
+            configuration, "retry_budget", |value| value.as_u64().map(Duration::from_millis)
+        );
+        let serverless_idle_timeout = optional_value!(
+            configuration, "serverless_idle_timeout", |value| value.as_u64().map(Duration::from_secs)
+        );
+        // The idle reaper would close the connection as soon as it is used
+        if serverless_idle_timeout == Some(Duration::from_secs(0)) {
+            return Err(error::Error::new(
+                error::ErrorKind::FormatError, "`serverless_idle_timeout` must be at least 1 second"
+            ));
+        }
+        let connection_lifetime = ConnectionLifetime {
+            idle_timeout: optional_value!(
+                configuration, "idle_timeout", |value| value.as_u64().map(Duration::from_secs)
+            ),
+            max_lifetime: optional_value!(
+                configuration, "max_lifetime", |value| value.as_u64().map(Duration::from_secs)
+            )
+        };
+        let slow_query_threshold = optional_value!(
+            configuration, "slow_query_threshold", |value| value.as_u64().map(Duration::from_millis)
+        );
+
+        let migrations = {
+            let table = ("migrations", optional_table!(configuration, "migrations"));
+
+            let policy = match nested_value!(
+                table, "policy", |value| value.as_str().map(str::to_owned)
+            ) {
+                None => MigrationPolicy::default(),
+                Some(policy) => policy.parse().map_err(|err| error::Error::new(
+                    error::ErrorKind::FormatError, format!("{} for `migrations.policy`", err)
+                ))?
+            };
+            let directory = nested_value!(
+                table, "directory", |value| value.as_str().map(PathBuf::from)
+            );
+            let dry_run = nested_value!(table, "dry_run", |value| value.as_bool());
+
+            Migrations::new(policy, directory).with_dry_run(dry_run.unwrap_or(false))
+        };
+        let (pool, fair_set, checkout_timeout_set) = {
+            let table = ("pool", optional_table!(configuration, "pool"));
+            let checkout_timeout = nested_value!(
+                table, "checkout_timeout", |value| value.as_u64().map(Duration::from_millis)
+            );
+            let fair = nested_value!(table, "fair", |value| value.as_bool());
+            let size = nested_value!(table, "size", |value| value.as_u64().map(|size| size as u32));
+            let max = nested_value!(table, "max", |value| value.as_u64().map(|max| max as u32));
+            let min = nested_value!(table, "min", |value| value.as_u64().map(|min| min as u32));
+            // `pool.max` is `pool.size` under the name matching `pool.min`
+            let size = match (size, max) {
+                (Some(size), Some(max)) if size != max => return Err(error::Error::new(
+                    error::ErrorKind::FormatError,
+                    format!("`pool.size` ({}) and `pool.max` ({}) differ", size, max)
+                )),
+                (size, max) => max.or(size)
+            };
+            if let (Some(min), Some(size)) = (min, size) {
+                if min > size {
+                    return Err(error::Error::new(
+                        error::ErrorKind::FormatError,
+                        format!("`pool.min` ({}) is above `pool.max` ({})", min, size)
+                    ));
+                }
+            }
+            // Without a pool, the single shared connection is all there is
+            if min.map_or(false, |min| min > 0) && size.map_or(true, |size| size <= 1) {
+                return Err(error::Error::new(
+                    error::ErrorKind::FormatError,
+                    "`pool.min` requires `pool.max` (or `pool.size`) above 1"
+                ));
+            }
+            let implementation = match nested_value!(
+                table, "implementation", |value| value.as_str().map(str::to_owned)
+            ) {
+                None => None,
+                Some(implementation) => Some(implementation.parse()?)
+            };
+
+            (PoolSettings {
+                checkout_timeout,
+                fair: fair.unwrap_or(false),
+                size,
+                min,
+                implementation
+            }, fair.is_some(), checkout_timeout.is_some())
+        };
+        let sessions = {
+            let table = ("sessions", optional_table!(configuration, "sessions"));
+            let defaults = SessionSettings::default();
+
+            SessionSettings {
+                max_sessions: nested_value!(table, "max", |value| value.as_u64().map(|max| max as usize))
+                    .unwrap_or(defaults.max_sessions),
+                max_ttl: nested_value!(table, "max_ttl", |value| value.as_u64().map(Duration::from_secs))
+                    .unwrap_or(defaults.max_ttl)
+            }
+        };
+        let redaction = {
+            let table = ("redaction", optional_table!(configuration, "redaction"));
+            let keys = nested_value!(
+                table, "keys", |value| value.as_array().and_then(|keys| {
+                    keys.iter()
+                        .map(|key| key.as_str().map(str::to_owned))
+                        .collect::<Option<Vec<String>>>()
+                })
+            );
+            let patterns = nested_value!(
+                table, "patterns", |value| value.as_array().and_then(|patterns| {
+                    patterns.iter()
+                        .map(|pattern| pattern.as_str().map(str::to_owned))
+                        .collect::<Option<Vec<String>>>()
+                })
+            );
+
+            Redaction::new(&keys.unwrap_or_default(), &patterns.unwrap_or_default())?
+        };
+        let tls = {
+            let table = ("tls", optional_table!(configuration, "tls"));
+            let tls = TlsSettings {
+                mode: match nested_value!(table, "mode", |value| value.as_str().map(str::to_owned)) {
+                    None => None,
+                    Some(mode) => Some(mode.parse()?)
+                },
+                root_cert: nested_value!(table, "root_cert", |value| value.as_str().map(PathBuf::from)),
+                client_cert: nested_value!(table, "client_cert", |value| value.as_str().map(PathBuf::from)),
+                client_key: nested_value!(table, "client_key", |value| value.as_str().map(PathBuf::from))
+            };
+
+            tls.validate()?;
+            tls
+        };
+        let profiling = {
+            let table = ("profiling", optional_table!(configuration, "profiling"));
+            let capacity = nested_value!(
+                table, "capacity", |value| value.as_u64().map(|capacity| capacity as usize)
+            );
+            let sample_every = nested_value!(table, "sample_every", |value| value.as_u64());
+
+            capacity.map(|capacity| ProfileSettings {
+                capacity,
+                sample_every: sample_every.unwrap_or(1)
+            })
+        };
+        let fail_on_critical_checks = {
+            let table = ("integrity_checks", optional_table!(configuration, "integrity_checks"));
+
+            nested_value!(table, "fail_on_critical", |value| value.as_bool())
+        };
+        let metrics_token = {
+            let table = ("metrics", optional_table!(configuration, "metrics"));
+
+            nested_value!(table, "token", |value| value.as_str().map(str::to_owned))
+                .filter(|token| !token.is_empty())
+                .map(MetricsToken::new)
+        };
+        let min_version = {
+            let table = ("min_version", optional_table!(configuration, "min_version"));
+            let backend = Backend::from_scheme(url.split(':').next().unwrap_or(""));
+
+            match backend {
+                None => None,
+                Some(backend) => match nested_value!(
+                    table, backend.as_str(), |value| value.as_str().map(str::to_owned)
+                ) {
+                    None => None,
+                    Some(version) => Some(version.parse::<ServerVersion>().map_err(|_err| error::Error::new(
+                        error::ErrorKind::FormatError,
+                        format!("invalid version `{}` for `min_version.{}`", version, backend)
+                    ))?)
+                }
+            }
+        };
+        #[cfg(feature = "crypto")]
+        let key_ring = {
+            let table = ("crypto", optional_table!(configuration, "crypto"));
+            let primary = nested_value!(
+                table, "primary", |value| value.as_u64().filter(|id| *id <= 255).map(|id| id as u8)
+            );
+            let keys = nested_value!(
+                table, "keys", |value| value.as_array().and_then(|keys| {
+                    keys.iter()
+                        .map(|key| key.as_str().map(str::to_owned))
+                        .collect::<Option<Vec<String>>>()
+                })
+            );
+
+            match (primary, keys) {
+                (None, None) => None,
+                (Some(primary), Some(keys)) => Some(KeyRing::parse(primary, &keys)?),
+                _ => return Err(error::Error::new(
+                    error::ErrorKind::MissingValue,
+                    "`crypto.primary` and `crypto.keys` must be set together"
+                ))
+            }
+        };
+        let replicas = {
+            let table = ("replicas", optional_table!(configuration, "replicas"));
+            let urls = nested_value!(
+                table, "urls", |value| value.as_array().and_then(|urls| {
+                    urls.iter()
+                        .map(|url| url.as_str().map(str::to_owned))
+                        .collect::<Option<Vec<String>>>()
+                })
+            );
+
+            ReplicaSettings::new(urls.unwrap_or_default())?
+        };
+        let pool = match self._autotune {
+            false => pool,
+            true => {
+                let recommendation = autotune::recommend(
+                    autotune::cpus(),
+                    self.workers(),
+                    autotune::connections(shard_urls.len(), pooled_connections(&url, &pool)),
+                    &pool
+                );
+
+                autotune::apply(pool, fair_set, checkout_timeout_set, &recommendation)
+            }
+        };
+
+        let settings = Settings::new(settings::encode_credentials(&url, credentials_encoding))?
+            .with_credentials(username, password)?
+            .with_fallback_url(fallback_url)?
+            .with_application_name(application_name.or_else(|| self._application.clone()))
+            .with_stats_interval(stats_interval)
+            .with_slow_query_threshold(slow_query_threshold)
+            .with_serverless_idle_timeout(serverless_idle_timeout)
+            .with_connection_lifetime(connection_lifetime)
+            .with_bootstrap(bootstrap.unwrap_or(false))
+            .with_sqlite_max_page_count(sqlite_max_page_count)
+            .with_warm_up_parallelism(warm_up_parallelism.unwrap_or(warmup::DEFAULT_PARALLELISM))
+            .with_fail_on_critical_checks(fail_on_critical_checks.unwrap_or(true))
+            .with_result_limit(ResultLimit::new(max_result_rows, max_result_bytes))
+            .with_compression(compression.unwrap_or(false))
+            .with_pg_options(pg_options)
+            .with_params(params)
+            .with_route_quotas(route_quotas)
+            .with_circuit_breaker(circuit_breaker)
+            .with_query_tagging(query_tagging.unwrap_or(false))
+            .with_query_tagging_client_ip(query_tagging_client_ip.unwrap_or(false))
+            .with_retry_on_disconnect(retry_on_disconnect.unwrap_or(true))
+            .with_retry_budget(retry_budget)
+            .with_shard_urls(shard_urls)
+            .with_pool(pool)
+            .with_sessions(sessions)
+            .with_redaction(redaction)
+            .with_tls(tls)
+            .with_replicas(replicas)
+            .with_profiling(profiling)
+            .with_min_version(min_version)
+            .with_metrics_token(metrics_token)
+            .with_migrations(migrations);
+
+        #[cfg(feature = "crypto")]
+        let settings = settings.with_key_ring(key_ring);
+
+        Ok(settings)
     }
 
     pub fn initialized(&self) -> Result<bool>
@@ -102,139 +1273,1899 @@ impl Database
         self._database.initialized()
     }
 
+    /// Applies the migration policy to `conn` with the migration engine.
+    fn apply_migrations(&self, settings: &Settings, conn: MigrationTarget<'_>) -> Result<()>
+    {
+        match &self._migration_engine {
+            Some(engine) => settings.migrations().apply(engine.get(), conn),
+            None => settings.migrations().apply(&settings.migrations().diesel_engine(), conn)
+        }
+    }
+
     fn initialize(&self) -> Result<()> {
-        log::info!("Initializing database...");
+        log_redacted!(log::Level::Info, "Initializing database...");
 
         let settings = self.settings()?;
-        log::debug!("Extracted settings from configurations: {:?}", settings);
+        redaction::configure(settings.redaction());
+        log_redacted!(log::Level::Debug, "Extracted settings from configurations: {:?}", settings);
+        self.watch_sqlite_file(None);
 
         let database = match settings.url().scheme() {
             "mysql" => {
-                let mysql = diesel::MysqlConnection::establish(
-                    settings.url().as_str()
-                ).unwrap();
+                if settings.compression() {
+                    log_redacted!(log::Level::Warn, "Compression is not supported for MySQL connections, ignoring it.");
+                }
+                if settings.tls().is_configured() {
+                    log_redacted!(log::Level::Warn, "TLS settings are not supported for MySQL connections, ignoring them.");
+                }
+                if settings.is_application_name_configured() {
+                    log_redacted!(log::Level::Warn, "Connection attributes are not supported for MySQL connections, ignoring `application_name`.");
+                }
+
+                let mysql = self.establish::<diesel::MysqlConnection>(&settings)?;
+                self.check_version(&mysql, &settings)?;
+                self.apply_migrations(&settings, (&mysql).into())?;
+                if settings.bootstrap() {
+                    bootstrap::run(&mysql, Backend::Mysql)?;
+                }
+                self.check_schema(&mysql);
 
                 Some(Box::new(mysql) as Box<dyn Any>)
             },
             "postgres" | "postgresql" => {
-                let postgresql = diesel::PgConnection::establish(
-                    settings.url().as_str()
-                ).unwrap();
+                if settings.compression() {
+                    log_redacted!(
+                        log::Level::Warn,
+                        "`compression` has no effect with libpq 14 or later, nor with OpenSSL builds without compression."
+                    );
+                }
+
+                let postgresql = self.establish::<diesel::PgConnection>(&settings)?;
+                self.check_version(&postgresql, &settings)?;
+                self.apply_migrations(&settings, (&postgresql).into())?;
+                if settings.bootstrap() {
+                    bootstrap::run(&postgresql, Backend::Pg)?;
+                }
+                self.check_schema(&postgresql);
 
                 Some(Box::new(postgresql) as Box<dyn Any>)
             },
             "sqlite" => {
-                let sqlite = diesel::SqliteConnection::establish(
-                    settings.url().path()
-                ).unwrap();
+                if settings.tls().is_configured() {
+                    log_redacted!(log::Level::Warn, "TLS settings do not apply to SQLite databases, ignoring them.");
+                }
+
+                let sqlite = establish_sqlite(&settings)?;
+                self.watch_sqlite_file(WatchedFile::new(settings.url().path()));
+                self.check_version(&sqlite, &settings)?;
+                self.apply_migrations(&settings, (&sqlite).into())?;
+                if settings.bootstrap() {
+                    bootstrap::run(&sqlite, Backend::Sqlite)?;
+                }
+                self.check_schema(&sqlite);
 
                 Some(Box::new(sqlite) as Box<dyn Any>)
             },
-            _ => { None }
+            scheme => return Err(unsupported_scheme(scheme))
         };
-
-        if database.is_some() {
-            log::debug!("Successfully wrapped database connection!");
-        } else {
-            log::debug!("Unknown database scheme.");
-        }
+        log_redacted!(log::Level::Debug, "Successfully wrapped database connection!");
 
         let mut guard = self._database.lock().map_err(|_err| error::Error::new(
             error::ErrorKind::Other, "failed to update database connection"
         ))?;
 
+        let established = database.is_some();
         *guard = database;
+        drop(guard);
+        if let Ok(mut established_at) = self._established.lock() {
+            *established_at = Some(self._clock.now());
+        }
+        self.start_pool(&settings)?;
+        self._tag_client_ip.store(settings.query_tagging_client_ip(), Ordering::Relaxed);
 
-        Ok(())
+        if established && !self._checks.is_empty() {
+            let results = self._checks.run(self);
+
+            if let Some(err) = integrity::critical_failure(&results) {
+                if settings.fail_on_critical_checks() {
+                    if let Ok(mut guard) = self._database.lock() {
+                        *guard = None;
+                    }
+                    return Err(err);
+                }
+                log_redacted!(log::Level::Warn, "{}, initializing anyway (`integrity_checks.fail_on_critical` is false).", err);
+            }
+        }
+        self._ready.store(established, Ordering::Release);
+
+        if let Some(interval) = settings.stats_interval() {
+            self.start_stats_logger(interval);
+        }
+        if let Some(timeout) = settings.serverless_idle_timeout() {
+            self.start_idle_reaper(timeout);
+        }
+        if self._autotune {
+            let _ = self.autotune();
+        }
+        self.touch();
+
+        Ok(())
+    }
+
+    /// Completes the shared connection with a pool of `pool.size`
+    /// connections, established with the same URL and credentials.
+    #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+    fn start_pool(&self, settings: &Settings) -> Result<()>
+    {
+        let backend = match Backend::from_scheme(settings.url().scheme()) {
+            Some(backend) => backend,
+            None => return Err(unsupported_scheme(settings.url().scheme()))
+        };
+        let url = match backend {
+            Backend::Sqlite => settings.url().path().to_owned(),
+            Backend::Mysql | Backend::Pg => {
+                let url = match (self.active_credentials(), settings.fallback_connection_url()) {
+                    (Some(ActiveCredentials::Fallback), Some(fallback)) => fallback,
+                    _ => settings.connection_url()
+                };
+
+                self._resolver.resolve(url)?.into_string()
+            }
+        };
+        let pool = match pool::enabled(backend, &url, settings.pool()) {
+            true => {
+                let min = settings.pool().min.unwrap_or(0);
+                let setup = ConnectionSetup {
+                    lifetime: settings.connection_lifetime(),
+                    sql: match backend {
+                        Backend::Sqlite => sqlite_setup_sql(settings),
+                        Backend::Mysql | Backend::Pg => None
+                    }
+                };
+                let pool = Pool::new(backend, &url, settings.pool(), &setup)?;
+
+                log_redacted!(
+                    log::Level::Info, "Pooling {} to {} connections.", min, settings.pool().size.unwrap_or(1)
+                );
+                pool.fill(min)?;
+                Some(Arc::new(pool))
+            },
+            false => {
+                if settings.pool().min.map_or(false, |min| min > 0) {
+                    log_redacted!(log::Level::Warn, "In-memory SQLite databases keep a single connection, ignoring `pool.min`.");
+                }
+                None
+            }
+        };
+
+        if let Ok(mut current) = self._pool.write() {
+            *current = pool;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(feature = "r2d2", feature = "deadpool")))]
+    fn start_pool(&self, settings: &Settings) -> Result<()>
+    {
+        if settings.pool().size.map_or(false, |size| size > 1) {
+            log_redacted!(log::Level::Warn, "`pool.size` requires the `r2d2` or `deadpool` feature, sharing a single connection (`pool.min` is ignored).");
+        }
+        Ok(())
+    }
+
+    #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+    fn pool(&self) -> Option<Arc<Pool>>
+    {
+        self._pool.read().ok().and_then(|pool| pool.clone())
+    }
+
+    /// Connections of the pool, `None` when the connection is shared (see
+    /// `pool.size`).
+    #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+    pub fn pool_state(&self) -> Option<PoolState>
+    {
+        self.pool().map(|pool| pool.state())
+    }
+
+    /// Establishes a connection with the credentials of `url`, then with
+    /// those of `fallback_url` when they are refused, recording which ones
+    /// were accepted.
+    fn establish<C: diesel::Connection>(&self, settings: &Settings) -> Result<C>
+    {
+        let primary = self._resolver.resolve(settings.connection_url()).and_then(|url| {
+            C::establish(url.as_str()).map_err(|err| establish_error(err, settings))
+        });
+
+        let (conn, credentials) = match (primary, settings.fallback_connection_url()) {
+            (Err(err), Some(fallback)) => match err.kind() {
+                error::ErrorKind::AuthenticationFailed => {
+                    log_redacted!(log::Level::Warn, "Credentials of `url` refused ({}), trying those of `fallback_url`.", err);
+
+                    let fallback = self._resolver.resolve(fallback)?;
+                    let conn = C::establish(fallback.as_str()).map_err(|err| establish_error(err, settings))?;
+                    (conn, ActiveCredentials::Fallback)
+                },
+                _ => return Err(err)
+            },
+            (primary, _) => (primary?, ActiveCredentials::Primary)
+        };
+
+        if let Ok(mut active) = self._credentials.write() {
+            *active = Some(credentials);
+        }
+        Ok(conn)
+    }
+
+    /// Returns the credentials the connection was last established with:
+    /// `Fallback` tells that the credentials of `url` were refused and
+    /// those of `fallback_url` accepted.
+    pub fn active_credentials(&self) -> Option<ActiveCredentials>
+    {
+        self._credentials.read().ok().and_then(|credentials| *credentials)
+    }
+
+    /// Records the version of the server of `conn`, failing when it is older
+    /// than the `min_version` of its backend.
+    fn check_version<C: VersionConnection>(&self, conn: &C, settings: &Settings) -> Result<()>
+    {
+        let version = version::check(conn, settings.min_version())?;
+        log_redacted!(log::Level::Info, "Connected to server version {}.", version);
+
+        if let Ok(mut server_version) = self._server_version.write() {
+            *server_version = Some(version);
+        }
+        Ok(())
+    }
+
+    /// Logs the divergences between the registered schema and the one of
+    /// `conn`, in debug builds only.
+    fn check_schema<C: SchemaConnection>(&self, conn: &C)
+    {
+        let schema = match self._schema {
+            Some(ref schema) if cfg!(debug_assertions) => schema,
+            _ => return
+        };
+
+        match schema.compare(conn) {
+            Ok(ref drifts) if drifts.is_empty() => {
+                log_redacted!(log::Level::Debug, "Database schema matches schema.rs (fingerprint {:016x}).", schema.fingerprint());
+            },
+            Ok(drifts) => {
+                log_redacted!(log::Level::Warn, "!!! Database schema diverges from schema.rs: were migrations run? !!!");
+                for drift in drifts {
+                    log_redacted!(log::Level::Warn, "!!!   {}", drift);
+                }
+            },
+            Err(err) => log_redacted!(log::Level::Warn, "Failed to compare the database schema with schema.rs: {}", err)
+        }
+    }
+
+    /// Establishes the connection now if it is not already, so that a
+    /// platform warmer can pay the connection cost before real traffic.
+    pub fn prewarm(&self) -> Result<()>
+    {
+        if self.initialized()? {
+            self.touch();
+            return Ok(());
+        }
+
+        self.try_initialize()
+    }
+
+    /// Establishes the connections of the database and of its shards, at
+    /// most `warm_up_parallelism` at a time, to pay the connection cost
+    /// without a burst of connects after a deploy.
+    ///
+    /// See [`WarmUp`](warmup/struct.WarmUp.html).
+    pub fn warm_up(&self) -> Result<()>
+    {
+        let settings = self.settings()?;
+        let mut warm_up = WarmUp::new(settings.warm_up_parallelism())
+            .with_database("primary", self.clone());
+
+        for index in 0..settings.shard_urls().len() {
+            warm_up = warm_up.with_database(&format!("shard #{}", index), self.shard(index)?);
+        }
+
+        warm_up.run().into_iter()
+            .map(|(_name, result)| result)
+            .collect()
+    }
+
+    /// Returns whether the connection is established on first use rather than
+    /// on the first request.
+    fn lazy(&self) -> bool
+    {
+        self.settings()
+            .map(|settings| settings.serverless_idle_timeout().is_some())
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn clock(&self) -> &SharedClock
+    {
+        &self._clock
+    }
+
+    /// Marks the connection as used now.
+    pub(crate) fn touch(&self)
+    {
+        if let Ok(mut last_used) = self._last_used.lock() {
+            *last_used = Some(self._clock.now());
+        }
+    }
+
+    /// Returns the limit of `connection_lifetime` the shared connection
+    /// exceeds, if any. Pooled connections are replaced by their pool, and
+    /// in-memory SQLite databases are never replaced as their data would be
+    /// lost.
+    fn connection_expired(&self) -> Option<&'static str>
+    {
+        let settings = self.settings().ok()?;
+        let lifetime = settings.connection_lifetime();
+
+        if lifetime == ConnectionLifetime::default() || !self.initialized().unwrap_or(false) {
+            return None;
+        }
+        if settings.url().scheme() == "sqlite" && sqlite_file::in_memory(settings.url().path()) {
+            return None;
+        }
+        #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+        {
+            if self.pool().is_some() {
+                return None;
+            }
+        }
+
+        let established = (*self._established.lock().ok()?)?;
+        let last_used = self._last_used.lock().ok()?.unwrap_or(established);
+
+        lifetime.exceeded(established, last_used, self._clock.now())
+    }
+
+    /// Spawns, once, the thread closing the connection after `timeout`
+    /// without interaction. It is re-established on its next use.
+    ///
+    /// The thread stops once every clone of the database has been dropped.
+    fn start_idle_reaper(&self, timeout: Duration)
+    {
+        let connection = Arc::downgrade(&self._database);
+        let last_used = Arc::downgrade(&self._last_used);
+        let clock = self._clock.clone();
+
+        self._idle_reaper.call_once(move || {
+            thread::spawn(move || loop {
+                thread::sleep(timeout / 2);
+
+                let (connection, last_used) = match (connection.upgrade(), last_used.upgrade()) {
+                    (Some(connection), Some(last_used)) => (connection, last_used),
+                    _ => break
+                };
+
+                // Holds the connection so that it can't be used while checked
+                let mut guard = match connection.lock() {
+                    Ok(guard) => guard,
+                    Err(_err) => break
+                };
+                let idle = match last_used.lock() {
+                    Ok(last_used) => last_used.map_or(false, |last_used| {
+                        clock.now().duration_since(last_used) >= timeout
+                    }),
+                    Err(_err) => break
+                };
+
+                if idle && guard.is_some() {
+                    log_redacted!(log::Level::Debug, "Closing idle database connection.");
+                    *guard = None;
+                }
+            });
+        });
+    }
+
+    /// Current time of the clock of the database.
+    pub(crate) fn now(&self) -> Instant
+    {
+        self._clock.now()
+    }
+
+    /// Current wall-clock time of the clock of the database.
+    pub(crate) fn system_now(&self) -> SystemTime
+    {
+        self._clock.system_now()
+    }
+
+    /// Registers the integrity check `check` as `name`, replacing the check
+    /// already registered with that name. Checks run once the connection is
+    /// established; clones of the database share them.
+    ///
+    /// See the [`integrity`] module.
+    ///
+    /// [`integrity`]: integrity/index.html
+    pub fn register_check<F>(&self, name: &str, severity: Severity, check: F)
+        where F: Fn(&Database) -> Result<()> + Send + Sync + 'static
+    {
+        self._checks.register(name, severity, check);
+    }
+
+    /// Runs the registered integrity checks now, returning their results.
+    pub fn run_checks(&self) -> Vec<CheckResult>
+    {
+        self._checks.run(self)
+    }
+
+    /// Begins a session: a transaction held open, on a connection of its
+    /// own, across requests until committed, rolled back or `ttl` elapsed
+    /// (capped by `sessions.max_ttl`). See `rocket_diesel::session` for
+    /// the risks.
+    #[cfg(feature = "sessions")]
+    pub fn begin_session(&self, ttl: Duration) -> Result<Session>
+    {
+        let settings = self.settings()?;
+        let limits = settings.sessions().clone();
+
+        let connection = match settings.url().scheme() {
+            "mysql" => Database::from_connection(self.establish::<diesel::MysqlConnection>(&settings)?),
+            "postgres" | "postgresql" => Database::from_connection(self.establish::<diesel::PgConnection>(&settings)?),
+            "sqlite" => Database::from_connection(establish_sqlite(&settings)?),
+            scheme => return Err(unsupported_scheme(scheme))
+        };
+
+        let sessions = self._sessions.clone();
+        let clock = self._clock.clone();
+        self._session_reaper.call_once(move || session::start_reaper(&sessions, clock));
+
+        Session::open(self, &self._sessions, connection, ttl.min(limits.max_ttl), limits.max_sessions)
+    }
+
+    /// Finds the open session of `token`, failing with `SessionExpired`
+    /// when it is unknown or expired.
+    #[cfg(feature = "sessions")]
+    pub fn session(&self, token: &str) -> Result<Session>
+    {
+        Session::find(self, &self._sessions, token)
+    }
+
+    /// Results of the last run of the integrity checks.
+    pub fn check_results(&self) -> Vec<CheckResult>
+    {
+        self._checks.results()
+    }
+
+    /// Number of streaming responses currently leasing the connection.
+    pub fn stream_leases(&self) -> usize
+    {
+        self._stream_leases.load(Ordering::SeqCst)
+    }
+
+    /// Streams the rows returned by `query` as CSV, `query` being called
+    /// with the chunk of rows to fetch until it returns fewer rows.
+    ///
+    /// See [`export`].
+    ///
+    /// [`export`]: export/index.html
+    pub fn export_csv<'r, T, F>(&self, query: F) -> CsvExport<'r>
+        where T: CsvRow,
+              F: FnMut(&StreamLease, Chunk) -> Result<Vec<T>> + 'r
+    {
+        CsvExport::new(self, export::DEFAULT_CHUNK_SIZE, query)
+    }
+
+    pub(crate) fn stream_leased(&self)
+    {
+        self._stream_leases.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn stream_released(&self)
+    {
+        self._stream_leases.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns a snapshot of the statistics gathered since the creation of
+    /// the database.
+    pub fn stats(&self) -> StatsSnapshot
+    {
+        self._stats.snapshot()
+    }
+
+    /// Returns the interactions in flight and the limit of each route
+    /// quota, empty until configured.
+    pub fn quota_usage(&self) -> Vec<QuotaUsage>
+    {
+        match self.settings() {
+            Ok(settings) => self._quotas.usage(settings.route_quotas()),
+            Err(_err) => Vec::new()
+        }
+    }
+
+    /// Returns the count, errors and average latency of the interactions
+    /// per operation, in total then per route template.
+    ///
+    /// Only interactions tagged with a matched route are broken down per
+    /// route: the first `MAX_ROUTES` templates seen, the others under
+    /// `OVERFLOW_ROUTE`.
+    ///
+    /// Helpers label their statements; closures given to `interact` are
+    /// counted as `Operation::Unknown` unless given to `interact_as`.
+    pub fn operation_stats(&self) -> Vec<OperationStats>
+    {
+        self._stats.operations()
+    }
+
+    /// Returns the version of the server, known once initialized.
+    ///
+    /// Databases created with `from_connection` only know it once their
+    /// connection has been re-established from the configuration.
+    pub fn server_version(&self) -> Option<ServerVersion>
+    {
+        self._server_version.read().ok().and_then(|version| *version)
+    }
+
+    /// Returns the distribution of the execution times of the
+    /// interactions, in microseconds.
+    pub fn latency_histogram(&self) -> HistogramSnapshot
+    {
+        self._stats.latency_histogram()
+    }
+
+    /// Returns the distribution of the number of rows of the results given
+    /// to `check_result_size`.
+    pub fn result_rows_histogram(&self) -> HistogramSnapshot
+    {
+        self._stats.result_rows_histogram()
+    }
+
+    /// Returns the token required by the `/metrics` route, if configured.
+    pub(crate) fn metrics_token(&self) -> Option<MetricsToken>
+    {
+        self.settings().ok().and_then(|settings| settings.metrics_token().cloned())
+    }
+
+    /// Returns the interactions sampled by the profiler, oldest first.
+    ///
+    /// Sampling is enabled by the `profiling.capacity` configuration key.
+    /// See [`profile::folded`] to render them for flamegraph tools.
+    ///
+    /// [`profile::folded`]: profile/fn.folded.html
+    pub fn profile_snapshot(&self) -> Vec<ProfileSample>
+    {
+        self._profiler.snapshot()
+    }
+
+    /// Returns the keys encrypting sensitive values, from the key provider
+    /// if any, else from the `crypto` section of the configuration.
+    #[cfg(feature = "crypto")]
+    pub fn key_ring(&self) -> Result<KeyRing>
+    {
+        if let Some(provider) = &self._key_provider {
+            return provider.key_ring();
+        }
+
+        self.settings()?.key_ring().cloned().ok_or(error::Error::new(
+            error::ErrorKind::MissingValue, "no `crypto` section in configuration"
+        ))
+    }
+
+    /// Spawns, once, the thread logging statistics every `interval`.
+    ///
+    /// The thread stops once every clone of the database has been dropped.
+    fn start_stats_logger(&self, interval: Duration)
+    {
+        let stats = Arc::downgrade(&self._stats);
+
+        self._stats_logger.call_once(move || {
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+
+                match stats.upgrade() {
+                    Some(stats) => log_redacted!(log::Level::Info, "Database stats: {}", stats.snapshot()),
+                    None => break
+                }
+            });
+        });
+    }
+
+    /// Gathers a report on the state of the database, without its secrets,
+    /// to attach to bug reports and incidents.
+    ///
+    /// Counting the pending migrations waits for the connection, if
+    /// initialized.
+    pub fn support_bundle(&self) -> SupportBundle
+    {
+        let settings = self.settings().ok();
+        let initialized = self.initialized().unwrap_or(false);
+
+        let migrations = settings.as_ref().map(|settings| {
+            let migrations = settings.migrations();
+            let diesel_engine = migrations.diesel_engine();
+            let engine: &dyn MigrationEngine = match &self._migration_engine {
+                Some(engine) => engine.get(),
+                None => &diesel_engine
+            };
+            let pending = match initialized {
+                false => None,
+                true => Some(self.interact::<_, error::Error, _, _, _>(
+                    |conn| engine.pending((&*conn).into()),
+                    |conn| engine.pending((&*conn).into()),
+                    |conn| engine.pending((&*conn).into())
+                ))
+            };
+
+            MigrationStatus {
+                policy: migrations.policy(),
+                directory: engine.source(),
+                pending: pending.as_ref().and_then(|pending| pending.as_ref().ok().cloned()),
+                error: pending.and_then(|pending| pending.err()).map(|err| err.to_string())
+            }
+        });
+
+        SupportBundle {
+            generated_at: self._clock.system_now(),
+            crate_version: env!("CARGO_PKG_VERSION"),
+            configuration: self.configuration_status(),
+            settings: settings.as_ref().map(support_settings).unwrap_or_default(),
+            initialized,
+            circuit: self.circuit_state(),
+            last_init_error: self.last_init_error().map(|err| err.to_string()),
+            stats: self.stats(),
+            waiting: self._queue.waiting(),
+            server_version: self.server_version(),
+            migrations
+        }
+    }
+
+    /// Lists the migrations not yet applied, in the order they would be
+    /// applied, with their SQL, without applying them.
+    ///
+    /// Waits for the connection, establishing it if needed: with the `run`
+    /// policy, initializing applies the migrations, unless
+    /// `migrations.dry_run` is set.
+    #[track_caller]
+    pub fn migrations_plan(&self) -> Result<Vec<PlannedMigration>>
+    {
+        let settings = self.settings()?;
+        let diesel_engine = settings.migrations().diesel_engine();
+        let engine: &dyn MigrationEngine = match &self._migration_engine {
+            Some(engine) => engine.get(),
+            None => &diesel_engine
+        };
+
+        self.interact::<_, error::Error, _, _, _>(
+            |conn| engine.plan((&*conn).into()),
+            |conn| engine.plan((&*conn).into()),
+            |conn| engine.plan((&*conn).into())
+        )
+    }
+
+    /// Returns the error of the last failed initialization attempt, if the
+    /// database has not been successfully initialized since.
+    pub fn last_init_error(&self) -> Option<error::Error>
+    {
+        self._backoff.lock().ok().and_then(|backoff| backoff.last_error())
+    }
+
+    /// Initializes the database unless a previous failure is still being
+    /// backed off.
+    fn try_initialize(&self) -> Result<()> {
+        // Concurrent first requests wait for a single initialization
+        self._initialization.run(|| self.initialized().unwrap_or(false), || self.initialize_once())
+    }
+
+    fn initialize_once(&self) -> Result<()> {
+        let mut backoff = self._backoff.lock().map_err(|_err| error::Error::new(
+            error::ErrorKind::Other, "initialization backoff got poisoned"
+        ))?;
+
+        if !backoff.ready(self._clock.now()) {
+            return Err(error::Error::new(
+                error::ErrorKind::Other, "initialization is backing off"
+            ));
+        }
+
+        match self.initialize() {
+            Ok(()) => {
+                backoff.reset();
+                Ok(())
+            },
+            Err(err) => {
+                backoff.record_failure(&err, self._clock.now());
+                log_redacted!(log::Level::Warn,
+                    "Failed to initialize database ({} attempt(s)): {}. Retrying in {:?}.",
+                    backoff.failures(), err, backoff.delay()
+                );
+                // Does not poison the backoff when misuse panics
+                drop(backoff);
+
+                match err.kind() {
+                    error::ErrorKind::MissingValue
+                        | error::ErrorKind::FormatError
+                        | error::ErrorKind::UnsupportedScheme => Err(self._strictness.misuse(err)),
+                    _ => Err(err)
+                }
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the connection, polling it.
+    fn lock_within(&self, timeout: Duration)
+        -> Result<LockResult<MutexGuard<'_, Option<Box<dyn Any>>>>>
+    {
+        const POLL_INTERVAL: Duration = Duration::from_millis(1);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self._database.try_lock() {
+                Ok(guard) => return Ok(Ok(guard)),
+                Err(TryLockError::Poisoned(err)) => return Ok(Err(err)),
+                Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                    return Err(error::Error::new(
+                        error::ErrorKind::CheckoutTimeout,
+                        format!("connection still in use after {:?} (`pool.checkout_timeout`)", timeout)
+                    ));
+                },
+                Err(TryLockError::WouldBlock) => thread::sleep(POLL_INTERVAL)
+            }
+        }
+    }
+
+    fn lock<'lock>(&'lock self) -> Result<LockedConnection<'lock>>
+    {
+        #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+        {
+            if let Some(pool) = self.pool() {
+                return pool.checkout().map(LockedConnection::pooled);
+            }
+        }
+
+        let pool = self.settings().map(|settings| settings.pool().clone()).unwrap_or_default();
+        let mut turn = None;
+
+        let lock = if pool.fair {
+            let (fair_turn, waited) = FairQueue::acquire(&self._queue, fairness::deadline(pool.checkout_timeout))?;
+
+            if waited {
+                self._stats.record_lock_contention();
+            }
+            turn = Some(fair_turn);
+
+            // Only briefly held outside of the queue (e.g. by the idle reaper)
+            self._database.lock()
+        } else {
+            match self._database.try_lock() {
+                Ok(guard) => Ok(guard),
+                Err(TryLockError::WouldBlock) => {
+                    self._stats.record_lock_contention();
+
+                    match pool.checkout_timeout {
+                        None => self._database.lock(),
+                        Some(timeout) => self.lock_within(timeout)?
+                    }
+                },
+                Err(TryLockError::Poisoned(err)) => Err(err)
+            }
+        };
+
+        if lock.is_err() {
+            return Err(error::Error::new(
+                error::ErrorKind::Other, "database got poisoned"
+            ));
+        }
+        let mut guard = lock.unwrap();
+
+        // The connection is either established from the configuration or
+        // injected with `from_connection`: its type tells its backend
+        let conn = match guard.as_mut() {
+            None => return Err(error::Error::new(
+                error::ErrorKind::Other, "database is not ready"
+            )),
+            Some(boxed_database) => {
+                if let Some(mysql) = boxed_database.downcast_mut::<diesel::MysqlConnection>() {
+                    crate::locked_connection::Connection::mysql(mysql)
+                } else if let Some(pg) = boxed_database.downcast_mut::<diesel::PgConnection>() {
+                    crate::locked_connection::Connection::pg(pg)
+                } else if let Some(sqlite) = boxed_database.downcast_mut::<diesel::SqliteConnection>() {
+                    crate::locked_connection::Connection::sqlite(sqlite)
+                } else {
+                    return Err(error::Error::new(
+                        error::ErrorKind::Other,
+                        "failed to downcast database"
+                    ));
+                }
+            }
+        };
+
+        Ok(LockedConnection::new(guard, conn).with_turn(turn))
+    }
+
+    /// Returns the backend selected by the configured URL, or the backend of
+    /// the connection given to `from_connection`.
+    pub fn backend(&self) -> Option<Backend>
+    {
+        #[cfg(any(test, feature = "testing"))]
+        {
+            if let Some(ref simulation) = self._simulation {
+                return Some(simulation.backend());
+            }
+        }
+
+        self.settings().ok()
+            .and_then(|settings| Backend::from_scheme(settings.url().scheme()))
+            .or_else(|| self.connection_backend())
+    }
+
+    /// Backend of the held connection, without waiting for it.
+    fn connection_backend(&self) -> Option<Backend>
+    {
+        let guard = self._database.try_lock().ok()?;
+        let connection = guard.as_ref()?;
+
+        if connection.is::<diesel::MysqlConnection>() {
+            Some(Backend::Mysql)
+        } else if connection.is::<diesel::PgConnection>() {
+            Some(Backend::Pg)
+        } else if connection.is::<diesel::SqliteConnection>() {
+            Some(Backend::Sqlite)
+        } else {
+            None
+        }
+    }
+
+    #[track_caller]
+    pub fn interact<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+              
+    {
+        self.interact_outcome(mysql_f, pg_f, sqlite_f).map(InteractOutcome::into_value)
+    }
+
+    /// Same as `interact`, but also reports which backend executed the
+    /// closure and how long it took.
+    #[track_caller]
+    pub fn interact_outcome<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF)
+        -> Result<InteractOutcome<T>>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+
+        self.layered(caller, |context| self.execute(context, mysql_f, pg_f, sqlite_f))
+    }
+
+    /// Same as `interact`, with a single closure given the connection of
+    /// whichever backend the database is connected to, for backend-generic
+    /// code (see `AnyConnection`).
+    #[track_caller]
+    pub fn interact_any<T, E, F>(&self, f: F) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              F: FnOnce(&mut AnyConnection<'_>) -> std::result::Result<T, E>
+    {
+        // Only the closure of the connected backend runs
+        let f = Cell::new(Some(f));
+        let run = |mut conn: AnyConnection<'_>| match f.take() {
+            Some(f) => f(&mut conn),
+            None => unreachable!()
+        };
+
+        self.interact(
+            |conn| run(AnyConnection::Mysql(conn)),
+            |conn| run(AnyConnection::Pg(conn)),
+            |conn| run(AnyConnection::Sqlite(conn))
+        )
+    }
+
+    /// Same as `interact`, returning `None` when a closure fails with
+    /// diesel's `NotFound` (e.g. `first` or `get_result` without a row), as
+    /// is or wrapped in an `Error` of this crate.
+    ///
+    /// A missing row is not an error of the interaction: it is not counted
+    /// as such by the statistics nor the circuit breaker.
+    #[track_caller]
+    pub fn interact_optional<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF)
+        -> Result<Option<T>>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        self.interact(
+            |conn| optional(mysql_f(conn)),
+            |conn| optional(pg_f(conn)),
+            |conn| optional(sqlite_f(conn))
+        )
+    }
+
+    /// Same as `interact`, labeling the interaction with `operation` in the
+    /// per-operation statistics (see `operation_stats`).
+    #[track_caller]
+    pub fn interact_as<T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        operation: Operation,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+
+        self.layered_as(caller, operation, |context| self.execute(context, mysql_f, pg_f, sqlite_f))
+            .map(InteractOutcome::into_value)
+    }
+
+    /// Same as `interact`, attributing the interaction to the request of
+    /// `tag` rather than to the request handled by the current thread.
+    ///
+    /// Layers see `tag` (`InteractContext::tag`), and so do `tag_sql` and
+    /// the helpers called by the closures, e.g. when interacting from
+    /// another thread with an `OwnedConn`. `QueryTag` is a request guard;
+    /// closures needing the tag can capture it.
+    #[track_caller]
+    pub fn interact_with_request<T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        tag: &QueryTag,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+        let _scoped = tagging::ScopedTag::new(tag.clone());
+
+        self.layered(caller, |context| self.execute(context, mysql_f, pg_f, sqlite_f))
+            .map(InteractOutcome::into_value)
+    }
+
+    /// Runs `core` through the layers.
+    pub(crate) fn layered<T, F>(&self, caller: &'static Location<'static>, core: F)
+        -> Result<InteractOutcome<T>>
+        where F: FnOnce(&mut InteractContext) -> Result<T>
+    {
+        self.layered_as(caller, Operation::Unknown, core)
+    }
+
+    /// Same as `layered`, labeling the interaction with `operation`.
+    pub(crate) fn layered_as<T, F>(&self, caller: &'static Location<'static>, operation: Operation, core: F)
+        -> Result<InteractOutcome<T>>
+        where F: FnOnce(&mut InteractContext) -> Result<T>
+    {
+        let mut context = InteractContext::new(caller, self.settings().ok(), self._clock.clone());
+        context.set_operation(operation);
+        let mut core = Some(core);
+        let mut value = None;
+
+        let result = {
+            let mut run = |context: &mut InteractContext| -> Result<()> {
+                let core = core.take().ok_or_else(|| error::Error::new(
+                    error::ErrorKind::Other, "interaction already executed"
+                ))?;
+
+                value = Some(core(context)?);
+                Ok(())
+            };
+
+            Next::new(self._layers.as_slice(), &mut run).run(&mut context)
+        };
+
+        match (result, value, context.backend(), context.duration()) {
+            (Ok(()), Some(value), Some(backend), Some(duration)) => Ok(InteractOutcome {
+                value,
+                backend,
+                duration
+            }),
+            (Err(err), _, _, _) => Err(err.with_location(caller)),
+            _ => Err(error::Error::new(
+                error::ErrorKind::Other, "an interaction layer skipped the execution"
+            ).with_location(caller))
+        }
+    }
+
+    /// Same as `interact`, for idempotent closures: when the server closed
+    /// the connection (e.g. "MySQL server has gone away" after a long idle
+    /// period), the connection is re-established and the interaction retried
+    /// once, unless `retry_on_disconnect` is disabled or the `retry_budget`
+    /// of the request is spent.
+    #[track_caller]
+    pub fn interact_idempotent<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: Fn(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: Fn(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: Fn(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+        let attempt = || self.layered(caller, |context| {
+            self.execute(context, &mysql_f, &pg_f, &sqlite_f)
+        });
+        let (retry, budget) = self.settings()
+            .map(|settings| (settings.retry_on_disconnect(), settings.retry_budget()))
+            .unwrap_or((false, None));
+
+        let outcome = match attempt() {
+            Err(ref err) if retry && err.is_disconnect() && retry::allows(budget) => {
+                log_redacted!(log::Level::Warn, "Retrying the interaction at {} on a new connection: {}", caller, err);
+
+                let started = self._clock.now();
+                let outcome = attempt();
+                retry::spend(self._clock.now().duration_since(started));
+                outcome
+            },
+            Err(err) => {
+                if retry && err.is_disconnect() {
+                    log_redacted!(log::Level::Warn, "Not retrying the interaction at {}, the retry budget of the request is spent: {}", caller, err);
+                }
+                Err(err)
+            },
+            outcome => outcome
+        };
+
+        outcome.map(InteractOutcome::into_value)
+    }
+
+    /// Returns the database of the shard `index`, creating the databases of
+    /// the configured shards on first use.
+    fn shard(&self, index: usize) -> Result<Database>
+    {
+        if let Some(shard) = self._shards.read().ok().and_then(|shards| shards.get(index).cloned()) {
+            return Ok(shard);
+        }
+
+        let count = self.settings()?.shard_urls().len();
+        let mut shards = self._shards.write().map_err(|_err| error::Error::new(
+            error::ErrorKind::Other, "shards got poisoned"
+        ))?;
+
+        while shards.len() < count {
+            let mut shard = Database::default();
+
+            shard._attached = self._attached.clone();
+            shard._autotune = self._autotune;
+            shard._workers = self._workers.clone();
+            shard._configuration = self._configuration.clone();
+            shard._configuration_version = self._configuration_version.clone();
+            shard._refresh_after = self._refresh_after;
+            shard._clock = self._clock.clone();
+            shard._shard = Some(shards.len());
+            shard._layers.inherit(&self._layers);
+            shard._checks = self._checks.clone();
+            shard._migration_engine = self._migration_engine.clone();
+            shard._resolver = self._resolver.clone();
+            shard._storage_full = self._storage_full.clone();
+            shard._strictness = self._strictness;
+            shards.push(shard);
+        }
+
+        shards.get(index).cloned().ok_or(error::Error::new(
+            error::ErrorKind::MissingValue,
+            format!("no shard #{} in `shards` configuration", index)
+        ))
+    }
+
+    /// Number of configured shards.
+    pub fn shard_count(&self) -> Result<usize>
+    {
+        Ok(self.settings()?.shard_urls().len())
+    }
+
+    /// Same as `interact`, on the shard selected for `key` by the router.
+    #[track_caller]
+    pub fn interact_on_shard<K, T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        key: K,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where K: AsRef<[u8]>,
+              E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let count = self.shard_count()?;
+
+        if count == 0 {
+            return Err(error::Error::new(
+                error::ErrorKind::MissingValue, "no `shards` in configuration"
+            ));
+        }
+
+        self.shard(self._router.route(key.as_ref(), count))?
+            .interact(mysql_f, pg_f, sqlite_f)
+    }
+
+    /// Same as `interact`, on every shard in turn: returns the results in
+    /// shard order, or the first error.
+    #[track_caller]
+    pub fn interact_all_shards<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF)
+        -> Result<Vec<T>>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: Fn(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: Fn(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: Fn(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let mut results = Vec::new();
+
+        for index in 0..self.shard_count()? {
+            results.push(self.shard(index)?.interact(&mysql_f, &pg_f, &sqlite_f)?);
+        }
+
+        Ok(results)
+    }
+
+    /// Returns the state of the circuit breaker, `Closed` when disabled.
+    pub fn circuit_state(&self) -> CircuitState
+    {
+        self._circuit.lock()
+            .map(|circuit| circuit.state())
+            .unwrap_or(CircuitState::Open)
+    }
+
+    /// Returns whether the database can't serve interactions for now: the
+    /// circuit breaker is open, or the connection could not be initialized
+    /// (unless it is only established on first use).
+    pub fn degraded(&self) -> bool
+    {
+        self.circuit_state() == CircuitState::Open
+            || (!self.lazy() && !self.initialized().unwrap_or(false))
+    }
+
+    /// Returns whether an interaction would start right away: the circuit
+    /// breaker lets it through and the connection is neither in use nor
+    /// awaited. A connection not established yet counts as available.
+    pub fn available(&self) -> bool
+    {
+        #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+        {
+            if let Some(pool) = self.pool() {
+                return self.circuit_state() != CircuitState::Open && pool.available();
+            }
+        }
+
+        self.circuit_state() != CircuitState::Open
+            && self._queue.waiting() == 0
+            && self._database.try_lock().is_ok()
+    }
+
+    /// Compares the connections of the database to the CPUs and Rocket
+    /// workers of the server, logging the pathological combinations found
+    /// (connections left unused, a connection shared by many workers), and
+    /// returns the pool settings recommended for them.
+    ///
+    /// See `autotuned` to apply the recommendation.
+    pub fn autotune(&self) -> Result<Recommendation>
+    {
+        let settings = self.settings()?;
+        let recommendation = autotune::recommend(
+            autotune::cpus(),
+            self.workers(),
+            autotune::connections(settings.shard_urls().len(), pooled_connections(settings.url().as_str(), settings.pool())),
+            settings.pool()
+        );
+
+        for warning in &recommendation.warnings {
+            log_redacted!(log::Level::Warn, "{}", warning);
+        }
+        log_redacted!(log::Level::Info, "Sizing: {}.", recommendation);
+
+        Ok(recommendation)
+    }
+
+    /// Rocket workers, once attached.
+    fn workers(&self) -> Option<usize>
+    {
+        match self._workers.load(Ordering::Acquire) {
+            0 => None,
+            workers => Some(workers)
+        }
+    }
+
+    /// Records the statement `sql` builds for the simulated backend, as an
+    /// interaction labeled with `operation`, when the database is simulated.
+    #[cfg(any(test, feature = "testing"))]
+    #[track_caller]
+    pub(crate) fn simulate<F>(&self, operation: Operation, sql: F) -> Option<Result<usize>>
+        where F: FnOnce(Backend) -> String
+    {
+        let caller = Location::caller();
+        let simulation = self._simulation.as_ref()?;
+        let backend = simulation.backend();
+        let sql = sql(backend);
+
+        Some(self.layered_as(caller, operation, |context| {
+            context.executed(backend, Duration::from_secs(0));
+            simulation.execute(sql)
+        }).map(InteractOutcome::into_value))
+    }
+
+    #[cfg(not(any(test, feature = "testing")))]
+    pub(crate) fn simulate<F>(&self, _operation: Operation, _sql: F) -> Option<Result<usize>>
+        where F: FnOnce(Backend) -> String
+    {
+        None
+    }
+
+    /// Executes the closure matching the backend of the connection.
+    ///
+    /// Core of the interaction, wrapped by the layers.
+    fn execute<T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        context: &mut InteractContext,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let mut guard = self.lock_initialized()?;
+        let value = run_on(&mut guard, context, mysql_f, pg_f, sqlite_f);
+
+        if value.as_ref().err().map_or(false, error::Error::is_disconnect) {
+            drop(guard);
+            self.reset_connection();
+        } else {
+            tx::rollback_idle(&mut guard, 0, context.caller());
+            self.touch();
+            drop(guard);
+        }
+        value.map_err(|err| self.storage_full(err))
+    }
+
+    /// Fails writes failing because the storage is full with `StorageFull`,
+    /// running the cleanup registered with `on_storage_full`.
+    fn storage_full(&self, err: error::Error) -> error::Error
+    {
+        if !err.is_storage_full() {
+            return err;
+        }
+        let err = match err.kind() {
+            error::ErrorKind::StorageFull => err,
+            _ => error::Error::new(
+                error::ErrorKind::StorageFull,
+                format!("database storage is full: {}", err)
+            )
+        };
+
+        log_redacted!(log::Level::Warn, "{}", err);
+        self._storage_full.run(self);
+        err
+    }
+
+    fn watch_sqlite_file(&self, file: Option<WatchedFile>)
+    {
+        if let Ok(mut watched) = self._sqlite_file.lock() {
+            *watched = file;
+        }
     }
 
-    fn lock<'lock>(&'lock self) -> Result<LockedConnection<'lock>>
+    /// Returns whether the file of the SQLite database was replaced since
+    /// the connection was established.
+    fn sqlite_file_replaced(&self) -> bool
     {
-        let settings = self.settings()?;
-        let lock = self._database.lock();
+        let watched = match self._sqlite_file.lock() {
+            Ok(watched) => watched,
+            Err(_err) => return false
+        };
 
-        if lock.is_err() {
-            return Err(error::Error::new(
-                error::ErrorKind::Other, "database got poisoned"
-            ));
+        match *watched {
+            Some(ref file) if file.replaced() => {
+                log_redacted!(log::Level::Info, "SQLite database `{}` was replaced, reopening it.", file.path().display());
+                true
+            },
+            _ => false
         }
-        let mut guard = lock.unwrap();
+    }
 
-        match guard.as_mut() {
-            None => Err(error::Error::new(
-                error::ErrorKind::Other, "database is not ready"
-            )),
-            Some(boxed_database) => {
-                let conn = match settings.url().scheme() {
-                    "mysql" => {
-                        crate::locked_connection::Connection::mysql(
-                            boxed_database.downcast_mut::<diesel::MysqlConnection>().ok_or(
-                                error::Error::new(
-                                    error::ErrorKind::Other,
-                                    "failed to downcast database"
-                                )
-                            )?
-                        )
-                    },
-                    "postgres" | "postgresql" => {
-                        crate::locked_connection::Connection::pg(
-                            boxed_database.downcast_mut::<diesel::PgConnection>().ok_or(
-                                error::Error::new(
-                                    error::ErrorKind::Other,
-                                    "failed to downcast database"
-                                )
-                            )?
-                        )
-                    },
-                    "sqlite" => {
-                        crate::locked_connection::Connection::sqlite(
-                            boxed_database.downcast_mut::<diesel::SqliteConnection>().ok_or(
-                                error::Error::new(
-                                    error::ErrorKind::Other,
-                                    "failed to downcast database"
-                                )
-                            )?
-                        )
-                    }
-                    _ => { unimplemented!() }
-                };
+    /// Closes the connection: it is re-established on its next use.
+    fn reset_connection(&self)
+    {
+        log_redacted!(log::Level::Info, "Database connection lost, it will be re-established on next use.");
 
-                Ok(LockedConnection::new(guard, conn))
+        if let Ok(mut guard) = self._database.lock() {
+            *guard = None;
+        }
+        self._ready.store(false, Ordering::Release);
+    }
+
+    /// Locks the connection, establishing it first if needed.
+    pub(crate) fn lock_initialized(&self) -> Result<LockedConnection<'_>>
+    {
+        #[cfg(any(test, feature = "testing"))]
+        {
+            if self._simulation.is_some() {
+                return Err(simulation::closures_unsupported());
+            }
+        }
+
+        // Reopens SQLite databases whose file was replaced
+        if self.sqlite_file_replaced() {
+            if let Ok(mut guard) = self._database.lock() {
+                *guard = None;
+            }
+        }
+
+        // Replaces connections beyond `idle_timeout` or `max_lifetime`
+        if let Some(limit) = self.connection_expired() {
+            if let Ok(mut guard) = self._database.lock() {
+                log_redacted!(log::Level::Debug, "Closing database connection beyond its `{}`.", limit);
+                *guard = None;
+            }
+        }
+
+        // Establishes lazily connections closed (or never opened) while idle
+        if !self.initialized().unwrap_or(false) {
+            if !self._attached.load(Ordering::Acquire) && !self.has_configuration() {
+                return Err(self._strictness.misuse(error::Error::new(
+                    error::ErrorKind::NotAttached,
+                    "the database was used before its fairing was attached to Rocket"
+                )));
             }
+            self.try_initialize()?;
         }
+
+        self.lock()
     }
 
-    pub fn interact<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
-        where E: From<diesel::result::Error> + Error,
+    /// Returns the configured limit on the size of loaded results.
+    pub fn result_limit(&self) -> ResultLimit
+    {
+        self.settings()
+            .map(|settings| settings.result_limit())
+            .unwrap_or_default()
+    }
+
+    /// Checks `rows` loaded in an `interact` closure against the configured
+    /// result limit, failing with `ErrorKind::ResultTooLarge` when exceeded.
+    ///
+    /// See [`ResultLimit::check_rows`] for how the size is estimated.
+    ///
+    /// [`ResultLimit::check_rows`]: struct.ResultLimit.html#method.check_rows
+    pub fn check_result_size<T>(&self, rows: &[T]) -> Result<()>
+        where T: HeapSize
+    {
+        self._stats.record_result_rows(rows.len());
+        self.result_limit().check_rows(rows)
+    }
+
+    /// Returns whether request tags carry the client IP address, as
+    /// configured by `query_tagging_client_ip` once initialized.
+    pub(crate) fn tags_client_ip(&self) -> bool
+    {
+        self._tag_client_ip.load(Ordering::Relaxed)
+    }
+
+    /// Prefixes `sql` with a comment attributing it to the current request
+    /// when the `query_tagging` setting is enabled.
+    ///
+    /// Helpers tag their statements with it; use it for raw SQL executed in
+    /// `interact` closures to get the same attribution.
+    pub fn tag_sql(&self, sql: &str) -> String
+    {
+        let enabled = self.settings()
+            .map(|settings| settings.query_tagging())
+            .unwrap_or(false);
+
+        match (enabled, tagging::current()) {
+            (true, Some(tag)) => tag.apply(sql),
+            _ => sql.to_owned()
+        }
+    }
+
+    /// Inserts `values` in `table`, updating the existing row when it
+    /// conflicts on `conflict_target`. Returns the number of affected rows.
+    ///
+    /// See [`helpers::upsert_sql`] for the generated statements.
+    ///
+    /// [`helpers::upsert_sql`]: helpers/fn.upsert_sql.html
+    #[track_caller]
+    pub fn upsert(&self, table: &str, conflict_target: &[&str], values: &[(&str, helpers::SqlValue)])
+        -> Result<usize>
+    {
+        let backend = self.backend().ok_or(error::Error::new(
+            error::ErrorKind::Other, "database is not ready"
+        ))?;
+        let sql = self.tag_sql(&helpers::upsert_sql(backend, table, conflict_target, values)?);
+
+        if let Some(result) = self.simulate(Operation::Insert, |_backend| sql.clone()) {
+            return result;
+        }
+        self.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::Insert,
+            |conn| diesel::sql_query(sql.as_str()).execute(&*conn),
+            |conn| diesel::sql_query(sql.as_str()).execute(&*conn),
+            |conn| diesel::sql_query(sql.as_str()).execute(&*conn)
+        )
+    }
+
+    /// Searches `query` in the `columns` of `table` with the full-text
+    /// search engine of the backend, returning the matching rows, the most
+    /// relevant first.
+    ///
+    /// See [`helpers::search_sql`] for the setup each backend requires and
+    /// the limitations. A query without words finds nothing.
+    ///
+    /// [`helpers::search_sql`]: helpers/fn.search_sql.html
+    #[track_caller]
+    pub fn search<U>(&self, table: &str, columns: &[&str], query: &str) -> Result<Vec<U>>
+        where U: QueryableByName<Mysql> + QueryableByName<Pg> + QueryableByName<Sqlite>
+    {
+        if query.split_whitespace().next().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let backend = self.backend().ok_or(error::Error::new(
+            error::ErrorKind::Other, "database is not ready"
+        ))?;
+        let sql = self.tag_sql(&helpers::search_sql(backend, table, columns, query)?);
+
+        self.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::Select,
+            |conn| diesel::sql_query(sql.as_str()).load::<U>(&*conn),
+            |conn| diesel::sql_query(sql.as_str()).load::<U>(&*conn),
+            |conn| diesel::sql_query(sql.as_str()).load::<U>(&*conn)
+        )
+    }
+
+    /// Executes `sql` translated for the backend of the connection, returning
+    /// the number of affected rows.
+    ///
+    /// See [`helpers::translate`] for the constructs written once for every
+    /// backend.
+    ///
+    /// [`helpers::translate`]: helpers/fn.translate.html
+    #[track_caller]
+    pub fn execute_sql(&self, sql: &str) -> Result<usize>
+    {
+        let translated = |backend| self.tag_sql(&helpers::translate(backend, sql));
+
+        if let Some(result) = self.simulate(Operation::from_sql(sql), translated) {
+            return result;
+        }
+        self.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::from_sql(sql),
+            |conn| diesel::sql_query(translated(Backend::Mysql)).execute(&*conn),
+            |conn| diesel::sql_query(translated(Backend::Pg)).execute(&*conn),
+            |conn| diesel::sql_query(translated(Backend::Sqlite)).execute(&*conn)
+        )
+    }
+
+    /// Prepares `sql`, labelled `label`, returning a handle executing it
+    /// with binds. The statement is kept prepared by the connection.
+    ///
+    /// See [`prepared`].
+    ///
+    /// [`prepared`]: prepared/index.html
+    pub fn prepare(&self, label: &str, sql: PreparedSql) -> Result<Prepared>
+    {
+        Prepared::new(self.clone(), label, &sql)
+    }
+
+    /// Same as `interact`, with the temporary table `table` created from
+    /// `definition` before the closure runs and dropped once it returned,
+    /// even on error.
+    ///
+    /// See [`helpers::with_temp_table`].
+    ///
+    /// [`helpers::with_temp_table`]: helpers/fn.with_temp_table.html
+    #[track_caller]
+    pub fn with_temp_table<T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        table: &str,
+        definition: &str,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
               MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
               PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
               SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
-              
     {
-        let lock = self.lock();
+        self.interact(
+            |conn| helpers::with_temp_table(conn, Backend::Mysql, table, definition, mysql_f),
+            |conn| helpers::with_temp_table(conn, Backend::Pg, table, definition, pg_f),
+            |conn| helpers::with_temp_table(conn, Backend::Sqlite, table, definition, sqlite_f)
+        )
+    }
 
-        if lock.is_err() {
-            return Err(error::Error::new(
-                error::ErrorKind::Other, lock.unwrap_err()
-            ));
+    /// Same as `interact`, with the privileges of the session switched to
+    /// those of `role` (`SET ROLE`) while the closure runs, and restored
+    /// once it returned, even on error.
+    ///
+    /// Fails without running the closure on SQLite, which has no roles.
+    /// See [`helpers::as_role`].
+    ///
+    /// [`helpers::as_role`]: helpers/fn.as_role.html
+    #[track_caller]
+    pub fn as_role<T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        role: &str,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        self.interact(
+            |conn| helpers::as_role(conn, Backend::Mysql, role, mysql_f),
+            |conn| helpers::as_role(conn, Backend::Pg, role, pg_f),
+            |conn| helpers::as_role(conn, Backend::Sqlite, role, sqlite_f)
+        )
+    }
+
+    /// Same as `interact`, with `options` applied to this interaction only.
+    ///
+    /// A `statement_timeout` is set on the session before the closure runs
+    /// and the default one restored once it returned, even on error.
+    /// Statements exceeding it fail with `StatementTimeout`. See
+    /// [`helpers::with_statement_timeout`].
+    ///
+    /// With `dry_run`, the closure runs in a transaction which is rolled
+    /// back once it returned, and its result is returned. See
+    /// [`helpers::dry_run`].
+    ///
+    /// [`helpers::with_statement_timeout`]: helpers/fn.with_statement_timeout.html
+    /// [`helpers::dry_run`]: helpers/fn.dry_run.html
+    #[track_caller]
+    pub fn interact_with_options<T, E, MysqlF, PgF, SqliteF>(
+        &self,
+        options: InteractOptions,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        if options == InteractOptions::default() {
+            return self.interact(mysql_f, pg_f, sqlite_f);
         }
-        let mut guard = lock.unwrap();
 
-        match guard.conn_mut() {
-            crate::locked_connection::Connection::Unknown => {
-                unimplemented!()
-            },
-            crate::locked_connection::Connection::Mysql(conn) => {
-                mysql_f(conn)
+        let result = self.interact(
+            |conn| with_options(conn, Backend::Mysql, options, mysql_f),
+            |conn| with_options(conn, Backend::Pg, options, pg_f),
+            |conn| with_options(conn, Backend::Sqlite, options, sqlite_f)
+        );
+        let timeout = match options.statement_timeout {
+            None => return result,
+            Some(timeout) => timeout
+        };
+
+        result.map_err(|err| match err.is_statement_timeout() && err.kind() != error::ErrorKind::StatementTimeout {
+            true => error::Error::new(
+                error::ErrorKind::StatementTimeout,
+                format!("statement exceeded the timeout of {:?}: {}", timeout, err)
+            ),
+            false => err
+        })
+    }
+
+    /// Creates the internal tables of the crate which do not exist yet.
+    ///
+    /// This is done on initialization when the `bootstrap` configuration key
+    /// is set.
+    #[track_caller]
+    pub fn bootstrap(&self) -> Result<()>
+    {
+        self.interact::<_, error::Error, _, _, _>(
+            |conn| bootstrap::run(&*conn, Backend::Mysql),
+            |conn| bootstrap::run(&*conn, Backend::Pg),
+            |conn| bootstrap::run(&*conn, Backend::Sqlite)
+        )
+    }
+
+    /// Writes `event` to the outbox in its own implicit transaction.
+    ///
+    /// Use [`outbox::publish`] from inside an `interact` closure to tie the
+    /// event to the caller's transaction instead.
+    ///
+    /// [`outbox::publish`]: outbox/fn.publish.html
+    #[track_caller]
+    pub fn outbox_publish(&self, event: &outbox::Event) -> Result<()>
+    {
+        self.interact_as::<_, error::Error, _, _, _>(
+            Operation::Insert,
+            |conn| Ok(outbox::publish(&*conn, event)?),
+            |conn| Ok(outbox::publish(&*conn, event)?),
+            |conn| Ok(outbox::publish(&*conn, event)?)
+        )
+    }
+
+    /// Returns the response stored for the idempotency `key`, or executes
+    /// the closure matching the backend and stores its serialized response
+    /// for `ttl`, in the same transaction as the closure's writes.
+    ///
+    /// Requires the bootstrap tables (see `bootstrap`).
+    #[track_caller]
+    pub fn idempotent<MysqlF, PgF, SqliteF>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<String>
+        where MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> Result<String>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> Result<String>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> Result<String>,
+    {
+        let now = self._clock.system_now();
+
+        self.interact::<_, error::Error, _, _, _>(
+            |conn| idempotency::run_at(conn, key, ttl, now, mysql_f),
+            |conn| idempotency::run_at(conn, key, ttl, now, pg_f),
+            |conn| idempotency::run_at(conn, key, ttl, now, sqlite_f)
+        )
+    }
+
+    /// Dispatches up to `limit` pending outbox events to `publisher`.
+    #[track_caller]
+    pub fn outbox_relay<P>(&self, publisher: &P, limit: i64) -> Result<usize>
+        where P: outbox::Publisher
+    {
+        let result_limit = self.result_limit();
+
+        self.interact::<_, error::Error, _, _, _>(
+            |conn| outbox::relay(&*conn, publisher, limit, &result_limit),
+            |conn| outbox::relay(&*conn, publisher, limit, &result_limit),
+            |conn| outbox::relay(&*conn, publisher, limit, &result_limit)
+        )
+    }
+
+    /// Runs the job `name` for the current period of `every`, unless it
+    /// already ran for it or is running, on this instance or another one.
+    /// Returns whether it ran.
+    ///
+    /// With a pool, the run keeps a connection of the pool checked out for
+    /// the lock of the job.
+    ///
+    /// See `rocket_diesel::schedule`. Requires the bootstrap tables.
+    #[track_caller]
+    pub fn run_exclusive<F>(&self, name: &str, every: Duration, f: F) -> Result<bool>
+        where F: FnOnce(&Database) -> Result<()>
+    {
+        let period = schedule::period(self._clock.system_now(), every);
+        // The locks of jobs are held by the session: the claim and the
+        // release run on the same connection, checked out for the whole run
+        // when pooled (the job itself uses the other connections)
+        #[allow(unused_mut)]
+        let mut held = None;
+        #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+        {
+            if self.pool().is_some() {
+                held = Some(self.lock_initialized()?);
+            }
+        }
+
+        let claim = self.interact_on(
+            held.as_mut(),
+            |conn| schedule::claim(&*conn, name, period),
+            |conn| schedule::claim(&*conn, name, period),
+            |conn| schedule::claim(&*conn, name, period)
+        )?;
+
+        match claim {
+            Claim::Claimed { missed } => {
+                if missed > 0 {
+                    log_redacted!(log::Level::Warn, "Job `{}` missed {} run(s) before period {}.", name, missed, period);
+                }
+
+                let result = f(self);
+                let released = self.interact_on(
+                    held.as_mut(),
+                    |conn| schedule::release(&*conn, name),
+                    |conn| schedule::release(&*conn, name),
+                    |conn| schedule::release(&*conn, name)
+                );
+                if let Err(err) = released {
+                    log_redacted!(log::Level::Warn, "Failed to release the lock of job `{}`: {}", name, err);
+                }
+
+                result.map(|()| true)
             },
-            crate::locked_connection::Connection::Pg(conn) => {
-                pg_f(conn)
+            Claim::Done => Ok(false),
+            Claim::Running => {
+                log_redacted!(log::Level::Debug, "Job `{}` is still running on another instance, skipping period {}.", name, period);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Same as `interact`, on the connection `held` when given.
+    #[track_caller]
+    fn interact_on<T, MysqlF, PgF, SqliteF>(
+        &self,
+        held: Option<&mut LockedConnection<'_>>,
+        mysql_f: MysqlF,
+        pg_f: PgF,
+        sqlite_f: SqliteF
+    ) -> Result<T>
+        where MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> Result<T>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> Result<T>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> Result<T>,
+    {
+        match held {
+            Some(lock) => {
+                let mut context = InteractContext::new(Location::caller(), None, self._clock.clone());
+
+                run_on(lock, &mut context, mysql_f, pg_f, sqlite_f)
             },
-            crate::locked_connection::Connection::Sqlite(conn) => {
-                sqlite_f(conn)
+            None => self.interact::<_, error::Error, _, _, _>(mysql_f, pg_f, sqlite_f)
+        }
+    }
+
+    /// Spawns a thread running the job `name` at the start of every period
+    /// of `every`, on a single instance of the fleet (see `run_exclusive`).
+    pub fn schedule_exclusive<F>(&self, name: &str, every: Duration, f: F)
+        -> thread::JoinHandle<()>
+        where F: Fn(&Database) -> Result<()> + Send + 'static
+    {
+        let database = self.clone();
+        let name = name.to_owned();
+
+        thread::spawn(move || loop {
+            thread::sleep(schedule::until_next_period(database.system_now(), every));
+
+            if !database.initialized().unwrap_or(false) {
+                continue;
+            }
+
+            match database.run_exclusive(&name, every, &f) {
+                Ok(true) => log_redacted!(log::Level::Debug, "Ran job `{}`.", name),
+                Ok(false) => {},
+                Err(err) => log_redacted!(log::Level::Warn, "Job `{}` failed: {}", name, err)
+            }
+        })
+    }
+
+    /// Registers `view`, replacing the view registered with that name, with
+    /// a warning check reporting it when stale. Views with a refresh period
+    /// are refreshed by a job (see `schedule_exclusive`), whose thread is
+    /// returned.
+    pub fn register_view(&self, view: View) -> Option<thread::JoinHandle<()>>
+    {
+        let name = view.name().to_owned();
+        let every = view.refresh_every();
+        self._views.register(view);
+
+        let check = name.clone();
+        self.register_check(&format!("view:{}", name), Severity::Warning, move |database| {
+            let status = database._views.status(&check);
+
+            match status.and_then(|status| status.stale(database.system_now())) {
+                None => Ok(()),
+                Some(stale) => Err(error::Error::new(
+                    error::ErrorKind::IntegrityCheck,
+                    format!("view `{}` is stale: {}", check, stale)
+                ))
+            }
+        });
+
+        every.map(|every| self.schedule_exclusive(
+            &format!("refresh_view:{}", name), every, move |database| database.refresh_view(&name)
+        ))
+    }
+
+    /// Refreshes the registered view `name` now.
+    pub fn refresh_view(&self, name: &str) -> Result<()>
+    {
+        let view = self._views.get(name)?;
+        let started = self._clock.now();
+
+        let result = self.interact_any::<_, diesel::result::Error, _>(|conn| {
+            let (sql, transactional) = view.refresh_sql(conn.backend());
+
+            match transactional {
+                true => conn.transaction(|| conn.batch_execute(&sql)),
+                false => conn.batch_execute(&sql)
+            }
+        }).map(|()| self._clock.now().duration_since(started));
+        self._views.record(name, self.system_now(), &result);
+
+        match result {
+            Ok(duration) => {
+                log_redacted!(log::Level::Debug, "Refreshed view `{}` in {:?}.", name, duration);
+                Ok(())
             },
-        }.map_err(|err| {
-            error::Error::new(error::ErrorKind::Other, err.description() )
+            Err(err) => {
+                log_redacted!(log::Level::Warn, "Failed to refresh view `{}`: {}", name, err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the last refresh of each registered view.
+    pub fn view_statuses(&self) -> Vec<ViewStatus>
+    {
+        self._views.statuses()
+    }
+
+    /// Runs `cleanup` (e.g. deleting old rows) when a write fails because
+    /// the database or its disk is full, replacing the cleanup registered.
+    ///
+    /// See [`storage`](storage/index.html).
+    pub fn on_storage_full<F>(&self, cleanup: F)
+        where F: Fn(&Database) -> Result<()> + Send + Sync + 'static
+    {
+        self._storage_full.register(cleanup);
+    }
+
+    /// Returns the version of `table`, incremented on every change by the
+    /// triggers of `install_version_triggers` or by `bump_table_version`.
+    pub fn table_version(&self, table: &str) -> Result<TableVersion>
+    {
+        let select = |backend| diesel::sql_query(versions::select_sql(backend, table));
+
+        let row = self.interact::<_, diesel::result::Error, _, _, _>(
+            |conn| select(Backend::Mysql).load::<VersionRow>(&*conn),
+            |conn| select(Backend::Pg).load::<VersionRow>(&*conn),
+            |conn| select(Backend::Sqlite).load::<VersionRow>(&*conn)
+        )?.into_iter().next();
+
+        Ok(TableVersion::new(table, row))
+    }
+
+    /// Increments the version of `table`, e.g. after changing it with
+    /// statements which do not fire the triggers.
+    pub fn bump_table_version(&self, table: &str) -> Result<()>
+    {
+        self.interact_any::<_, diesel::result::Error, _>(|conn| {
+            conn.batch_execute(&versions::bump_sql(conn.backend(), table))
+        })
+    }
+
+    /// Installs the triggers incrementing the version of `table` on every
+    /// change, replacing those already installed.
+    pub fn install_version_triggers(&self, table: &str) -> Result<()>
+    {
+        self.interact_any::<_, diesel::result::Error, _>(|conn| {
+            for statement in versions::trigger_sql(conn.backend(), table) {
+                conn.batch_execute(&statement)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Spawns a thread relaying pending outbox events to `publisher` every
+    /// `interval`.
+    pub fn spawn_outbox_relay<P>(&self, publisher: P, interval: Duration)
+        -> thread::JoinHandle<()>
+        where P: outbox::Publisher
+    {
+        let database = self.clone();
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+
+            if !database.initialized().unwrap_or(false) {
+                continue;
+            }
+
+            match database.outbox_relay(&publisher, 100) {
+                Ok(0) => {},
+                Ok(dispatched) => {
+                    log_redacted!(log::Level::Debug, "Relayed {} outbox event(s).", dispatched);
+                },
+                Err(err) => {
+                    log_redacted!(log::Level::Warn, "Failed to relay outbox events: {}", err);
+                }
+            }
         })
     }
 }
@@ -245,40 +3176,396 @@ impl Fairing for Database
     {
         Info {
             name: "Diesel dynamic database",
-            kind: Kind::Attach | Kind::Request
+            kind: Kind::Attach | Kind::Request | Kind::Response
         }
     }
 
     fn on_attach(&self, rocket: Rocket)
         -> std::result::Result<Rocket, Rocket>
     {
+        self._attached.store(true, Ordering::Release);
+        self._workers.store(usize::from(rocket.config().workers), Ordering::Release);
+        self.configuration_changed();
         Ok(rocket.manage((*self).clone()))
     } 
 
     fn on_request(&self, request: &mut Request<'_>, _data: &Data)
     {
+        retry::begin();
+        // Not routed yet: the guards of the crate add the route to the tag
+        tagging::set_current(Some(match self.tags_client_ip() {
+            true => tagging::QueryTag::with_client_ip(request),
+            false => tagging::QueryTag::from_request(request)
+        }));
+        tagging::set_path(Some(request.uri().path().to_owned()));
+
+        // Fast path: a single atomic load once initialized, without waiting
+        // for the connection nor reading the configuration
+        if self._ready.load(Ordering::Acquire) {
+            return ;
+        }
+
         if !self.initialized().unwrap_or(false) {
+            // Forgets a configuration which keeps failing
+            if self.has_configuration() {
+                self.forget_failing_configuration();
+            }
+
             // If configuration is not yet available
             if !self.has_configuration() {
+                // Does not read it again until the failure cache expires
+                if !self.configuration_ready() {
+                    return ;
+                }
+
                 // Tries to get configuration
                 let configuration = match request.guard::<Configuration>() {
                     Outcome::Success(configuration) => configuration,
                     Outcome::Forward(_) => { unreachable!() },
-                    Outcome::Failure(_err) => {
-                        // Ignores the absence of configuration
+                    Outcome::Failure((status, _err)) => {
+                        // Records the absence of configuration
                         //   => Guard will give an error
+                        self.configuration_failed(status);
                         return ;
                     }
                 };
 
+                if let Ok(mut backoff) = self._configuration_backoff.lock() {
+                    backoff.reset();
+                }
+
                 // Stores configuration
                 if let Ok(mut lock) = self._configuration.write() {
                     *lock = Some(configuration);
                 }
+                self.configuration_changed();
+            }
+
+            // Initialize database connection, unless established on first use
+            if !self.lazy() {
+                let _ = self.try_initialize();
             }
+        }
+    }
+
+    fn on_response(&self, _request: &Request<'_>, _response: &mut Response<'_>)
+    {
+        tagging::set_current(None);
+        tagging::set_path(None);
+        retry::end();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection as _,
+        query_dsl::RunQueryDsl,
+        sqlite::SqliteConnection
+    };
+    use std::{
+        env,
+        fs,
+        panic::Location,
+        process,
+        sync::{atomic::Ordering, Mutex},
+        time::Duration
+    };
+    use crate::{bootstrap, Backend, ConfigurationStatus, Settings, Strictness};
+    use crate::clock::SharedClock;
+    use crate::error::ErrorKind;
+    use crate::layer::InteractContext;
+    use crate::locked_connection::{Connection, LockedConnection};
+    use crate::settings::ConnectionLifetime;
+    use crate::tagging::{self, QueryTag};
+    use crate::clock::TestClock;
+    use super::{run_on, unsupported_scheme, Database};
+
+    #[test]
+    fn unsupported_scheme_is_an_error() {
+        let err = unsupported_scheme("redis");
+
+        assert_eq!(err.kind(), ErrorKind::UnsupportedScheme);
+        assert!(err.to_string().contains("`redis`"));
+    }
+
+    #[test]
+    fn unknown_connection_does_not_panic() {
+        let mutex = Mutex::new(None);
+        let mut guard = LockedConnection::new(mutex.lock().unwrap(), Connection::Unknown);
+        let mut context = InteractContext::new(Location::caller(), None, SharedClock::default());
+
+        let err = run_on::<(), diesel::result::Error, _, _, _>(
+            &mut guard, &mut context,
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| unreachable!()
+        ).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnsupportedScheme);
+    }
+
+    #[test]
+    fn interact_without_configuration_does_not_panic() {
+        let result = Database::new().interact::<(), diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| unreachable!()
+        );
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotAttached);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "before its fairing was attached")]
+    fn strict_interact_before_attach_panics() {
+        let _ = Database::new().strictness(Strictness::Strict).interact::<(), diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| unreachable!()
+        );
+    }
+
+    #[test]
+    fn interact_optional_maps_not_found() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+
+        let missing = database.interact_optional::<i32, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Err(diesel::result::Error::NotFound)
+        );
+        assert_eq!(missing.unwrap(), None);
+        assert_eq!(database.stats().errors, 0);
+
+        let found = database.interact_optional::<_, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Ok(1)
+        );
+        assert_eq!(found.unwrap(), Some(1));
+
+        let err = database.interact_optional::<i32, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Err(diesel::result::Error::RollbackTransaction)
+        ).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Diesel);
+    }
+
+    #[test]
+    fn interact_with_request_tags_the_interaction() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let tag = QueryTag { route: Some("/reports".to_owned()), ..QueryTag::default() };
+
+        let seen = std::thread::spawn(move || database.interact_with_request::<_, diesel::result::Error, _, _, _>(
+            &tag,
+            |_conn| Ok(None),
+            |_conn| Ok(None),
+            |_conn| Ok(tagging::current())
+        )).join().unwrap().unwrap();
+
+        assert_eq!(seen.and_then(|tag| tag.route), Some("/reports".to_owned()));
+        assert_eq!(tagging::current(), None);
+    }
+
+    #[test]
+    fn active_credentials_before_connecting() {
+        assert_eq!(Database::new().active_credentials(), None);
+    }
+
+    #[test]
+    fn configuration_status_before_any_request() {
+        let status = Database::new().configuration_status();
+
+        assert_eq!(status, ConfigurationStatus::Pending);
+        assert!(!status.is_misconfigured());
+    }
+
+    #[test]
+    fn from_connection() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+
+        assert!(database.initialized().unwrap());
+        assert_eq!(database.backend(), Some(Backend::Sqlite));
+
+        let affected = database.interact::<_, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query("CREATE TABLE users (id INTEGER)").execute(&*conn)
+        ).unwrap();
+        assert_eq!(affected, 0);
+    }
+
+    #[test]
+    fn idempotency_keys_expire_on_the_database_clock() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        bootstrap::run(&conn, Backend::Sqlite).unwrap();
+
+        let clock = TestClock::new();
+        let database = Database::from_connection(conn).with_clock(clock.clone());
+        let respond = |response: &'static str| database.idempotent(
+            "key", Duration::from_secs(60),
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            move |_conn| Ok(response.to_owned())
+        ).unwrap();
+
+        assert_eq!(respond("first"), "first");
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(respond("second"), "first");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(respond("third"), "third");
+    }
+
+    #[test]
+    fn replaces_connections_beyond_their_lifetime() {
+        let path = env::temp_dir().join(format!("rocket-diesel-lifetime-{}.db", process::id()));
+        let lifetime = ConnectionLifetime {
+            idle_timeout: Some(Duration::from_secs(60)),
+            max_lifetime: Some(Duration::from_secs(300))
+        };
+        let clock = TestClock::new();
+        let configured = |url: String| {
+            let database = Database::new().with_clock(clock.clone()).with_settings(
+                Settings::new(url).unwrap().with_connection_lifetime(lifetime)
+            );
+            database._attached.store(true, Ordering::Release);
+            database
+        };
+        let use_connection = |database: &Database| {
+            database.interact::<_, diesel::result::Error, _, _, _>(
+                |_conn| unreachable!(),
+                |_conn| unreachable!(),
+                |conn| diesel::sql_query("SELECT 1").execute(&*conn)
+            ).unwrap();
+            database._established.lock().unwrap().unwrap()
+        };
+
+        let file = configured(format!("sqlite://{}", path.display()));
+        let memory = configured("sqlite::memory:".to_owned());
+        let established = use_connection(&file);
+        assert_eq!(use_connection(&memory), established);
 
-            // Initialize database connection
-            let _ = self.initialize();
+        // Idle for less than `idle_timeout`, then for `idle_timeout`
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(use_connection(&file), established);
+        clock.advance(Duration::from_secs(60));
+        let established = use_connection(&file);
+        assert_eq!(established, file.now());
+
+        // Used regularly until `max_lifetime`
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(50));
+            assert_eq!(use_connection(&file), established);
         }
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(use_connection(&file), file.now());
+
+        // In-memory databases would lose their data
+        assert_eq!(use_connection(&memory) + Duration::from_secs(419), memory.now());
+
+        drop(file);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn refreshes_configuration_after_failures() {
+        let clock = TestClock::new();
+        let database = Database::new()
+            .with_clock(clock.clone())
+            .with_configuration_refresh(2)
+            .with_settings(Settings::new("sqlite:///nonexistent/rocket-diesel/db.sqlite".to_owned()).unwrap());
+        let wait_backoff = || {
+            let delay = database._backoff.lock().unwrap().delay();
+            clock.advance(delay);
+        };
+
+        assert!(database.try_initialize().is_err());
+        wait_backoff();
+        assert!(!database.should_refresh_configuration());
+
+        // Backing off after the second failure
+        assert!(database.try_initialize().is_err());
+        assert!(!database.should_refresh_configuration());
+        assert!(!database.forget_failing_configuration());
+        assert!(database.settings().is_ok());
+
+        wait_backoff();
+        assert!(database.should_refresh_configuration());
+        assert!(database.forget_failing_configuration());
+        assert!(database.settings().unwrap_err().to_string().contains("no configuration available"));
+    }
+}
+
+/// Overhead of the fairing on requests once the database is initialized,
+/// compared with a request served without it (`cargo bench --features nightly database`).
+#[cfg(all(test, feature = "nightly"))]
+mod benches {
+    extern crate test;
+
+    use diesel::{
+        connection::Connection as _,
+        sqlite::SqliteConnection
+    };
+    use rocket::{
+        config::{Config, Environment},
+        local::Client
+    };
+    use std::{
+        sync::mpsc,
+        thread
+    };
+    use self::test::Bencher;
+    use super::Database;
+
+    fn client(database: Option<Database>) -> Client {
+        let rocket = rocket::custom(Config::new(Environment::Development));
+        let rocket = match database {
+            Some(database) => rocket.attach(database),
+            None => rocket
+        };
+
+        Client::new(rocket).unwrap()
+    }
+
+    #[bench]
+    fn request_without_fairing(bencher: &mut Bencher) {
+        let client = client(None);
+
+        bencher.iter(|| client.get("/").dispatch());
+    }
+
+    #[bench]
+    fn request_initialized(bencher: &mut Bencher) {
+        let client = client(Some(Database::from_connection(SqliteConnection::establish(":memory:").unwrap())));
+
+        bencher.iter(|| client.get("/").dispatch());
+    }
+
+    /// Requests while a long interaction holds the connection: they used to
+    /// wait for it to check whether the database was initialized.
+    #[bench]
+    fn request_while_connection_in_use(bencher: &mut Bencher) {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let client = client(Some(database.clone()));
+        let (started, start) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+
+        let holder = thread::spawn(move || {
+            database.interact::<_, diesel::result::Error, _, _, _>(
+                |_conn| Ok(()),
+                |_conn| Ok(()),
+                |_conn| {
+                    started.send(()).unwrap();
+                    released.recv().unwrap();
+                    Ok(())
+                }
+            ).unwrap();
+        });
+
+        start.recv().unwrap();
+        bencher.iter(|| client.get("/").dispatch());
+        release.send(()).unwrap();
+        holder.join().unwrap();
     }
-}
\ No newline at end of file
+}
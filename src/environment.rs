@@ -0,0 +1,112 @@
+//! Configuration overrides read from environment variables.
+//!
+//! Every key of the `diesel` configuration can be overridden by the
+//! variable named after it: `ROCKET_DIESEL_` followed by the key in upper
+//! case, nested keys being joined with `_` (e.g. `ROCKET_DIESEL_URL`,
+//! `ROCKET_DIESEL_CIRCUIT_BREAKER_ERROR_RATE`). A variable takes precedence
+//! over the configuration file.
+
+use std::env;
+
+/// Prefix of the overriding variables.
+pub(crate) const PREFIX: &str = "ROCKET_DIESEL_";
+
+/// Name of the variable overriding `key` (e.g. `migrations.policy`).
+pub(crate) fn variable_name(key: &str) -> String {
+    let mut name = String::with_capacity(PREFIX.len() + key.len());
+
+    name.push_str(PREFIX);
+    for c in key.chars() {
+        match c {
+            '.' | '-' => name.push('_'),
+            _ => name.extend(c.to_uppercase())
+        }
+    }
+
+    name
+}
+
+/// Value of a variable, read with the accessors of configuration values.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct EnvValue(String);
+
+impl EnvValue {
+    pub fn new<S: Into<String>>(value: S) -> Self {
+        EnvValue(value.into())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        Some(&self.0)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.0.trim().parse().ok()
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.0.trim().parse().ok()
+    }
+
+    /// Comma-separated list.
+    pub fn as_array(&self) -> Option<Vec<EnvValue>> {
+        Some(self.0.split(',').map(|item| EnvValue::new(item.trim())).collect())
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.0.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on"     => Some(true),
+            "false" | "0" | "no" | "off"    => Some(false),
+            _                               => None,
+        }
+    }
+}
+
+/// Returns the value overriding `key`, if any.
+pub(crate) fn lookup(key: &str) -> Option<EnvValue> {
+    env::var(variable_name(key)).ok().map(EnvValue::new)
+}
+
+/// Applies `convert` to `value`.
+///
+/// Closures given to the configuration macros are written once and applied
+/// both to environment and configuration values: going through a function
+/// lets the compiler infer the type of their parameter.
+pub(crate) fn convert<V, T, F>(value: V, convert: F) -> Option<T>
+    where F: FnOnce(V) -> Option<T>
+{
+    convert(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lookup, variable_name, EnvValue};
+
+    #[test]
+    fn variable_names() {
+        assert_eq!(variable_name("url"), "ROCKET_DIESEL_URL");
+        assert_eq!(variable_name("circuit_breaker.error_rate"), "ROCKET_DIESEL_CIRCUIT_BREAKER_ERROR_RATE");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(EnvValue::new("42").as_u64(), Some(42));
+        assert_eq!(EnvValue::new("0.5").as_f64(), Some(0.5));
+        assert_eq!(EnvValue::new("abc").as_u64(), None);
+        assert_eq!(EnvValue::new("TRUE").as_bool(), Some(true));
+        assert_eq!(EnvValue::new("off").as_bool(), Some(false));
+        assert_eq!(EnvValue::new("maybe").as_bool(), None);
+        assert_eq!(EnvValue::new("x").as_str(), Some("x"));
+        assert_eq!(
+            EnvValue::new("a, b").as_array(),
+            Some(vec![EnvValue::new("a"), EnvValue::new("b")])
+        );
+    }
+
+    #[test]
+    fn lookup_reads_environment() {
+        std::env::set_var("ROCKET_DIESEL_TEST_LOOKUP", "value");
+
+        assert_eq!(lookup("test_lookup"), Some(EnvValue::new("value")));
+        assert_eq!(lookup("test_missing"), None);
+    }
+}
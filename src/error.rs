@@ -1,9 +1,25 @@
 #![allow(dead_code)]
 //! Type representing various errors that can occur in a Rocket application.
 
+use std::backtrace::{
+    Backtrace,
+    BacktraceStatus
+};
+use std::env;
 use std::error;
 use std::error::Error as _;
 use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{
+    AtomicU8,
+    Ordering
+};
+
+use crate::redaction;
+
+/// Environment variable forcing the capture of backtraces by errors, even
+/// without `RUST_BACKTRACE`/`RUST_LIB_BACKTRACE`.
+pub const BACKTRACE_VARIABLE: &str = "ROCKET_DIESEL_BACKTRACE";
 
 /// The error type for rocket-diesel operations of the associated traits.
 ///
@@ -12,19 +28,213 @@ use std::fmt;
 ///
 /// [`rocket-diesel::error::ErrorKind`]: enum.ErrorKind.html
 pub struct Error {
-    repr: Repr
+    repr: Repr,
+    location: Option<&'static Location<'static>>,
+    backtrace: Option<Box<Backtrace>>
 }
 
+/// The alternate form (`{:#?}`) also prints the backtrace, if captured.
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&self.repr, f)
+        fmt::Debug::fmt(&self.repr, f)?;
+
+        match self.backtrace {
+            Some(ref backtrace) if f.alternate() => write!(f, "\n\nStack backtrace:\n{}", backtrace),
+            _ => Ok(())
+        }
+    }
+}
+
+/// Whether `ROCKET_DIESEL_BACKTRACE` forces the capture of backtraces:
+/// 0 when not read yet, 1 when it does not, 2 when it does.
+static BACKTRACE_FORCED: AtomicU8 = AtomicU8::new(0);
+
+fn backtrace_forced() -> bool {
+    match BACKTRACE_FORCED.load(Ordering::Relaxed) {
+        0 => {
+            let forced = env::var_os(BACKTRACE_VARIABLE).map_or(false, |value| value != "0");
+
+            BACKTRACE_FORCED.store(if forced { 2 } else { 1 }, Ordering::Relaxed);
+            forced
+        },
+        forced => forced == 2
+    }
+}
+
+/// Environment variable making the creation of `Other` errors panic, as
+/// `panic_on_other` does for the current thread. Only read with the
+/// `testing` feature.
+pub const PANIC_ON_OTHER_VARIABLE: &str = "ROCKET_DIESEL_PANIC_ON_OTHER";
+
+#[cfg(any(test, feature = "testing"))]
+thread_local! {
+    static PANIC_ON_OTHER: std::cell::Cell<bool> = std::cell::Cell::new(false);
+}
+
+/// Makes the creation of errors of kind `Other` on the current thread
+/// panic with the location of the code creating them, to find the failure
+/// paths left unclassified.
+///
+/// Errors wrapped by `Database::interact` from the closures are not
+/// concerned: they are the application's own.
+#[cfg(any(test, feature = "testing"))]
+pub fn panic_on_other(enabled: bool) {
+    PANIC_ON_OTHER.with(|flag| flag.set(enabled));
+}
+
+/// Panics when creating an error of `kind` must panic (see
+/// `panic_on_other`).
+#[track_caller]
+fn check_classified(kind: ErrorKind, error: &dyn fmt::Display) {
+    #[cfg(any(test, feature = "testing"))]
+    {
+        let enabled = || PANIC_ON_OTHER.with(|flag| flag.get())
+            || env::var_os(PANIC_ON_OTHER_VARIABLE).map_or(false, |value| value != "0");
+
+        if kind == ErrorKind::Other && enabled() {
+            panic!("unclassified rocket-diesel error (`ErrorKind::Other`) at {}: {}", Location::caller(), error);
+        }
+    }
+    #[cfg(not(any(test, feature = "testing")))]
+    {
+        let _ = (kind, error);
+    }
+}
+
+/// Captures a backtrace if enabled by `ROCKET_DIESEL_BACKTRACE`, or by the
+/// variables of the standard library.
+fn capture_backtrace(force: bool) -> Option<Box<Backtrace>> {
+    let backtrace = match force || backtrace_forced() {
+        true => Backtrace::force_capture(),
+        false => Backtrace::capture()
+    };
+
+    match backtrace.status() {
+        BacktraceStatus::Captured => Some(Box::new(backtrace)),
+        _ => None
     }
 }
 
 impl From<diesel::result::Error> for Error {
 
     fn from(err: diesel::result::Error) -> Self {
-        Self::new(ErrorKind::Diesel, err.description())
+        Self::new(ErrorKind::Diesel, err)
+    }
+}
+
+impl From<diesel::result::ConnectionError> for Error {
+
+    fn from(err: diesel::result::ConnectionError) -> Self {
+        let kind = match err {
+            diesel::result::ConnectionError::InvalidCString(_) |
+            diesel::result::ConnectionError::InvalidConnectionUrl(_) => {
+                ErrorKind::BadConnectionString
+            },
+            diesel::result::ConnectionError::BadConnection(ref message) => {
+                ErrorKind::from_connection_message(message)
+            },
+            diesel::result::ConnectionError::CouldntSetupConfiguration(_) => {
+                ErrorKind::Diesel
+            },
+            _ => ErrorKind::ConnectionFailed,
+        };
+
+        Self::new(kind, err)
+    }
+}
+
+/// Details of an error reported by the database server, e.g. to map a
+/// unique violation on `users_email_key` to a validation message of the
+/// `email` field.
+///
+/// Postgres reports the table, column and constraint; they are parsed from
+/// the message for MySQL and SQLite, which only report a message. Diesel
+/// does not expose SQLSTATE codes: `sqlstate` is known for the error kinds
+/// and messages which have a standard code.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ErrorDetails {
+    pub message: String,
+    pub details: Option<String>,
+    pub hint: Option<String>,
+    pub table: Option<String>,
+    pub column: Option<String>,
+    pub constraint: Option<String>,
+    pub sqlstate: Option<&'static str>,
+}
+
+impl ErrorDetails {
+    fn new(kind: &diesel::result::DatabaseErrorKind, info: &dyn diesel::result::DatabaseErrorInformation) -> Self {
+        let mut details = ErrorDetails {
+            message: info.message().to_owned(),
+            details: info.details().map(str::to_owned),
+            hint: info.hint().map(str::to_owned),
+            table: info.table_name().map(str::to_owned),
+            column: info.column_name().map(str::to_owned),
+            constraint: info.constraint_name().map(str::to_owned),
+            sqlstate: sqlstate(kind, info.message())
+        };
+
+        if details.table.is_none() && details.column.is_none() && details.constraint.is_none() {
+            details.parse_message();
+        }
+        details
+    }
+
+    /// Reads the table, column and constraint of MySQL and SQLite messages.
+    fn parse_message(&mut self) {
+        const PATTERNS: &[&str] = &[
+            // MySQL, the key being prefixed by its table since MySQL 8
+            r"(?i)duplicate entry '.*' for key '(?:(?P<table>[^'.]+)\.)?(?P<constraint>[^']+)'",
+            r"(?i)foreign key constraint fails \(`[^`]+`\.`(?P<table>[^`]+)`, CONSTRAINT `(?P<constraint>[^`]+)` FOREIGN KEY \(`(?P<column>[^`]+)`\)",
+            r"(?i)column '(?P<column>[^']+)' cannot be null",
+            // SQLite
+            r"(?:UNIQUE|NOT NULL) constraint failed: (?P<table>\w+)\.(?P<column>\w+)",
+            r"CHECK constraint failed: (?P<constraint>\w+)",
+        ];
+
+        let captures = PATTERNS.iter()
+            .filter_map(|pattern| regex::Regex::new(pattern).ok())
+            .find_map(|pattern| pattern.captures(&self.message).map(|captures| {
+                let name = |name: &str| captures.name(name).map(|value| value.as_str().to_owned());
+
+                (name("table"), name("column"), name("constraint"))
+            }));
+
+        if let Some((table, column, constraint)) = captures {
+            self.table = table;
+            self.column = column;
+            self.constraint = constraint;
+        }
+    }
+}
+
+/// Returns whether the lowercased `message` is the one of MySQL error 1114
+/// (`ER_RECORD_FILE_FULL`): `The table '...' is full`.
+fn is_table_full(message: &str) -> bool {
+    message.starts_with("the table '") && message.ends_with("' is full")
+}
+
+/// SQLSTATE code of a database error of `kind` reported with `message`,
+/// when it has a standard one.
+fn sqlstate(kind: &diesel::result::DatabaseErrorKind, message: &str) -> Option<&'static str> {
+    const CODES: &[(&str, &str)] = &[
+        ("null value in column", "23502"),
+        ("not null constraint failed", "23502"),
+        ("cannot be null", "23502"),
+        ("check constraint", "23514"),
+        ("deadlock detected", "40P01"),
+        ("deadlock found", "40001"),
+    ];
+
+    match kind {
+        diesel::result::DatabaseErrorKind::UniqueViolation => Some("23505"),
+        diesel::result::DatabaseErrorKind::ForeignKeyViolation => Some("23503"),
+        diesel::result::DatabaseErrorKind::SerializationFailure => Some("40001"),
+        _ => {
+            let message = message.to_lowercase();
+
+            CODES.iter().find(|(pattern, _code)| message.contains(pattern)).map(|(_pattern, code)| *code)
+        }
     }
 }
 
@@ -53,6 +263,25 @@ pub enum ErrorKind {
     MissingValue,
     UnimplementedFormat,
     Diesel,
+    BadConnectionString,
+    AuthenticationFailed,
+    TlsError,
+    ConnectionFailed,
+    HostResolution,
+    PendingMigrations,
+    Migration,
+    ResultTooLarge,
+    CircuitOpen,
+    CheckoutTimeout,
+    Crypto,
+    UnsupportedVersion,
+    IntegrityCheck,
+    UnsupportedScheme,
+    StatementTimeout,
+    QuotaExceeded,
+    SessionExpired,
+    NotAttached,
+    StorageFull,
     Other,
 }
 
@@ -63,9 +292,60 @@ impl ErrorKind {
             ErrorKind::MissingValue         => "missing_value",
             ErrorKind::UnimplementedFormat  => "unimplemented_format",
             ErrorKind::Diesel               => "diesel",
+            ErrorKind::BadConnectionString  => "bad_connection_string",
+            ErrorKind::AuthenticationFailed => "authentication_failed",
+            ErrorKind::TlsError             => "tls_error",
+            ErrorKind::ConnectionFailed     => "connection_failed",
+            ErrorKind::HostResolution       => "host_resolution",
+            ErrorKind::PendingMigrations    => "pending_migrations",
+            ErrorKind::Migration            => "migration",
+            ErrorKind::ResultTooLarge       => "result_too_large",
+            ErrorKind::CircuitOpen          => "circuit_open",
+            ErrorKind::CheckoutTimeout      => "checkout_timeout",
+            ErrorKind::Crypto               => "crypto",
+            ErrorKind::UnsupportedVersion   => "unsupported_version",
+            ErrorKind::IntegrityCheck       => "integrity_check",
+            ErrorKind::UnsupportedScheme    => "unsupported_scheme",
+            ErrorKind::StatementTimeout     => "statement_timeout",
+            ErrorKind::QuotaExceeded        => "quota_exceeded",
+            ErrorKind::SessionExpired       => "session_expired",
+            ErrorKind::NotAttached          => "not_attached",
+            ErrorKind::StorageFull          => "storage_full",
             ErrorKind::Other                => "other",
         }
     }
+
+    /// Classifies the message reported by a backend when establishing a
+    /// connection failed.
+    fn from_connection_message(message: &str) -> ErrorKind {
+        const AUTHENTICATION: &[&str] = &[
+            "password authentication failed",
+            "authentication failed",
+            "access denied",
+            "no password supplied",
+            "no pg_hba.conf entry",
+        ];
+        const HOST_RESOLUTION: &[&str] = &[
+            "could not translate host name",
+            "unknown mysql server host",
+            "name or service not known",
+            "nodename nor servname provided",
+            "temporary failure in name resolution",
+        ];
+        const TLS: &[&str] = &["ssl", "tls", "certificate"];
+
+        let message = message.to_lowercase();
+
+        if AUTHENTICATION.iter().any(|pattern| message.contains(pattern)) {
+            ErrorKind::AuthenticationFailed
+        } else if HOST_RESOLUTION.iter().any(|pattern| message.contains(pattern)) {
+            ErrorKind::HostResolution
+        } else if TLS.iter().any(|pattern| message.contains(pattern)) {
+            ErrorKind::TlsError
+        } else {
+            ErrorKind::ConnectionFailed
+        }
+    }
 }
 
 /// Intended for use for errors not exposed to the user, where allocating onto
@@ -77,9 +357,14 @@ impl From<ErrorKind> for Error {
     /// [`ErrorKind`]: ./enum.ErrorKind.html
     /// [`Error`]: ./struct.Error.html
     #[inline]
+    #[track_caller]
     fn from(kind: ErrorKind) -> Error {
+        check_classified(kind, &kind.as_str());
+
         Error {
-            repr: Repr::Simple(kind)
+            repr: Repr::Simple(kind),
+            location: None,
+            backtrace: capture_backtrace(false)
         }
     }
 }
@@ -91,18 +376,38 @@ impl Error {
     /// This function is used to generically create I/O errors which do not
     /// originate from the OS itself. The `error` argument is an arbitrary
     /// payload which will be contained in this `Error`.
+    ///
+    /// A backtrace is captured when `RUST_BACKTRACE`, `RUST_LIB_BACKTRACE`
+    /// or `ROCKET_DIESEL_BACKTRACE` enables it.
+    #[track_caller]
     pub fn new<E>(kind: ErrorKind, error: E) -> Error
         where E: Into<Box<dyn error::Error+Send+Sync>>
     {
-        Self::_new(kind, error.into())
+        let error = error.into();
+
+        check_classified(kind, &error);
+        Self::_new(kind, error, capture_backtrace(false))
+    }
+
+    /// Same as `new`, always capturing a backtrace.
+    #[track_caller]
+    pub fn with_backtrace<E>(kind: ErrorKind, error: E) -> Error
+        where E: Into<Box<dyn error::Error+Send+Sync>>
+    {
+        let error = error.into();
+
+        check_classified(kind, &error);
+        Self::_new(kind, error, capture_backtrace(true))
     }
 
-    fn _new(kind: ErrorKind, error: Box<dyn error::Error+Send+Sync>) -> Error {
+    fn _new(kind: ErrorKind, error: Box<dyn error::Error+Send+Sync>, backtrace: Option<Box<Backtrace>>) -> Error {
         Error {
             repr: Repr::Custom(Box::new(Custom {
                 kind,
                 error,
-            }))
+            })),
+            location: None,
+            backtrace
         }
     }
 
@@ -140,6 +445,190 @@ impl Error {
         }
     }
 
+    /// Attempts to downcast the inner error to `E`, giving the error back
+    /// unchanged when it does not wrap an `E`.
+    pub fn downcast<E>(self) -> std::result::Result<E, Error>
+        where E: error::Error+Send+Sync+'static
+    {
+        match self.repr {
+            Repr::Simple(..) => Err(self),
+            Repr::Custom(c) => {
+                let Custom { kind, error } = *c;
+                let (location, backtrace) = (self.location, self.backtrace);
+
+                error.downcast::<E>()
+                    .map(|error| *error)
+                    .map_err(|error| Error { location, ..Error::_new(kind, error, backtrace) })
+            }
+        }
+    }
+
+    /// Returns a reference to the inner error if it is of type `E`.
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+        where E: error::Error+Send+Sync+'static
+    {
+        self.get_ref().and_then(|error| error.downcast_ref::<E>())
+    }
+
+    /// Returns a mutable reference to the inner error if it is of type `E`.
+    pub fn downcast_mut<E>(&mut self) -> Option<&mut E>
+        where E: error::Error+Send+Sync+'static
+    {
+        self.get_mut().and_then(|error| error.downcast_mut::<E>())
+    }
+
+    /// Wraps an error returned by a closure given to `Database::interact`.
+    ///
+    /// Errors of this crate are returned as is and diesel errors get the
+    /// `Diesel` kind, anything else is wrapped with the `Other` kind.
+    pub(crate) fn from_interaction<E>(err: E) -> Error
+        where E: error::Error+Send+Sync+'static
+    {
+        let error: Box<dyn error::Error+Send+Sync> = Box::new(err);
+
+        match error.downcast::<Error>() {
+            Ok(error) => *error,
+            Err(error) => match error.downcast::<diesel::result::Error>() {
+                Ok(error) => Error::from(*error),
+                Err(error) => Error::_new(ErrorKind::Other, error, capture_backtrace(false))
+            }
+        }
+    }
+
+    /// Returns whether this error reports the loss of the connection to the
+    /// server (MySQL errors 2006/2013, Postgres closing the connection).
+    pub fn is_disconnect(&self) -> bool {
+        const DISCONNECT: &[&str] = &[
+            "server has gone away",
+            "lost connection to mysql server",
+            "server closed the connection unexpectedly",
+            "no connection to the server",
+        ];
+
+        match self.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(kind, info)) => {
+                let message = info.message().to_lowercase();
+
+                match kind {
+                    diesel::result::DatabaseErrorKind::UnableToSendCommand => true,
+                    _ => DISCONNECT.iter().any(|pattern| message.contains(pattern))
+                }
+            },
+            _ => false
+        }
+    }
+
+    /// Returns whether this error is diesel's `NotFound`, i.e. a query
+    /// expected a row and got none.
+    pub fn is_not_found(&self) -> bool {
+        match self.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::NotFound) => true,
+            _ => false
+        }
+    }
+
+    /// Returns whether this error reports a statement cancelled by the
+    /// statement timeout (Postgres `statement_timeout`, MySQL
+    /// `max_execution_time`).
+    pub fn is_statement_timeout(&self) -> bool {
+        const TIMEOUT: &[&str] = &[
+            "canceling statement due to statement timeout",
+            "maximum statement execution time exceeded",
+        ];
+
+        if self.kind() == ErrorKind::StatementTimeout {
+            return true;
+        }
+        match self.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(_kind, info)) => {
+                let message = info.message().to_lowercase();
+
+                TIMEOUT.iter().any(|pattern| message.contains(pattern))
+            },
+            _ => false
+        }
+    }
+
+    /// Returns whether this error reports a write failing because the
+    /// database or its disk is full (SQLite `SQLITE_FULL`, e.g. beyond
+    /// `max_page_count`, a full MySQL table, Postgres out of disk space).
+    pub fn is_storage_full(&self) -> bool {
+        const FULL: &[&str] = &[
+            "database or disk is full",
+            "no space left on device",
+            "could not extend file",
+        ];
+
+        if self.kind() == ErrorKind::StorageFull {
+            return true;
+        }
+        match self.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(_kind, info)) => {
+                let message = info.message().to_lowercase();
+
+                FULL.iter().any(|pattern| message.contains(pattern)) || is_table_full(&message)
+            },
+            _ => false
+        }
+    }
+
+    /// Returns whether this error reports a conflict with a concurrent
+    /// transaction, which the same statements may not hit when retried:
+    /// deadlocks, serialization failures, lock wait timeouts and locked
+    /// SQLite databases.
+    pub fn is_transient(&self) -> bool {
+        const TRANSIENT: &[&str] = &[
+            "deadlock found when trying to get lock",
+            "lock wait timeout exceeded",
+            "deadlock detected",
+            "could not serialize access",
+            "database is locked",
+            "database table is locked",
+        ];
+
+        match self.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(kind, info)) => {
+                let message = info.message().to_lowercase();
+
+                match kind {
+                    diesel::result::DatabaseErrorKind::SerializationFailure => true,
+                    _ => TRANSIENT.iter().any(|pattern| message.contains(pattern))
+                }
+            },
+            _ => false
+        }
+    }
+
+    /// Returns the details reported by the database server when this error
+    /// is a database error (e.g. a constraint violation).
+    pub fn details(&self) -> Option<ErrorDetails> {
+        match self.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(kind, info)) => Some(ErrorDetails::new(kind, &**info)),
+            _ => None
+        }
+    }
+
+    /// Returns the location of the call (e.g. to `Database::interact`) which
+    /// produced this error, if known.
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        self.location
+    }
+
+    /// Records the location of the call which produced this error, unless one
+    /// is already recorded.
+    pub(crate) fn with_location(mut self, location: &'static Location<'static>) -> Error {
+        if self.location.is_none() {
+            self.location = Some(location);
+        }
+        self
+    }
+
+    /// Returns the backtrace captured when this error was created, if
+    /// enabled (see `new`).
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref().map(|backtrace| &**backtrace)
+    }
+
     /// Returns the corresponding `ErrorKind` for this error.
     pub fn kind(&self) -> ErrorKind {
         match self.repr {
@@ -161,8 +650,17 @@ impl fmt::Debug for Repr {
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.repr {
-            Repr::Custom(ref c) => c.error.fmt(fmt),
-            Repr::Simple(kind) => write!(fmt, "{}", kind.as_str()),
+            // Messages may carry values masked by the `redaction` rules
+            Repr::Custom(ref c) if redaction::enabled() => {
+                fmt.write_str(&redaction::redact(&c.error.to_string()))?
+            },
+            Repr::Custom(ref c) => c.error.fmt(fmt)?,
+            Repr::Simple(kind) => write!(fmt, "{}", kind.as_str())?,
+        }
+
+        match self.location {
+            Some(location) => write!(fmt, " (at {})", location),
+            None => Ok(())
         }
     }
 }
@@ -210,6 +708,33 @@ mod tests {
         assert_eq!(error.kind(), ErrorKind::Other);
     }
 
+    #[test]
+    fn with_backtrace() {
+        let error = Error::with_backtrace(ErrorKind::Other, "test error");
+
+        assert!(error.backtrace().is_some());
+        assert!(format!("{:#?}", error).contains("Stack backtrace:"));
+        assert!(!format!("{:?}", error).contains("Stack backtrace:"));
+
+        let error = error.downcast::<std::fmt::Error>().unwrap_err();
+        assert!(error.backtrace().is_some());
+    }
+
+    #[test]
+    fn panics_on_other_when_enabled() {
+        super::panic_on_other(true);
+        let other = std::panic::catch_unwind(|| Error::new(ErrorKind::Other, "unclassified"));
+        let diesel = std::panic::catch_unwind(|| Error::new(ErrorKind::Diesel, "classified"));
+        super::panic_on_other(false);
+
+        let message = other.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("unclassified"));
+        assert!(message.contains(file!()));
+        assert!(diesel.is_ok());
+        assert_eq!(Error::new(ErrorKind::Other, "test error").kind(), ErrorKind::Other);
+    }
+
     #[test]
     fn simple() {
         let error = Error::from(
@@ -232,6 +757,40 @@ mod tests {
         assert_eq!(error_unimplemented_format.kind().as_str(), "unimplemented_format");
     }
 
+    #[test]
+    fn from_connection_error() {
+        let bad_url = Error::from(diesel::result::ConnectionError::InvalidConnectionUrl(
+            "invalid url".to_owned()
+        ));
+        let authentication = Error::from(diesel::result::ConnectionError::BadConnection(
+            "FATAL:  password authentication failed for user \"rocket\"".to_owned()
+        ));
+        let mysql_authentication = Error::from(diesel::result::ConnectionError::BadConnection(
+            "Access denied for user 'rocket'@'localhost'".to_owned()
+        ));
+        let tls = Error::from(diesel::result::ConnectionError::BadConnection(
+            "SSL error: certificate verify failed".to_owned()
+        ));
+        let refused = Error::from(diesel::result::ConnectionError::BadConnection(
+            "could not connect to server: Connection refused".to_owned()
+        ));
+        let pg_resolution = Error::from(diesel::result::ConnectionError::BadConnection(
+            "could not translate host name \"db\" to address: Name or service not known".to_owned()
+        ));
+        let mysql_resolution = Error::from(diesel::result::ConnectionError::BadConnection(
+            "Unknown MySQL server host 'db' (-2)".to_owned()
+        ));
+
+        assert_eq!(bad_url.kind(), ErrorKind::BadConnectionString);
+        assert_eq!(authentication.kind(), ErrorKind::AuthenticationFailed);
+        assert_eq!(mysql_authentication.kind(), ErrorKind::AuthenticationFailed);
+        assert_eq!(tls.kind(), ErrorKind::TlsError);
+        assert_eq!(refused.kind(), ErrorKind::ConnectionFailed);
+        assert_eq!(pg_resolution.kind(), ErrorKind::HostResolution);
+        assert_eq!(mysql_resolution.kind(), ErrorKind::HostResolution);
+        assert_eq!(refused.to_string(), "could not connect to server: Connection refused");
+    }
+
     #[test]
     fn custom_get_ref() {
         let error = Error::new(ErrorKind::Other, "test error");
@@ -359,6 +918,176 @@ mod tests {
         assert_eq!(format!("{}", error), "other");
     }
 
+    #[test]
+    fn custom_downcast() {
+        let error = Error::new(
+            ErrorKind::Other, std::io::Error::new(std::io::ErrorKind::NotFound, "test error")
+        );
+        let error = error.downcast::<std::fmt::Error>().unwrap_err();
+        let io_error = error.downcast::<std::io::Error>().unwrap();
+
+        assert_eq!(io_error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn simple_downcast() {
+        let error = Error::from(ErrorKind::Other);
+        let error = error.downcast::<std::io::Error>().unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn custom_downcast_ref_mut() {
+        let mut error = Error::from(diesel::result::Error::NotFound);
+
+        assert_eq!(error.kind(), ErrorKind::Diesel);
+        assert_eq!(
+            error.downcast_ref::<diesel::result::Error>(),
+            Some(&diesel::result::Error::NotFound)
+        );
+        assert!(error.downcast_mut::<diesel::result::Error>().is_some());
+        assert!(error.downcast_ref::<std::io::Error>().is_none());
+    }
+
+    #[test]
+    fn from_interaction() {
+        let error = Error::from_interaction(Error::new(ErrorKind::MissingValue, "test error"));
+        assert_eq!(error.kind(), ErrorKind::MissingValue);
+
+        let error = Error::from_interaction(diesel::result::Error::NotFound);
+        assert_eq!(error.kind(), ErrorKind::Diesel);
+
+        let error = Error::from_interaction(std::fmt::Error);
+        assert_eq!(error.kind(), ErrorKind::Other);
+        assert!(error.downcast_ref::<std::fmt::Error>().is_some());
+    }
+
+    #[test]
+    fn is_disconnect() {
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        let database_error = |kind, message: &str| Error::from(DieselError::DatabaseError(
+            kind, Box::new(message.to_owned())
+        ));
+
+        assert!(database_error(DatabaseErrorKind::__Unknown, "MySQL server has gone away").is_disconnect());
+        assert!(database_error(
+            DatabaseErrorKind::__Unknown, "Lost connection to MySQL server during query"
+        ).is_disconnect());
+        assert!(database_error(
+            DatabaseErrorKind::__Unknown, "server closed the connection unexpectedly"
+        ).is_disconnect());
+        assert!(database_error(DatabaseErrorKind::UnableToSendCommand, "").is_disconnect());
+        assert!(!database_error(DatabaseErrorKind::UniqueViolation, "duplicate key").is_disconnect());
+        assert!(!Error::from(DieselError::NotFound).is_disconnect());
+    }
+
+    #[test]
+    fn is_not_found() {
+        assert!(Error::from(diesel::result::Error::NotFound).is_not_found());
+        assert!(Error::from_interaction(diesel::result::Error::NotFound).is_not_found());
+        assert!(!Error::from(diesel::result::Error::RollbackTransaction).is_not_found());
+        assert!(!Error::new(ErrorKind::Other, "test error").is_not_found());
+    }
+
+    #[test]
+    fn is_statement_timeout() {
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        let database_error = |message: &str| Error::from(DieselError::DatabaseError(
+            DatabaseErrorKind::__Unknown, Box::new(message.to_owned())
+        ));
+
+        assert!(database_error("canceling statement due to statement timeout").is_statement_timeout());
+        assert!(database_error(
+            "Query execution was interrupted, maximum statement execution time exceeded"
+        ).is_statement_timeout());
+        assert!(!database_error("duplicate key").is_statement_timeout());
+        assert!(Error::from(ErrorKind::StatementTimeout).is_statement_timeout());
+    }
+
+    #[test]
+    fn is_storage_full() {
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        let database_error = |message: &str| Error::from(DieselError::DatabaseError(
+            DatabaseErrorKind::__Unknown, Box::new(message.to_owned())
+        ));
+
+        assert!(database_error("database or disk is full").is_storage_full());
+        assert!(database_error("could not extend file \"base/16384/16385\": No space left on device").is_storage_full());
+        assert!(database_error("The table 'orders' is full").is_storage_full());
+        assert!(database_error("The table '/tmp/#sql_1_0' is full").is_storage_full());
+        // Raised by a trigger, not by the storage
+        assert!(!database_error("cart is full").is_storage_full());
+        assert!(!database_error("The cart 'abc' is full").is_storage_full());
+        assert!(Error::from(ErrorKind::StorageFull).is_storage_full());
+    }
+
+    #[test]
+    fn is_transient() {
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        let database_error = |kind, message: &str| Error::from(DieselError::DatabaseError(
+            kind, Box::new(message.to_owned())
+        ));
+
+        assert!(database_error(
+            DatabaseErrorKind::__Unknown, "Deadlock found when trying to get lock; try restarting transaction"
+        ).is_transient());
+        assert!(database_error(DatabaseErrorKind::__Unknown, "deadlock detected").is_transient());
+        assert!(database_error(DatabaseErrorKind::SerializationFailure, "").is_transient());
+        assert!(database_error(DatabaseErrorKind::__Unknown, "database is locked").is_transient());
+        assert!(!database_error(DatabaseErrorKind::UniqueViolation, "duplicate key").is_transient());
+        assert!(!Error::from(DieselError::NotFound).is_transient());
+    }
+
+    #[test]
+    fn details() {
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        let database_error = |kind, message: &str| Error::from(DieselError::DatabaseError(
+            kind, Box::new(message.to_owned())
+        ));
+
+        let details = database_error(
+            DatabaseErrorKind::UniqueViolation, "Duplicate entry 'a@b.c' for key 'users.users_email_key'"
+        ).details().unwrap();
+        assert_eq!(details.table.as_deref(), Some("users"));
+        assert_eq!(details.constraint.as_deref(), Some("users_email_key"));
+        assert_eq!(details.sqlstate, Some("23505"));
+
+        let details = database_error(
+            DatabaseErrorKind::ForeignKeyViolation,
+            "Cannot add or update a child row: a foreign key constraint fails (`app`.`posts`, \
+             CONSTRAINT `posts_user_id_fkey` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`))"
+        ).details().unwrap();
+        assert_eq!(details.table.as_deref(), Some("posts"));
+        assert_eq!(details.column.as_deref(), Some("user_id"));
+        assert_eq!(details.constraint.as_deref(), Some("posts_user_id_fkey"));
+
+        let details = database_error(
+            DatabaseErrorKind::__Unknown, "NOT NULL constraint failed: users.name"
+        ).details().unwrap();
+        assert_eq!(details.table.as_deref(), Some("users"));
+        assert_eq!(details.column.as_deref(), Some("name"));
+        assert_eq!(details.sqlstate, Some("23502"));
+
+        assert!(Error::from(DieselError::NotFound).details().is_none());
+        assert!(Error::new(ErrorKind::Other, "test error").details().is_none());
+    }
+
+    #[test]
+    fn location() {
+        let location = std::panic::Location::caller();
+        let error = Error::new(ErrorKind::Other, "test error").with_location(location);
+
+        assert_eq!(error.location(), Some(location));
+        assert_eq!(format!("{}", error), format!("test error (at {})", location));
+        assert!(Error::from(ErrorKind::Other).location().is_none());
+    }
+
     #[test]
     fn assert_error_is_sync_send() {
         super::_assert_error_is_sync_send();
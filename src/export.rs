@@ -0,0 +1,340 @@
+//! CSV exports streamed in chunks, the "download report" endpoint.
+//!
+//! ```rust,ignore
+//! csv_row!(Order { id, customer => "Customer", total, shipped_at });
+//!
+//! #[get("/orders.csv")]
+//! fn orders(database: State<'_, Database>) -> CsvExport<'static> {
+//!     database.export_csv(|lease, chunk| {
+//!         lease.database().interact_any(|conn| /* orders ordered by id, chunk.offset, chunk.limit */)
+//!     }).with_filename("orders.csv")
+//! }
+//! ```
+//!
+//! The header line comes from the `CsvRow` implementation of the rows,
+//! written by hand or with `csv_row!`. Rows are fetched `chunk.limit` at a
+//! time (`DEFAULT_CHUNK_SIZE` by default) until a chunk comes back short,
+//! each chunk in an interaction of its own: the export leases the
+//! connection like an `EventStream`, other requests run between chunks and
+//! the client going away ends the export. The query must order its rows
+//! for chunks not to skip or repeat any.
+//!
+//! The status and headers are sent before the first chunk is fetched: a
+//! query failing ends the body early, after logging the error.
+//!
+//! Fields starting like a spreadsheet formula (`=`, `+`, `-`, `@`, a tab or
+//! a carriage return), other than numbers, are prefixed with `'` so that
+//! opening the export does not evaluate them. `with_formula_escaping(false)`
+//! writes them as they are, for exports not meant for spreadsheets.
+
+use rocket::{
+    http::ContentType,
+    request::Request,
+    response::{
+        self,
+        Responder,
+        Response
+    }
+};
+
+use std::{
+    cell::Cell,
+    rc::Rc
+};
+
+use crate::stream::{
+    EventStream,
+    StreamEvent,
+    StreamLease
+};
+use crate::Database;
+use crate::Result;
+
+/// Number of rows fetched by each call of the query of an export.
+pub const DEFAULT_CHUNK_SIZE: i64 = 1000;
+
+/// A row of a CSV export.
+pub trait CsvRow
+{
+    /// Names of the columns, written as the header line.
+    fn headers() -> Vec<&'static str>;
+
+    /// Values of the columns, in the order of `headers`.
+    fn fields(&self) -> Vec<String>;
+}
+
+/// A value written in a CSV field.
+pub trait CsvField
+{
+    fn to_csv(&self) -> String;
+}
+
+macro_rules! impl_csv_field {
+    ($($value:ty),*) => {
+        $(
+            impl CsvField for $value
+            {
+                fn to_csv(&self) -> String
+                {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_csv_field!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64, bool, char, str, String);
+
+/// `None` is written as an empty field.
+impl<T> CsvField for Option<T>
+    where T: CsvField
+{
+    fn to_csv(&self) -> String
+    {
+        self.as_ref().map(CsvField::to_csv).unwrap_or_default()
+    }
+}
+
+impl<'a, T> CsvField for &'a T
+    where T: CsvField + ?Sized
+{
+    fn to_csv(&self) -> String
+    {
+        (**self).to_csv()
+    }
+}
+
+/// Implements `CsvRow` for a struct, with a column per listed field, named
+/// after the field unless a header is given:
+///
+/// ```rust,ignore
+/// csv_row!(Order { id, customer => "Customer", total });
+/// ```
+///
+/// The fields must implement `rocket_diesel::export::CsvField`.
+#[macro_export]
+macro_rules! csv_row {
+    ($row:ty { $($field:ident $(=> $header:expr)?),* $(,)? }) => {
+        impl $crate::export::CsvRow for $row
+        {
+            fn headers() -> Vec<&'static str>
+            {
+                vec![$($crate::csv_row!(@header $field $(, $header)?)),*]
+            }
+
+            fn fields(&self) -> Vec<String>
+            {
+                vec![$($crate::export::CsvField::to_csv(&self.$field)),*]
+            }
+        }
+    };
+    (@header $field:ident) => { stringify!($field) };
+    (@header $field:ident, $header:expr) => { $header };
+}
+
+/// Rows fetched by a call of the query of an export.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Chunk
+{
+    /// Rows already fetched, to skip.
+    pub offset: i64,
+    /// Rows to fetch.
+    pub limit: i64,
+}
+
+/// Returns whether spreadsheets would evaluate `field` as a formula.
+fn is_formula(field: &str) -> bool
+{
+    field.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) && field.parse::<f64>().is_err()
+}
+
+/// Quotes `field` when it contains a separator, a quote or a line break,
+/// after prefixing formulas with `'` when `formulas` are escaped.
+fn escape(field: &str, formulas: bool) -> String
+{
+    let field = match formulas && is_formula(field) {
+        true => format!("'{}", field),
+        false => field.to_owned()
+    };
+
+    match field.contains(&[',', '"', '\n', '\r'][..]) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field
+    }
+}
+
+/// Line of `fields`, terminated by CRLF (RFC 4180).
+fn line<S>(fields: &[S], formulas: bool) -> String
+    where S: AsRef<str>
+{
+    let mut line = fields.iter()
+        .map(|field| escape(field.as_ref(), formulas))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    line.push_str("\r\n");
+    line
+}
+
+type Producer<'r> = Box<dyn FnMut(&StreamLease) -> Result<StreamEvent> + 'r>;
+
+/// Streaming CSV response, see `Database::export_csv`.
+pub struct CsvExport<'r>
+{
+    _stream: EventStream<Producer<'r>>,
+    _filename: Option<String>,
+    // Read by the producer as it writes the lines
+    _formulas: Rc<Cell<bool>>,
+}
+
+impl<'r> CsvExport<'r>
+{
+    /// Export of the rows returned by `query`, fetched `chunk_size` at a
+    /// time (at least one).
+    pub fn new<T, F>(database: &Database, chunk_size: i64, mut query: F) -> Self
+        where T: CsvRow,
+              F: FnMut(&StreamLease, Chunk) -> Result<Vec<T>> + 'r
+    {
+        let limit = chunk_size.max(1);
+        let mut offset = None;
+        let formulas = Rc::new(Cell::new(true));
+        let escape_formulas = formulas.clone();
+
+        let producer: Producer<'r> = Box::new(move |lease| {
+            let formulas = escape_formulas.get();
+            let chunk = match offset {
+                None => {
+                    offset = Some(0);
+                    return Ok(StreamEvent::Data(line(&T::headers(), formulas).into_bytes()));
+                },
+                Some(offset) if offset < 0 => return Ok(StreamEvent::End),
+                Some(offset) => Chunk { offset, limit }
+            };
+            let rows = query(lease, chunk)?;
+
+            // A short chunk is the last one
+            offset = match (rows.len() as i64) < limit {
+                true => Some(-1),
+                false => Some(chunk.offset + limit)
+            };
+            Ok(match rows.is_empty() {
+                true => StreamEvent::End,
+                false => StreamEvent::Data(
+                    rows.iter().map(|row| line(&row.fields(), formulas)).collect::<String>().into_bytes()
+                )
+            })
+        });
+
+        Self {
+            _stream: EventStream::new(database, producer),
+            _filename: None,
+            _formulas: formulas
+        }
+    }
+
+    /// Sets whether fields starting like a spreadsheet formula are prefixed
+    /// with `'` (the default).
+    pub fn with_formula_escaping(self, escape: bool) -> Self
+    {
+        self._formulas.set(escape);
+        self
+    }
+
+    /// Sends the export as an attachment named `filename`.
+    pub fn with_filename(mut self, filename: &str) -> Self
+    {
+        self._filename = Some(filename.to_owned());
+        self
+    }
+}
+
+impl<'r> Responder<'r> for CsvExport<'r>
+{
+    fn respond_to(self, _request: &Request<'_>) -> response::Result<'r>
+    {
+        let mut response = Response::build();
+
+        response.header(ContentType::new("text", "csv")).raw_header("Cache-Control", "no-cache");
+        if let Some(filename) = self._filename {
+            let filename = filename.replace(&['"', '\\', '\r', '\n'][..], "_");
+
+            response.raw_header("Content-Disposition", format!("attachment; filename=\"{}\"", filename));
+        }
+        response.streamed_body(self._stream).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        query_dsl::RunQueryDsl,
+        sql_types::{BigInt, Integer, Nullable, Text},
+        sqlite::SqliteConnection
+    };
+    use std::{
+        cell::RefCell,
+        io::Read
+    };
+    use crate::Database;
+    use super::{escape, Chunk, CsvExport};
+
+    #[derive(QueryableByName)]
+    struct Order {
+        #[sql_type = "Integer"]
+        id: i32,
+        #[sql_type = "Text"]
+        customer: String,
+        #[sql_type = "Nullable<Integer>"]
+        total: Option<i32>,
+    }
+
+    crate::csv_row!(Order { id, customer => "Customer", total });
+
+    #[test]
+    fn escapes_fields() {
+        assert_eq!(escape("plain", true), "plain");
+        assert_eq!(escape("a,b", true), "\"a,b\"");
+        assert_eq!(escape("say \"hi\"\n", true), "\"say \"\"hi\"\"\n\"");
+    }
+
+    #[test]
+    fn escapes_formulas() {
+        assert_eq!(escape("=1+1", true), "'=1+1");
+        assert_eq!(escape("@SUM(A1:A2)", true), "'@SUM(A1:A2)");
+        assert_eq!(escape("=HYPERLINK(\"x\")", true), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(escape("-2+3", true), "'-2+3");
+        // Numbers are kept as they are
+        assert_eq!(escape("-12.5", true), "-12.5");
+        assert_eq!(escape("+3", true), "+3");
+        assert_eq!(escape("=1+1", false), "=1+1");
+    }
+
+    #[test]
+    fn exports_in_chunks() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("
+            CREATE TABLE orders (id INTEGER PRIMARY KEY, customer TEXT NOT NULL, total INTEGER);
+            INSERT INTO orders VALUES (1, 'alice', 10), (2, 'bob, jr', NULL), (3, 'carol', 30);
+        ").unwrap();
+        let database = Database::from_connection(conn);
+        let chunks = RefCell::new(Vec::new());
+
+        let mut export = CsvExport::new(&database, 2, |lease, chunk: Chunk| {
+            chunks.borrow_mut().push(chunk);
+            lease.database().interact_any(|conn| {
+                diesel::sql_query("SELECT * FROM orders ORDER BY id LIMIT ? OFFSET ?")
+                    .bind::<BigInt, _>(chunk.limit)
+                    .bind::<BigInt, _>(chunk.offset)
+                    .load::<Order>(conn.as_sqlite().unwrap())
+            })
+        });
+        let mut body = String::new();
+        export._stream.read_to_string(&mut body).unwrap();
+
+        assert_eq!(body, "id,Customer,total\r\n1,alice,10\r\n2,\"bob, jr\",\r\n3,carol,30\r\n");
+        assert_eq!(*chunks.borrow(), vec![Chunk { offset: 0, limit: 2 }, Chunk { offset: 2, limit: 2 }]);
+        drop(export);
+        assert_eq!(database.stream_leases(), 0);
+    }
+}
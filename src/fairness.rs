@@ -0,0 +1,175 @@
+//! First-come, first-served access to the connection.
+//!
+//! `std::sync::Mutex` does not order its waiters: under contention, a
+//! thread locking the connection in a loop can keep winning it while others
+//! wait. With the `pool.fair` configuration key, callers first queue for
+//! their turn, served in arrival order, and only then lock the connection.
+//! A caller whose `pool.checkout_timeout` elapses leaves the queue and fails
+//! with `CheckoutTimeout`.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        Condvar,
+        Mutex
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::error;
+use crate::Result;
+
+#[derive(Debug, Default)]
+struct QueueState {
+    _next_ticket: u64,
+    _waiting: VecDeque<u64>,
+    _taken: bool,
+}
+
+/// Queue of the callers waiting for the connection.
+#[derive(Debug, Default)]
+pub(crate) struct FairQueue {
+    _state: Mutex<QueueState>,
+    _turns: Condvar,
+}
+
+impl FairQueue {
+    /// Waits for the turn of the caller, until `deadline` if any.
+    ///
+    /// Returns the turn, held until dropped, and whether the caller had to
+    /// wait for it.
+    pub fn acquire(queue: &Arc<FairQueue>, deadline: Option<Instant>) -> Result<(Turn, bool)> {
+        let poisoned = |_err| error::Error::new(error::ErrorKind::Other, "connection queue got poisoned");
+        let mut state = queue._state.lock().map_err(poisoned)?;
+
+        let ticket = state._next_ticket;
+        state._next_ticket += 1;
+        state._waiting.push_back(ticket);
+
+        let mut waited = false;
+        loop {
+            if !state._taken && state._waiting.front() == Some(&ticket) {
+                state._waiting.pop_front();
+                state._taken = true;
+
+                return Ok((Turn(queue.clone()), waited));
+            }
+            waited = true;
+
+            state = match deadline {
+                None => queue._turns.wait(state).map_err(poisoned)?,
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        state._waiting.retain(|waiting| *waiting != ticket);
+                        // The next waiter may be the head of the queue now
+                        queue._turns.notify_all();
+
+                        return Err(error::Error::new(
+                            error::ErrorKind::CheckoutTimeout,
+                            "timed out waiting for the connection (`pool.checkout_timeout`)"
+                        ));
+                    }
+                    queue._turns.wait_timeout(state, deadline - now).map_err(|_err| error::Error::new(
+                        error::ErrorKind::Other, "connection queue got poisoned"
+                    ))?.0
+                }
+            };
+        }
+    }
+
+    /// Number of callers waiting for their turn.
+    pub fn waiting(&self) -> usize {
+        self._state.lock().map(|state| state._waiting.len()).unwrap_or(0)
+    }
+
+    fn release(&self) {
+        if let Ok(mut state) = self._state.lock() {
+            state._taken = false;
+        }
+        self._turns.notify_all();
+    }
+}
+
+/// Turn of a caller: the next caller in the queue gets the connection once
+/// it is dropped.
+#[derive(Debug)]
+pub(crate) struct Turn(Arc<FairQueue>);
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Deadline of a caller starting to wait now for at most `timeout`.
+pub(crate) fn deadline(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.map(|timeout| Instant::now() + timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant}
+    };
+    use crate::error::ErrorKind;
+    use super::FairQueue;
+
+    /// Waits until `count` callers are queued.
+    fn wait_for_waiters(queue: &FairQueue, count: usize) {
+        while queue.waiting() < count {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn serves_waiters_in_arrival_order() {
+        let queue = Arc::new(FairQueue::default());
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let (turn, _) = FairQueue::acquire(&queue, None).unwrap();
+
+        let waiters: Vec<_> = (0..5).map(|index| {
+            let waiter_queue = queue.clone();
+            let served = served.clone();
+            let waiter = thread::spawn(move || {
+                let (_turn, waited) = FairQueue::acquire(&waiter_queue, None).unwrap();
+
+                assert!(waited);
+                served.lock().unwrap().push(index);
+                thread::sleep(Duration::from_millis(1));
+            });
+
+            wait_for_waiters(&queue, index + 1);
+            waiter
+        }).collect();
+
+        drop(turn);
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+
+        assert_eq!(*served.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn waiters_give_up_at_their_deadline() {
+        let queue = Arc::new(FairQueue::default());
+        let (turn, _) = FairQueue::acquire(&queue, None).unwrap();
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let err = FairQueue::acquire(&queue, Some(deadline)).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::CheckoutTimeout);
+        assert_eq!(queue.waiting(), 0);
+
+        drop(turn);
+        assert!(!FairQueue::acquire(&queue, Some(deadline)).unwrap().1);
+    }
+}
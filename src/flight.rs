@@ -0,0 +1,82 @@
+use std::sync::Mutex;
+
+use crate::redaction::log_redacted;
+use crate::Result;
+
+/// Single-flight coordination of the initialization.
+///
+/// Simultaneous first requests all find the database uninitialized: one of
+/// them initializes it while the others wait, then use its connection
+/// instead of establishing their own.
+#[derive(Debug, Default)]
+pub(crate) struct SingleFlight
+{
+    _flight: Mutex<()>,
+}
+
+impl SingleFlight
+{
+    /// Runs `f` unless `done` once the callers which came first are through,
+    /// so that `f` does not run again once it succeeded.
+    pub fn run<D, F>(&self, done: D, f: F) -> Result<()>
+        where D: Fn() -> bool,
+              F: FnOnce() -> Result<()>
+    {
+        // A flight which panicked did not initialize: the next one does
+        let _flight = self._flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if done() {
+            log_redacted!(log::Level::Debug, "Database got initialized by a concurrent request.");
+            return Ok(());
+        }
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc
+        },
+        thread,
+        time::Duration
+    };
+    use crate::error::{Error, ErrorKind};
+    use super::SingleFlight;
+
+    #[test]
+    fn concurrent_callers_initialize_once() {
+        let flight = Arc::new(SingleFlight::default());
+        let established = Arc::new(AtomicUsize::new(0));
+
+        let callers: Vec<_> = (0..8).map(|_| {
+            let flight = flight.clone();
+            let established = established.clone();
+
+            thread::spawn(move || flight.run(
+                || established.load(Ordering::SeqCst) > 0,
+                || {
+                    thread::sleep(Duration::from_millis(20));
+                    established.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            ))
+        }).collect();
+
+        for caller in callers {
+            caller.join().unwrap().unwrap();
+        }
+        assert_eq!(established.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn failed_flights_are_retried() {
+        let flight = SingleFlight::default();
+
+        let err = flight.run(|| false, || Err(Error::new(ErrorKind::Other, "unreachable"))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(flight.run(|| false, || Ok(())).is_ok());
+    }
+}
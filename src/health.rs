@@ -0,0 +1,114 @@
+//! `/health` route reporting whether the database is usable, with the
+//! results of its integrity checks.
+//!
+//! The route is public: it reports the name, severity and outcome of each
+//! check, never why they failed (see `Database::check_results` or the
+//! support bundle). It answers `503 Service Unavailable` while the database
+//! is not initialized or a critical check failed.
+
+use rocket::{
+    Data,
+    handler,
+    http::{
+        ContentType,
+        Method,
+        Status
+    },
+    Outcome,
+    request::Request,
+    response::{
+        content::Content,
+        status::Custom
+    },
+    Route,
+    State
+};
+
+use crate::integrity::{
+    CheckResult,
+    Severity
+};
+use crate::support::string;
+use crate::Database;
+
+/// Routes to mount to serve the health of the database.
+pub fn routes() -> Vec<Route>
+{
+    vec![Route::new(Method::Get, "/health", health)]
+}
+
+fn health<'r>(request: &'r Request<'_>, _data: Data) -> handler::Outcome<'r>
+{
+    let database = match request.guard::<State<'_, Database>>() {
+        Outcome::Success(database) => database,
+        _ => return Outcome::Failure(Status::InternalServerError)
+    };
+    let initialized = database.initialized().unwrap_or(false);
+    let (status, json) = render(initialized, &database.check_results());
+
+    handler::Outcome::from(request, Custom(status, Content(ContentType::JSON, json)))
+}
+
+/// Renders the health of a database as a JSON object, with its status.
+fn render(initialized: bool, results: &[CheckResult]) -> (Status, String)
+{
+    let critical = results.iter()
+        .any(|result| result.severity == Severity::Critical && !result.passed());
+    let degraded = results.iter().any(|result| !result.passed());
+
+    let (status, label) = match (initialized, critical, degraded) {
+        (false, _, _) | (_, true, _) => (Status::ServiceUnavailable, "unavailable"),
+        (true, false, true) => (Status::Ok, "degraded"),
+        (true, false, false) => (Status::Ok, "ok")
+    };
+    let checks = results.iter()
+        .map(|result| format!(
+            "{{\"name\":{},\"severity\":{},\"passed\":{}}}",
+            string(&result.name), string(result.severity.as_str()), result.passed()
+        ))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    (status, format!(
+        "{{\"status\":{},\"initialized\":{},\"checks\":[{}]}}", string(label), initialized, checks
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use std::time::SystemTime;
+    use crate::integrity::{CheckResult, Severity};
+    use super::render;
+
+    fn result(name: &str, severity: Severity, error: Option<&str>) -> CheckResult {
+        CheckResult {
+            name: name.to_owned(),
+            severity,
+            checked_at: SystemTime::now(),
+            error: error.map(str::to_owned)
+        }
+    }
+
+    #[test]
+    fn renders_health() {
+        assert_eq!(
+            render(false, &[]),
+            (Status::ServiceUnavailable, "{\"status\":\"unavailable\",\"initialized\":false,\"checks\":[]}".to_owned())
+        );
+
+        let (status, json) = render(true, &[
+            result("tables", Severity::Critical, None),
+            result("orphans", Severity::Warning, Some("2 orphan token(s)"))
+        ]);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(json, concat!(
+            "{\"status\":\"degraded\",\"initialized\":true,\"checks\":[",
+            "{\"name\":\"tables\",\"severity\":\"critical\",\"passed\":true},",
+            "{\"name\":\"orphans\",\"severity\":\"warning\",\"passed\":false}]}"
+        ));
+
+        let (status, _json) = render(true, &[result("tables", Severity::Critical, Some("missing"))]);
+        assert_eq!(status, Status::ServiceUnavailable);
+    }
+}
@@ -0,0 +1,939 @@
+//! Helpers generating raw SQL statements and expressions which differ
+//! between backends.
+//!
+//! Values are rendered as escaped SQL literals for the targeted backend.
+
+use diesel::connection::{
+    SimpleConnection,
+    TransactionManager
+};
+
+use std::{
+    fmt,
+    iter::Peekable,
+    str::CharIndices,
+    time::Duration
+};
+
+use crate::redaction::log_redacted;
+use crate::Backend;
+use crate::error;
+use crate::Result;
+
+/// A value rendered as a literal in generated statements.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl SqlValue {
+    /// Renders the value as a literal for `backend`.
+    pub fn to_sql(&self, backend: Backend) -> String {
+        match *self {
+            SqlValue::Null => "NULL".to_owned(),
+            SqlValue::Bool(value) => match (backend, value) {
+                (Backend::Pg, true)     => "TRUE".to_owned(),
+                (Backend::Pg, false)    => "FALSE".to_owned(),
+                (_, true)               => "1".to_owned(),
+                (_, false)              => "0".to_owned(),
+            },
+            SqlValue::Integer(value) => value.to_string(),
+            SqlValue::Float(value) => value.to_string(),
+            SqlValue::Text(ref value) => quote_literal(backend, value),
+        }
+    }
+}
+
+impl From<bool> for SqlValue {
+    fn from(value: bool) -> Self { SqlValue::Bool(value) }
+}
+
+impl From<i32> for SqlValue {
+    fn from(value: i32) -> Self { SqlValue::Integer(i64::from(value)) }
+}
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self { SqlValue::Integer(value) }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self { SqlValue::Float(value) }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self { SqlValue::Text(value.to_owned()) }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self { SqlValue::Text(value) }
+}
+
+impl<T> From<Option<T>> for SqlValue
+    where T: Into<SqlValue>
+{
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+/// Quotes `value` as a string literal for `backend`.
+pub fn quote_literal(backend: Backend, value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+
+    quoted.push('\'');
+    for c in value.chars() {
+        match c {
+            '\'' => quoted.push_str("''"),
+            '\\' if backend == Backend::Mysql => quoted.push_str("\\\\"),
+            _ => quoted.push(c)
+        }
+    }
+    quoted.push('\'');
+
+    quoted
+}
+
+/// Quotes `identifier` (a table or column name) for `backend`.
+pub fn quote_identifier(backend: Backend, identifier: &str) -> String {
+    match backend {
+        Backend::Mysql => format!("`{}`", identifier.replace('`', "``")),
+        _ => format!("\"{}\"", identifier.replace('"', "\"\"")),
+    }
+}
+
+/// Comma-separated list of quoted identifiers.
+struct Identifiers<'a>(Backend, &'a [&'a str]);
+
+impl fmt::Display for Identifiers<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, identifier) in self.1.iter().enumerate() {
+            if index > 0 {
+                fmt.write_str(", ")?;
+            }
+            fmt.write_str(&quote_identifier(self.0, identifier))?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates an insert statement updating the conflicting row instead of
+/// failing.
+///
+/// `conflict_target` lists the columns of the unique constraint the conflict
+/// is detected on. MySQL does not take a target: any unique key conflict
+/// updates the row. Columns of `values` not part of the target are updated.
+pub fn upsert_sql(
+    backend: Backend,
+    table: &str,
+    conflict_target: &[&str],
+    values: &[(&str, SqlValue)]
+) -> Result<String>
+{
+    if values.is_empty() {
+        return Err(error::Error::new(
+            error::ErrorKind::MissingValue, "no value to upsert"
+        ));
+    }
+    if conflict_target.is_empty() && backend != Backend::Mysql {
+        return Err(error::Error::new(
+            error::ErrorKind::MissingValue, "no conflict target to upsert on"
+        ));
+    }
+
+    let columns: Vec<&str> = values.iter().map(|(column, _)| *column).collect();
+    let literals: Vec<String> = values.iter().map(|(_, value)| value.to_sql(backend)).collect();
+    let updated: Vec<&str> = columns.iter()
+        .filter(|column| !conflict_target.contains(*column))
+        .cloned()
+        .collect();
+
+    let mut sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote_identifier(backend, table),
+        Identifiers(backend, &columns),
+        literals.join(", ")
+    );
+
+    match backend {
+        Backend::Mysql => {
+            // Updating a column to itself makes conflicts a no-op
+            let assignments: Vec<String> = if updated.is_empty() {
+                vec![format!("{0} = {0}", quote_identifier(backend, columns[0]))]
+            } else {
+                updated.iter().map(|column| format!(
+                    "{0} = VALUES({0})", quote_identifier(backend, column)
+                )).collect()
+            };
+
+            sql.push_str(" ON DUPLICATE KEY UPDATE ");
+            sql.push_str(&assignments.join(", "));
+        },
+        Backend::Pg | Backend::Sqlite => {
+            sql.push_str(&format!(
+                " ON CONFLICT ({}) ", Identifiers(backend, conflict_target)
+            ));
+
+            if updated.is_empty() {
+                sql.push_str("DO NOTHING");
+            } else {
+                let assignments: Vec<String> = updated.iter().map(|column| format!(
+                    "{0} = excluded.{0}", quote_identifier(backend, column)
+                )).collect();
+
+                sql.push_str("DO UPDATE SET ");
+                sql.push_str(&assignments.join(", "));
+            }
+        }
+    }
+
+    Ok(sql)
+}
+
+/// Returns whether `segment` can appear unquoted in a JSON path.
+fn is_plain_segment(segment: &str) -> bool {
+    !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Generates an expression reading, as text, the value at `path` in the
+/// JSON `column`.
+///
+/// `path` lists object keys, array indices being given as numbers (e.g.
+/// `&["tags", "0"]`). Postgres uses the `#>>` operator on `json`/`jsonb`
+/// columns, MySQL `JSON_EXTRACT` and SQLite the json1 `json_extract`.
+pub fn json_get(backend: Backend, column: &str, path: &[&str]) -> String {
+    let column = quote_identifier(backend, column);
+
+    match backend {
+        Backend::Pg => {
+            let elements: Vec<String> = path.iter().map(|segment| {
+                if is_plain_segment(segment) {
+                    (*segment).to_owned()
+                } else {
+                    format!("\"{}\"", segment.replace('\\', "\\\\").replace('"', "\\\""))
+                }
+            }).collect();
+
+            format!("{} #>> {}", column, quote_literal(backend, &format!("{{{}}}", elements.join(","))))
+        },
+        Backend::Mysql | Backend::Sqlite => {
+            let mut json_path = String::from("$");
+
+            for segment in path {
+                if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+                    json_path.push_str(&format!("[{}]", segment));
+                } else if is_plain_segment(segment) {
+                    json_path.push('.');
+                    json_path.push_str(segment);
+                } else {
+                    json_path.push_str(&format!(".\"{}\"", segment.replace('"', "\\\"")));
+                }
+            }
+
+            let path = quote_literal(backend, &json_path);
+            match backend {
+                Backend::Mysql => format!("JSON_UNQUOTE(JSON_EXTRACT({}, {}))", column, path),
+                _ => format!("json_extract({}, {})", column, path),
+            }
+        }
+    }
+}
+
+/// Generates a full-text search of `query` in the `columns` of `table`,
+/// selecting its matching rows, the most relevant first.
+///
+/// Each backend searches with its own engine, which has to be set up
+/// beforehand:
+///
+/// - Postgres matches `plainto_tsquery(query)` against the `tsvector` of
+///   the columns, with the default text search configuration of the
+///   server. Without a GIN index on the same expression, every row is
+///   scanned.
+/// - MySQL uses `MATCH ... AGAINST` in natural language mode, which
+///   requires a `FULLTEXT` index on exactly `columns`. Words shorter than
+///   the minimum token size, or found in half of the rows, are ignored.
+/// - SQLite needs `table` to be an FTS5 virtual table. The words of `query`
+///   are searched as quoted terms, FTS5 operators in it being matched
+///   literally.
+///
+/// Stemming, stop words and ranking therefore differ between backends:
+/// the same query may not return the same rows everywhere. Fails when
+/// `columns` or the words of `query` are empty.
+pub fn search_sql(backend: Backend, table: &str, columns: &[&str], query: &str) -> Result<String> {
+    if columns.is_empty() {
+        return Err(error::Error::new(
+            error::ErrorKind::MissingValue, "no column to search in"
+        ));
+    }
+    if query.split_whitespace().next().is_none() {
+        return Err(error::Error::new(
+            error::ErrorKind::MissingValue, "nothing to search for"
+        ));
+    }
+
+    let table = quote_identifier(backend, table);
+
+    Ok(match backend {
+        Backend::Pg => {
+            let document = columns.iter()
+                .map(|column| format!("coalesce({}, '')", quote_identifier(backend, column)))
+                .collect::<Vec<_>>()
+                .join(" || ' ' || ");
+            let query = format!("plainto_tsquery({})", quote_literal(backend, query));
+
+            format!(
+                "SELECT * FROM {0} WHERE to_tsvector({1}) @@ {2} ORDER BY ts_rank(to_tsvector({1}), {2}) DESC",
+                table, document, query
+            )
+        },
+        Backend::Mysql => format!(
+            "SELECT * FROM {} WHERE MATCH ({}) AGAINST ({} IN NATURAL LANGUAGE MODE)",
+            table, Identifiers(backend, columns), quote_literal(backend, query)
+        ),
+        Backend::Sqlite => {
+            let filter = columns.iter()
+                .map(|column| quote_identifier(backend, column))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let terms = query.split_whitespace()
+                .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            format!(
+                "SELECT * FROM {0} WHERE {0} MATCH {1} ORDER BY rank",
+                table, quote_literal(backend, &format!("{{{}}} : ({})", filter, terms))
+            )
+        }
+    })
+}
+
+/// Generates the statement creating the temporary table `table`, with the
+/// columns and constraints of `definition` (e.g. `id INTEGER, name TEXT`).
+pub fn create_temp_table_sql(backend: Backend, table: &str, definition: &str) -> String {
+    let keyword = match backend {
+        Backend::Sqlite => "TEMP",
+        _ => "TEMPORARY",
+    };
+
+    format!("CREATE {} TABLE {} ({})", keyword, quote_identifier(backend, table), definition)
+}
+
+/// Generates the statement dropping the temporary table `table`, if it
+/// exists.
+pub fn drop_temp_table_sql(backend: Backend, table: &str) -> String {
+    match backend {
+        // Never drops a regular table of the same name
+        Backend::Mysql => format!("DROP TEMPORARY TABLE IF EXISTS {}", quote_identifier(backend, table)),
+        _ => format!("DROP TABLE IF EXISTS {}", quote_identifier(backend, table)),
+    }
+}
+
+/// Creates the temporary table `table` on `conn`, runs `f` and drops the
+/// table, whether `f` succeeded or not.
+///
+/// A table left behind by a panicking `f` is dropped before being created
+/// again. Outside of MySQL, `DROP TABLE` drops the temporary table, which
+/// shadows a regular table of the same name.
+pub fn with_temp_table<C, T, E, F>(conn: &mut C, backend: Backend, table: &str, definition: &str, f: F)
+    -> std::result::Result<T, E>
+    where C: diesel::Connection,
+          E: From<diesel::result::Error>,
+          F: FnOnce(&mut C) -> std::result::Result<T, E>
+{
+    let drop = drop_temp_table_sql(backend, table);
+
+    conn.batch_execute(&drop)?;
+    conn.batch_execute(&create_temp_table_sql(backend, table, definition))?;
+
+    let value = f(&mut *conn);
+    let dropped = conn.batch_execute(&drop);
+
+    let value = value?;
+    dropped?;
+    Ok(value)
+}
+
+/// Generates the statement switching the privileges of the session to
+/// those of `role`. SQLite has no roles.
+pub fn set_role_sql(backend: Backend, role: &str) -> Option<String> {
+    match backend {
+        Backend::Sqlite => None,
+        _ => Some(format!("SET ROLE {}", quote_identifier(backend, role))),
+    }
+}
+
+/// Generates the statement restoring the privileges of the session after
+/// `set_role_sql`.
+pub fn reset_role_sql(backend: Backend) -> Option<String> {
+    match backend {
+        Backend::Mysql => Some("SET ROLE DEFAULT".to_owned()),
+        Backend::Pg => Some("RESET ROLE".to_owned()),
+        Backend::Sqlite => None,
+    }
+}
+
+/// Switches the session of `conn` to `role`, runs `f` and restores the
+/// privileges of the session, whether `f` succeeded or not.
+///
+/// Postgres `SET ROLE` requires the user to be a member of `role`. MySQL
+/// activates `role`, which must be granted to the user, in addition to the
+/// ones of the user. Fails on SQLite, which has no roles.
+pub fn as_role<C, T, E, F>(conn: &mut C, backend: Backend, role: &str, f: F)
+    -> std::result::Result<T, E>
+    where C: diesel::Connection,
+          E: From<diesel::result::Error>,
+          F: FnOnce(&mut C) -> std::result::Result<T, E>
+{
+    let (set, reset) = match (set_role_sql(backend, role), reset_role_sql(backend)) {
+        (Some(set), Some(reset)) => (set, reset),
+        _ => return Err(E::from(diesel::result::Error::QueryBuilderError(
+            format!("{} has no roles", backend).into()
+        )))
+    };
+
+    conn.batch_execute(&set)?;
+
+    let value = f(&mut *conn);
+    let reset = conn.batch_execute(&reset);
+
+    let value = value?;
+    reset?;
+    Ok(value)
+}
+
+/// Generates the statement limiting the execution time of the statements
+/// of the session to `timeout`: `statement_timeout` (Postgres),
+/// `max_execution_time` (MySQL, read-only `SELECT`s only). SQLite has no
+/// such setting.
+pub fn set_statement_timeout_sql(backend: Backend, timeout: Duration) -> Option<String> {
+    // 0 disables the timeout
+    let millis = timeout.as_millis().max(1);
+
+    match backend {
+        Backend::Mysql => Some(format!("SET SESSION max_execution_time = {}", millis)),
+        Backend::Pg => Some(format!("SET statement_timeout = {}", millis)),
+        Backend::Sqlite => None,
+    }
+}
+
+/// Generates the statement restoring the default statement timeout after
+/// `set_statement_timeout_sql`.
+pub fn reset_statement_timeout_sql(backend: Backend) -> Option<String> {
+    match backend {
+        Backend::Mysql => Some("SET SESSION max_execution_time = DEFAULT".to_owned()),
+        Backend::Pg => Some("RESET statement_timeout".to_owned()),
+        Backend::Sqlite => None,
+    }
+}
+
+/// Limits the execution time of the statements of `f` to `timeout`, then
+/// restores the default timeout of the session, whether `f` succeeded or
+/// not.
+///
+/// The timeout is set for the session rather than with `SET LOCAL`, which
+/// would only last until the end of a transaction `f` may not open. On
+/// SQLite, whose connection handle diesel does not expose to install an
+/// interrupt handler, `f` runs without timeout.
+pub fn with_statement_timeout<C, T, E, F>(conn: &mut C, backend: Backend, timeout: Duration, f: F)
+    -> std::result::Result<T, E>
+    where C: diesel::Connection,
+          E: From<diesel::result::Error>,
+          F: FnOnce(&mut C) -> std::result::Result<T, E>
+{
+    let (set, reset) = match (set_statement_timeout_sql(backend, timeout), reset_statement_timeout_sql(backend)) {
+        (Some(set), Some(reset)) => (set, reset),
+        _ => {
+            log_redacted!(log::Level::Debug, "Statement timeouts are not supported by {}, ignoring it.", backend);
+            return f(conn);
+        }
+    };
+
+    conn.batch_execute(&set)?;
+
+    let value = f(&mut *conn);
+    let reset = conn.batch_execute(&reset);
+
+    let value = value?;
+    reset?;
+    Ok(value)
+}
+
+/// Runs `f` in a transaction which is always rolled back, returning the
+/// result of `f`: its writes are validated by the database (constraints,
+/// triggers) without being persisted.
+///
+/// Within a transaction, `f` runs in a savepoint rolled back the same way.
+/// Statements committing implicitly (MySQL DDL) are persisted anyway.
+pub fn dry_run<C, T, E, F>(conn: &mut C, f: F) -> std::result::Result<T, E>
+    where C: diesel::Connection,
+          E: From<diesel::result::Error>,
+          F: FnOnce(&mut C) -> std::result::Result<T, E>
+{
+    conn.transaction_manager().begin_transaction(&*conn)?;
+
+    let value = f(&mut *conn);
+    let rollback = conn.transaction_manager().rollback_transaction(&*conn);
+
+    let value = value?;
+    rollback?;
+    Ok(value)
+}
+
+/// Lexical unit of a statement, as far as `translate` is concerned.
+#[derive(Clone, Debug, PartialEq)]
+enum Token<'a> {
+    /// Keyword, unquoted identifier or function name
+    Word(&'a str),
+    Number(&'a str),
+    /// Identifier quoted with `"` or `` ` ``, unescaped
+    Identifier(String),
+    /// String literal, comment, whitespace or punctuation: kept as is
+    Verbatim(&'a str),
+}
+
+/// Consumes the chars following `start` while they match `pred`, returning
+/// the byte offset after the last one.
+fn consume_while<P>(chars: &mut Peekable<CharIndices<'_>>, start: usize, pred: P) -> usize
+    where P: Fn(char) -> bool
+{
+    let mut end = start;
+
+    while let Some(&(index, next)) = chars.peek() {
+        if !pred(next) {
+            break;
+        }
+        end = index + next.len_utf8();
+        chars.next();
+    }
+    end
+}
+
+/// Splits `sql` in tokens.
+fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let next = start + c.len_utf8();
+
+        match c {
+            '\'' | '"' | '`' => {
+                // Doubled quotes escape the quote
+                let mut end = sql.len();
+                let mut content = String::new();
+                while let Some((index, quoted)) = chars.next() {
+                    if quoted == c {
+                        if chars.peek().map(|&(_, after)| after) == Some(c) {
+                            chars.next();
+                        } else {
+                            end = index + 1;
+                            break;
+                        }
+                    } else if quoted == '\\' && c == '\'' {
+                        // MySQL escapes, kept verbatim
+                        content.push(quoted);
+                        if let Some((_, escaped)) = chars.next() {
+                            content.push(escaped);
+                        }
+                        continue;
+                    }
+                    content.push(quoted);
+                }
+
+                tokens.push(match c {
+                    '\'' => Token::Verbatim(&sql[start..end]),
+                    _ => Token::Identifier(content)
+                });
+            },
+            '-' if chars.peek().map(|&(_, next)| next) == Some('-') => {
+                let end = consume_while(&mut chars, next, |c| c != '\n');
+                tokens.push(Token::Verbatim(&sql[start..end]));
+            },
+            c if c.is_whitespace() => {
+                let end = consume_while(&mut chars, next, char::is_whitespace);
+                tokens.push(Token::Verbatim(&sql[start..end]));
+            },
+            c if c.is_ascii_digit() => {
+                let end = consume_while(&mut chars, next, |c| c.is_ascii_digit() || c == '.');
+                tokens.push(Token::Number(&sql[start..end]));
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let end = consume_while(&mut chars, next, |c| c.is_alphanumeric() || c == '_' || c == '$');
+                tokens.push(Token::Word(&sql[start..end]));
+            },
+            _ => tokens.push(Token::Verbatim(&sql[start..next]))
+        }
+    }
+
+    tokens
+}
+
+/// Index of the first token from `index` which is not whitespace.
+fn skip_whitespace(tokens: &[Token<'_>], mut index: usize) -> usize {
+    while let Some(Token::Verbatim(text)) = tokens.get(index) {
+        if !text.chars().all(char::is_whitespace) {
+            break;
+        }
+        index += 1;
+    }
+    index
+}
+
+/// Rewrites the portable subset of `sql` for `backend`, so that a single
+/// statement can be written for the three backends:
+///
+/// - identifiers quoted with `"` or `` ` `` get the quotes of `backend`,
+/// - `TRUE` and `FALSE` become `1` and `0` outside of Postgres, as in
+///   `SqlValue::to_sql`,
+/// - `NOW()` becomes `CURRENT_TIMESTAMP`,
+/// - MySQL's `LIMIT offset, count` becomes `LIMIT count OFFSET offset`
+///   outside of MySQL (with numeric literals only, placeholders would be
+///   bound in the wrong order).
+///
+/// String literals and comments are left untouched. Anything else, e.g.
+/// functions or types specific to a backend, is not translated.
+pub fn translate(backend: Backend, sql: &str) -> String {
+    let tokens = tokenize(sql);
+    let mut translated = String::with_capacity(sql.len());
+    let mut index = 0;
+
+    while let Some(token) = tokens.get(index) {
+        index += 1;
+
+        match token {
+            Token::Identifier(identifier) => translated.push_str(&quote_identifier(backend, identifier)),
+            Token::Word(word) if word.eq_ignore_ascii_case("true") && backend != Backend::Pg => {
+                translated.push('1');
+            },
+            Token::Word(word) if word.eq_ignore_ascii_case("false") && backend != Backend::Pg => {
+                translated.push('0');
+            },
+            Token::Word(word) if word.eq_ignore_ascii_case("now") => {
+                let open = skip_whitespace(&tokens, index);
+                let close = skip_whitespace(&tokens, open + 1);
+
+                match (tokens.get(open), tokens.get(close)) {
+                    (Some(Token::Verbatim("(")), Some(Token::Verbatim(")"))) => {
+                        translated.push_str("CURRENT_TIMESTAMP");
+                        index = close + 1;
+                    },
+                    _ => translated.push_str(word)
+                }
+            },
+            Token::Word(word) if word.eq_ignore_ascii_case("limit") && backend != Backend::Mysql => {
+                let offset = skip_whitespace(&tokens, index);
+                let comma = skip_whitespace(&tokens, offset + 1);
+                let count = skip_whitespace(&tokens, comma + 1);
+
+                translated.push_str(word);
+                if let (Some(Token::Number(offset)), Some(Token::Verbatim(",")), Some(Token::Number(count)))
+                    = (tokens.get(offset), tokens.get(comma), tokens.get(count))
+                {
+                    translated.push_str(&format!(" {} OFFSET {}", count, offset));
+                    index = skip_whitespace(&tokens, comma + 1) + 1;
+                }
+            },
+            Token::Word(text) | Token::Number(text) | Token::Verbatim(text) => translated.push_str(text),
+        }
+    }
+
+    translated
+}
+
+/// Rewrites the `?` placeholders of `sql` in the placeholder style of
+/// `backend`: `$1`, `$2`, ... with Postgres, unchanged otherwise.
+///
+/// String literals and comments are left untouched, and quoted
+/// identifiers get the quotes of `backend`.
+pub fn number_placeholders(backend: Backend, sql: &str) -> String {
+    let mut numbered = String::with_capacity(sql.len());
+    let mut count = 0;
+
+    for token in tokenize(sql) {
+        match token {
+            Token::Verbatim("?") if backend == Backend::Pg => {
+                count += 1;
+                numbered.push_str(&format!("${}", count));
+            },
+            Token::Identifier(identifier) => numbered.push_str(&quote_identifier(backend, &identifier)),
+            Token::Word(text) | Token::Number(text) | Token::Verbatim(text) => numbered.push_str(text),
+        }
+    }
+
+    numbered
+}
+
+/// Splits `sql` at its `?` placeholders, the identifiers getting the quotes
+/// of `backend`: `n` placeholders give `n + 1` segments.
+///
+/// String literals and comments are left untouched.
+pub(crate) fn split_placeholders(backend: Backend, sql: &str) -> Vec<String> {
+    let mut segments = vec![String::new()];
+
+    for token in tokenize(sql) {
+        let segment = segments.last_mut().expect("at least one segment");
+
+        match token {
+            Token::Verbatim("?") => segments.push(String::new()),
+            Token::Identifier(identifier) => segment.push_str(&quote_identifier(backend, &identifier)),
+            Token::Word(text) | Token::Number(text) | Token::Verbatim(text) => segment.push_str(text),
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        query_dsl::RunQueryDsl,
+        sqlite::SqliteConnection
+    };
+    use crate::Backend;
+    use super::{
+        as_role,
+        create_temp_table_sql,
+        dry_run,
+        json_get,
+        number_placeholders,
+        quote_identifier,
+        quote_literal,
+        reset_role_sql,
+        reset_statement_timeout_sql,
+        search_sql,
+        set_role_sql,
+        set_statement_timeout_sql,
+        translate,
+        upsert_sql,
+        with_statement_timeout,
+        with_temp_table,
+        SqlValue
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn literals() {
+        assert_eq!(quote_literal(Backend::Pg, "it's"), "'it''s'");
+        assert_eq!(quote_literal(Backend::Sqlite, "a\\b"), "'a\\b'");
+        assert_eq!(quote_literal(Backend::Mysql, "a\\b"), "'a\\\\b'");
+        assert_eq!(SqlValue::from(true).to_sql(Backend::Pg), "TRUE");
+        assert_eq!(SqlValue::from(true).to_sql(Backend::Sqlite), "1");
+        assert_eq!(SqlValue::from(None::<i32>).to_sql(Backend::Mysql), "NULL");
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(quote_identifier(Backend::Mysql, "users"), "`users`");
+        assert_eq!(quote_identifier(Backend::Pg, "users"), "\"users\"");
+        assert_eq!(quote_identifier(Backend::Sqlite, "we\"ird"), "\"we\"\"ird\"");
+    }
+
+    #[test]
+    fn upsert_pg() {
+        let sql = upsert_sql(Backend::Pg, "users", &["id"], &[
+            ("id", SqlValue::from(1)),
+            ("name", SqlValue::from("Doe")),
+        ]).unwrap();
+
+        assert_eq!(
+            sql,
+            "INSERT INTO \"users\" (\"id\", \"name\") VALUES (1, 'Doe') \
+             ON CONFLICT (\"id\") DO UPDATE SET \"name\" = excluded.\"name\""
+        );
+    }
+
+    #[test]
+    fn upsert_sqlite_nothing_to_update() {
+        let sql = upsert_sql(Backend::Sqlite, "users", &["id"], &[
+            ("id", SqlValue::from(1)),
+        ]).unwrap();
+
+        assert_eq!(
+            sql,
+            "INSERT INTO \"users\" (\"id\") VALUES (1) ON CONFLICT (\"id\") DO NOTHING"
+        );
+    }
+
+    #[test]
+    fn upsert_mysql() {
+        let sql = upsert_sql(Backend::Mysql, "users", &["id"], &[
+            ("id", SqlValue::from(1)),
+            ("name", SqlValue::from("Doe")),
+        ]).unwrap();
+
+        assert_eq!(
+            sql,
+            "INSERT INTO `users` (`id`, `name`) VALUES (1, 'Doe') \
+             ON DUPLICATE KEY UPDATE `name` = VALUES(`name`)"
+        );
+    }
+
+    #[test]
+    fn json_paths() {
+        assert_eq!(json_get(Backend::Pg, "data", &["user", "name"]), "\"data\" #>> '{user,name}'");
+        assert_eq!(
+            json_get(Backend::Mysql, "data", &["tags", "0"]),
+            "JSON_UNQUOTE(JSON_EXTRACT(`data`, '$.tags[0]'))"
+        );
+        assert_eq!(json_get(Backend::Sqlite, "data", &["user", "name"]), "json_extract(\"data\", '$.user.name')");
+        assert_eq!(json_get(Backend::Sqlite, "data", &["first name"]), "json_extract(\"data\", '$.\"first name\"')");
+        assert_eq!(json_get(Backend::Pg, "data", &["it's", "a,b"]), "\"data\" #>> '{\"it''s\",\"a,b\"}'");
+    }
+
+    #[test]
+    fn searches() {
+        assert_eq!(
+            search_sql(Backend::Pg, "posts", &["title", "body"], "rust diesel").unwrap(),
+            "SELECT * FROM \"posts\" WHERE to_tsvector(coalesce(\"title\", '') || ' ' || coalesce(\"body\", '')) \
+             @@ plainto_tsquery('rust diesel') ORDER BY ts_rank(to_tsvector(coalesce(\"title\", '') || ' ' || \
+             coalesce(\"body\", '')), plainto_tsquery('rust diesel')) DESC"
+        );
+        assert_eq!(
+            search_sql(Backend::Mysql, "posts", &["title", "body"], "it's").unwrap(),
+            "SELECT * FROM `posts` WHERE MATCH (`title`, `body`) AGAINST ('it''s' IN NATURAL LANGUAGE MODE)"
+        );
+        assert_eq!(
+            search_sql(Backend::Sqlite, "posts", &["title"], "rust OR \"x").unwrap(),
+            "SELECT * FROM \"posts\" WHERE \"posts\" MATCH '{\"title\"} : (\"rust\" \"OR\" \"\"\"x\")' ORDER BY rank"
+        );
+        assert!(search_sql(Backend::Pg, "posts", &[], "rust").is_err());
+        assert!(search_sql(Backend::Pg, "posts", &["title"], "  ").is_err());
+    }
+
+    #[test]
+    fn translations() {
+        let sql = "SELECT \"id\" FROM `users` WHERE active = TRUE AND seen < NOW() LIMIT 20, 10";
+
+        assert_eq!(
+            translate(Backend::Pg, sql),
+            "SELECT \"id\" FROM \"users\" WHERE active = TRUE AND seen < CURRENT_TIMESTAMP LIMIT 10 OFFSET 20"
+        );
+        assert_eq!(
+            translate(Backend::Mysql, sql),
+            "SELECT `id` FROM `users` WHERE active = 1 AND seen < CURRENT_TIMESTAMP LIMIT 20, 10"
+        );
+        assert_eq!(
+            translate(Backend::Sqlite, sql),
+            "SELECT \"id\" FROM \"users\" WHERE active = 1 AND seen < CURRENT_TIMESTAMP LIMIT 10 OFFSET 20"
+        );
+    }
+
+    #[test]
+    fn translation_keeps_literals() {
+        let sql = "SELECT 'true, now()' -- LIMIT 1, 2\nFROM t LIMIT ?";
+
+        assert_eq!(translate(Backend::Sqlite, sql), sql);
+        assert_eq!(translate(Backend::Mysql, "SELECT \"we\"\"ird\""), "SELECT `we\"ird`");
+    }
+
+    #[test]
+    fn numbered_placeholders() {
+        let sql = "SELECT * FROM users WHERE id = ? AND name <> '?' -- ?\nAND age > ?";
+
+        assert_eq!(
+            number_placeholders(Backend::Pg, sql),
+            "SELECT * FROM users WHERE id = $1 AND name <> '?' -- ?\nAND age > $2"
+        );
+        assert_eq!(number_placeholders(Backend::Mysql, sql), sql);
+        assert_eq!(number_placeholders(Backend::Sqlite, sql), sql);
+        assert_eq!(
+            super::split_placeholders(Backend::Mysql, sql),
+            vec!["SELECT * FROM users WHERE id = ", " AND name <> '?' -- ?\nAND age > ", ""]
+        );
+    }
+
+    #[test]
+    fn temp_tables() {
+        assert_eq!(
+            create_temp_table_sql(Backend::Pg, "staging", "id INTEGER"),
+            "CREATE TEMPORARY TABLE \"staging\" (id INTEGER)"
+        );
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        let failed = with_temp_table(&mut conn, Backend::Sqlite, "staging", "id INTEGER", |conn| {
+            conn.batch_execute("INSERT INTO staging VALUES (1)")?;
+            Err(diesel::result::Error::RollbackTransaction)
+        });
+        assert!(failed.is_err());
+        assert!(conn.batch_execute("SELECT * FROM staging").is_err());
+
+        let done = with_temp_table(&mut conn, Backend::Sqlite, "staging", "id INTEGER", |conn| {
+            conn.batch_execute("INSERT INTO staging VALUES (1)")
+        });
+        assert!(done.is_ok());
+    }
+
+    #[test]
+    fn roles() {
+        assert_eq!(set_role_sql(Backend::Pg, "reporting").unwrap(), "SET ROLE \"reporting\"");
+        assert_eq!(set_role_sql(Backend::Mysql, "reporting").unwrap(), "SET ROLE `reporting`");
+        assert_eq!(reset_role_sql(Backend::Pg).unwrap(), "RESET ROLE");
+        assert_eq!(reset_role_sql(Backend::Mysql).unwrap(), "SET ROLE DEFAULT");
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        let mut called = false;
+        let result = as_role(&mut conn, Backend::Sqlite, "reporting", |_conn| {
+            called = true;
+            Ok::<_, diesel::result::Error>(())
+        });
+        assert!(result.is_err());
+        assert!(!called);
+    }
+
+    #[test]
+    fn upsert_without_values() {
+        assert!(upsert_sql(Backend::Pg, "users", &["id"], &[]).is_err());
+        assert!(upsert_sql(Backend::Pg, "users", &[], &[("id", SqlValue::from(1))]).is_err());
+    }
+
+    #[test]
+    fn dry_runs_roll_back() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY)").unwrap();
+        let count = |conn: &SqliteConnection| {
+            diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>("(SELECT COUNT(*) FROM users)"))
+                .get_result::<i64>(conn)
+                .unwrap()
+        };
+
+        let value = dry_run::<_, _, diesel::result::Error, _>(&mut conn, |conn| {
+            conn.batch_execute("INSERT INTO users VALUES (1)")?;
+            Ok(count(conn))
+        }).unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(count(&conn), 0);
+
+        // Failing writes are rolled back as well
+        let result = dry_run::<(), _, diesel::result::Error, _>(&mut conn, |conn| {
+            conn.batch_execute("INSERT INTO users VALUES (2); INSERT INTO users VALUES (2)")
+        });
+        assert!(result.is_err());
+        assert_eq!(count(&conn), 0);
+    }
+
+    #[test]
+    fn statement_timeout() {
+        let timeout = Duration::from_millis(2500);
+
+        assert_eq!(
+            set_statement_timeout_sql(Backend::Pg, timeout).unwrap(), "SET statement_timeout = 2500"
+        );
+        assert_eq!(
+            set_statement_timeout_sql(Backend::Mysql, Duration::from_micros(10)).unwrap(),
+            "SET SESSION max_execution_time = 1"
+        );
+        assert_eq!(reset_statement_timeout_sql(Backend::Pg).unwrap(), "RESET statement_timeout");
+        assert_eq!(set_statement_timeout_sql(Backend::Sqlite, timeout), None);
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        let value = with_statement_timeout::<_, _, diesel::result::Error, _>(
+            &mut conn, Backend::Sqlite, timeout, |conn| conn.batch_execute("SELECT 1").map(|_| 42)
+        ).unwrap();
+        assert_eq!(value, 42);
+    }
+}
@@ -0,0 +1,231 @@
+//! Idempotency keys for POST handlers.
+//!
+//! The serialized result of a request is stored in the
+//! `_rocket_diesel_idempotency` table under the idempotency key given by the
+//! client, in the same transaction as the writes producing it: a replayed
+//! request gets the stored result back instead of executing again, so that
+//! retried POSTs take effect exactly once.
+//!
+//! The table is created by the bootstrap step (see the `bootstrap`
+//! configuration key and `Database::bootstrap`).
+
+use diesel::{
+    connection::TransactionManager,
+    query_dsl::RunQueryDsl,
+    result::QueryResult,
+    sql_types::{
+        BigInt,
+        Text
+    }
+};
+
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH
+};
+
+use crate::redaction::log_redacted;
+use crate::Result;
+
+/// Name of the idempotency table.
+pub const TABLE: &str = "_rocket_diesel_idempotency";
+
+#[derive(QueryableByName)]
+struct ResponseRow
+{
+    #[sql_type = "Text"]
+    response: String,
+}
+
+/// Backend-specific statements of the idempotency table.
+pub trait IdempotencyConnection: diesel::Connection
+{
+    /// Stored response of `key`, unless expired at `now`.
+    fn stored_response(&self, key: &str, now: i64) -> QueryResult<Option<String>>;
+
+    /// Deletes `key` if expired at `now`.
+    fn delete_expired(&self, key: &str, now: i64) -> QueryResult<()>;
+
+    fn store_response(&self, key: &str, response: &str, expires_at: i64) -> QueryResult<()>;
+}
+
+macro_rules! impl_idempotency_connection {
+    ($connection:ty, $select:expr, $delete:expr, $insert:expr) => {
+        impl IdempotencyConnection for $connection
+        {
+            fn stored_response(&self, key: &str, now: i64) -> QueryResult<Option<String>>
+            {
+                Ok(diesel::sql_query($select)
+                    .bind::<Text, _>(key)
+                    .bind::<BigInt, _>(now)
+                    .load::<ResponseRow>(self)?
+                    .into_iter()
+                    .next()
+                    .map(|row| row.response))
+            }
+
+            fn delete_expired(&self, key: &str, now: i64) -> QueryResult<()>
+            {
+                diesel::sql_query($delete)
+                    .bind::<Text, _>(key)
+                    .bind::<BigInt, _>(now)
+                    .execute(self)
+                    .map(|_| ())
+            }
+
+            fn store_response(&self, key: &str, response: &str, expires_at: i64) -> QueryResult<()>
+            {
+                diesel::sql_query($insert)
+                    .bind::<Text, _>(key)
+                    .bind::<Text, _>(response)
+                    .bind::<BigInt, _>(expires_at)
+                    .execute(self)
+                    .map(|_| ())
+            }
+        }
+    };
+}
+
+impl_idempotency_connection!(
+    diesel::MysqlConnection,
+    "SELECT response FROM _rocket_diesel_idempotency WHERE idempotency_key = ? AND expires_at > ?",
+    "DELETE FROM _rocket_diesel_idempotency WHERE idempotency_key = ? AND expires_at <= ?",
+    "INSERT INTO _rocket_diesel_idempotency (idempotency_key, response, expires_at) VALUES (?, ?, ?)"
+);
+
+impl_idempotency_connection!(
+    diesel::PgConnection,
+    "SELECT response FROM _rocket_diesel_idempotency WHERE idempotency_key = $1 AND expires_at > $2",
+    "DELETE FROM _rocket_diesel_idempotency WHERE idempotency_key = $1 AND expires_at <= $2",
+    "INSERT INTO _rocket_diesel_idempotency (idempotency_key, response, expires_at) VALUES ($1, $2, $3)"
+);
+
+impl_idempotency_connection!(
+    diesel::SqliteConnection,
+    "SELECT response FROM _rocket_diesel_idempotency WHERE idempotency_key = ? AND expires_at > ?",
+    "DELETE FROM _rocket_diesel_idempotency WHERE idempotency_key = ? AND expires_at <= ?",
+    "INSERT INTO _rocket_diesel_idempotency (idempotency_key, response, expires_at) VALUES (?, ?, ?)"
+);
+
+/// Seconds elapsed between the Unix epoch and `time`.
+fn unix_seconds(time: SystemTime) -> i64
+{
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Returns the response stored for `key`, or executes `f` and stores its
+/// (serialized) response for `ttl`, in a single transaction.
+///
+/// When `f` fails, the transaction is rolled back and nothing is stored: the
+/// request can be retried with the same key.
+pub fn run<C, F>(conn: &mut C, key: &str, ttl: Duration, f: F) -> Result<String>
+    where C: IdempotencyConnection,
+          F: FnOnce(&mut C) -> Result<String>
+{
+    run_at(conn, key, ttl, SystemTime::now(), f)
+}
+
+/// Same as `run`, expiring responses relatively to `now`.
+pub(crate) fn run_at<C, F>(conn: &mut C, key: &str, ttl: Duration, now: SystemTime, f: F) -> Result<String>
+    where C: IdempotencyConnection,
+          F: FnOnce(&mut C) -> Result<String>
+{
+    let now = unix_seconds(now);
+    let manager = conn.transaction_manager();
+    manager.begin_transaction(&*conn)?;
+
+    let result = (|| -> Result<String> {
+        conn.delete_expired(key, now)?;
+        if let Some(response) = conn.stored_response(key, now)? {
+            log_redacted!(log::Level::Debug, "Replaying the stored response of idempotency key `{}`.", key);
+            return Ok(response);
+        }
+
+        let response = f(&mut *conn)?;
+        conn.store_response(key, &response, now + ttl.as_secs() as i64)?;
+        Ok(response)
+    })();
+
+    let manager = conn.transaction_manager();
+    match result {
+        Ok(response) => {
+            manager.commit_transaction(&*conn)?;
+            Ok(response)
+        },
+        Err(err) => {
+            manager.rollback_transaction(&*conn)?;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use std::time::{Duration, SystemTime};
+    use crate::{bootstrap, error, Backend};
+    use super::{run, run_at};
+
+    fn connection() -> SqliteConnection {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        bootstrap::run(&conn, Backend::Sqlite).unwrap();
+        conn.batch_execute("CREATE TABLE orders (id INTEGER)").unwrap();
+        conn
+    }
+
+    #[test]
+    fn replays_stored_response() {
+        let mut conn = connection();
+        let place_order = |conn: &mut SqliteConnection| -> crate::Result<String> {
+            conn.batch_execute("INSERT INTO orders VALUES (1)")?;
+            Ok("{\"order\":1}".to_owned())
+        };
+
+        let first = run(&mut conn, "key", Duration::from_secs(60), place_order).unwrap();
+        let second = run(&mut conn, "key", Duration::from_secs(60), |_conn: &mut SqliteConnection| {
+            panic!("executed twice")
+        }).unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn failure_stores_nothing() {
+        let mut conn = connection();
+
+        let failed = run(&mut conn, "key", Duration::from_secs(60), |_conn: &mut SqliteConnection| {
+            Err(error::Error::new(error::ErrorKind::Other, "failed"))
+        });
+        assert!(failed.is_err());
+
+        let retried = run(&mut conn, "key", Duration::from_secs(60), |_conn: &mut SqliteConnection| {
+            Ok("done".to_owned())
+        });
+        assert_eq!(retried.unwrap(), "done");
+    }
+
+    #[test]
+    fn expired_response_is_not_replayed() {
+        let mut conn = connection();
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(60);
+
+        run_at(&mut conn, "key", ttl, now, |_conn: &mut SqliteConnection| Ok("first".to_owned())).unwrap();
+
+        let replayed = run_at(&mut conn, "key", ttl, now + Duration::from_secs(59), |_conn: &mut SqliteConnection| {
+            Ok("second".to_owned())
+        });
+        assert_eq!(replayed.unwrap(), "first");
+
+        let expired = run_at(&mut conn, "key", ttl, now + ttl, |_conn: &mut SqliteConnection| {
+            Ok("third".to_owned())
+        });
+        assert_eq!(expired.unwrap(), "third");
+    }
+}
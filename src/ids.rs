@@ -0,0 +1,265 @@
+//! Strongly typed identifiers.
+//!
+//! `Id<T, R>` wraps a raw identifier of type `R` (`i32`, `i64` or, with the
+//! `uuid` feature, `Uuid`) and tags it with the type `T` it identifies, so
+//! that an `Id<User>` can't be passed where an `Id<Client>` is expected. It
+//! can be bound in Diesel queries, loaded from Diesel results and extracted
+//! from Rocket route parameters.
+//!
+//! ```rust,ignore
+//! #[get("/users/<id>")]
+//! fn user(database: State<'_, Database>, id: Id<User, i32>) -> Option<String> {
+//!     // users.find(id)...
+//! }
+//! ```
+
+use diesel::{
+    backend::Backend,
+    deserialize::{
+        self,
+        FromSql,
+        FromSqlRow,
+        Queryable
+    },
+    expression::{
+        AsExpression,
+        bound::Bound
+    },
+    serialize::{
+        self,
+        Output,
+        ToSql
+    },
+    sql_types::{
+        BigInt,
+        Integer,
+        Nullable
+    },
+    row::Row
+};
+
+use rocket::{
+    http::RawStr,
+    request::FromParam
+};
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{
+        Hash,
+        Hasher
+    },
+    io::Write,
+    marker::PhantomData
+};
+
+/// Identifier of a `T`, stored as a `R`.
+pub struct Id<T, R = i64> {
+    raw: R,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, R> Id<T, R> {
+    pub fn new(raw: R) -> Self {
+        Self {
+            raw,
+            _marker: PhantomData
+        }
+    }
+
+    pub fn raw(&self) -> &R {
+        &self.raw
+    }
+
+    pub fn into_raw(self) -> R {
+        self.raw
+    }
+}
+
+// Implemented by hand: derived implementations would require `T` to
+// implement the traits as well.
+
+impl<T, R: Clone> Clone for Id<T, R> {
+    fn clone(&self) -> Self {
+        Self::new(self.raw.clone())
+    }
+}
+
+impl<T, R: Copy> Copy for Id<T, R> {}
+
+impl<T, R: fmt::Debug> fmt::Debug for Id<T, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("Id").field(&self.raw).finish()
+    }
+}
+
+impl<T, R: fmt::Display> fmt::Display for Id<T, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.raw.fmt(fmt)
+    }
+}
+
+impl<T, R: PartialEq> PartialEq for Id<T, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T, R: Eq> Eq for Id<T, R> {}
+
+impl<T, R: PartialOrd> PartialOrd for Id<T, R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.raw.partial_cmp(&other.raw)
+    }
+}
+
+impl<T, R: Ord> Ord for Id<T, R> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.raw.cmp(&other.raw)
+    }
+}
+
+impl<T, R: Hash> Hash for Id<T, R> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.raw.hash(state)
+    }
+}
+
+impl<T, R> From<R> for Id<T, R> {
+    fn from(raw: R) -> Self {
+        Self::new(raw)
+    }
+}
+
+/// Implements the Diesel traits binding and loading `Id<T, $raw>` as
+/// `$sql_type`.
+macro_rules! impl_diesel_id {
+    ($raw:ty, $sql_type:ty) => {
+        impl<T> AsExpression<$sql_type> for Id<T, $raw> {
+            type Expression = Bound<$sql_type, Self>;
+
+            fn as_expression(self) -> Self::Expression {
+                Bound::new(self)
+            }
+        }
+
+        impl<'a, T> AsExpression<$sql_type> for &'a Id<T, $raw> {
+            type Expression = Bound<$sql_type, Self>;
+
+            fn as_expression(self) -> Self::Expression {
+                Bound::new(self)
+            }
+        }
+
+        impl<T> AsExpression<Nullable<$sql_type>> for Id<T, $raw> {
+            type Expression = Bound<Nullable<$sql_type>, Self>;
+
+            fn as_expression(self) -> Self::Expression {
+                Bound::new(self)
+            }
+        }
+
+        impl<'a, T> AsExpression<Nullable<$sql_type>> for &'a Id<T, $raw> {
+            type Expression = Bound<Nullable<$sql_type>, Self>;
+
+            fn as_expression(self) -> Self::Expression {
+                Bound::new(self)
+            }
+        }
+
+        // `ToSql<Nullable<_>, _>` comes from Diesel's blanket implementation
+        impl<T, DB> ToSql<$sql_type, DB> for Id<T, $raw>
+            where DB: Backend,
+                  $raw: ToSql<$sql_type, DB>
+        {
+            fn to_sql<W: Write>(&self, out: &mut Output<'_, W, DB>) -> serialize::Result {
+                <$raw as ToSql<$sql_type, DB>>::to_sql(&self.raw, out)
+            }
+        }
+
+        impl<T, DB> FromSql<$sql_type, DB> for Id<T, $raw>
+            where DB: Backend,
+                  $raw: FromSql<$sql_type, DB>
+        {
+            fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+                <$raw as FromSql<$sql_type, DB>>::from_sql(bytes).map(Id::new)
+            }
+        }
+
+        impl<T, DB> FromSqlRow<$sql_type, DB> for Id<T, $raw>
+            where DB: Backend,
+                  Self: FromSql<$sql_type, DB>
+        {
+            fn build_from_row<R: Row<DB>>(row: &mut R) -> deserialize::Result<Self> {
+                <Self as FromSql<$sql_type, DB>>::from_sql(row.take())
+            }
+        }
+
+        impl<T, DB> Queryable<$sql_type, DB> for Id<T, $raw>
+            where DB: Backend,
+                  Self: FromSql<$sql_type, DB>
+        {
+            type Row = Self;
+
+            fn build(row: Self::Row) -> Self {
+                row
+            }
+        }
+    };
+}
+
+/// Implements Rocket's `FromParam` for `Id<T, $raw>` using `$parse`.
+macro_rules! impl_from_param_id {
+    ($raw:ty, $parse:expr) => {
+        impl<'a, T> FromParam<'a> for Id<T, $raw> {
+            type Error = &'a RawStr;
+
+            fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+                ($parse)(param.as_str()).map(Id::new).map_err(|_| param)
+            }
+        }
+    };
+}
+
+impl_diesel_id!(i32, Integer);
+impl_diesel_id!(i64, BigInt);
+impl_from_param_id!(i32, str::parse::<i32>);
+impl_from_param_id!(i64, str::parse::<i64>);
+
+#[cfg(feature = "uuid")]
+mod uuid_ids {
+    use super::*;
+    use diesel::sql_types::Uuid as UuidSql;
+    use uuid_crate::Uuid;
+
+    impl_diesel_id!(Uuid, UuidSql);
+    impl_from_param_id!(Uuid, Uuid::parse_str);
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{http::RawStr, request::FromParam};
+    use super::Id;
+
+    struct User;
+
+    #[test]
+    fn traits_do_not_require_bounds_on_t() {
+        let id: Id<User, i32> = Id::new(7);
+        let copy = id;
+
+        assert_eq!(id, copy);
+        assert_eq!(format!("{:?}", id), "Id(7)");
+        assert_eq!(id.to_string(), "7");
+        assert_eq!(id.into_raw(), 7);
+    }
+
+    #[test]
+    fn from_param() {
+        let id = Id::<User, i64>::from_param(RawStr::from_str("42")).unwrap();
+        assert_eq!(*id.raw(), 42);
+
+        assert!(Id::<User, i32>::from_param(RawStr::from_str("abc")).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Registry of data integrity checks.
+//!
+//! Applications register named checks of their data (orphan rows, broken
+//! invariants...) with `Database::register_check`:
+//!
+//! ```rust,ignore
+//! database.register_check("orphan_tokens", Severity::Critical, |database| {
+//!     let orphans = database.interact(/* count tokens without user */)?;
+//!     match orphans {
+//!         0 => Ok(()),
+//!         count => Err(Error::new(ErrorKind::IntegrityCheck, format!("{} orphan token(s)", count)))
+//!     }
+//! });
+//! ```
+//!
+//! Checks run once the connection is established, and again with
+//! `Database::run_checks`. A failing critical check fails the
+//! initialization with `IntegrityCheck`, unless
+//! `integrity_checks.fail_on_critical` is `false`. The results are served
+//! by the `/health` route (`rocket_diesel::health::routes()`).
+
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        RwLock
+    },
+    time::SystemTime
+};
+
+use crate::redaction::log_redacted;
+use crate::Database;
+use crate::error;
+use crate::Result;
+
+/// Consequence of the failure of a check.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// Logged and reported as degraded.
+    Warning,
+    /// Fails the initialization and reports the database as unhealthy.
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Warning   => "warning",
+            Severity::Critical  => "critical",
+        }
+    }
+}
+
+/// Outcome of the last run of a check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckResult {
+    pub name: String,
+    pub severity: Severity,
+    pub checked_at: SystemTime,
+    /// Why the check failed, `None` when it passed.
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+type CheckFn = dyn Fn(&Database) -> Result<()> + Send + Sync;
+
+struct Check {
+    _name: String,
+    _severity: Severity,
+    _check: Arc<CheckFn>,
+}
+
+/// Checks registered on a database, and their last results. Clones share
+/// them.
+#[derive(Clone, Default)]
+pub(crate) struct Checks {
+    _checks: Arc<RwLock<Vec<Check>>>,
+    _results: Arc<RwLock<Vec<CheckResult>>>,
+}
+
+impl Checks {
+    /// Registers `check` as `name`, replacing the check already registered
+    /// with that name.
+    pub fn register<F>(&self, name: &str, severity: Severity, check: F)
+        where F: Fn(&Database) -> Result<()> + Send + Sync + 'static
+    {
+        if let Ok(mut checks) = self._checks.write() {
+            checks.retain(|registered| registered._name != name);
+            checks.push(Check {
+                _name: name.to_owned(),
+                _severity: severity,
+                _check: Arc::new(check)
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self._checks.read().map(|checks| checks.is_empty()).unwrap_or(true)
+    }
+
+    /// Runs every check against `database`, recording and returning their
+    /// results.
+    pub fn run(&self, database: &Database) -> Vec<CheckResult> {
+        // Runs the checks without holding the lock, so that they may
+        // register checks themselves
+        let checks = match self._checks.read() {
+            Ok(checks) => checks.iter()
+                .map(|check| (check._name.clone(), check._severity, check._check.clone()))
+                .collect::<Vec<_>>(),
+            Err(_err) => Vec::new()
+        };
+
+        let results = checks.into_iter().map(|(name, severity, check)| {
+            let error = check(database).err().map(|err| err.to_string());
+
+            match error {
+                None => log_redacted!(log::Level::Debug, "Integrity check `{}` passed.", name),
+                Some(ref error) => match severity {
+                    Severity::Warning => log_redacted!(log::Level::Warn, "Integrity check `{}` failed: {}", name, error),
+                    Severity::Critical => log_redacted!(log::Level::Error, "Critical integrity check `{}` failed: {}", name, error)
+                }
+            }
+
+            CheckResult {
+                name,
+                severity,
+                checked_at: database.system_now(),
+                error
+            }
+        }).collect::<Vec<_>>();
+
+        if let Ok(mut last) = self._results.write() {
+            *last = results.clone();
+        }
+        results
+    }
+
+    /// Results of the last run.
+    pub fn results(&self) -> Vec<CheckResult> {
+        self._results.read().map(|results| results.clone()).unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Checks {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self._checks.read()
+            .map(|checks| checks.iter().map(|check| check._name.clone()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        fmt.debug_tuple("Checks").field(&names).finish()
+    }
+}
+
+/// Error failing the initialization when critical checks failed.
+pub(crate) fn critical_failure(results: &[CheckResult]) -> Option<error::Error> {
+    let failed = results.iter()
+        .filter(|result| result.severity == Severity::Critical && !result.passed())
+        .map(|result| format!("`{}`", result.name))
+        .collect::<Vec<_>>();
+
+    match failed.is_empty() {
+        true => None,
+        false => Some(error::Error::new(
+            error::ErrorKind::IntegrityCheck,
+            format!("critical integrity check(s) failed: {}", failed.join(", "))
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use crate::error::{Error, ErrorKind};
+    use crate::Database;
+    use super::{critical_failure, Checks, Severity};
+
+    #[test]
+    fn runs_and_records_checks() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let checks = Checks::default();
+
+        checks.register("tables", Severity::Critical, |database| database.execute_sql("SELECT 1").map(|_| ()));
+        checks.register("orphans", Severity::Warning, |_database| {
+            Err(Error::new(ErrorKind::IntegrityCheck, "2 orphan token(s)"))
+        });
+        assert!(checks.results().is_empty());
+
+        let results = checks.run(&database);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].passed());
+        assert!(results[1].error.as_ref().unwrap().starts_with("2 orphan token(s)"));
+        assert_eq!(checks.results(), results);
+        assert!(critical_failure(&results).is_none());
+
+        checks.register("orphans", Severity::Critical, |_database| {
+            Err(Error::new(ErrorKind::IntegrityCheck, "2 orphan token(s)"))
+        });
+        let err = critical_failure(&checks.run(&database)).unwrap();
+        assert_eq!(err.kind(), ErrorKind::IntegrityCheck);
+        assert!(err.to_string().contains("`orphans`"));
+    }
+}
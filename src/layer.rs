@@ -0,0 +1,425 @@
+//! Middleware composed around the execution of interactions.
+//!
+//! Every call to `Database::interact` goes through a chain of layers before
+//! reaching the core execution (initialization, locking and running the
+//! closure), like tower layers wrap a service. The circuit breaker, the
+//! statistics, the route quotas, the slow interaction log and tracing spans
+//! are built-in layers; applications add their own with `Database::layer`.
+//!
+//! ```rust,ignore
+//! struct Logging;
+//!
+//! impl InteractLayer for Logging {
+//!     fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+//!         let result = next.run(context);
+//!         log::debug!("interaction at {} took {:?}", context.caller(), context.duration());
+//!         result
+//!     }
+//! }
+//!
+//! let database = Database::new().layer(Logging);
+//! ```
+//!
+//! The closures given to `interact` can only run once: a layer calls `next`
+//! at most once, and may return without calling it to short-circuit the
+//! interaction.
+
+use std::{
+    fmt,
+    panic::Location,
+    sync::{
+        Arc,
+        Mutex
+    },
+    thread,
+    time::Duration
+};
+
+use crate::redaction::log_redacted;
+use crate::Backend;
+use crate::circuit::{
+    self,
+    CircuitBreaker,
+    CircuitBreakerSettings
+};
+use crate::clock::SharedClock;
+use crate::error;
+use crate::profile::{
+    ProfileLayer,
+    Profiler
+};
+use crate::quota::{
+    QuotaLayer,
+    Quotas
+};
+use crate::retry;
+use crate::Settings;
+use crate::stats::{
+    Operation,
+    Stats
+};
+use crate::summary;
+use crate::tagging::{
+    self,
+    QueryTag
+};
+use crate::Result;
+
+/// What a layer knows about the interaction it wraps.
+///
+/// `backend` and `duration` are filled by the core execution, once the
+/// closure ran.
+#[derive(Debug)]
+pub struct InteractContext {
+    _caller: &'static Location<'static>,
+    _tag: Option<QueryTag>,
+    _path: Option<String>,
+    _route_slot: Option<usize>,
+    _backend: Option<Backend>,
+    _duration: Option<Duration>,
+    _settings: Option<Settings>,
+    _clock: SharedClock,
+    _operation: Operation,
+}
+
+impl InteractContext {
+    pub(crate) fn new(
+        caller: &'static Location<'static>,
+        settings: Option<Settings>,
+        clock: SharedClock
+    ) -> Self {
+        Self {
+            _caller: caller,
+            _tag: tagging::current(),
+            _path: tagging::current_path(),
+            _route_slot: tagging::current_route_slot(),
+            _backend: None,
+            _duration: None,
+            _settings: settings,
+            _clock: clock,
+            _operation: Operation::Unknown
+        }
+    }
+
+    /// Location of the call to `interact`.
+    pub fn caller(&self) -> &'static Location<'static> {
+        self._caller
+    }
+
+    /// Tag of the request executing the interaction, if any.
+    pub fn tag(&self) -> Option<&QueryTag> {
+        self._tag.as_ref()
+    }
+
+    /// Path of the request executing the interaction, if any.
+    pub(crate) fn request_path(&self) -> Option<&str> {
+        self._path.as_ref().map(String::as_str)
+    }
+
+    /// Slot of the route of the tag in the per-route statistics, if any.
+    pub(crate) fn route_slot(&self) -> Option<usize> {
+        self._route_slot
+    }
+
+    /// Backend which executed the closure.
+    pub fn backend(&self) -> Option<Backend> {
+        self._backend
+    }
+
+    /// Execution time of the closure, lock wait excluded.
+    pub fn duration(&self) -> Option<Duration> {
+        self._duration
+    }
+
+    /// Operation the interaction was labeled with, `Unknown` by default.
+    pub fn operation(&self) -> Operation {
+        self._operation
+    }
+
+    pub(crate) fn set_operation(&mut self, operation: Operation) {
+        self._operation = operation;
+    }
+
+    /// Time left to the retries of the current request (`retry_budget`),
+    /// `None` when they are not limited. Retrying layers do not retry once
+    /// it is zero.
+    pub fn retry_budget(&self) -> Option<Duration> {
+        retry::remaining(self.settings().and_then(Settings::retry_budget))
+    }
+
+    /// Charges `elapsed`, the time a retry took, to the retry budget of the
+    /// current request.
+    pub fn spend_retry_budget(&self, elapsed: Duration) {
+        retry::spend(elapsed);
+    }
+
+    pub(crate) fn settings(&self) -> Option<&Settings> {
+        self._settings.as_ref()
+    }
+
+    pub(crate) fn clock(&self) -> &SharedClock {
+        &self._clock
+    }
+
+    pub(crate) fn executed(&mut self, backend: Backend, duration: Duration) {
+        self._backend = Some(backend);
+        self._duration = Some(duration);
+    }
+}
+
+/// Middleware wrapping the execution of interactions.
+pub trait InteractLayer: Send + Sync + 'static {
+    /// Handles the interaction described by `context`, calling `next` to
+    /// hand it to the following layer.
+    fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()>;
+}
+
+/// Remainder of the chain after the current layer.
+pub struct Next<'a> {
+    _layers: &'a [Arc<dyn InteractLayer>],
+    _core: &'a mut dyn FnMut(&mut InteractContext) -> Result<()>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        layers: &'a [Arc<dyn InteractLayer>],
+        core: &'a mut dyn FnMut(&mut InteractContext) -> Result<()>
+    ) -> Self {
+        Self {
+            _layers: layers,
+            _core: core
+        }
+    }
+
+    /// Runs the following layers, then the core execution.
+    pub fn run(self, context: &mut InteractContext) -> Result<()> {
+        match self._layers.split_first() {
+            Some((layer, layers)) => layer.call(context, Next::new(layers, self._core)),
+            None => (self._core)(context)
+        }
+    }
+}
+
+/// Ordered chain of layers: the first one is the outermost.
+#[derive(Clone)]
+pub(crate) struct Layers {
+    _layers: Vec<Arc<dyn InteractLayer>>,
+    _user_layers: usize,
+}
+
+impl Layers {
+    /// Built-in chain: tracing (with the `tracing` feature), statistics,
+    /// profiling, route quotas, circuit breaker and slow interaction log. Layers added by the
+    /// application go between the circuit breaker and the slow interaction
+    /// log.
+    pub fn new(
+        circuit: Arc<Mutex<CircuitBreaker>>,
+        stats: Arc<Stats>,
+        profiler: Arc<Profiler>,
+        quotas: Arc<Quotas>
+    ) -> Self {
+        let mut layers: Vec<Arc<dyn InteractLayer>> = Vec::new();
+
+        #[cfg(feature = "tracing")]
+        layers.push(Arc::new(crate::telemetry::TracingLayer));
+        layers.push(Arc::new(StatsLayer { _stats: stats }));
+        layers.push(Arc::new(ProfileLayer::new(profiler)));
+        layers.push(Arc::new(QuotaLayer::new(quotas)));
+        layers.push(Arc::new(CircuitBreakerLayer { _circuit: circuit }));
+        layers.push(Arc::new(SlowInteractionLayer));
+
+        Self {
+            _layers: layers,
+            _user_layers: 0
+        }
+    }
+
+    pub fn push<L: InteractLayer>(&mut self, layer: L) {
+        // Before the slow interaction log
+        let index = self._layers.len() - 1;
+
+        self._layers.insert(index, Arc::new(layer));
+        self._user_layers += 1;
+    }
+
+    /// Adds to this chain the layers the application added to `other`.
+    pub fn inherit(&mut self, other: &Layers) {
+        let first = other._layers.len() - 1 - other._user_layers;
+
+        for layer in &other._layers[first..first + other._user_layers] {
+            let index = self._layers.len() - 1;
+
+            self._layers.insert(index, layer.clone());
+            self._user_layers += 1;
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Arc<dyn InteractLayer>] {
+        &self._layers
+    }
+}
+
+impl fmt::Debug for Layers {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Layers")
+            .field("user_layers", &self._user_layers)
+            .finish()
+    }
+}
+
+/// Fails fast while the circuit breaker is open.
+struct CircuitBreakerLayer {
+    _circuit: Arc<Mutex<CircuitBreaker>>,
+}
+
+impl InteractLayer for CircuitBreakerLayer {
+    fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+        let circuit_settings = match context.settings().and_then(Settings::circuit_breaker) {
+            Some(circuit_settings) => circuit_settings,
+            None => return next.run(context)
+        };
+
+        self._circuit.lock()
+            .map_err(|_err| error::Error::new(
+                error::ErrorKind::Other, "circuit breaker got poisoned"
+            ))
+            .and_then(|mut circuit| circuit.acquire(&circuit_settings, context.clock().now()))?;
+
+        let unwinding = Unwinding {
+            _circuit: &self._circuit,
+            _settings: circuit_settings,
+            _clock: context.clock().clone()
+        };
+        let result = next.run(context);
+        let success = result.as_ref().err().map_or(true, |err| !circuit::is_failure(err));
+
+        drop(unwinding);
+        if let Ok(mut circuit) = self._circuit.lock() {
+            circuit.record(&circuit_settings, success, context.clock().now());
+        }
+
+        result
+    }
+}
+
+/// Records a failure when the interaction panics, so that a panicking probe
+/// does not leave the circuit breaker half-open.
+struct Unwinding<'a> {
+    _circuit: &'a Mutex<CircuitBreaker>,
+    _settings: CircuitBreakerSettings,
+    _clock: SharedClock,
+}
+
+impl<'a> Drop for Unwinding<'a> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return ;
+        }
+        if let Ok(mut circuit) = self._circuit.lock() {
+            circuit.record(&self._settings, false, self._clock.now());
+        }
+    }
+}
+
+/// Updates the statistics of the database and the summary of the request.
+struct StatsLayer {
+    _stats: Arc<Stats>,
+}
+
+impl InteractLayer for StatsLayer {
+    fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+        let result = next.run(context);
+
+        match context.duration() {
+            Some(duration) => {
+                self._stats.record_interaction(duration, result.is_err());
+                summary::record(duration);
+                self._stats.record_operation(
+                    context.operation(), context.route_slot(), duration, result.is_err()
+                );
+            },
+            None => self._stats.record_error()
+        }
+
+        result
+    }
+}
+
+/// Logs interactions slower than the configured threshold.
+struct SlowInteractionLayer;
+
+impl InteractLayer for SlowInteractionLayer {
+    fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+        let result = next.run(context);
+        let threshold = context.settings().and_then(Settings::slow_query_threshold);
+
+        if let (Some(threshold), Some(duration)) = (threshold, context.duration()) {
+            if duration >= threshold {
+                log_redacted!(log::Level::Warn, "Slow database interaction ({:?}) at {}", duration, context.caller());
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::Location,
+        sync::{Arc, Mutex}
+    };
+    use crate::clock::SharedClock;
+    use crate::error;
+    use crate::Result;
+    use super::{InteractContext, InteractLayer, Next};
+
+    struct Record(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl InteractLayer for Record {
+        fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+            self.1.lock().unwrap().push(self.0);
+            next.run(context)
+        }
+    }
+
+    struct ShortCircuit;
+
+    impl InteractLayer for ShortCircuit {
+        fn call(&self, _context: &mut InteractContext, _next: Next<'_>) -> Result<()> {
+            Err(error::Error::new(error::ErrorKind::Other, "short-circuited"))
+        }
+    }
+
+    #[test]
+    fn runs_layers_in_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Arc<dyn InteractLayer>> = vec![
+            Arc::new(Record("outer", calls.clone())),
+            Arc::new(Record("inner", calls.clone())),
+        ];
+        let mut context = InteractContext::new(Location::caller(), None, SharedClock::default());
+        let mut core = |_context: &mut InteractContext| {
+            calls.lock().unwrap().push("core");
+            Ok(())
+        };
+
+        Next::new(&layers, &mut core).run(&mut context).unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), vec!["outer", "inner", "core"]);
+    }
+
+    #[test]
+    fn short_circuits() {
+        let layers: Vec<Arc<dyn InteractLayer>> = vec![Arc::new(ShortCircuit)];
+        let mut context = InteractContext::new(Location::caller(), None, SharedClock::default());
+        let mut executed = false;
+        let mut core = |_context: &mut InteractContext| {
+            executed = true;
+            Ok(())
+        };
+
+        assert!(Next::new(&layers, &mut core).run(&mut context).is_err());
+        assert!(!executed);
+    }
+}
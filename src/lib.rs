@@ -1,17 +1,137 @@
-#![feature(arbitrary_self_types, decl_macro, proc_macro_hygiene)]
+#![cfg_attr(all(test, feature = "nightly"), feature(test))]
 #![warn(rust_2018_idioms)]
 
+#[macro_use] extern crate diesel;
+
+pub mod admission;
+mod any_connection;
+mod autotune;
+mod backend;
+mod backoff;
+mod bootstrap;
+mod circuit;
+pub mod clock;
+mod config_schema;
 mod configuration;
 mod connection;
+pub mod contrib;
+#[cfg(feature = "crypto")]
+pub mod crypto;
 mod database;
+pub mod export;
+mod environment;
 pub mod error;
+mod fairness;
+mod flight;
+pub mod health;
+pub mod helpers;
+pub mod idempotency;
+#[cfg(feature = "ids")]
+pub mod ids;
+pub mod integrity;
+pub mod layer;
+mod limits;
 mod locked_connection;
+#[macro_use]
+mod macros;
+pub mod metrics;
+mod migrations;
+pub mod outbox;
+mod owned_connection;
+#[cfg(any(feature = "r2d2", feature = "deadpool"))]
+mod pool;
+pub mod prepared;
+pub mod profile;
+mod options;
+mod outcome;
+mod quota;
+mod raw_query;
+mod redaction;
+pub mod resolver;
 mod result;
+mod retry;
+pub mod schedule;
+pub mod schema;
+pub mod service;
+#[cfg(feature = "sessions")]
+pub mod session;
 mod settings;
+#[cfg(any(test, feature = "testing"))]
+pub mod simulation;
+mod sqlite_file;
+pub mod sharding;
+mod stats;
+mod status;
+pub mod storage;
+mod strictness;
+pub mod stream;
+pub mod summary;
+pub mod support;
+pub mod tagging;
+mod telemetry;
+mod tx;
+mod version;
+pub mod versions;
+pub mod views;
+pub mod warmup;
 
 pub(crate) use configuration::DieselConfiguration as Configuration;
 pub(crate) use settings::Settings;
 pub(crate) use connection::Connection;
 pub(crate) use locked_connection::LockedConnection;
+pub use any_connection::AnyConnection;
+pub use autotune::Recommendation;
+pub use backend::Backend;
+pub use backend::BackendConnection;
+pub use backend::Backend as ConnectionKind;
+pub use circuit::CircuitState;
+pub use config_schema::{
+    config_schema,
+    ConfigSchema,
+    Setting,
+    SettingType
+};
 pub use database::Database as Database;
-pub use result::Result;
\ No newline at end of file
+pub use limits::{
+    HeapSize,
+    ResultLimit
+};
+#[cfg(any(feature = "r2d2", feature = "deadpool"))]
+pub use pool::PoolState;
+pub use migrations::{
+    DieselMigrations,
+    MigrationEngine,
+    MigrationPolicy,
+    MigrationTarget,
+    PlannedMigration
+};
+pub use options::InteractOptions;
+pub use outcome::InteractOutcome;
+pub use owned_connection::OwnedConn;
+pub use quota::QuotaUsage;
+pub use raw_query::{
+    BindValue,
+    RawQuery
+};
+pub use result::Result;
+pub use settings::ActiveCredentials;
+pub use stats::HistogramSnapshot;
+pub use stats::Operation;
+pub use stats::OperationStats;
+pub use stats::StatsSnapshot;
+pub use stats::MAX_ROUTES;
+pub use stats::OVERFLOW_ROUTE;
+pub use status::ConfigurationStatus;
+pub use strictness::Strictness;
+pub use tx::Tx;
+pub use version::ServerVersion;
+
+// Re-exported so that applications use the same versions as rocket-diesel
+// without repeating its diesel feature selection.
+pub use diesel;
+#[cfg(feature = "chrono")]
+pub use chrono_crate as chrono;
+#[cfg(feature = "serde_json")]
+pub use serde_json_crate as serde_json;
+#[cfg(feature = "uuid")]
+pub use uuid_crate as uuid;
\ No newline at end of file
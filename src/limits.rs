@@ -0,0 +1,256 @@
+use std::mem;
+
+use crate::error;
+use crate::Result;
+
+/// Data a loaded value owns on the heap, counted by `ResultLimit::check_rows`
+/// on top of the inline size of the rows.
+///
+/// Rows implement it with `heap_size!`, listing their fields which own data
+/// on the heap (fields of other types own none):
+///
+/// ```rust,ignore
+/// heap_size!(Order { customer, notes });
+/// ```
+pub trait HeapSize
+{
+    /// Bytes owned on the heap, none by default.
+    fn heap_size(&self) -> usize
+    {
+        0
+    }
+}
+
+macro_rules! impl_heap_size {
+    ($($value:ty),*) => {
+        $(impl HeapSize for $value {})*
+    };
+}
+
+impl_heap_size!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64, bool, char, ());
+
+impl HeapSize for String
+{
+    fn heap_size(&self) -> usize
+    {
+        self.capacity()
+    }
+}
+
+impl<T> HeapSize for Vec<T>
+    where T: HeapSize
+{
+    fn heap_size(&self) -> usize
+    {
+        self.capacity() * mem::size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<T> HeapSize for Box<T>
+    where T: HeapSize
+{
+    fn heap_size(&self) -> usize
+    {
+        mem::size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl<T> HeapSize for Option<T>
+    where T: HeapSize
+{
+    fn heap_size(&self) -> usize
+    {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+macro_rules! impl_heap_size_tuple {
+    ($(($($field:tt $value:ident),+)),*) => {
+        $(
+            impl<$($value),+> HeapSize for ($($value,)+)
+                where $($value: HeapSize),+
+            {
+                fn heap_size(&self) -> usize
+                {
+                    0 $(+ self.$field.heap_size())+
+                }
+            }
+        )*
+    };
+}
+
+impl_heap_size_tuple!(
+    (0 A, 1 B),
+    (0 A, 1 B, 2 C),
+    (0 A, 1 B, 2 C, 3 D),
+    (0 A, 1 B, 2 C, 3 D, 4 E),
+    (0 A, 1 B, 2 C, 3 D, 4 E, 5 F)
+);
+
+#[cfg(feature = "chrono")]
+impl_heap_size!(
+    chrono_crate::NaiveDate, chrono_crate::NaiveDateTime, chrono_crate::NaiveTime,
+    chrono_crate::DateTime<chrono_crate::Utc>
+);
+
+#[cfg(feature = "uuid")]
+impl_heap_size!(uuid_crate::Uuid);
+
+/// Estimated from the length of the serialized value.
+#[cfg(feature = "serde_json")]
+impl HeapSize for serde_json_crate::Value
+{
+    fn heap_size(&self) -> usize
+    {
+        self.to_string().len()
+    }
+}
+
+/// Implements `HeapSize` for a struct, owning the heap data of the listed
+/// fields:
+///
+/// ```rust,ignore
+/// heap_size!(Order { customer, notes });
+/// ```
+///
+/// The fields must implement `rocket_diesel::HeapSize`.
+#[macro_export]
+macro_rules! heap_size {
+    ($row:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::HeapSize for $row
+        {
+            fn heap_size(&self) -> usize
+            {
+                0 $(+ $crate::HeapSize::heap_size(&self.$field))*
+            }
+        }
+    };
+}
+
+/// Upper bounds on the size of a result loaded from the database.
+///
+/// Loading an unbounded result through the shared connection holds it for
+/// as long as the rows are transferred, and the whole result in memory.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResultLimit {
+    /// Maximum number of rows.
+    pub max_rows: Option<usize>,
+    /// Maximum (estimated) size in bytes.
+    pub max_bytes: Option<usize>,
+}
+
+impl ResultLimit {
+    pub fn new(max_rows: Option<usize>, max_bytes: Option<usize>) -> Self {
+        Self {
+            max_rows,
+            max_bytes
+        }
+    }
+
+    /// Returns whether no limit is set.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_rows.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Fails with `ErrorKind::ResultTooLarge` if `rows` rows weighing `bytes`
+    /// bytes exceed the limit.
+    pub fn check(&self, rows: usize, bytes: usize) -> Result<()> {
+        if let Some(max_rows) = self.max_rows {
+            if rows > max_rows {
+                return Err(error::Error::new(
+                    error::ErrorKind::ResultTooLarge,
+                    format!("result has {} rows, more than the {} allowed", rows, max_rows)
+                ));
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if bytes > max_bytes {
+                return Err(error::Error::new(
+                    error::ErrorKind::ResultTooLarge,
+                    format!("result weighs {} bytes, more than the {} allowed", bytes, max_bytes)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks loaded `rows` against the limit.
+    ///
+    /// The size of the rows is their inline size plus the data they own on
+    /// the heap, as reported by `HeapSize`.
+    pub fn check_rows<T>(&self, rows: &[T]) -> Result<()>
+        where T: HeapSize
+    {
+        let bytes = match self.max_bytes {
+            Some(_) => mem::size_of_val(rows) + rows.iter().map(HeapSize::heap_size).sum::<usize>(),
+            None => 0
+        };
+
+        self.check(rows.len(), bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorKind;
+    use super::{HeapSize, ResultLimit};
+
+    struct Row {
+        id: i32,
+        name: String,
+        tags: Vec<String>,
+    }
+
+    crate::heap_size!(Row { name, tags });
+
+    #[test]
+    fn unlimited() {
+        let limit = ResultLimit::default();
+
+        assert!(limit.is_unlimited());
+        assert!(limit.check(usize::max_value(), usize::max_value()).is_ok());
+    }
+
+    #[test]
+    fn max_rows() {
+        let limit = ResultLimit::new(Some(2), None);
+
+        assert!(limit.check_rows(&[1, 2]).is_ok());
+        assert_eq!(
+            limit.check_rows(&[1, 2, 3]).unwrap_err().kind(),
+            ErrorKind::ResultTooLarge
+        );
+    }
+
+    #[test]
+    fn max_bytes() {
+        let limit = ResultLimit::new(None, Some(8));
+
+        assert!(limit.check_rows(&[0u32, 1u32]).is_ok());
+        assert_eq!(
+            limit.check_rows(&[0u32, 1u32, 2u32]).unwrap_err().kind(),
+            ErrorKind::ResultTooLarge
+        );
+    }
+
+    #[test]
+    fn counts_heap_data() {
+        let row = Row {
+            id: 1,
+            name: String::with_capacity(100),
+            tags: vec![String::with_capacity(10)]
+        };
+        let rows = vec![row];
+        let inline = std::mem::size_of::<Row>();
+        let tags = std::mem::size_of::<String>() + 10;
+
+        assert_eq!(rows[0].id, 1);
+        assert_eq!(rows[0].heap_size(), 100 + tags);
+        assert!(ResultLimit::new(None, Some(inline + 100 + tags)).check_rows(&rows).is_ok());
+        assert_eq!(
+            ResultLimit::new(None, Some(inline + 100)).check_rows(&rows).unwrap_err().kind(),
+            ErrorKind::ResultTooLarge
+        );
+    }
+}
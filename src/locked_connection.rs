@@ -2,59 +2,67 @@
 
 use diesel;
 
+use crate::Backend;
+use crate::fairness::Turn;
+
 use std::{
     any::Any,
-    mem::ManuallyDrop,
+    ptr::NonNull,
     sync::{
         MutexGuard,
     }
 };
 
-/// Makes use of ManuallyDrop to avoid dropping the pointers inside the boxes as
-/// they only are copies of downcasted Any inside the Database struct.
+/// Connection a `LockedConnection` points to.
+///
+/// The connection is owned by what the `LockedConnection` holds (the boxed
+/// connection in the locked mutex, or the checkout of the pool), whose
+/// memory stays in place while it is held: the pointers are only
+/// dereferenced through `LockedConnection::conn_mut`, for as long as the
+/// lock is borrowed.
 pub(crate) enum Connection {
     // Default status of a locked connection
     Unknown,
 
     // MySql Connection
-    Mysql(ManuallyDrop<Box<diesel::MysqlConnection>>),
+    Mysql(NonNull<diesel::MysqlConnection>),
 
     // PgSql Connection
-    Pg(ManuallyDrop<Box<diesel::PgConnection>>),
+    Pg(NonNull<diesel::PgConnection>),
 
     // Sqlite Connection
-    Sqlite(ManuallyDrop<Box<diesel::SqliteConnection>>),
+    Sqlite(NonNull<diesel::SqliteConnection>),
+}
+
+/// Connection of a `LockedConnection`, borrowed while it is locked.
+pub(crate) enum ConnectionMut<'a> {
+    Unknown,
+    Mysql(&'a mut diesel::MysqlConnection),
+    Pg(&'a mut diesel::PgConnection),
+    Sqlite(&'a mut diesel::SqliteConnection),
 }
 
 impl Connection {
     pub fn mysql(mysql: &mut diesel::MysqlConnection) -> Self {
-        Self::Mysql(
-            ManuallyDrop::new(
-                unsafe {
-                    Box::from_raw(mysql)
-                }
-            )
-        )
+        Self::Mysql(NonNull::from(mysql))
     }
 
     pub fn pg(pg: &mut diesel::PgConnection) -> Self {
-        Self::Pg(
-            ManuallyDrop::new(
-                unsafe {
-                    Box::from_raw(pg)
-                }
-            )
-        )
+        Self::Pg(NonNull::from(pg))
     }
 
     pub fn sqlite(sqlite: &mut diesel::SqliteConnection) -> Self {
-        Self::Sqlite(
-            ManuallyDrop::new(
-                unsafe {
-                    Box::from_raw(sqlite)
-                }
-            )
-        )
+        Self::Sqlite(NonNull::from(sqlite))
+    }
+
+    /// Kind of the connection, `None` when unknown.
+    pub fn kind(&self) -> Option<Backend> {
+        match *self {
+            Self::Unknown => None,
+            Self::Mysql(_) => Some(Backend::Mysql),
+            Self::Pg(_) => Some(Backend::Pg),
+            Self::Sqlite(_) => Some(Backend::Sqlite),
+        }
     }
 
     #[inline]
@@ -92,10 +100,7 @@ impl Connection {
 
 impl PartialEq for Connection {
     fn eq(&self, other: &Self) -> bool {
-        (self.is_unknown() && other.is_unknown()) ||
-        (self.is_mysql() && other.is_mysql()) ||
-        (self.is_pg() && other.is_pg()) ||
-        (self.is_sqlite() && other.is_sqlite())
+        self.kind() == other.kind()
     }
 }
 
@@ -110,20 +115,32 @@ impl Default for Connection {
 impl std::fmt::Debug for Connection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
     {
-        write!(f, "{}", match self {
-            Self::Unknown => "Unkown",
-            Self::Mysql(_) => "MysqlConnection",
-            Self::Pg(_) => "PgConnection",
-            Self::Sqlite(_) => "SqliteConnection"
-        })
+        match self.kind() {
+            None => write!(f, "Connection(unknown)"),
+            Some(kind) => write!(f, "Connection({})", kind)
+        }
     }
 }
 
+/// What keeps the connection to ourselves while it is used.
+#[derive(Debug)]
+enum Hold<'lock> {
+    // The shared connection, locked
+    Shared(MutexGuard<'lock, Option<Box<dyn Any>>>),
+
+    // A connection checked out of the pool, given back when dropped
+    #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+    Pooled(Box<crate::pool::Checkout>),
+}
+
 /// 
 #[derive(Debug)]
 pub(crate) struct LockedConnection<'lock> {
-    guard: MutexGuard<'lock, Option<Box<dyn Any>>>,
-    connection: Connection
+    guard: Hold<'lock>,
+    connection: Connection,
+    // Dropped after the guard: the next caller in line finds the connection
+    // unlocked
+    turn: Option<Turn>
 }
 
 impl<'lock> LockedConnection<'lock> {
@@ -133,21 +150,39 @@ impl<'lock> LockedConnection<'lock> {
     ) -> Self
     {
         Self {
-            guard,
-            connection
+            guard: Hold::Shared(guard),
+            connection,
+            turn: None
+        }
+    }
+
+    /// Connection checked out of the pool.
+    #[cfg(any(feature = "r2d2", feature = "deadpool"))]
+    pub fn pooled(mut checkout: Box<crate::pool::Checkout>) -> Self {
+        let connection = checkout.connection();
+
+        Self {
+            guard: Hold::Pooled(checkout),
+            connection,
+            turn: None
         }
     }
 
+    /// Holds `turn` until the connection is released.
+    pub fn with_turn(mut self, turn: Option<Turn>) -> Self {
+        self.turn = turn;
+        self
+    }
+
     pub fn from_mysql_connection(
         guard: MutexGuard<'lock, Option<Box<dyn Any>>>,
         mysql_connection: &'lock mut diesel::mysql::MysqlConnection
     ) -> Self
     {
         Self {
-            guard: guard,
-            connection: Connection::Mysql(ManuallyDrop::new( unsafe {
-                Box::from_raw(mysql_connection as *mut diesel::mysql::MysqlConnection)
-            } ))
+            guard: Hold::Shared(guard),
+            connection: Connection::Mysql(NonNull::from(mysql_connection)),
+            turn: None
         }
     }
 
@@ -157,10 +192,9 @@ impl<'lock> LockedConnection<'lock> {
     ) -> Self
     {
         Self {
-            guard: guard,
-            connection: Connection::Sqlite(ManuallyDrop::new( unsafe {
-                Box::from_raw(sqlite_connection as *mut diesel::sqlite::SqliteConnection)
-            } ))
+            guard: Hold::Shared(guard),
+            connection: Connection::Sqlite(NonNull::from(sqlite_connection)),
+            turn: None
         }
     }
 
@@ -170,10 +204,9 @@ impl<'lock> LockedConnection<'lock> {
     ) -> Self
     {
         Self {
-            guard: guard,
-            connection: Connection::Pg(ManuallyDrop::new( unsafe {
-                Box::from_raw(pg_connection as *mut diesel::pg::PgConnection)
-            } ))
+            guard: Hold::Shared(guard),
+            connection: Connection::Pg(NonNull::from(pg_connection)),
+            turn: None
         }
     }
 
@@ -181,14 +214,23 @@ impl<'lock> LockedConnection<'lock> {
         &self.connection
     }
 
-    pub fn conn_mut(&mut self) -> &mut Connection {
-        &mut self.connection
+    pub fn conn_mut(&mut self) -> ConnectionMut<'_> {
+        // Safety: the connection is owned by `guard`, held (and not moved
+        // from) as long as `self`, and only borrowed through `&mut self`
+        unsafe {
+            match self.connection {
+                Connection::Unknown => ConnectionMut::Unknown,
+                Connection::Mysql(mut conn) => ConnectionMut::Mysql(conn.as_mut()),
+                Connection::Pg(mut conn) => ConnectionMut::Pg(conn.as_mut()),
+                Connection::Sqlite(mut conn) => ConnectionMut::Sqlite(conn.as_mut()),
+            }
+        }
     }
 }
 
 impl<'lock> Drop for LockedConnection<'lock> {
     fn drop(&mut self) {
         // Don't do anything: compiler will drop the MutexGuard and unlock the
-        // underlying Mutex.
+        // underlying Mutex, or give the checkout back to the pool.
     }
 }
@@ -0,0 +1,29 @@
+/// Executes the same body on whichever backend the database is connected
+/// to, expanding it into the three closures taken by `Database::interact`.
+///
+/// The body must compile for the three connection types, which is the case
+/// of most diesel queries since they are generic over the backend. The
+/// error type of the closures can be given when it can't be inferred:
+///
+/// ```rust,ignore
+/// let users = interact_all!(database, rocket_diesel::error::Error, |conn| {
+///     Ok(users.limit(5).load::<User>(conn)?)
+/// })?;
+/// ```
+#[macro_export]
+macro_rules! interact_all {
+    ($database:expr, |$conn:ident| $body:expr $(,)?) => {
+        $database.interact(
+            |$conn| $body,
+            |$conn| $body,
+            |$conn| $body
+        )
+    };
+    ($database:expr, $error:ty, |$conn:ident| $body:expr $(,)?) => {
+        $database.interact::<_, $error, _, _, _>(
+            |$conn| $body,
+            |$conn| $body,
+            |$conn| $body
+        )
+    };
+}
@@ -0,0 +1,254 @@
+//! `/metrics` route rendering the statistics of the database in the
+//! Prometheus text format.
+//!
+//! ```rust,ignore
+//! rocket::ignite()
+//!     .attach(Database::default())
+//!     .mount("/", rocket_diesel::metrics::routes())
+//! ```
+//!
+//! The route answers `404 Not Found` until the `metrics.token`
+//! configuration key is set, then requires it as a bearer token
+//! (`Authorization: Bearer <token>`), so that mounting it does not expose
+//! the statistics by accident.
+
+use rocket::{
+    Data,
+    handler,
+    http::{
+        ContentType,
+        Method,
+        Status
+    },
+    Outcome,
+    request::{
+        self,
+        FromRequest,
+        Request
+    },
+    response::content::Content,
+    Route,
+    State
+};
+
+use std::{
+    fmt,
+    fmt::Write as _,
+    time::{
+        Duration,
+        UNIX_EPOCH
+    }
+};
+
+use crate::Database;
+use crate::HistogramSnapshot;
+
+/// Token required to read the metrics.
+#[derive(Clone, Eq, PartialEq)]
+pub(crate) struct MetricsToken(String);
+
+impl MetricsToken
+{
+    pub fn new(token: String) -> Self
+    {
+        MetricsToken(token)
+    }
+
+    /// Compares `candidate` with the token in constant time.
+    pub fn matches(&self, candidate: &str) -> bool
+    {
+        let (expected, candidate) = (self.0.as_bytes(), candidate.as_bytes());
+
+        expected.len() == candidate.len() &&
+            expected.iter().zip(candidate).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+impl fmt::Debug for MetricsToken
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        // Never prints the token
+        fmt.write_str("MetricsToken(***)")
+    }
+}
+
+/// Guard of the requests bearing the `metrics.token`.
+pub struct Authorized;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Authorized
+{
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error>
+    {
+        let database = match request.guard::<State<'_, Database>>() {
+            Outcome::Success(database) => database,
+            _ => return Outcome::Failure((Status::InternalServerError, ()))
+        };
+        let token = match database.metrics_token() {
+            Some(token) => token,
+            None => return Outcome::Failure((Status::NotFound, ()))
+        };
+
+        let bearer = request.headers().get_one("Authorization")
+            .filter(|value| value.starts_with("Bearer "))
+            .map(|value| &value["Bearer ".len()..]);
+
+        match bearer {
+            Some(bearer) if token.matches(bearer) => Outcome::Success(Authorized),
+            _ => Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// Returns the managed database once the request is `Authorized`, or the
+/// status to fail with.
+///
+/// The routes of the crate are declared without Rocket's code generation,
+/// which requires nightly features.
+pub(crate) fn authorized_database<'r>(request: &'r Request<'_>) -> std::result::Result<&'r Database, Status>
+{
+    let database = match request.guard::<State<'_, Database>>() {
+        Outcome::Success(database) => database.inner(),
+        _ => return Err(Status::InternalServerError)
+    };
+
+    match request.guard::<Authorized>() {
+        Outcome::Success(Authorized) => Ok(database),
+        Outcome::Failure((status, ())) => Err(status),
+        Outcome::Forward(()) => Err(Status::NotFound)
+    }
+}
+
+/// Routes to mount to serve the metrics.
+pub fn routes() -> Vec<Route>
+{
+    vec![Route::new(Method::Get, "/metrics", metrics)]
+}
+
+fn metrics<'r>(request: &'r Request<'_>, _data: Data) -> handler::Outcome<'r>
+{
+    match authorized_database(request) {
+        Ok(database) => handler::Outcome::from(request, Content(
+            ContentType::with_params("text", "plain", ("version", "0.0.4")),
+            render(database)
+        )),
+        Err(status) => Outcome::Failure(status)
+    }
+}
+
+/// Renders the statistics of `database` in the Prometheus text format.
+pub fn render(database: &Database) -> String
+{
+    let mut output = String::new();
+    let stats = database.stats();
+
+    counter(&mut output, "interactions_total", "Interactions executed against the database.", stats.interactions);
+    counter(&mut output, "errors_total", "Interactions which failed.", stats.errors);
+    counter(
+        &mut output, "lock_contentions_total", "Interactions which waited for the connection.", stats.lock_contentions
+    );
+
+    header(&mut output, "operation_interactions_total", "counter", "Interactions per operation and route.");
+    for operation in database.operation_stats() {
+        let labels = match operation.route {
+            None => format!("operation=\"{}\"", operation.operation),
+            Some(ref route) => format!("operation=\"{}\",route=\"{}\"", operation.operation, escape(route))
+        };
+        let _ = writeln!(output, "rocket_diesel_operation_interactions_total{{{}}} {}", labels, operation.interactions);
+    }
+
+    header(&mut output, "route_quota_in_use", "gauge", "Interactions in flight per route quota.");
+    for quota in database.quota_usage() {
+        let _ = writeln!(output, "rocket_diesel_route_quota_in_use{{prefix=\"{}\"}} {}", escape(&quota.prefix), quota.in_use);
+    }
+
+    header(&mut output, "view_last_refresh_timestamp_seconds", "gauge", "End of the last successful refresh per view.");
+    for view in database.view_statuses() {
+        if let Some(last_refresh) = view.last_refresh {
+            let timestamp = last_refresh.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+            let _ = writeln!(
+                output, "rocket_diesel_view_last_refresh_timestamp_seconds{{view=\"{}\"}} {}", escape(&view.name), timestamp
+            );
+        }
+    }
+
+    histogram(
+        &mut output, "interaction_duration_seconds", "Execution time of the interactions.",
+        &database.latency_histogram(), |micros| Duration::from_micros(micros).as_secs_f64().to_string()
+    );
+    histogram(
+        &mut output, "result_rows", "Rows of the results checked against the result limit.",
+        &database.result_rows_histogram(), |rows| rows.to_string()
+    );
+
+    output
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str)
+{
+    let _ = writeln!(output, "# HELP rocket_diesel_{} {}", name, help);
+    let _ = writeln!(output, "# TYPE rocket_diesel_{} {}", name, kind);
+}
+
+fn counter(output: &mut String, name: &str, help: &str, value: u64)
+{
+    header(output, name, "counter", help);
+    let _ = writeln!(output, "rocket_diesel_{} {}", name, value);
+}
+
+fn histogram<F>(output: &mut String, name: &str, help: &str, histogram: &HistogramSnapshot, unit: F)
+    where F: Fn(u64) -> String
+{
+    header(output, name, "histogram", help);
+    for (bound, count) in &histogram.buckets {
+        let _ = writeln!(output, "rocket_diesel_{}_bucket{{le=\"{}\"}} {}", name, unit(*bound), count);
+    }
+    let _ = writeln!(output, "rocket_diesel_{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+    let _ = writeln!(output, "rocket_diesel_{}_sum {}", name, unit(histogram.sum));
+    let _ = writeln!(output, "rocket_diesel_{}_count {}", name, histogram.count);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String
+{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use crate::Database;
+    use super::{escape, render, MetricsToken};
+
+    #[test]
+    fn token_comparison() {
+        let token = MetricsToken::new("s3cret".to_owned());
+
+        assert!(token.matches("s3cret"));
+        assert!(!token.matches("s3cre"));
+        assert!(!token.matches("s3creT"));
+        assert_eq!(format!("{:?}", token), "MetricsToken(***)");
+    }
+
+    #[test]
+    fn escapes_labels() {
+        assert_eq!(escape("/a\"b\\c"), "/a\\\"b\\\\c");
+    }
+
+    #[test]
+    fn renders_prometheus_text() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        database.check_result_size(&[1, 2, 3]).unwrap();
+
+        let output = render(&database);
+        assert!(output.contains("# TYPE rocket_diesel_interactions_total counter\nrocket_diesel_interactions_total 0\n"));
+        assert!(output.contains("rocket_diesel_result_rows_bucket{le=\"10\"} 1\n"));
+        assert!(output.contains("rocket_diesel_interaction_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(output.contains("rocket_diesel_result_rows_count 1\n"));
+    }
+}
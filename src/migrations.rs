@@ -0,0 +1,466 @@
+//! Migrations applied when the database is initialized.
+//!
+//! The `migrations.policy` configuration key decides what happens to pending
+//! migrations; a `MigrationEngine` finds and applies them. The default
+//! engine, `DieselMigrations`, runs the diesel migrations of
+//! `migrations.directory`. Another engine (e.g. refinery, or plain SQL
+//! files) is plugged with `Database::with_migration_engine`, and still gets
+//! the policy, the execution on initialization while the connection is
+//! held, and the status of the support bundle.
+//!
+//! `Database::migrations_plan` lists the pending migrations with their SQL
+//! without applying them. With `migrations.dry_run`, the plan is logged on
+//! initialization instead of being run, e.g. to review a production deploy.
+
+use std::{
+    error::Error as _,
+    fmt,
+    fs,
+    path::{
+        Path,
+        PathBuf
+    },
+    str::FromStr,
+    sync::Arc
+};
+
+use diesel::connection::SimpleConnection;
+use diesel_migrations::MigrationConnection;
+
+use crate::redaction::log_redacted;
+use crate::error;
+use crate::Backend;
+use crate::Result;
+
+/// What to do with pending migrations when the database is initialized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MigrationPolicy {
+    /// Applies pending migrations.
+    Run,
+    /// Fails the initialization if migrations are pending.
+    Check,
+    /// Does not look at migrations at all.
+    Ignore,
+}
+
+impl MigrationPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MigrationPolicy::Run    => "run",
+            MigrationPolicy::Check  => "check",
+            MigrationPolicy::Ignore => "ignore",
+        }
+    }
+}
+
+impl Default for MigrationPolicy {
+    fn default() -> Self {
+        MigrationPolicy::Ignore
+    }
+}
+
+impl FromStr for MigrationPolicy {
+    type Err = error::Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "run"       => Ok(MigrationPolicy::Run),
+            "check"     => Ok(MigrationPolicy::Check),
+            "ignore"    => Ok(MigrationPolicy::Ignore),
+            _           => Err(error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("unknown migration policy `{}`", policy)
+            ))
+        }
+    }
+}
+
+/// Connection migrations are applied with.
+#[derive(Clone, Copy)]
+pub enum MigrationTarget<'a> {
+    Mysql(&'a diesel::MysqlConnection),
+    Pg(&'a diesel::PgConnection),
+    Sqlite(&'a diesel::SqliteConnection),
+}
+
+impl MigrationTarget<'_> {
+    pub fn backend(&self) -> Backend {
+        match self {
+            MigrationTarget::Mysql(_)   => Backend::Mysql,
+            MigrationTarget::Pg(_)      => Backend::Pg,
+            MigrationTarget::Sqlite(_)  => Backend::Sqlite,
+        }
+    }
+
+    /// Executes `sql`, which may hold several statements, e.g. the content
+    /// of a migration file.
+    pub fn batch_execute(&self, sql: &str) -> Result<()> {
+        let result = match self {
+            MigrationTarget::Mysql(conn)    => conn.batch_execute(sql),
+            MigrationTarget::Pg(conn)       => conn.batch_execute(sql),
+            MigrationTarget::Sqlite(conn)   => conn.batch_execute(sql),
+        };
+
+        result.map_err(error::Error::from)
+    }
+}
+
+impl fmt::Debug for MigrationTarget<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("MigrationTarget").field(&self.backend()).finish()
+    }
+}
+
+impl<'a> From<&'a diesel::MysqlConnection> for MigrationTarget<'a> {
+    fn from(conn: &'a diesel::MysqlConnection) -> Self {
+        MigrationTarget::Mysql(conn)
+    }
+}
+
+impl<'a> From<&'a diesel::PgConnection> for MigrationTarget<'a> {
+    fn from(conn: &'a diesel::PgConnection) -> Self {
+        MigrationTarget::Pg(conn)
+    }
+}
+
+impl<'a> From<&'a diesel::SqliteConnection> for MigrationTarget<'a> {
+    fn from(conn: &'a diesel::SqliteConnection) -> Self {
+        MigrationTarget::Sqlite(conn)
+    }
+}
+
+/// A migration not yet applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlannedMigration {
+    /// Version (or name) of the migration.
+    pub version: String,
+    /// Statements the migration executes, `None` when the engine can't tell.
+    pub sql: Option<String>,
+}
+
+impl fmt::Display for PlannedMigration {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sql {
+            Some(ref sql) => write!(fmt, "-- {}\n{}", self.version, sql.trim_end()),
+            None => write!(fmt, "-- {} (SQL unavailable)", self.version)
+        }
+    }
+}
+
+/// Finds and applies migrations.
+pub trait MigrationEngine: Send + Sync + 'static {
+    /// Number of migrations not yet applied to `conn`.
+    fn pending(&self, conn: MigrationTarget<'_>) -> Result<usize>;
+
+    /// Migrations not yet applied to `conn`, in the order they would be
+    /// applied. Engines which can't list them fail with `Migration`.
+    fn plan(&self, conn: MigrationTarget<'_>) -> Result<Vec<PlannedMigration>> {
+        let _ = conn;
+
+        Err(error::Error::new(
+            error::ErrorKind::Migration,
+            format!("the migrations of `{}` can't be planned", self.source())
+        ))
+    }
+
+    /// Applies the pending migrations to `conn`.
+    fn run_pending(&self, conn: MigrationTarget<'_>) -> Result<()>;
+
+    /// Where the migrations come from, for logs and reports.
+    fn source(&self) -> String;
+}
+
+/// Engine running the diesel migrations of a directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DieselMigrations {
+    _directory: PathBuf,
+}
+
+impl DieselMigrations {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            _directory: directory.into()
+        }
+    }
+
+    fn pending_on<Conn>(&self, conn: &Conn) -> Result<usize>
+        where Conn: MigrationConnection
+    {
+        let migrations = diesel_migrations::mark_migrations_in_directory(
+            conn, &self._directory
+        ).map_err(|err| error::Error::new(
+            error::ErrorKind::Migration, err.description()
+        ))?;
+
+        Ok(migrations.iter().filter(|(_, applied)| !applied).count())
+    }
+
+    fn plan_on<Conn>(&self, conn: &Conn) -> Result<Vec<PlannedMigration>>
+        where Conn: MigrationConnection
+    {
+        let migrations = diesel_migrations::mark_migrations_in_directory(
+            conn, &self._directory
+        ).map_err(|err| error::Error::new(
+            error::ErrorKind::Migration, err.description()
+        ))?;
+
+        let mut directories: Vec<PathBuf> = migrations.into_iter()
+            .filter(|(_, applied)| !applied)
+            .filter_map(|(directory, _)| directory)
+            .collect();
+        directories.sort();
+
+        Ok(directories.into_iter().map(|directory| PlannedMigration {
+            version: directory.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| directory.display().to_string()),
+            sql: fs::read_to_string(directory.join("up.sql")).ok()
+        }).collect())
+    }
+
+    fn run_on<Conn>(&self, conn: &Conn) -> Result<()>
+        where Conn: MigrationConnection
+    {
+        diesel_migrations::run_pending_migrations_in_directory(
+            conn, &self._directory, &mut std::io::sink()
+        ).map_err(|err| error::Error::new(
+            error::ErrorKind::Migration, err.description()
+        ))
+    }
+}
+
+impl MigrationEngine for DieselMigrations {
+    fn pending(&self, conn: MigrationTarget<'_>) -> Result<usize> {
+        match conn {
+            MigrationTarget::Mysql(conn)    => self.pending_on(conn),
+            MigrationTarget::Pg(conn)       => self.pending_on(conn),
+            MigrationTarget::Sqlite(conn)   => self.pending_on(conn),
+        }
+    }
+
+    fn plan(&self, conn: MigrationTarget<'_>) -> Result<Vec<PlannedMigration>> {
+        match conn {
+            MigrationTarget::Mysql(conn)    => self.plan_on(conn),
+            MigrationTarget::Pg(conn)       => self.plan_on(conn),
+            MigrationTarget::Sqlite(conn)   => self.plan_on(conn),
+        }
+    }
+
+    fn run_pending(&self, conn: MigrationTarget<'_>) -> Result<()> {
+        match conn {
+            MigrationTarget::Mysql(conn)    => self.run_on(conn),
+            MigrationTarget::Pg(conn)       => self.run_on(conn),
+            MigrationTarget::Sqlite(conn)   => self.run_on(conn),
+        }
+    }
+
+    fn source(&self) -> String {
+        self._directory.display().to_string()
+    }
+}
+
+/// Migration engine of a database.
+#[derive(Clone)]
+pub(crate) struct SharedEngine(Arc<dyn MigrationEngine>);
+
+impl SharedEngine {
+    pub fn new<E: MigrationEngine>(engine: E) -> Self {
+        SharedEngine(Arc::new(engine))
+    }
+
+    pub fn get(&self) -> &dyn MigrationEngine {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for SharedEngine {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "SharedEngine({})", self.0.source())
+    }
+}
+
+/// Migration settings: the policy and the directory holding the migrations.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Migrations {
+    _policy: MigrationPolicy,
+    _directory: PathBuf,
+    _dry_run: bool,
+}
+
+impl Default for Migrations {
+    fn default() -> Self {
+        Self {
+            _policy: MigrationPolicy::default(),
+            _directory: PathBuf::from("migrations"),
+            _dry_run: false
+        }
+    }
+}
+
+impl Migrations {
+    pub fn new(policy: MigrationPolicy, directory: Option<PathBuf>) -> Self {
+        Self {
+            _policy: policy,
+            _directory: directory.unwrap_or_else(|| Migrations::default()._directory),
+            _dry_run: false
+        }
+    }
+
+    /// Logs the plan of the pending migrations on initialization instead
+    /// of running them.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self._dry_run = dry_run;
+        self
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self._dry_run
+    }
+
+    pub fn policy(&self) -> MigrationPolicy {
+        self._policy
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self._directory
+    }
+
+    /// Engine running the diesel migrations of the directory.
+    pub fn diesel_engine(&self) -> DieselMigrations {
+        DieselMigrations::new(self.directory())
+    }
+
+    /// Applies the policy to `conn` with `engine`, or logs the plan of the
+    /// pending migrations in dry runs.
+    pub fn apply(&self, engine: &dyn MigrationEngine, conn: MigrationTarget<'_>) -> Result<()> {
+        if self._dry_run {
+            let plan = engine.plan(conn)?;
+
+            log_redacted!(
+                log::Level::Warn,
+                "Dry run (`migrations.dry_run`): {} pending migration(s) from `{}` not applied.",
+                plan.len(), engine.source()
+            );
+            for migration in &plan {
+                log_redacted!(log::Level::Info, "{}", migration);
+            }
+            return Ok(());
+        }
+
+        match self._policy {
+            MigrationPolicy::Ignore => Ok(()),
+            MigrationPolicy::Check => {
+                let pending = engine.pending(conn)?;
+
+                if pending > 0 {
+                    return Err(error::Error::new(
+                        error::ErrorKind::PendingMigrations,
+                        format!("{} pending migration(s) in `{}`", pending, engine.source())
+                    ));
+                }
+                Ok(())
+            },
+            MigrationPolicy::Run => {
+                log_redacted!(log::Level::Info, "Running pending migrations from `{}`...", engine.source());
+
+                engine.run_pending(conn)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use std::{
+        path::Path,
+        sync::Mutex
+    };
+    use crate::error::ErrorKind;
+    use crate::Result;
+    use super::{MigrationEngine, MigrationPolicy, MigrationTarget, Migrations, PlannedMigration};
+
+    /// Engine of plain SQL migrations, given in order.
+    struct SqlMigrations(Vec<&'static str>, Mutex<usize>);
+
+    impl MigrationEngine for SqlMigrations {
+        fn pending(&self, _conn: MigrationTarget<'_>) -> Result<usize> {
+            Ok(self.0.len() - *self.1.lock().unwrap())
+        }
+
+        fn run_pending(&self, conn: MigrationTarget<'_>) -> Result<()> {
+            let mut applied = self.1.lock().unwrap();
+
+            for sql in &self.0[*applied..] {
+                conn.batch_execute(sql)?;
+                *applied += 1;
+            }
+            Ok(())
+        }
+
+        fn plan(&self, _conn: MigrationTarget<'_>) -> Result<Vec<PlannedMigration>> {
+            Ok(self.0[*self.1.lock().unwrap()..].iter().enumerate().map(|(index, sql)| PlannedMigration {
+                version: index.to_string(),
+                sql: Some((*sql).to_owned())
+            }).collect())
+        }
+
+        fn source(&self) -> String {
+            "sql".to_owned()
+        }
+    }
+
+    #[test]
+    fn policy_from_str() {
+        assert_eq!("run".parse::<MigrationPolicy>().unwrap(), MigrationPolicy::Run);
+        assert_eq!("check".parse::<MigrationPolicy>().unwrap(), MigrationPolicy::Check);
+        assert_eq!("ignore".parse::<MigrationPolicy>().unwrap(), MigrationPolicy::Ignore);
+        assert!("apply".parse::<MigrationPolicy>().is_err());
+
+        for policy in &[MigrationPolicy::Run, MigrationPolicy::Check, MigrationPolicy::Ignore] {
+            assert_eq!(policy.as_str().parse::<MigrationPolicy>().unwrap(), *policy);
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let migrations = Migrations::new(MigrationPolicy::Check, None);
+
+        assert_eq!(migrations.policy(), MigrationPolicy::Check);
+        assert_eq!(migrations.directory(), Path::new("migrations"));
+        assert_eq!(Migrations::default().policy(), MigrationPolicy::Ignore);
+    }
+
+    #[test]
+    fn custom_engine() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        let engine = SqlMigrations(vec!["CREATE TABLE users (id INTEGER)"], Mutex::new(0));
+
+        let err = Migrations::new(MigrationPolicy::Check, None)
+            .apply(&engine, MigrationTarget::from(&conn))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PendingMigrations);
+
+        Migrations::new(MigrationPolicy::Run, None).apply(&engine, MigrationTarget::from(&conn)).unwrap();
+        assert_eq!(engine.pending(MigrationTarget::from(&conn)).unwrap(), 0);
+        conn.batch_execute("SELECT id FROM users").unwrap();
+    }
+
+    #[test]
+    fn dry_run_does_not_apply() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        let engine = SqlMigrations(vec!["CREATE TABLE users (id INTEGER)"], Mutex::new(0));
+
+        Migrations::new(MigrationPolicy::Run, None)
+            .with_dry_run(true)
+            .apply(&engine, MigrationTarget::from(&conn))
+            .unwrap();
+        assert_eq!(engine.pending(MigrationTarget::from(&conn)).unwrap(), 1);
+
+        let plan = engine.plan(MigrationTarget::from(&conn)).unwrap();
+        assert_eq!(plan[0].to_string(), "-- 0\nCREATE TABLE users (id INTEGER)");
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+/// Options of a single interaction, given to `Database::interact_with_options`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct InteractOptions {
+    /// Maximum execution time of each statement of the interaction, which
+    /// then fails with `ErrorKind::StatementTimeout`. `None` keeps the
+    /// timeout of the session.
+    pub statement_timeout: Option<Duration>,
+    /// Runs the interaction in a transaction which is always rolled back,
+    /// e.g. for preview endpoints reusing the real write logic.
+    pub dry_run: bool,
+}
+
+impl InteractOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
@@ -0,0 +1,173 @@
+//! Transactional outbox.
+//!
+//! Events are written to the `_rocket_diesel_outbox` table with the same
+//! connection (and therefore within the same transaction) as the business
+//! data they describe. A relay later polls the table and hands every pending
+//! event to a [`Publisher`], marking it as dispatched only once the publisher
+//! succeeded: events are delivered at least once, and never for a transaction
+//! which was rolled back.
+//!
+//! The table is created by the bootstrap step (see the `bootstrap`
+//! configuration key and `Database::bootstrap`).
+//!
+//! [`Publisher`]: trait.Publisher.html
+
+use diesel::{
+    query_dsl::RunQueryDsl,
+    result::QueryResult,
+    sql_types::{
+        BigInt,
+        Text
+    }
+};
+
+use std::error::Error as StdError;
+
+use crate::error;
+use crate::ResultLimit;
+use crate::Result;
+
+/// Name of the outbox table.
+pub const TABLE: &str = "_rocket_diesel_outbox";
+
+/// An event waiting in, or read from, the outbox.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event
+{
+    pub topic: String,
+    pub payload: String,
+}
+
+impl Event
+{
+    pub fn new<T, P>(topic: T, payload: P) -> Self
+        where T: Into<String>,
+              P: Into<String>
+    {
+        Self {
+            topic: topic.into(),
+            payload: payload.into()
+        }
+    }
+}
+
+crate::heap_size!(Event { topic, payload });
+
+/// Destination of the events relayed from the outbox.
+pub trait Publisher: Send + Sync + 'static
+{
+    fn publish(&self, event: &Event)
+        -> std::result::Result<(), Box<dyn StdError + Send + Sync>>;
+}
+
+#[derive(QueryableByName)]
+struct OutboxRow
+{
+    #[sql_type = "BigInt"]
+    id: i64,
+    #[sql_type = "Text"]
+    topic: String,
+    #[sql_type = "Text"]
+    payload: String,
+}
+
+/// Backend-specific statements of the outbox.
+pub trait OutboxConnection
+{
+    fn insert_event(&self, event: &Event) -> QueryResult<()>;
+
+    fn pending_events(&self, limit: i64) -> QueryResult<Vec<(i64, Event)>>;
+
+    fn mark_dispatched(&self, id: i64) -> QueryResult<()>;
+}
+
+macro_rules! impl_outbox_connection {
+    ($connection:ty, $insert:expr, $mark:expr) => {
+        impl OutboxConnection for $connection
+        {
+            fn insert_event(&self, event: &Event) -> QueryResult<()>
+            {
+                diesel::sql_query($insert)
+                    .bind::<Text, _>(event.topic.as_str())
+                    .bind::<Text, _>(event.payload.as_str())
+                    .execute(self)
+                    .map(|_| ())
+            }
+
+            fn pending_events(&self, limit: i64) -> QueryResult<Vec<(i64, Event)>>
+            {
+                let query = format!(
+                    "SELECT id, topic, payload FROM {} WHERE dispatched_at IS NULL ORDER BY id LIMIT {}",
+                    TABLE, limit
+                );
+
+                Ok(diesel::sql_query(query)
+                    .load::<OutboxRow>(self)?
+                    .into_iter()
+                    .map(|row| (row.id, Event::new(row.topic, row.payload)))
+                    .collect())
+            }
+
+            fn mark_dispatched(&self, id: i64) -> QueryResult<()>
+            {
+                diesel::sql_query($mark)
+                    .bind::<BigInt, _>(id)
+                    .execute(self)
+                    .map(|_| ())
+            }
+        }
+    };
+}
+
+impl_outbox_connection!(
+    diesel::MysqlConnection,
+    "INSERT INTO _rocket_diesel_outbox (topic, payload) VALUES (?, ?)",
+    "UPDATE _rocket_diesel_outbox SET dispatched_at = CURRENT_TIMESTAMP WHERE id = ?"
+);
+
+impl_outbox_connection!(
+    diesel::PgConnection,
+    "INSERT INTO _rocket_diesel_outbox (topic, payload) VALUES ($1, $2)",
+    "UPDATE _rocket_diesel_outbox SET dispatched_at = CURRENT_TIMESTAMP WHERE id = $1"
+);
+
+impl_outbox_connection!(
+    diesel::SqliteConnection,
+    "INSERT INTO _rocket_diesel_outbox (topic, payload) VALUES (?, ?)",
+    "UPDATE _rocket_diesel_outbox SET dispatched_at = CURRENT_TIMESTAMP WHERE id = ?"
+);
+
+/// Writes `event` to the outbox using `conn`.
+///
+/// Call it from inside the transaction writing the data the event describes:
+/// the event then only becomes visible to the relay if the transaction
+/// commits.
+pub fn publish<C>(conn: &C, event: &Event) -> QueryResult<()>
+    where C: OutboxConnection
+{
+    conn.insert_event(event)
+}
+
+/// Dispatches up to `limit` pending events to `publisher`, in insertion
+/// order, and returns how many were dispatched.
+///
+/// Stops at the first event the publisher rejects so that it is retried,
+/// with the following ones, on the next run.
+pub fn relay<C>(conn: &C, publisher: &dyn Publisher, limit: i64, result_limit: &ResultLimit)
+    -> Result<usize>
+    where C: OutboxConnection
+{
+    let events = conn.pending_events(limit)?;
+    result_limit.check_rows(&events)?;
+    let mut dispatched = 0;
+
+    for (id, event) in events {
+        publisher.publish(&event).map_err(|err| {
+            error::Error::new(error::ErrorKind::Other, err)
+        })?;
+        conn.mark_dispatched(id)?;
+        dispatched += 1;
+    }
+
+    Ok(dispatched)
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use crate::Backend;
+
+/// Result of a successful interaction with the database.
+///
+/// Besides the value produced by the closure, it tells which backend
+/// executed the closure and how long the execution took (lock acquisition
+/// excluded).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InteractOutcome<T>
+{
+    pub value: T,
+    pub backend: Backend,
+    pub duration: Duration,
+}
+
+impl<T> InteractOutcome<T>
+{
+    /// Consumes the outcome, returning the value produced by the closure.
+    pub fn into_value(self) -> T
+    {
+        self.value
+    }
+
+    /// Maps the value of the outcome, keeping its metadata.
+    pub fn map<U, F>(self, f: F) -> InteractOutcome<U>
+        where F: FnOnce(T) -> U
+    {
+        InteractOutcome {
+            value: f(self.value),
+            backend: self.backend,
+            duration: self.duration
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Connection handle owning its database.
+//!
+//! `LockedConnection` borrows the `Database` managed by Rocket and cannot
+//! outlive the request. An `OwnedConn` shares the database instead, so it
+//! can be moved into a background thread:
+//!
+//! ```rust,ignore
+//! #[post("/reports")]
+//! fn generate(conn: OwnedConn) -> Status {
+//!     std::thread::spawn(move || {
+//!         conn.interact(|conn| /* report */, |conn| /* report */, |conn| /* report */)
+//!     });
+//!     Status::Accepted
+//! }
+//! ```
+//!
+//! The database holds a single connection: the handle locks it for each
+//! interaction only, so requests keep being served while the background
+//! task is running.
+
+use rocket::{
+    http::Status,
+    Outcome,
+    request::{
+        self,
+        FromRequest,
+        Request
+    },
+    State
+};
+
+use std::error::Error;
+
+use crate::Database;
+use crate::error;
+use crate::InteractOutcome;
+use crate::Result;
+use crate::tagging;
+
+/// Owned, `'static` handle on the connection of a database.
+#[derive(Clone, Debug)]
+pub struct OwnedConn
+{
+    _database: Database,
+}
+
+impl OwnedConn
+{
+    /// Handle sharing the connection of `database`.
+    pub fn new(database: &Database) -> Self
+    {
+        Self {
+            _database: database.clone()
+        }
+    }
+
+    /// Database the handle shares its connection with.
+    pub fn database(&self) -> &Database
+    {
+        &self._database
+    }
+
+    /// Same as `Database::interact`.
+    #[track_caller]
+    pub fn interact<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        self._database.interact(mysql_f, pg_f, sqlite_f)
+    }
+
+    /// Same as `Database::interact_outcome`.
+    #[track_caller]
+    pub fn interact_outcome<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF)
+        -> Result<InteractOutcome<T>>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        self._database.interact_outcome(mysql_f, pg_f, sqlite_f)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for OwnedConn
+{
+    type Error = error::Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error>
+    {
+        tagging::set_route(request);
+
+        match request.guard::<State<'_, Database>>() {
+            Outcome::Success(database) => Outcome::Success(OwnedConn::new(database.inner())),
+            _ => Outcome::Failure((
+                Status::InternalServerError,
+                error::Error::new(error::ErrorKind::Other, "database fairing is not attached")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection as _,
+        query_dsl::RunQueryDsl,
+        sqlite::SqliteConnection
+    };
+    use std::thread;
+    use crate::Database;
+    use super::OwnedConn;
+
+    #[test]
+    fn moves_into_threads() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let conn = OwnedConn::new(&database);
+
+        thread::spawn(move || {
+            conn.interact::<_, diesel::result::Error, _, _, _>(
+                |_conn| unreachable!(),
+                |_conn| unreachable!(),
+                |conn| diesel::sql_query("CREATE TABLE reports (id INTEGER)").execute(&*conn)
+            )
+        }).join().unwrap().unwrap();
+
+        let inserted = database.interact::<_, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query("INSERT INTO reports VALUES (1)").execute(&*conn)
+        ).unwrap();
+        assert_eq!(inserted, 1);
+    }
+}
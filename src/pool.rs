@@ -0,0 +1,334 @@
+//! Pool of connections checked out by concurrent interactions.
+//!
+//! By default a database holds a single connection, shared by the Rocket
+//! workers: interactions run one at a time. With the `r2d2` or `deadpool`
+//! feature and a `pool.size` above 1, the connection established on
+//! initialization (and used to check the server version, apply migrations
+//! and bootstrap) is completed by a pool of `pool.size` connections to the
+//! same URL, from which each interaction checks out its own connection.
+//!
+//! The implementation of the pool is selected by `pool.implementation`
+//! (`r2d2` or `deadpool`), the first one enabled by default:
+//!
+//! - `r2d2`: `diesel::r2d2`, as used by `rocket_contrib`;
+//! - `deadpool`: a `deadpool` pool, for teams standardized on its
+//!   recycling, which checks connections with `SELECT 1` before handing
+//!   them out again. Rocket handlers being synchronous, checkouts don't
+//!   need an async runtime.
+//!
+//! Pooled connections are established with the URL the shared one was
+//! established with, after address resolution and credential fallback.
+//! In-memory SQLite databases are never pooled: each connection would open
+//! a database of its own.
+
+#[cfg(feature = "deadpool")]
+mod deadpool;
+#[cfg(feature = "r2d2")]
+mod r2d2;
+
+use std::{
+    fmt,
+    time::Duration
+};
+
+use crate::Backend;
+use crate::error;
+use crate::locked_connection;
+use crate::Result;
+use crate::sqlite_file;
+use crate::settings::{
+    ConnectionLifetime,
+    PoolImplementation,
+    PoolSettings
+};
+
+/// Time waited for a pooled connection when none is set by
+/// `pool.checkout_timeout`.
+const DEFAULT_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pool of connections, of one of the implementations.
+pub(crate) enum Pool
+{
+    #[cfg(feature = "r2d2")]
+    R2d2(r2d2::Pool),
+    #[cfg(feature = "deadpool")]
+    Deadpool(deadpool::Pool),
+}
+
+/// Connection checked out of a pool, given back when dropped.
+pub(crate) enum Checkout
+{
+    #[cfg(feature = "r2d2")]
+    R2d2(r2d2::Checkout),
+    #[cfg(feature = "deadpool")]
+    Deadpool(deadpool::Checkout),
+}
+
+/// Setup of each connection of a pool.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ConnectionSetup
+{
+    /// Limits on the age of the connections.
+    pub lifetime: ConnectionLifetime,
+    /// Statements run on each connection once established, as on the
+    /// shared connection (e.g. `PRAGMA max_page_count`).
+    pub sql: Option<String>,
+}
+
+/// Connections of a pool.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PoolState
+{
+    /// Established connections.
+    pub connections: u32,
+    /// Established connections not checked out.
+    pub idle: u32,
+    /// Maximum number of connections (`pool.size`).
+    pub max_size: u32,
+}
+
+/// Returns whether a pool completes the shared connection to `url` with
+/// `settings`.
+pub(crate) fn enabled(backend: Backend, url: &str, settings: &PoolSettings) -> bool
+{
+    settings.size.map_or(false, |size| size > 1) && !(backend == Backend::Sqlite && sqlite_file::in_memory(url))
+}
+
+/// Implementation selected by `settings`, failing when its feature is not
+/// enabled.
+fn implementation(settings: &PoolSettings) -> Result<PoolImplementation>
+{
+    match settings.implementation {
+        Some(PoolImplementation::R2d2) if cfg!(feature = "r2d2") => Ok(PoolImplementation::R2d2),
+        Some(PoolImplementation::Deadpool) if cfg!(feature = "deadpool") => Ok(PoolImplementation::Deadpool),
+        Some(implementation) => Err(error::Error::new(
+            error::ErrorKind::FormatError,
+            format!(
+                "`pool.implementation` `{0}` requires the `{0}` feature",
+                implementation.as_str()
+            )
+        )),
+        None if cfg!(feature = "r2d2") => Ok(PoolImplementation::R2d2),
+        None => Ok(PoolImplementation::Deadpool),
+    }
+}
+
+impl Pool
+{
+    /// Pool of up to `settings.size` connections of `backend` to `url`,
+    /// established on first use (see `fill`) with `setup`.
+    pub fn new(backend: Backend, url: &str, settings: &PoolSettings, setup: &ConnectionSetup) -> Result<Self>
+    {
+        let size = settings.size.unwrap_or(1).max(1);
+        let checkout_timeout = settings.checkout_timeout.unwrap_or(DEFAULT_CHECKOUT_TIMEOUT);
+
+        match implementation(settings)? {
+            #[cfg(feature = "r2d2")]
+            PoolImplementation::R2d2 => {
+                let min = settings.min.unwrap_or(0).min(size);
+
+                Ok(Pool::R2d2(r2d2::Pool::new(backend, url, size, min, checkout_timeout, setup)))
+            },
+            #[cfg(feature = "deadpool")]
+            PoolImplementation::Deadpool => {
+                Ok(Pool::Deadpool(deadpool::Pool::new(backend, url, size, checkout_timeout, setup)?))
+            },
+            #[allow(unreachable_patterns)]
+            implementation => unreachable!("`{}` is not enabled", implementation.as_str()),
+        }
+    }
+
+    /// Checks out a connection, failing with `CheckoutTimeout` when none is
+    /// available within `pool.checkout_timeout`.
+    pub fn checkout(&self) -> Result<Box<Checkout>>
+    {
+        Ok(Box::new(match self {
+            #[cfg(feature = "r2d2")]
+            Pool::R2d2(pool) => Checkout::R2d2(pool.checkout()?),
+            #[cfg(feature = "deadpool")]
+            Pool::Deadpool(pool) => Checkout::Deadpool(pool.checkout()?),
+        }))
+    }
+
+    pub fn state(&self) -> PoolState
+    {
+        match self {
+            #[cfg(feature = "r2d2")]
+            Pool::R2d2(pool) => pool.state(),
+            #[cfg(feature = "deadpool")]
+            Pool::Deadpool(pool) => pool.state(),
+        }
+    }
+
+    /// Establishes `count` connections (at most the size of the pool), left
+    /// idle, failing when one can't be established.
+    pub fn fill(&self, count: u32) -> Result<()>
+    {
+        let count = count.min(self.state().max_size);
+        let checkouts = (0..count)
+            .map(|_index| self.checkout())
+            .collect::<Result<Vec<_>>>()?;
+
+        drop(checkouts);
+        Ok(())
+    }
+
+    /// Returns whether a checkout would not wait.
+    pub fn available(&self) -> bool
+    {
+        let state = self.state();
+
+        state.idle > 0 || state.connections < state.max_size
+    }
+}
+
+impl fmt::Debug for Pool
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.debug_tuple("Pool").field(&self.state()).finish()
+    }
+}
+
+impl Checkout
+{
+    /// Connection of the checkout, valid while the checkout is alive.
+    pub fn connection(&mut self) -> locked_connection::Connection
+    {
+        match self {
+            #[cfg(feature = "r2d2")]
+            Checkout::R2d2(checkout) => checkout.connection(),
+            #[cfg(feature = "deadpool")]
+            Checkout::Deadpool(checkout) => checkout.connection(),
+        }
+    }
+}
+
+impl fmt::Debug for Checkout
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let backend = match self {
+            #[cfg(feature = "r2d2")]
+            Checkout::R2d2(checkout) => checkout.backend(),
+            #[cfg(feature = "deadpool")]
+            Checkout::Deadpool(checkout) => checkout.backend(),
+        };
+
+        write!(fmt, "Checkout({})", backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        fs,
+        process,
+        time::Duration
+    };
+    use diesel::connection::SimpleConnection;
+    use crate::Backend;
+    use crate::error::{Error, ErrorKind};
+    use crate::locked_connection::ConnectionMut;
+    use crate::LockedConnection;
+    use crate::settings::{PoolImplementation, PoolSettings};
+    use super::{enabled, ConnectionSetup, Pool};
+
+    #[test]
+    fn pools_file_databases_only() {
+        let pooled = PoolSettings { size: Some(4), ..PoolSettings::default() };
+
+        assert!(enabled(Backend::Pg, "postgres://localhost/app", &pooled));
+        assert!(enabled(Backend::Sqlite, "/var/lib/app.db", &pooled));
+        assert!(!enabled(Backend::Sqlite, ":memory:", &pooled));
+        assert!(!enabled(Backend::Pg, "postgres://localhost/app", &PoolSettings::default()));
+    }
+
+    fn checks_out_concurrent_connections(implementation: PoolImplementation) {
+        let path = env::temp_dir().join(format!(
+            "rocket-diesel-pool-{}-{}.db", implementation.as_str(), process::id()
+        ));
+        let settings = PoolSettings {
+            size: Some(2),
+            checkout_timeout: Some(Duration::from_millis(50)),
+            implementation: Some(implementation),
+            ..PoolSettings::default()
+        };
+        let pool = Pool::new(Backend::Sqlite, path.to_str().unwrap(), &settings, &ConnectionSetup::default()).unwrap();
+        pool.fill(1).unwrap();
+        assert_eq!((pool.state().connections, pool.state().idle), (1, 1));
+
+        let mut first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        assert!(!pool.available());
+        assert_eq!(pool.checkout().unwrap_err().kind(), ErrorKind::CheckoutTimeout);
+
+        drop(second);
+        assert!(pool.available());
+        assert_eq!(pool.state().connections, 2);
+        assert!(first.connection().is_sqlite());
+
+        drop(first);
+        drop(pool);
+        let _ = fs::remove_file(path);
+    }
+
+    fn sets_up_connections(implementation: PoolImplementation) {
+        let path = env::temp_dir().join(format!(
+            "rocket-diesel-pool-setup-{}-{}.db", implementation.as_str(), process::id()
+        ));
+        let settings = PoolSettings {
+            size: Some(2),
+            implementation: Some(implementation),
+            ..PoolSettings::default()
+        };
+        let setup = ConnectionSetup {
+            sql: Some("PRAGMA max_page_count = 16".to_owned()),
+            ..ConnectionSetup::default()
+        };
+        let pool = Pool::new(Backend::Sqlite, path.to_str().unwrap(), &settings, &setup).unwrap();
+
+        let mut lock = LockedConnection::pooled(pool.checkout().unwrap());
+        let err = match lock.conn_mut() {
+            ConnectionMut::Sqlite(conn) => conn.batch_execute(
+                "CREATE TABLE events (payload BLOB); INSERT INTO events VALUES (zeroblob(1048576));"
+            ).unwrap_err(),
+            _ => unreachable!()
+        };
+        assert!(Error::from(err).is_storage_full());
+
+        drop(lock);
+        drop(pool);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    #[cfg(feature = "r2d2")]
+    fn r2d2_checkouts() {
+        checks_out_concurrent_connections(PoolImplementation::R2d2);
+        sets_up_connections(PoolImplementation::R2d2);
+    }
+
+    #[test]
+    #[cfg(feature = "deadpool")]
+    fn deadpool_checkouts() {
+        checks_out_concurrent_connections(PoolImplementation::Deadpool);
+        sets_up_connections(PoolImplementation::Deadpool);
+    }
+
+    #[test]
+    #[cfg(not(feature = "deadpool"))]
+    fn requires_the_feature_of_the_implementation() {
+        let settings = PoolSettings {
+            size: Some(2),
+            implementation: Some(PoolImplementation::Deadpool),
+            ..PoolSettings::default()
+        };
+
+        assert_eq!(
+            Pool::new(Backend::Pg, "postgres://localhost/app", &settings, &ConnectionSetup::default()).unwrap_err().kind(),
+            ErrorKind::FormatError
+        );
+    }
+}
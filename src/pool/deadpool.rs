@@ -0,0 +1,257 @@
+//! `deadpool` pools, with a manager of diesel connections.
+//!
+//! `deadpool` is asynchronous, Rocket handlers are not: checkouts block on
+//! the pool's future. Timeouts of `deadpool` require an async runtime, so
+//! the checkout timeout is enforced by polling the pool without waiting,
+//! backing off from 1ms up to 20ms between polls.
+//!
+//! Polling checkouts do not queue in the pool: a connection released while
+//! a checkout sleeps goes to whichever checkout polls first, not to the one
+//! waiting the longest, and can be picked up up to 20ms late. Under
+//! contention, `r2d2` wakes a waiting checkout as soon as a connection is
+//! released.
+//!
+//! `deadpool` does not limit the age of connections: the manager times its
+//! connections and fails to recycle those beyond `idle_timeout` or
+//! `max_lifetime`, which the pool then replaces.
+
+use deadpool_crate::managed::{
+    self,
+    Object,
+    PoolError,
+    RecycleError,
+    RecycleResult,
+    Timeouts
+};
+
+use diesel::{
+    ConnectionError,
+    MysqlConnection,
+    PgConnection,
+    SqliteConnection
+};
+
+use std::{
+    marker::PhantomData,
+    thread,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::Backend;
+use crate::error;
+use crate::locked_connection;
+use crate::Result;
+use crate::settings::ConnectionLifetime;
+use super::{
+    ConnectionSetup,
+    PoolState
+};
+
+/// Intervals at which a checkout waiting for a connection polls the pool,
+/// doubling from the first to the last.
+const FIRST_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Connection of a pool, with its establishment and last use.
+pub(crate) struct Timed<C>
+{
+    _connection: C,
+    _established: Instant,
+    _last_used: Instant,
+}
+
+/// Establishes connections of `C` running the setup statements, recycled
+/// once `SELECT 1` succeeds within `lifetime`.
+pub(crate) struct Manager<C>
+{
+    _url: String,
+    _setup_sql: Option<String>,
+    _lifetime: ConnectionLifetime,
+    _connection: PhantomData<fn() -> C>,
+}
+
+#[async_trait_crate::async_trait]
+impl<C> managed::Manager for Manager<C>
+    where C: diesel::Connection + Send + 'static
+{
+    type Type = Timed<C>;
+    type Error = ConnectionError;
+
+    async fn create(&self) -> std::result::Result<Timed<C>, ConnectionError>
+    {
+        let now = Instant::now();
+        let connection = C::establish(&self._url)?;
+
+        if let Some(ref sql) = self._setup_sql {
+            connection.batch_execute(sql).map_err(ConnectionError::CouldntSetupConfiguration)?;
+        }
+
+        Ok(Timed {
+            _connection: connection,
+            _established: now,
+            _last_used: now
+        })
+    }
+
+    async fn recycle(&self, conn: &mut Timed<C>) -> RecycleResult<ConnectionError>
+    {
+        if let Some(limit) = self._lifetime.exceeded(conn._established, conn._last_used, Instant::now()) {
+            return Err(RecycleError::Message(format!("connection beyond its `{}`", limit)));
+        }
+
+        conn._connection.execute("SELECT 1")
+            .map(|_rows| ())
+            .map_err(|err| RecycleError::Message(err.to_string()))
+    }
+}
+
+enum Pools
+{
+    Mysql(managed::Pool<Manager<MysqlConnection>>),
+    Pg(managed::Pool<Manager<PgConnection>>),
+    Sqlite(managed::Pool<Manager<SqliteConnection>>),
+}
+
+pub(crate) struct Pool
+{
+    _pools: Pools,
+    _checkout_timeout: Duration,
+}
+
+pub(crate) enum Checkout
+{
+    Mysql(Object<Manager<MysqlConnection>>),
+    Pg(Object<Manager<PgConnection>>),
+    Sqlite(Object<Manager<SqliteConnection>>),
+}
+
+/// Checks out a connection of `pool`, waiting up to `timeout`.
+fn get<C>(pool: &managed::Pool<Manager<C>>, timeout: Duration) -> Result<Object<Manager<C>>>
+    where C: diesel::Connection + Send + 'static
+{
+    let deadline = Instant::now() + timeout;
+    let mut interval = FIRST_POLL_INTERVAL;
+    // Waiting without timeout needs no runtime
+    let immediately = Timeouts {
+        wait: Some(Duration::from_secs(0)),
+        create: None,
+        recycle: None
+    };
+
+    loop {
+        match futures_executor_crate::block_on(pool.timeout_get(&immediately)) {
+            Ok(conn) => return Ok(conn),
+            Err(PoolError::Timeout(_)) if Instant::now() < deadline => {
+                thread::sleep(interval.min(deadline.saturating_duration_since(Instant::now())));
+                interval = (interval * 2).min(MAX_POLL_INTERVAL);
+            },
+            Err(PoolError::Timeout(_)) => return Err(error::Error::new(
+                error::ErrorKind::CheckoutTimeout,
+                format!("no pooled connection available after {:?} (`pool.checkout_timeout`)", timeout)
+            )),
+            Err(PoolError::Backend(err)) => return Err(error::Error::from(err)),
+            Err(err) => return Err(error::Error::new(error::ErrorKind::ConnectionFailed, err.to_string()))
+        }
+    }
+}
+
+impl Pool
+{
+    pub fn new(
+        backend: Backend,
+        url: &str,
+        size: u32,
+        checkout_timeout: Duration,
+        setup: &ConnectionSetup
+    ) -> Result<Self>
+    {
+        fn build<C>(url: &str, size: u32, setup: &ConnectionSetup) -> Result<managed::Pool<Manager<C>>>
+            where C: diesel::Connection + Send + 'static
+        {
+            let manager = Manager {
+                _url: url.to_owned(),
+                _setup_sql: setup.sql.clone(),
+                _lifetime: setup.lifetime,
+                _connection: PhantomData
+            };
+
+            managed::Pool::builder(manager)
+                .max_size(size as usize)
+                .build()
+                .map_err(|err| error::Error::new(error::ErrorKind::ConnectionFailed, err.to_string()))
+        }
+
+        Ok(Self {
+            _pools: match backend {
+                Backend::Mysql => Pools::Mysql(build(url, size, setup)?),
+                Backend::Pg => Pools::Pg(build(url, size, setup)?),
+                Backend::Sqlite => Pools::Sqlite(build(url, size, setup)?),
+            },
+            _checkout_timeout: checkout_timeout
+        })
+    }
+
+    pub fn checkout(&self) -> Result<Checkout>
+    {
+        let timeout = self._checkout_timeout;
+
+        Ok(match self._pools {
+            Pools::Mysql(ref pool) => Checkout::Mysql(get(pool, timeout)?),
+            Pools::Pg(ref pool) => Checkout::Pg(get(pool, timeout)?),
+            Pools::Sqlite(ref pool) => Checkout::Sqlite(get(pool, timeout)?),
+        })
+    }
+
+    pub fn state(&self) -> PoolState
+    {
+        let status = match self._pools {
+            Pools::Mysql(ref pool) => pool.status(),
+            Pools::Pg(ref pool) => pool.status(),
+            Pools::Sqlite(ref pool) => pool.status(),
+        };
+
+        PoolState {
+            connections: status.size as u32,
+            idle: status.available.max(0) as u32,
+            max_size: status.max_size as u32
+        }
+    }
+}
+
+impl Checkout
+{
+    pub fn connection(&mut self) -> locked_connection::Connection
+    {
+        match self {
+            Checkout::Mysql(conn) => locked_connection::Connection::mysql(&mut conn._connection),
+            Checkout::Pg(conn) => locked_connection::Connection::pg(&mut conn._connection),
+            Checkout::Sqlite(conn) => locked_connection::Connection::sqlite(&mut conn._connection),
+        }
+    }
+
+    pub fn backend(&self) -> Backend
+    {
+        match self {
+            Checkout::Mysql(_) => Backend::Mysql,
+            Checkout::Pg(_) => Backend::Pg,
+            Checkout::Sqlite(_) => Backend::Sqlite,
+        }
+    }
+}
+
+impl Drop for Checkout
+{
+    fn drop(&mut self)
+    {
+        let now = Instant::now();
+
+        match self {
+            Checkout::Mysql(conn) => conn._last_used = now,
+            Checkout::Pg(conn) => conn._last_used = now,
+            Checkout::Sqlite(conn) => conn._last_used = now,
+        }
+    }
+}
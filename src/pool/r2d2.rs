@@ -0,0 +1,146 @@
+//! `r2d2` pools, from `diesel::r2d2`.
+
+use diesel::{
+    r2d2::{
+        ConnectionManager,
+        CustomizeConnection,
+        Error as R2d2Error,
+        Pool as R2d2Pool,
+        PooledConnection
+    },
+    MysqlConnection,
+    PgConnection,
+    SqliteConnection
+};
+
+use std::time::Duration;
+
+use crate::Backend;
+use crate::error;
+use crate::locked_connection;
+use crate::Result;
+use super::{
+    ConnectionSetup,
+    PoolState
+};
+
+pub(crate) enum Pool
+{
+    Mysql(R2d2Pool<ConnectionManager<MysqlConnection>>),
+    Pg(R2d2Pool<ConnectionManager<PgConnection>>),
+    Sqlite(R2d2Pool<ConnectionManager<SqliteConnection>>),
+}
+
+pub(crate) enum Checkout
+{
+    Mysql(PooledConnection<ConnectionManager<MysqlConnection>>),
+    Pg(PooledConnection<ConnectionManager<PgConnection>>),
+    Sqlite(PooledConnection<ConnectionManager<SqliteConnection>>),
+}
+
+/// Runs the setup statements on each established connection.
+#[derive(Debug)]
+struct Setup(String);
+
+impl<C> CustomizeConnection<C, R2d2Error> for Setup
+    where C: diesel::Connection
+{
+    fn on_acquire(&self, conn: &mut C) -> std::result::Result<(), R2d2Error>
+    {
+        conn.batch_execute(&self.0).map_err(R2d2Error::QueryError)
+    }
+}
+
+impl Pool
+{
+    /// Pool keeping `min` idle connections once established. The limits of
+    /// `setup.lifetime` left unset keep the defaults of `r2d2`.
+    pub fn new(
+        backend: Backend,
+        url: &str,
+        size: u32,
+        min: u32,
+        checkout_timeout: Duration,
+        setup: &ConnectionSetup
+    ) -> Self
+    {
+        // Connections are established by the checkouts (and `Pool::fill`),
+        // which report the failures
+        fn build<C>(url: &str, size: u32, min: u32, checkout_timeout: Duration, setup: &ConnectionSetup)
+            -> R2d2Pool<ConnectionManager<C>>
+            where C: diesel::Connection + Send + 'static
+        {
+            let mut builder = R2d2Pool::builder()
+                .max_size(size)
+                .min_idle(Some(min))
+                .connection_timeout(checkout_timeout);
+
+            if let Some(idle_timeout) = setup.lifetime.idle_timeout {
+                builder = builder.idle_timeout(Some(idle_timeout));
+            }
+            if let Some(max_lifetime) = setup.lifetime.max_lifetime {
+                builder = builder.max_lifetime(Some(max_lifetime));
+            }
+            if let Some(ref sql) = setup.sql {
+                builder = builder.connection_customizer(Box::new(Setup(sql.clone())));
+            }
+            builder.build_unchecked(ConnectionManager::new(url))
+        }
+
+        match backend {
+            Backend::Mysql => Pool::Mysql(build(url, size, min, checkout_timeout, setup)),
+            Backend::Pg => Pool::Pg(build(url, size, min, checkout_timeout, setup)),
+            Backend::Sqlite => Pool::Sqlite(build(url, size, min, checkout_timeout, setup)),
+        }
+    }
+
+    pub fn checkout(&self) -> Result<Checkout>
+    {
+        let timeout = |err| error::Error::new(
+            error::ErrorKind::CheckoutTimeout,
+            format!("no pooled connection available: {} (`pool.checkout_timeout`)", err)
+        );
+
+        Ok(match self {
+            Pool::Mysql(pool) => Checkout::Mysql(pool.get().map_err(timeout)?),
+            Pool::Pg(pool) => Checkout::Pg(pool.get().map_err(timeout)?),
+            Pool::Sqlite(pool) => Checkout::Sqlite(pool.get().map_err(timeout)?),
+        })
+    }
+
+    pub fn state(&self) -> PoolState
+    {
+        let (state, max_size) = match self {
+            Pool::Mysql(pool) => (pool.state(), pool.max_size()),
+            Pool::Pg(pool) => (pool.state(), pool.max_size()),
+            Pool::Sqlite(pool) => (pool.state(), pool.max_size()),
+        };
+
+        PoolState {
+            connections: state.connections,
+            idle: state.idle_connections,
+            max_size
+        }
+    }
+}
+
+impl Checkout
+{
+    pub fn connection(&mut self) -> locked_connection::Connection
+    {
+        match self {
+            Checkout::Mysql(conn) => locked_connection::Connection::mysql(&mut **conn),
+            Checkout::Pg(conn) => locked_connection::Connection::pg(&mut **conn),
+            Checkout::Sqlite(conn) => locked_connection::Connection::sqlite(&mut **conn),
+        }
+    }
+
+    pub fn backend(&self) -> Backend
+    {
+        match self {
+            Checkout::Mysql(_) => Backend::Mysql,
+            Checkout::Pg(_) => Backend::Pg,
+            Checkout::Sqlite(_) => Backend::Sqlite,
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! Prepared statements kept on the connection.
+//!
+//! Hot queries parsed and planned on every call waste time on the server.
+//! `Database::prepare` returns a handle to a statement, parsed once, which
+//! is executed repeatedly with binds:
+//!
+//! ```rust,ignore
+//! let user_by_id = database.prepare("user_by_id", PreparedSql::new("SELECT * FROM users WHERE id = ?"))?;
+//!
+//! let users = user_by_id.query().bind::<i32>(7).load::<User>()?;
+//! ```
+//!
+//! The statement is prepared on the connection by its first execution and
+//! kept in the statement cache of the connection (unlike `sql_query` and
+//! `RawQuery`, whose statements are prepared for each call). Connections
+//! established again, after being lost or closed while idle, prepare it
+//! again on their first execution.
+//!
+//! The statement is written once with `?` placeholders and translated for
+//! the backend (see `helpers::translate`), unless `PreparedSql::with_sql`
+//! gives the statement of a backend. Query tags are not added to prepared
+//! statements: they would make a new statement of every request.
+
+use diesel::{
+    backend,
+    connection::Connection,
+    deserialize::QueryableByName,
+    mysql::Mysql,
+    pg::Pg,
+    query_builder::{
+        AstPass,
+        QueryFragment,
+        QueryId
+    },
+    result::QueryResult,
+    serialize::ToSql,
+    sql_types::HasSqlType,
+    sqlite::Sqlite
+};
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    sync::Arc
+};
+
+use crate::Backend;
+use crate::Database;
+use crate::error;
+use crate::helpers;
+use crate::Operation;
+use crate::raw_query::BindValue;
+use crate::Result;
+
+/// Statement of a prepared query, per backend.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PreparedSql
+{
+    _sql: String,
+    _overrides: Vec<(Backend, String)>,
+}
+
+impl PreparedSql
+{
+    /// Statement `sql`, with `?` placeholders, translated for each backend.
+    pub fn new(sql: &str) -> Self
+    {
+        Self {
+            _sql: sql.to_owned(),
+            _overrides: Vec::new()
+        }
+    }
+
+    /// Runs `sql`, with `?` placeholders, as is on `backend`.
+    pub fn with_sql(mut self, backend: Backend, sql: &str) -> Self
+    {
+        self._overrides.retain(|(overridden, _sql)| *overridden != backend);
+        self._overrides.push((backend, sql.to_owned()));
+        self
+    }
+
+    /// Statement run on `backend`, split at its placeholders.
+    fn segments(&self, backend: Backend) -> Vec<String>
+    {
+        match self._overrides.iter().find(|(overridden, _sql)| *overridden == backend) {
+            Some((_backend, sql)) => helpers::split_placeholders(backend, sql),
+            None => helpers::split_placeholders(backend, &helpers::translate(backend, &self._sql))
+        }
+    }
+}
+
+/// A bound value, pushed to the statement of each backend.
+trait Bind: Send + Sync
+{
+    fn mysql(&self, out: &mut AstPass<'_, Mysql>) -> QueryResult<()>;
+
+    fn pg(&self, out: &mut AstPass<'_, Pg>) -> QueryResult<()>;
+
+    fn sqlite(&self, out: &mut AstPass<'_, Sqlite>) -> QueryResult<()>;
+}
+
+struct Typed<ST, V>(V, PhantomData<fn() -> ST>);
+
+impl<ST, V> Bind for Typed<ST, V>
+    where V: ToSql<ST, Mysql> + ToSql<ST, Pg> + ToSql<ST, Sqlite> + Send + Sync,
+          Mysql: HasSqlType<ST>,
+          Pg: HasSqlType<ST>,
+          Sqlite: HasSqlType<ST>
+{
+    fn mysql(&self, out: &mut AstPass<'_, Mysql>) -> QueryResult<()>
+    {
+        out.push_bind_param::<ST, V>(&self.0)
+    }
+
+    fn pg(&self, out: &mut AstPass<'_, Pg>) -> QueryResult<()>
+    {
+        out.push_bind_param::<ST, V>(&self.0)
+    }
+
+    fn sqlite(&self, out: &mut AstPass<'_, Sqlite>) -> QueryResult<()>
+    {
+        out.push_bind_param::<ST, V>(&self.0)
+    }
+}
+
+/// Statement of a backend with its binds, cached by the connection under
+/// its SQL.
+struct Statement<'a, DB>
+{
+    _segments: &'a [String],
+    _binds: &'a [Box<dyn Bind>],
+    _backend: PhantomData<DB>,
+}
+
+impl<'a, DB> Statement<'a, DB>
+    where DB: backend::Backend
+{
+    fn new(segments: &'a [String], binds: &'a [Box<dyn Bind>]) -> Self
+    {
+        Self {
+            _segments: segments,
+            _binds: binds,
+            _backend: PhantomData
+        }
+    }
+
+    fn walk<F>(&self, out: &mut AstPass<'_, DB>, bind: F) -> QueryResult<()>
+        where F: Fn(&dyn Bind, &mut AstPass<'_, DB>) -> QueryResult<()>
+    {
+        for (index, segment) in self._segments.iter().enumerate() {
+            if index > 0 {
+                bind(&*self._binds[index - 1], out)?;
+            }
+            out.push_sql(segment);
+        }
+        Ok(())
+    }
+}
+
+// Not `HAS_STATIC_QUERY_ID`: the statement cache is keyed by the SQL
+impl<'a, DB> QueryId for Statement<'a, DB>
+{
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> QueryFragment<Mysql> for Statement<'a, Mysql>
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Mysql>) -> QueryResult<()>
+    {
+        self.walk(&mut out, |bind, out| bind.mysql(out))
+    }
+}
+
+impl<'a> QueryFragment<Pg> for Statement<'a, Pg>
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()>
+    {
+        self.walk(&mut out, |bind, out| bind.pg(out))
+    }
+}
+
+impl<'a> QueryFragment<Sqlite> for Statement<'a, Sqlite>
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Sqlite>) -> QueryResult<()>
+    {
+        self.walk(&mut out, |bind, out| bind.sqlite(out))
+    }
+}
+
+struct Statements
+{
+    _label: String,
+    _sql: String,
+    _mysql: Vec<String>,
+    _pg: Vec<String>,
+    _sqlite: Vec<String>,
+}
+
+/// Handle to a prepared statement of a database. Clones share it.
+#[derive(Clone)]
+pub struct Prepared
+{
+    _database: Database,
+    _statements: Arc<Statements>,
+}
+
+impl Prepared
+{
+    /// Statement `sql` of `database`, labelled `label` in logs and errors,
+    /// failing when its statements have different numbers of placeholders.
+    pub(crate) fn new(database: Database, label: &str, sql: &PreparedSql) -> Result<Self>
+    {
+        let statements = Statements {
+            _label: label.to_owned(),
+            _sql: sql._sql.clone(),
+            _mysql: sql.segments(Backend::Mysql),
+            _pg: sql.segments(Backend::Pg),
+            _sqlite: sql.segments(Backend::Sqlite)
+        };
+
+        if statements._mysql.len() != statements._pg.len() || statements._pg.len() != statements._sqlite.len() {
+            return Err(error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("the statements of prepared query `{}` have different numbers of placeholders", label)
+            ));
+        }
+
+        Ok(Self {
+            _database: database,
+            _statements: Arc::new(statements)
+        })
+    }
+
+    pub fn label(&self) -> &str
+    {
+        &self._statements._label
+    }
+
+    /// Number of values to bind.
+    pub fn placeholders(&self) -> usize
+    {
+        self._statements._sqlite.len() - 1
+    }
+
+    /// Starts a call of the statement, without binds yet.
+    pub fn query(&self) -> PreparedQuery<'_>
+    {
+        PreparedQuery {
+            _prepared: self,
+            _binds: Vec::new()
+        }
+    }
+}
+
+impl fmt::Debug for Prepared
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.debug_struct("Prepared")
+            .field("label", &self._statements._label)
+            .field("sql", &self._statements._sql)
+            .finish()
+    }
+}
+
+/// Call of a prepared statement, with its binds.
+pub struct PreparedQuery<'a>
+{
+    _prepared: &'a Prepared,
+    _binds: Vec<Box<dyn Bind>>,
+}
+
+impl<'a> PreparedQuery<'a>
+{
+    /// Binds `value` to the next placeholder, with the SQL type of `T`.
+    pub fn bind<T>(self, value: T) -> Self
+        where T: BindValue + ToSql<T::SqlType, Mysql> + ToSql<T::SqlType, Pg> + ToSql<T::SqlType, Sqlite>
+                 + Send + Sync + 'static,
+              T::SqlType: 'static,
+              Mysql: HasSqlType<T::SqlType>,
+              Pg: HasSqlType<T::SqlType>,
+              Sqlite: HasSqlType<T::SqlType>
+    {
+        self.bind_as::<T::SqlType, T>(value)
+    }
+
+    /// Binds `value` to the next placeholder, with the SQL type `ST`.
+    pub fn bind_as<ST, V>(mut self, value: V) -> Self
+        where ST: 'static,
+              V: ToSql<ST, Mysql> + ToSql<ST, Pg> + ToSql<ST, Sqlite> + Send + Sync + 'static,
+              Mysql: HasSqlType<ST>,
+              Pg: HasSqlType<ST>,
+              Sqlite: HasSqlType<ST>
+    {
+        self._binds.push(Box::new(Typed::<ST, V>(value, PhantomData)));
+        self
+    }
+
+    fn check_binds(&self) -> Result<()>
+    {
+        match self._binds.len() == self._prepared.placeholders() {
+            true => Ok(()),
+            false => Err(error::Error::new(
+                error::ErrorKind::FormatError,
+                format!(
+                    "prepared query `{}` takes {} value(s), {} bound",
+                    self._prepared.label(), self._prepared.placeholders(), self._binds.len()
+                )
+            ))
+        }
+    }
+
+    /// Runs the statement, loading its rows as `U`.
+    #[track_caller]
+    pub fn load<U>(&self) -> Result<Vec<U>>
+        where U: QueryableByName<Mysql> + QueryableByName<Pg> + QueryableByName<Sqlite>
+    {
+        self.check_binds()?;
+
+        let statements = &self._prepared._statements;
+        let binds = &self._binds[..];
+
+        self._prepared._database.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::from_sql(&statements._sql),
+            |conn| conn.query_by_name::<_, U>(&Statement::<Mysql>::new(&statements._mysql, binds)),
+            |conn| conn.query_by_name::<_, U>(&Statement::<Pg>::new(&statements._pg, binds)),
+            |conn| conn.query_by_name::<_, U>(&Statement::<Sqlite>::new(&statements._sqlite, binds))
+        )
+    }
+
+    /// Executes the statement, returning the number of affected rows.
+    #[track_caller]
+    pub fn execute(&self) -> Result<usize>
+    {
+        self.check_binds()?;
+
+        let statements = &self._prepared._statements;
+        let binds = &self._binds[..];
+
+        self._prepared._database.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::from_sql(&statements._sql),
+            |conn| conn.execute_returning_count(&Statement::<Mysql>::new(&statements._mysql, binds)),
+            |conn| conn.execute_returning_count(&Statement::<Pg>::new(&statements._pg, binds)),
+            |conn| conn.execute_returning_count(&Statement::<Sqlite>::new(&statements._sqlite, binds))
+        )
+    }
+}
+
+impl<'a> fmt::Debug for PreparedQuery<'a>
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.debug_struct("PreparedQuery")
+            .field("label", &self._prepared.label())
+            .field("binds", &self._binds.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sql_types::{Integer, Text},
+        sqlite::SqliteConnection
+    };
+    use crate::{Backend, Database};
+    use crate::error::ErrorKind;
+    use super::PreparedSql;
+
+    #[derive(QueryableByName, Debug, PartialEq)]
+    struct User {
+        #[sql_type = "Integer"]
+        id: i32,
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    #[test]
+    fn statements_per_backend() {
+        let sql = PreparedSql::new("SELECT * FROM \"users\" WHERE id = ?")
+            .with_sql(Backend::Mysql, "SELECT * FROM users FORCE INDEX (PRIMARY) WHERE id = ?");
+
+        assert_eq!(sql.segments(Backend::Pg), vec!["SELECT * FROM \"users\" WHERE id = ", ""]);
+        assert_eq!(sql.segments(Backend::Mysql)[0], "SELECT * FROM users FORCE INDEX (PRIMARY) WHERE id = ");
+    }
+
+    #[test]
+    fn executes_repeatedly() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL);").unwrap();
+        let database = Database::from_connection(conn);
+
+        let insert = database.prepare("insert_user", PreparedSql::new("INSERT INTO users (id, name) VALUES (?, ?)"))
+            .unwrap();
+        let by_id = database.prepare("user_by_id", PreparedSql::new("SELECT * FROM users WHERE id = ?")).unwrap();
+        assert_eq!(insert.placeholders(), 2);
+
+        for (id, name) in vec![(7, "alice"), (8, "bob")] {
+            assert_eq!(insert.query().bind::<i32>(id).bind::<String>(name.to_owned()).execute().unwrap(), 1);
+        }
+        assert_eq!(
+            by_id.query().bind::<i32>(8).load::<User>().unwrap(),
+            vec![User { id: 8, name: "bob".to_owned() }]
+        );
+        assert_eq!(by_id.query().bind::<i32>(9).load::<User>().unwrap(), vec![]);
+        assert_eq!(by_id.query().load::<User>().unwrap_err().kind(), ErrorKind::FormatError);
+    }
+
+    #[test]
+    fn placeholders_must_match() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let sql = PreparedSql::new("SELECT * FROM users WHERE id = ?")
+            .with_sql(Backend::Pg, "SELECT * FROM users WHERE id = $1");
+
+        assert_eq!(database.prepare("user_by_id", sql).unwrap_err().kind(), ErrorKind::FormatError);
+    }
+}
@@ -0,0 +1,179 @@
+//! Sampling of interactions for profiling.
+//!
+//! With the `profiling.capacity` configuration key set, one interaction out
+//! of `profiling.sample_every` is recorded, with the route it served, its
+//! call site and its duration, in a ring buffer keeping the latest
+//! `capacity` samples. `Database::profile_snapshot` returns them, and
+//! `folded` renders them in the folded stacks format read by flamegraph
+//! tools (e.g. `inferno-flamegraph`), showing which routes monopolize the
+//! connection.
+
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque
+    },
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering
+        },
+        Arc,
+        Mutex
+    },
+    time::Duration
+};
+
+use crate::layer::{
+    InteractContext,
+    InteractLayer,
+    Next
+};
+use crate::Result;
+
+/// `profiling` section of the configuration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct ProfileSettings {
+    /// Number of samples kept.
+    pub capacity: usize,
+    /// Records one interaction out of `sample_every`.
+    pub sample_every: u64,
+}
+
+/// Interaction recorded by the profiler.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProfileSample {
+    /// Route of the request, if executed while serving one.
+    pub route: Option<String>,
+    /// Call site of the interaction (`file:line`).
+    pub label: String,
+    pub duration: Duration,
+}
+
+impl ProfileSample {
+    /// Frames of the sample, outermost first.
+    pub fn stack(&self) -> String {
+        format!("{};{}", self.route.as_ref().map_or("<none>", String::as_str), self.label)
+    }
+}
+
+/// Renders `samples` as folded stacks: one `route;label micros` line per
+/// stack, durations of identical stacks being summed.
+pub fn folded(samples: &[ProfileSample]) -> String {
+    let mut stacks = BTreeMap::new();
+
+    for sample in samples {
+        let micros = sample.duration.as_secs() * 1_000_000 + u64::from(sample.duration.subsec_micros());
+
+        *stacks.entry(sample.stack()).or_insert(0) += micros;
+    }
+
+    stacks.iter()
+        .map(|(stack, micros)| format!("{} {}\n", stack, micros))
+        .collect()
+}
+
+/// Ring buffer of the latest samples.
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    _seen: AtomicU64,
+    _samples: Mutex<VecDeque<ProfileSample>>,
+}
+
+impl Profiler {
+    /// Returns whether the next interaction is to be sampled.
+    pub fn should_sample(&self, settings: &ProfileSettings) -> bool {
+        self._seen.fetch_add(1, Ordering::Relaxed) % settings.sample_every.max(1) == 0
+    }
+
+    pub fn record(&self, sample: ProfileSample, settings: &ProfileSettings) {
+        if let Ok(mut samples) = self._samples.lock() {
+            samples.push_back(sample);
+            while samples.len() > settings.capacity {
+                samples.pop_front();
+            }
+        }
+    }
+
+    /// Recorded samples, oldest first.
+    pub fn snapshot(&self) -> Vec<ProfileSample> {
+        self._samples.lock()
+            .map(|samples| samples.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Records sampled interactions.
+pub(crate) struct ProfileLayer {
+    _profiler: Arc<Profiler>,
+}
+
+impl ProfileLayer {
+    pub fn new(profiler: Arc<Profiler>) -> Self {
+        Self {
+            _profiler: profiler
+        }
+    }
+}
+
+impl InteractLayer for ProfileLayer {
+    fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+        let settings = match context.settings().and_then(|settings| settings.profiling()) {
+            Some(settings) if self._profiler.should_sample(&settings) => settings,
+            _ => return next.run(context)
+        };
+
+        let result = next.run(context);
+
+        if let Some(duration) = context.duration() {
+            self._profiler.record(ProfileSample {
+                route: context.tag().and_then(|tag| tag.route.clone()),
+                label: format!("{}:{}", context.caller().file(), context.caller().line()),
+                duration
+            }, &settings);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{folded, ProfileSample, ProfileSettings, Profiler};
+
+    fn sample(route: Option<&str>, label: &str, millis: u64) -> ProfileSample {
+        ProfileSample {
+            route: route.map(str::to_owned),
+            label: label.to_owned(),
+            duration: Duration::from_millis(millis)
+        }
+    }
+
+    #[test]
+    fn keeps_latest_samples() {
+        let settings = ProfileSettings { capacity: 2, sample_every: 2 };
+        let profiler = Profiler::default();
+
+        let sampled: Vec<bool> = (0..4).map(|_| profiler.should_sample(&settings)).collect();
+        assert_eq!(sampled, vec![true, false, true, false]);
+
+        for millis in 1..=3 {
+            profiler.record(sample(None, "src/main.rs:1", millis), &settings);
+        }
+        let durations: Vec<u64> = profiler.snapshot().iter()
+            .map(|sample| sample.duration.as_millis() as u64)
+            .collect();
+        assert_eq!(durations, vec![2, 3]);
+    }
+
+    #[test]
+    fn folds_stacks() {
+        let samples = vec![
+            sample(Some("/users/<id>"), "src/users.rs:10", 2),
+            sample(None, "src/jobs.rs:5", 1),
+            sample(Some("/users/<id>"), "src/users.rs:10", 3),
+        ];
+
+        assert_eq!(folded(&samples), "/users/<id>;src/users.rs:10 5000\n<none>;src/jobs.rs:5 1000\n");
+    }
+}
@@ -0,0 +1,187 @@
+//! Per-route limits of concurrent interactions.
+//!
+//! The `route_quotas` configuration table maps path prefixes (mount points
+//! or single routes) to the maximum number of interactions their requests
+//! may have in flight, waiting for the connection included:
+//!
+//! ```json
+//! "route_quotas": { "/reports": 2, "/admin/export": 1 }
+//! ```
+//!
+//! An interaction of a request whose path starts with a prefix (at a
+//! segment boundary) counts against the longest one, and fails right away
+//! with `QuotaExceeded` once that quota is used up, so that a heavy endpoint
+//! does not keep every other request waiting for the connection.
+//! Interactions outside of requests are not limited.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use crate::error;
+use crate::layer::{
+    InteractContext,
+    InteractLayer,
+    Next
+};
+use crate::Result;
+
+/// Current usage of a quota.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuotaUsage {
+    /// Path prefix the quota is configured for.
+    pub prefix: String,
+    /// Interactions in flight.
+    pub in_use: usize,
+    /// Maximum number of interactions in flight.
+    pub limit: usize,
+}
+
+/// Returns the longest prefix of `quotas` matching `path`, and its limit.
+pub(crate) fn quota_of<'a>(quotas: &'a [(String, usize)], path: &str) -> Option<(&'a str, usize)> {
+    quotas.iter()
+        .filter(|(prefix, _)| matches(prefix, path))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(prefix, limit)| (prefix.as_str(), *limit))
+}
+
+fn matches(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+
+    path.starts_with(prefix) && match path[prefix.len()..].chars().next() {
+        None | Some('/') | Some('?') => true,
+        Some(_) => false
+    }
+}
+
+/// Interactions in flight per quota. Clones of a database share it.
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    _in_use: Mutex<HashMap<String, usize>>,
+}
+
+impl Quotas {
+    /// Takes a slot of the quota of `prefix`, held until dropped.
+    pub fn acquire(quotas: &Arc<Quotas>, prefix: &str, limit: usize) -> Result<Slot> {
+        let mut in_use = quotas._in_use.lock().map_err(|_err| error::Error::new(
+            error::ErrorKind::Other, "route quotas got poisoned"
+        ))?;
+        let count = in_use.entry(prefix.to_owned()).or_insert(0);
+
+        if *count >= limit {
+            return Err(error::Error::new(
+                error::ErrorKind::QuotaExceeded,
+                format!("`{}` already has {} database interaction(s) in flight (`route_quotas`)", prefix, limit)
+            ));
+        }
+        *count += 1;
+
+        Ok(Slot {
+            _quotas: quotas.clone(),
+            _prefix: prefix.to_owned()
+        })
+    }
+
+    /// Usage of each of `quotas`.
+    pub fn usage(&self, quotas: &[(String, usize)]) -> Vec<QuotaUsage> {
+        let in_use = self._in_use.lock().map(|in_use| in_use.clone()).unwrap_or_default();
+
+        quotas.iter().map(|(prefix, limit)| QuotaUsage {
+            prefix: prefix.clone(),
+            in_use: in_use.get(prefix).cloned().unwrap_or(0),
+            limit: *limit
+        }).collect()
+    }
+}
+
+/// Slot of a quota, released when dropped.
+pub(crate) struct Slot {
+    _quotas: Arc<Quotas>,
+    _prefix: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Ok(mut in_use) = self._quotas._in_use.lock() {
+            if let Some(count) = in_use.get_mut(&self._prefix) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Fails the interactions of routes which used up their quota.
+pub(crate) struct QuotaLayer {
+    _quotas: Arc<Quotas>,
+}
+
+impl QuotaLayer {
+    pub fn new(quotas: Arc<Quotas>) -> Self {
+        Self {
+            _quotas: quotas
+        }
+    }
+}
+
+impl InteractLayer for QuotaLayer {
+    fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+        let quota = {
+            // Interactions attributed with `interact_with_request` only
+            // carry the route of their tag
+            let route = context.request_path()
+                .or_else(|| context.tag().and_then(|tag| tag.route.as_ref().map(String::as_str)));
+            let quotas = context.settings().map(|settings| settings.route_quotas());
+
+            match (route, quotas) {
+                (Some(route), Some(quotas)) => quota_of(quotas, route)
+                    .map(|(prefix, limit)| (prefix.to_owned(), limit)),
+                _ => None
+            }
+        };
+
+        let _slot = match quota {
+            Some((prefix, limit)) => Some(Quotas::acquire(&self._quotas, &prefix, limit)?),
+            None => None
+        };
+
+        next.run(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::error::ErrorKind;
+    use super::{quota_of, Quotas, QuotaUsage};
+
+    #[test]
+    fn matches_the_longest_prefix() {
+        let quotas = vec![("/reports".to_owned(), 2), ("/reports/export/".to_owned(), 1), ("/".to_owned(), 8)];
+
+        assert_eq!(quota_of(&quotas, "/reports"), Some(("/reports", 2)));
+        assert_eq!(quota_of(&quotas, "/reports/42"), Some(("/reports", 2)));
+        assert_eq!(quota_of(&quotas, "/reports/export/csv"), Some(("/reports/export/", 1)));
+        assert_eq!(quota_of(&quotas, "/reportsx"), Some(("/", 8)));
+        assert_eq!(quota_of(&quotas[..2], "/users"), None);
+    }
+
+    #[test]
+    fn limits_slots_in_flight() {
+        let quotas = Arc::new(Quotas::default());
+        let configured = vec![("/reports".to_owned(), 2)];
+
+        let first = Quotas::acquire(&quotas, "/reports", 2).unwrap();
+        let _second = Quotas::acquire(&quotas, "/reports", 2).unwrap();
+        let err = Quotas::acquire(&quotas, "/reports", 2).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+        assert_eq!(quotas.usage(&configured), vec![QuotaUsage { prefix: "/reports".to_owned(), in_use: 2, limit: 2 }]);
+
+        drop(first);
+        assert_eq!(quotas.usage(&configured)[0].in_use, 1);
+        assert!(Quotas::acquire(&quotas, "/reports", 2).is_ok());
+    }
+}
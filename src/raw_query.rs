@@ -0,0 +1,283 @@
+//! Raw queries with typed binds.
+//!
+//! Between the query builder of Diesel and SQL formatted by hand, a
+//! `RawQuery` is written once with `?` placeholders and typed binds, and
+//! runs on the three backends:
+//!
+//! ```rust,ignore
+//! #[derive(QueryableByName)]
+//! #[table_name = "users"]
+//! struct User { id: i32, name: String }
+//!
+//! let users = RawQuery::new("SELECT * FROM users WHERE id = ?")
+//!     .bind::<i32>(7)
+//!     .load::<User>(&database)?;
+//! ```
+//!
+//! The statement is translated for the backend (see `helpers::translate`),
+//! and its placeholders become `$1`, `$2`, ... with Postgres. Values are
+//! bound with the SQL type of their Rust type (see `BindValue`), or with an
+//! explicit one with `bind_as`.
+
+use diesel::{
+    deserialize::QueryableByName,
+    mysql::Mysql,
+    pg::Pg,
+    query_builder::{
+        BoxedSqlQuery,
+        SqlQuery
+    },
+    query_dsl::RunQueryDsl,
+    serialize::ToSql,
+    sql_types::{
+        self,
+        HasSqlType,
+        NotNull,
+        Nullable
+    },
+    sqlite::Sqlite
+};
+
+use std::{
+    fmt,
+    marker::PhantomData
+};
+
+use crate::Backend;
+use crate::Database;
+use crate::helpers;
+use crate::Operation;
+use crate::Result;
+
+type Boxed<DB> = BoxedSqlQuery<'static, DB, SqlQuery>;
+
+/// Rust type bound with `RawQuery::bind`, with its SQL type.
+pub trait BindValue
+{
+    type SqlType;
+}
+
+macro_rules! impl_bind_value {
+    ($($value:ty => $sql_type:ty),*) => {
+        $(
+            impl BindValue for $value
+            {
+                type SqlType = $sql_type;
+            }
+        )*
+    };
+}
+
+impl_bind_value!(
+    bool => sql_types::Bool,
+    i16 => sql_types::SmallInt,
+    i32 => sql_types::Integer,
+    i64 => sql_types::BigInt,
+    f32 => sql_types::Float,
+    f64 => sql_types::Double,
+    String => sql_types::Text,
+    Vec<u8> => sql_types::Binary
+);
+
+impl<T> BindValue for Option<T>
+    where T: BindValue,
+          T::SqlType: NotNull
+{
+    type SqlType = Nullable<T::SqlType>;
+}
+
+/// A bound value, applied to the query of each backend.
+trait Bind: Send + Sync
+{
+    fn mysql(&self, query: Boxed<Mysql>) -> Boxed<Mysql>;
+
+    fn pg(&self, query: Boxed<Pg>) -> Boxed<Pg>;
+
+    fn sqlite(&self, query: Boxed<Sqlite>) -> Boxed<Sqlite>;
+}
+
+struct Typed<ST, V>(V, PhantomData<fn() -> ST>);
+
+impl<ST, V> Bind for Typed<ST, V>
+    where ST: 'static,
+          V: ToSql<ST, Mysql> + ToSql<ST, Pg> + ToSql<ST, Sqlite> + Clone + Send + Sync + 'static,
+          Mysql: HasSqlType<ST>,
+          Pg: HasSqlType<ST>,
+          Sqlite: HasSqlType<ST>
+{
+    fn mysql(&self, query: Boxed<Mysql>) -> Boxed<Mysql>
+    {
+        query.bind::<ST, _>(self.0.clone())
+    }
+
+    fn pg(&self, query: Boxed<Pg>) -> Boxed<Pg>
+    {
+        query.bind::<ST, _>(self.0.clone())
+    }
+
+    fn sqlite(&self, query: Boxed<Sqlite>) -> Boxed<Sqlite>
+    {
+        query.bind::<ST, _>(self.0.clone())
+    }
+}
+
+/// Raw SQL statement with `?` placeholders and typed binds.
+pub struct RawQuery
+{
+    _sql: String,
+    _binds: Vec<Box<dyn Bind>>,
+}
+
+impl RawQuery
+{
+    pub fn new(sql: &str) -> Self
+    {
+        Self {
+            _sql: sql.to_owned(),
+            _binds: Vec::new()
+        }
+    }
+
+    /// Binds `value` to the next placeholder, with the SQL type of `T`.
+    pub fn bind<T>(self, value: T) -> Self
+        where T: BindValue + ToSql<T::SqlType, Mysql> + ToSql<T::SqlType, Pg> + ToSql<T::SqlType, Sqlite>
+                 + Clone + Send + Sync + 'static,
+              T::SqlType: 'static,
+              Mysql: HasSqlType<T::SqlType>,
+              Pg: HasSqlType<T::SqlType>,
+              Sqlite: HasSqlType<T::SqlType>
+    {
+        self.bind_as::<T::SqlType, T>(value)
+    }
+
+    /// Binds `value` to the next placeholder, with the SQL type `ST`.
+    pub fn bind_as<ST, V>(mut self, value: V) -> Self
+        where ST: 'static,
+              V: ToSql<ST, Mysql> + ToSql<ST, Pg> + ToSql<ST, Sqlite> + Clone + Send + Sync + 'static,
+              Mysql: HasSqlType<ST>,
+              Pg: HasSqlType<ST>,
+              Sqlite: HasSqlType<ST>
+    {
+        self._binds.push(Box::new(Typed::<ST, V>(value, PhantomData)));
+        self
+    }
+
+    /// Statement run on `backend`.
+    pub fn sql(&self, backend: Backend) -> String
+    {
+        helpers::number_placeholders(backend, &helpers::translate(backend, &self._sql))
+    }
+
+    /// Runs the query, loading its rows as `U`.
+    #[track_caller]
+    pub fn load<U>(&self, database: &Database) -> Result<Vec<U>>
+        where U: QueryableByName<Mysql> + QueryableByName<Pg> + QueryableByName<Sqlite>
+    {
+        database.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::from_sql(&self._sql),
+            |conn| self.mysql(database).load::<U>(&*conn),
+            |conn| self.pg(database).load::<U>(&*conn),
+            |conn| self.sqlite(database).load::<U>(&*conn)
+        )
+    }
+
+    /// Executes the statement, returning the number of affected rows.
+    #[track_caller]
+    pub fn execute(&self, database: &Database) -> Result<usize>
+    {
+        if let Some(result) = database.simulate(Operation::from_sql(&self._sql), |backend| {
+            database.tag_sql(&self.sql(backend))
+        }) {
+            return result;
+        }
+        database.interact_as::<_, diesel::result::Error, _, _, _>(
+            Operation::from_sql(&self._sql),
+            |conn| self.mysql(database).execute(&*conn),
+            |conn| self.pg(database).execute(&*conn),
+            |conn| self.sqlite(database).execute(&*conn)
+        )
+    }
+
+    fn mysql(&self, database: &Database) -> Boxed<Mysql>
+    {
+        let query = diesel::sql_query(database.tag_sql(&self.sql(Backend::Mysql))).into_boxed();
+
+        self._binds.iter().fold(query, |query, bind| bind.mysql(query))
+    }
+
+    fn pg(&self, database: &Database) -> Boxed<Pg>
+    {
+        let query = diesel::sql_query(database.tag_sql(&self.sql(Backend::Pg))).into_boxed();
+
+        self._binds.iter().fold(query, |query, bind| bind.pg(query))
+    }
+
+    fn sqlite(&self, database: &Database) -> Boxed<Sqlite>
+    {
+        let query = diesel::sql_query(database.tag_sql(&self.sql(Backend::Sqlite))).into_boxed();
+
+        self._binds.iter().fold(query, |query, bind| bind.sqlite(query))
+    }
+}
+
+impl fmt::Debug for RawQuery
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.debug_struct("RawQuery")
+            .field("sql", &self._sql)
+            .field("binds", &self._binds.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sql_types::{Integer, Text},
+        sqlite::SqliteConnection
+    };
+    use crate::{Backend, Database};
+    use super::RawQuery;
+
+    #[derive(QueryableByName, Debug, PartialEq)]
+    struct User {
+        #[sql_type = "Integer"]
+        id: i32,
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    #[test]
+    fn placeholders_per_backend() {
+        let query = RawQuery::new("SELECT * FROM \"users\" WHERE id = ? AND name = ?")
+            .bind::<i32>(7)
+            .bind::<String>("alice".to_owned());
+
+        assert_eq!(query.sql(Backend::Pg), "SELECT * FROM \"users\" WHERE id = $1 AND name = $2");
+        assert_eq!(query.sql(Backend::Mysql), "SELECT * FROM `users` WHERE id = ? AND name = ?");
+    }
+
+    #[test]
+    fn loads_with_typed_binds() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL);").unwrap();
+        let database = Database::from_connection(conn);
+
+        let inserted = RawQuery::new("INSERT INTO users (id, name) VALUES (?, ?), (?, ?)")
+            .bind::<i32>(7)
+            .bind::<String>("alice".to_owned())
+            .bind::<i32>(8)
+            .bind::<Option<String>>(Some("bob".to_owned()))
+            .execute(&database)
+            .unwrap();
+        assert_eq!(inserted, 2);
+
+        let users = RawQuery::new("SELECT * FROM users WHERE id = ?")
+            .bind::<i32>(7)
+            .load::<User>(&database)
+            .unwrap();
+        assert_eq!(users, vec![User { id: 7, name: "alice".to_owned() }]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Redaction of the log output and error messages of the crate.
+//!
+//! Beyond the password of URLs, which is always masked in reports, the
+//! `redaction` configuration table masks sensitive values wherever the
+//! crate logs or displays an error:
+//!
+//! ```json
+//! "redaction": {
+//!     "keys": ["password", "token"],
+//!     "patterns": ["\\b\\d{13,19}\\b"]
+//! }
+//! ```
+//!
+//! The value following a key of `keys` (`token=...`, `token: ...`,
+//! `"token": "..."`, case-insensitively) is masked, and so is every match
+//! of the regular expressions of `patterns`.
+//!
+//! The rules are process-wide, as the logger is: the last database
+//! initialized sets them. The crate logs through `log_redacted!`, which
+//! formats the message only when its level is enabled.
+
+use regex::Regex;
+
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering
+        },
+        RwLock
+    }
+};
+
+use crate::error;
+use crate::Result;
+
+/// Replacement of the redacted values.
+pub const MASK: &str = "***";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RULES: RwLock<Redaction> = RwLock::new(Redaction { _rules: Vec::new() });
+
+/// Logs at `$level` (a `log::Level`), with the redaction rules applied to
+/// the message.
+macro_rules! log_redacted {
+    ($level:expr, $($arg:tt)+) => {{
+        let level = $level;
+
+        if ::log::log_enabled!(level) {
+            ::log::log!(level, "{}", $crate::redaction::redact(&format!($($arg)+)));
+        }
+    }};
+}
+
+pub(crate) use log_redacted;
+
+/// `redaction` section: rules masking sensitive values.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redaction {
+    _rules: Vec<(Regex, &'static str)>,
+}
+
+impl Redaction {
+    /// Rules masking the values of `keys` and the matches of `patterns`.
+    pub fn new(keys: &[String], patterns: &[String]) -> Result<Self> {
+        let mut rules = Vec::with_capacity(keys.len() + patterns.len());
+
+        for key in keys {
+            let rule = format!(
+                r#"(?i)(["']?\b{}\b["']?\s*[:=]\s*["']?)[^\s"'&,;}}]+"#, regex::escape(key)
+            );
+            rules.push((Regex::new(&rule).map_err(|err| error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("invalid key `{}` in `redaction.keys`: {}", key, err)
+            ))?, "${1}***"));
+        }
+        for pattern in patterns {
+            rules.push((Regex::new(pattern).map_err(|err| error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("invalid pattern `{}` in `redaction.patterns`: {}", pattern, err)
+            ))?, MASK));
+        }
+
+        Ok(Self { _rules: rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self._rules.is_empty()
+    }
+
+    /// Masks the sensitive values of `text`.
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self._rules.iter().fold(Cow::Borrowed(text), |text, (rule, replacement)| {
+            match rule.replace_all(&text, *replacement) {
+                Cow::Borrowed(_) => text,
+                Cow::Owned(redacted) => Cow::Owned(redacted)
+            }
+        })
+    }
+}
+
+/// Makes `redaction` the rules of the process.
+pub(crate) fn configure(redaction: &Redaction) {
+    if let Ok(mut rules) = RULES.write() {
+        *rules = redaction.clone();
+        ENABLED.store(!redaction.is_empty(), Ordering::Release);
+    }
+}
+
+/// Returns whether rules are configured.
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Masks the sensitive values of `text` with the rules of the process.
+pub(crate) fn redact(text: &str) -> Cow<'_, str> {
+    if !enabled() {
+        return Cow::Borrowed(text);
+    }
+
+    match RULES.read() {
+        Ok(rules) => Cow::Owned(rules.apply(text).into_owned()),
+        Err(_) => Cow::Borrowed(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redaction;
+
+    #[test]
+    fn masks_keys_and_patterns() {
+        let redaction = Redaction::new(
+            &["token".to_owned(), "password".to_owned()],
+            &[r"\b\d{16}\b".to_owned()]
+        ).unwrap();
+
+        assert_eq!(
+            redaction.apply("token=abc&Password: hunter2 card 4111111111111111"),
+            "token=***&Password: *** card ***"
+        );
+        assert_eq!(redaction.apply(r#"{"token": "abc", "id": 1}"#), r#"{"token": "***", "id": 1}"#);
+        assert_eq!(redaction.apply("tokens are fine"), "tokens are fine");
+    }
+
+    #[test]
+    fn invalid_patterns_are_format_errors() {
+        let err = Redaction::new(&[], &["(".to_owned()]).unwrap_err();
+
+        assert_eq!(err.kind(), crate::error::ErrorKind::FormatError);
+    }
+}
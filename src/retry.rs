@@ -0,0 +1,83 @@
+//! Time budget of the retries of a request.
+//!
+//! Retries (`retry_on_disconnect`, retrying layers) each look reasonable,
+//! but stacked they multiply into multi-second tail latencies. With the
+//! `retry_budget` configuration key, the time spent retrying is summed over
+//! the whole request, and retries stop once it reaches the budget.
+//!
+//! The time spent is kept in a thread local, reset by the fairing when a
+//! request comes in: interactions outside of requests are not limited.
+//! Retrying layers check `InteractContext::retry_budget` before retrying,
+//! and charge the time the retry took with
+//! `InteractContext::spend_retry_budget`.
+
+use std::{
+    cell::Cell,
+    time::Duration
+};
+
+thread_local! {
+    static SPENT: Cell<Option<Duration>> = Cell::new(None);
+}
+
+/// Starts accounting the retries of the request handled by the current
+/// thread.
+pub(crate) fn begin() {
+    SPENT.with(|spent| spent.set(Some(Duration::from_secs(0))));
+}
+
+/// Stops accounting retries on the current thread.
+pub(crate) fn end() {
+    SPENT.with(|spent| spent.set(None));
+}
+
+/// Charges `duration` to the retries of the current request, if any.
+pub(crate) fn spend(duration: Duration) {
+    SPENT.with(|spent| if let Some(total) = spent.get() {
+        spent.set(Some(total + duration));
+    });
+}
+
+/// Returns the budget left to the retries of the current request out of
+/// `budget`, `None` when not limited.
+pub(crate) fn remaining(budget: Option<Duration>) -> Option<Duration> {
+    let spent = SPENT.with(Cell::get)?;
+
+    budget.map(|budget| budget.checked_sub(spent).unwrap_or_else(|| Duration::from_secs(0)))
+}
+
+/// Returns whether the budget `budget` allows one more retry.
+pub(crate) fn allows(budget: Option<Duration>) -> bool {
+    remaining(budget).map_or(true, |remaining| remaining > Duration::from_secs(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{allows, begin, end, remaining, spend};
+
+    #[test]
+    fn limits_retries_of_the_request() {
+        let budget = Some(Duration::from_millis(100));
+
+        end();
+        spend(Duration::from_secs(1));
+        assert_eq!(remaining(budget), None);
+
+        begin();
+        assert_eq!(remaining(None), None);
+        assert_eq!(remaining(budget), budget);
+
+        spend(Duration::from_millis(60));
+        assert_eq!(remaining(budget), Some(Duration::from_millis(40)));
+        assert!(allows(budget));
+
+        spend(Duration::from_millis(60));
+        assert_eq!(remaining(budget), Some(Duration::from_secs(0)));
+        assert!(!allows(budget));
+        assert!(allows(None));
+
+        end();
+        assert!(allows(budget));
+    }
+}
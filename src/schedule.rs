@@ -0,0 +1,264 @@
+//! Jobs run by a single instance of a fleet each period.
+//!
+//! `Database::schedule_exclusive(name, every, f)` runs `f` once every
+//! `every`, on one instance only when several share the database:
+//!
+//! ```rust,ignore
+//! database.schedule_exclusive("purge_tokens", Duration::from_secs(3600), |database| {
+//!     database.execute_sql("DELETE FROM tokens WHERE expires_at < CURRENT_TIMESTAMP").map(|_| ())
+//! });
+//! ```
+//!
+//! Time is split in periods of `every` since the Unix epoch. The instance
+//! which first advances the last period of the job, in the
+//! `_rocket_diesel_schedules` table, runs it for that period; the others
+//! skip it. Periods skipped by every instance (e.g. while all were down)
+//! are logged as missed runs, and are not caught up.
+//!
+//! While a job runs, its instance holds a lock named after the job
+//! (a Postgres advisory lock, a MySQL `GET_LOCK` lock): an instance does
+//! not start the next period while the previous one is still running
+//! elsewhere. SQLite databases, local to a host, are not locked.
+//!
+//! The table is created by the bootstrap step (see the `bootstrap`
+//! configuration key and `Database::bootstrap`).
+
+use diesel::{
+    query_dsl::RunQueryDsl,
+    result::QueryResult,
+    sql_types::{
+        BigInt,
+        Text
+    }
+};
+
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH
+};
+
+use crate::Result;
+
+/// Name of the schedules table.
+pub const TABLE: &str = "_rocket_diesel_schedules";
+
+#[derive(QueryableByName)]
+struct PeriodRow
+{
+    #[sql_type = "BigInt"]
+    last_period: i64,
+}
+
+#[derive(QueryableByName)]
+struct LockRow
+{
+    #[sql_type = "BigInt"]
+    locked: i64,
+}
+
+/// Backend-specific statements of the schedules table and job locks.
+pub trait ScheduleConnection: diesel::Connection
+{
+    /// Creates the row of `name` unless it exists.
+    fn ensure_schedule(&self, name: &str) -> QueryResult<()>;
+
+    /// Last period `name` ran for, `-1` if it never ran.
+    fn last_period(&self, name: &str) -> QueryResult<i64>;
+
+    /// Moves the last period of `name` from `last` to `period`, returning
+    /// whether this connection did.
+    fn advance(&self, name: &str, last: i64, period: i64) -> QueryResult<bool>;
+
+    /// Takes the lock of `name` without waiting, returning whether it did.
+    fn try_lock(&self, name: &str) -> QueryResult<bool>;
+
+    fn unlock(&self, name: &str) -> QueryResult<()>;
+}
+
+macro_rules! impl_schedule_connection {
+    ($connection:ty, $insert:expr, $select:expr, $update:expr, $lock:expr, $unlock:expr) => {
+        impl ScheduleConnection for $connection
+        {
+            fn ensure_schedule(&self, name: &str) -> QueryResult<()>
+            {
+                diesel::sql_query($insert)
+                    .bind::<Text, _>(name)
+                    .execute(self)
+                    .map(|_| ())
+            }
+
+            fn last_period(&self, name: &str) -> QueryResult<i64>
+            {
+                Ok(diesel::sql_query($select)
+                    .bind::<Text, _>(name)
+                    .load::<PeriodRow>(self)?
+                    .into_iter()
+                    .next()
+                    .map_or(-1, |row| row.last_period))
+            }
+
+            fn advance(&self, name: &str, last: i64, period: i64) -> QueryResult<bool>
+            {
+                diesel::sql_query($update)
+                    .bind::<BigInt, _>(period)
+                    .bind::<Text, _>(name)
+                    .bind::<BigInt, _>(last)
+                    .execute(self)
+                    .map(|updated| updated == 1)
+            }
+
+            fn try_lock(&self, name: &str) -> QueryResult<bool>
+            {
+                match $lock {
+                    None => Ok(true),
+                    Some(lock) => Ok(diesel::sql_query(lock)
+                        .bind::<Text, _>(name)
+                        .load::<LockRow>(self)?
+                        .into_iter()
+                        .next()
+                        .map_or(false, |row| row.locked == 1))
+                }
+            }
+
+            fn unlock(&self, name: &str) -> QueryResult<()>
+            {
+                match $unlock {
+                    None => Ok(()),
+                    Some(unlock) => diesel::sql_query(unlock)
+                        .bind::<Text, _>(name)
+                        .load::<LockRow>(self)
+                        .map(|_| ())
+                }
+            }
+        }
+    };
+}
+
+impl_schedule_connection!(
+    diesel::MysqlConnection,
+    "INSERT IGNORE INTO _rocket_diesel_schedules (name, last_period) VALUES (?, -1)",
+    "SELECT last_period FROM _rocket_diesel_schedules WHERE name = ?",
+    "UPDATE _rocket_diesel_schedules SET last_period = ? WHERE name = ? AND last_period = ?",
+    Some("SELECT CAST(COALESCE(GET_LOCK(CONCAT('_rocket_diesel_', ?), 0), 0) AS SIGNED) AS locked"),
+    Some("SELECT CAST(COALESCE(RELEASE_LOCK(CONCAT('_rocket_diesel_', ?)), 0) AS SIGNED) AS locked")
+);
+
+impl_schedule_connection!(
+    diesel::PgConnection,
+    "INSERT INTO _rocket_diesel_schedules (name, last_period) VALUES ($1, -1) ON CONFLICT (name) DO NOTHING",
+    "SELECT last_period FROM _rocket_diesel_schedules WHERE name = $1",
+    "UPDATE _rocket_diesel_schedules SET last_period = $1 WHERE name = $2 AND last_period = $3",
+    Some("SELECT CAST(CASE WHEN pg_try_advisory_lock(hashtext('_rocket_diesel_' || $1)) THEN 1 ELSE 0 END AS BIGINT) AS locked"),
+    Some("SELECT CAST(CASE WHEN pg_advisory_unlock(hashtext('_rocket_diesel_' || $1)) THEN 1 ELSE 0 END AS BIGINT) AS locked")
+);
+
+impl_schedule_connection!(
+    diesel::SqliteConnection,
+    "INSERT OR IGNORE INTO _rocket_diesel_schedules (name, last_period) VALUES (?, -1)",
+    "SELECT last_period FROM _rocket_diesel_schedules WHERE name = ?",
+    "UPDATE _rocket_diesel_schedules SET last_period = ? WHERE name = ? AND last_period = ?",
+    None::<&str>,
+    None::<&str>
+);
+
+/// Outcome of an attempt to run a job for a period.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Claim
+{
+    /// This instance runs the job; `missed` periods were skipped since its
+    /// last run. The lock of the job is held.
+    Claimed { missed: i64 },
+    /// The job already ran for the period.
+    Done,
+    /// The job is running on another instance.
+    Running,
+}
+
+/// Period of `every` that `now` falls in.
+pub(crate) fn period(now: SystemTime, every: Duration) -> i64
+{
+    let elapsed = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+    (elapsed / every.as_millis().max(1)) as i64
+}
+
+/// Time from `now` to the start of the next period of `every`.
+pub(crate) fn until_next_period(now: SystemTime, every: Duration) -> Duration
+{
+    let every = every.as_millis().max(1);
+    let elapsed = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+    Duration::from_millis((every - elapsed % every) as u64)
+}
+
+/// Claims the run of the job `name` for `period`, taking its lock.
+pub fn claim<C: ScheduleConnection>(conn: &C, name: &str, period: i64) -> Result<Claim>
+{
+    if !conn.try_lock(name)? {
+        return Ok(Claim::Running);
+    }
+
+    let result = (|| -> Result<Claim> {
+        conn.ensure_schedule(name)?;
+        let last = conn.last_period(name)?;
+
+        if last >= period || !conn.advance(name, last, period)? {
+            return Ok(Claim::Done);
+        }
+        Ok(Claim::Claimed {
+            missed: match last {
+                -1 => 0,
+                last => period - last - 1
+            }
+        })
+    })();
+
+    match result {
+        Ok(Claim::Claimed { .. }) => {},
+        _ => conn.unlock(name)?
+    }
+    result
+}
+
+/// Releases the lock of the job `name` once it ran.
+pub fn release<C: ScheduleConnection>(conn: &C, name: &str) -> Result<()>
+{
+    conn.unlock(name).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use std::time::{
+        Duration,
+        UNIX_EPOCH
+    };
+    use crate::{bootstrap, Backend};
+    use super::{claim, period, release, until_next_period, Claim};
+
+    #[test]
+    fn splits_time_in_periods() {
+        let now = UNIX_EPOCH + Duration::from_secs(3 * 3600 + 60);
+        let every = Duration::from_secs(3600);
+
+        assert_eq!(period(now, every), 3);
+        assert_eq!(until_next_period(now, every), Duration::from_secs(3540));
+    }
+
+    #[test]
+    fn claims_each_period_once() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        bootstrap::run(&conn, Backend::Sqlite).unwrap();
+
+        assert_eq!(claim(&conn, "purge", 10).unwrap(), Claim::Claimed { missed: 0 });
+        release(&conn, "purge").unwrap();
+        assert_eq!(claim(&conn, "purge", 10).unwrap(), Claim::Done);
+        assert_eq!(claim(&conn, "purge", 9).unwrap(), Claim::Done);
+        assert_eq!(claim(&conn, "purge", 13).unwrap(), Claim::Claimed { missed: 2 });
+        assert_eq!(claim(&conn, "other", 13).unwrap(), Claim::Claimed { missed: 0 });
+    }
+}
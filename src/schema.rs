@@ -0,0 +1,251 @@
+//! Detection of a live schema diverging from the application's `table!`s.
+//!
+//! ```rust,ignore
+//! let schema = Schema::new()
+//!     .register::<users::table>("users")
+//!     .register::<posts::table>("posts");
+//!
+//! rocket::ignite().attach(Database::default().with_schema(schema))
+//! ```
+//!
+//! In debug builds, once the connection is established (and migrations
+//! applied), the columns of the registered tables are compared with the
+//! database and every divergence is logged as a warning: it usually means
+//! migrations were not run, or `schema.rs` was not regenerated.
+
+use diesel::{
+    query_dsl::RunQueryDsl,
+    result::QueryResult,
+    sql_types::Text,
+    Column,
+    Table
+};
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet
+    },
+    fmt
+};
+
+use crate::sharding::HashRouter;
+
+/// Names of the columns of a tuple of columns, i.e. of `table::all_columns`.
+pub trait ColumnNames
+{
+    fn column_names() -> Vec<&'static str>;
+}
+
+macro_rules! impl_column_names {
+    ($($column:ident),+) => {
+        impl<$($column: Column),+> ColumnNames for ($($column,)+)
+        {
+            fn column_names() -> Vec<&'static str>
+            {
+                vec![$($column::NAME),+]
+            }
+        }
+    };
+}
+
+impl_column_names!(A);
+impl_column_names!(A, B);
+impl_column_names!(A, B, C);
+impl_column_names!(A, B, C, D);
+impl_column_names!(A, B, C, D, E);
+impl_column_names!(A, B, C, D, E, F);
+impl_column_names!(A, B, C, D, E, F, G);
+impl_column_names!(A, B, C, D, E, F, G, H);
+impl_column_names!(A, B, C, D, E, F, G, H, I);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J, K);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J, K, L);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J, K, L, M);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
+impl_column_names!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+/// Tables and columns expected by the application.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Schema
+{
+    _tables: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Schema
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Expects the columns of the `table!` `T`, named `name` in the database.
+    pub fn register<T>(self, name: &str) -> Self
+        where T: Table,
+              T::AllColumns: ColumnNames
+    {
+        self.table(name, &<T::AllColumns as ColumnNames>::column_names())
+    }
+
+    /// Expects the table `name` with `columns`.
+    pub fn table(mut self, name: &str, columns: &[&str]) -> Self
+    {
+        self._tables.entry(name.to_owned())
+            .or_insert_with(BTreeSet::new)
+            .extend(columns.iter().map(|column| (*column).to_owned()));
+        self
+    }
+
+    /// Hash of the expected tables and columns, stable across builds.
+    pub fn fingerprint(&self) -> u64
+    {
+        let mut description = String::new();
+
+        for (table, columns) in &self._tables {
+            description.push_str(table);
+            for column in columns {
+                description.push(' ');
+                description.push_str(column);
+            }
+            description.push('\n');
+        }
+
+        HashRouter::hash(description.as_bytes())
+    }
+
+    /// Differences between the expected schema and the one of `conn`.
+    pub fn compare<C: SchemaConnection>(&self, conn: &C) -> QueryResult<Vec<SchemaDrift>>
+    {
+        let mut drifts = Vec::new();
+
+        for (table, expected) in &self._tables {
+            let live = conn.column_names(table)?.into_iter().collect::<BTreeSet<String>>();
+
+            if live.is_empty() {
+                drifts.push(SchemaDrift::MissingTable(table.clone()));
+                continue;
+            }
+
+            for column in expected.difference(&live) {
+                drifts.push(SchemaDrift::MissingColumn(table.clone(), column.clone()));
+            }
+            for column in live.difference(expected) {
+                drifts.push(SchemaDrift::UnexpectedColumn(table.clone(), column.clone()));
+            }
+        }
+
+        Ok(drifts)
+    }
+}
+
+/// Difference between the expected and the live schema.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SchemaDrift
+{
+    /// The table does not exist in the database.
+    MissingTable(String),
+    /// The column (table, column) does not exist in the database.
+    MissingColumn(String, String),
+    /// The column (table, column) exists in the database only.
+    UnexpectedColumn(String, String),
+}
+
+impl fmt::Display for SchemaDrift
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            SchemaDrift::MissingTable(table) => write!(fmt, "table `{}` is missing", table),
+            SchemaDrift::MissingColumn(table, column) => write!(fmt, "column `{}.{}` is missing", table, column),
+            SchemaDrift::UnexpectedColumn(table, column) => write!(fmt, "column `{}.{}` is not in schema.rs", table, column),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct ColumnRow
+{
+    #[sql_type = "Text"]
+    name: String,
+}
+
+/// Backend-specific introspection of the live schema.
+pub trait SchemaConnection: diesel::Connection
+{
+    /// Columns of `table`, empty when it does not exist.
+    fn column_names(&self, table: &str) -> QueryResult<Vec<String>>;
+}
+
+macro_rules! impl_schema_connection {
+    ($connection:ty, $select:expr) => {
+        impl SchemaConnection for $connection
+        {
+            fn column_names(&self, table: &str) -> QueryResult<Vec<String>>
+            {
+                Ok(diesel::sql_query($select)
+                    .bind::<Text, _>(table)
+                    .load::<ColumnRow>(self)?
+                    .into_iter()
+                    .map(|row| row.name)
+                    .collect())
+            }
+        }
+    };
+}
+
+impl_schema_connection!(
+    diesel::MysqlConnection,
+    "SELECT COLUMN_NAME AS name FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?"
+);
+
+impl_schema_connection!(
+    diesel::PgConnection,
+    "SELECT column_name::TEXT AS name FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1"
+);
+
+impl_schema_connection!(
+    diesel::SqliteConnection,
+    "SELECT name FROM pragma_table_info(?)"
+);
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use super::{Schema, SchemaDrift};
+
+    table! {
+        users (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    #[test]
+    fn registers_table_columns() {
+        let schema = Schema::new().register::<users::table>("users");
+
+        assert_eq!(schema, Schema::new().table("users", &["name", "id"]));
+        assert_eq!(schema.fingerprint(), Schema::new().table("users", &["id", "name"]).fingerprint());
+        assert_ne!(schema.fingerprint(), Schema::new().table("users", &["id"]).fingerprint());
+    }
+
+    #[test]
+    fn detects_drifts() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER, email TEXT)").unwrap();
+
+        let schema = Schema::new()
+            .register::<users::table>("users")
+            .table("posts", &["id"]);
+
+        assert_eq!(schema.compare(&conn).unwrap(), vec![
+            SchemaDrift::MissingTable("posts".to_owned()),
+            SchemaDrift::MissingColumn("users".to_owned(), "name".to_owned()),
+            SchemaDrift::UnexpectedColumn("users".to_owned(), "email".to_owned()),
+        ]);
+    }
+}
@@ -0,0 +1,190 @@
+//! Database interactions behind a service interface.
+//!
+//! `DatabaseService` follows the shape of tower's `Service`: `poll_ready`
+//! tells whether an interaction would start right away, `call` runs one.
+//! Admission control (load shedding, concurrency limits) composed around
+//! services can so take the readiness of the database into account:
+//!
+//! ```rust,ignore
+//! let mut service = DatabaseService::new(database.clone());
+//!
+//! match service.poll_ready() {
+//!     Poll::Ready(Ok(())) => service.call(InteractRequest::new(
+//!         |conn| users::table.count().get_result(conn),
+//!         |conn| users::table.count().get_result(conn),
+//!         |conn| users::table.count().get_result(conn)
+//!     )),
+//!     _ => Err(overloaded()),
+//! }
+//! ```
+//!
+//! The service is pending while the connection is in use or awaited, and
+//! while the circuit breaker is open (see `Database::available`). A
+//! connection not established yet counts as ready: `call` establishes it.
+//!
+//! With the `tower` feature, `DatabaseService` implements
+//! `tower_service::Service<InteractRequest<T>>`. Interactions being
+//! blocking, `call` runs the interaction before returning an already
+//! completed future; while pending, `poll_ready` asks to be polled again.
+
+use diesel::{
+    mysql::MysqlConnection,
+    pg::PgConnection,
+    result::QueryResult,
+    sqlite::SqliteConnection
+};
+
+use std::{
+    fmt,
+    task::Poll
+};
+
+use crate::Database;
+use crate::Operation;
+use crate::Result;
+
+/// Interaction run by a `DatabaseService`, with a closure per backend as
+/// for `Database::interact`.
+pub struct InteractRequest<T>
+{
+    _operation: Operation,
+    _mysql: Box<dyn FnOnce(&mut MysqlConnection) -> QueryResult<T> + Send>,
+    _pg: Box<dyn FnOnce(&mut PgConnection) -> QueryResult<T> + Send>,
+    _sqlite: Box<dyn FnOnce(&mut SqliteConnection) -> QueryResult<T> + Send>,
+}
+
+impl<T> InteractRequest<T>
+{
+    pub fn new<MysqlF, PgF, SqliteF>(mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Self
+        where MysqlF: FnOnce(&mut MysqlConnection) -> QueryResult<T> + Send + 'static,
+              PgF: FnOnce(&mut PgConnection) -> QueryResult<T> + Send + 'static,
+              SqliteF: FnOnce(&mut SqliteConnection) -> QueryResult<T> + Send + 'static
+    {
+        Self {
+            _operation: Operation::Unknown,
+            _mysql: Box::new(mysql_f),
+            _pg: Box::new(pg_f),
+            _sqlite: Box::new(sqlite_f)
+        }
+    }
+
+    /// Labels the interaction in the statistics (see
+    /// `Database::interact_as`).
+    pub fn with_operation(mut self, operation: Operation) -> Self
+    {
+        self._operation = operation;
+        self
+    }
+
+    pub fn operation(&self) -> Operation
+    {
+        self._operation
+    }
+}
+
+impl<T> fmt::Debug for InteractRequest<T>
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.debug_struct("InteractRequest")
+            .field("operation", &self._operation)
+            .finish()
+    }
+}
+
+/// Service running the interactions it is called with on a database.
+#[derive(Clone, Debug)]
+pub struct DatabaseService
+{
+    _database: Database,
+}
+
+impl DatabaseService
+{
+    pub fn new(database: Database) -> Self
+    {
+        Self { _database: database }
+    }
+
+    pub fn database(&self) -> &Database
+    {
+        &self._database
+    }
+
+    /// `Ready` when an interaction would start right away, `Pending`
+    /// otherwise.
+    pub fn poll_ready(&mut self) -> Poll<Result<()>>
+    {
+        match self._database.available() {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending
+        }
+    }
+
+    /// Runs the interaction of `request`.
+    #[track_caller]
+    pub fn call<T>(&mut self, request: InteractRequest<T>) -> Result<T>
+    {
+        let InteractRequest { _operation: operation, _mysql: mysql_f, _pg: pg_f, _sqlite: sqlite_f } = request;
+
+        self._database.interact_as(operation, mysql_f, pg_f, sqlite_f)
+    }
+}
+
+#[cfg(feature = "tower")]
+impl<T> tower_service_crate::Service<InteractRequest<T>> for DatabaseService
+{
+    type Response = T;
+    type Error = crate::error::Error;
+    type Future = std::future::Ready<Result<T>>;
+
+    fn poll_ready(&mut self, context: &mut std::task::Context<'_>) -> Poll<Result<()>>
+    {
+        match DatabaseService::poll_ready(self) {
+            Poll::Pending => {
+                // Nothing notifies the release of the connection
+                context.waker().wake_by_ref();
+                Poll::Pending
+            },
+            ready => ready
+        }
+    }
+
+    fn call(&mut self, request: InteractRequest<T>) -> Self::Future
+    {
+        std::future::ready(DatabaseService::call(self, request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use crate::Database;
+    use super::{DatabaseService, InteractRequest};
+
+    #[test]
+    fn ready_unless_the_connection_is_in_use() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let mut service = DatabaseService::new(database.clone());
+
+        assert!(service.poll_ready().is_ready());
+
+        let mut inner = service.clone();
+        let ready = database.interact::<_, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Ok(inner.poll_ready().is_ready())
+        ).unwrap();
+        assert!(!ready);
+
+        let answer = service.call(InteractRequest::new(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Ok(42)
+        )).unwrap();
+        assert_eq!(answer, 42);
+    }
+}
@@ -0,0 +1,389 @@
+//! Experimental: transactions held open across several requests.
+//!
+//! Some multi-step operations (e.g. an admin UI previewing changes before
+//! they are confirmed) need a transaction spanning several requests. A
+//! session begins a transaction on a connection of its own, and hands out a
+//! token the next requests use to find it:
+//!
+//! ```rust,ignore
+//! #[post("/imports")]
+//! fn preview(database: State<'_, Database>) -> Result<String, Error> {
+//!     let session = database.begin_session(Duration::from_secs(120))?;
+//!     session.run(|conn| /* import */, |conn| /* import */, |conn| /* import */)?;
+//!     Ok(session.token().to_owned())
+//! }
+//!
+//! #[post("/imports/<token>/confirm")]
+//! fn confirm(database: State<'_, Database>, token: String) -> Result<(), Error> {
+//!     database.session(&token)?.commit()
+//! }
+//! ```
+//!
+//! A session lives until committed, rolled back or expired: its time to
+//! live is strict (using the session does not extend it) and capped by
+//! `sessions.max_ttl`. A reaper thread rolls back expired sessions every
+//! second, and so does looking an expired session up. Dropping a `Session`
+//! handle leaves the session open.
+//!
+//! Use with care:
+//!
+//! - each session holds a connection of its own, outside of the connection
+//!   of the database, and at most `sessions.max` sessions are open at once;
+//! - the transaction holds its locks until it ends: rows (with SQLite, the
+//!   whole database file) written by a session block other writers for up
+//!   to its time to live, which should thus be as short as possible;
+//! - the token is a bearer secret: anyone knowing it can act within the
+//!   transaction. It is not tied to a user, nor to a server: sessions do not
+//!   survive a restart and are not shared between instances.
+
+use rand_crate::{
+    rngs::OsRng,
+    RngCore
+};
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    panic::Location,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering
+        },
+        Arc,
+        Mutex,
+        Weak
+    },
+    thread,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::redaction::log_redacted;
+use crate::clock::SharedClock;
+use crate::database;
+use crate::Database;
+use crate::error;
+use crate::Result;
+use crate::tx::{
+    self,
+    Action
+};
+
+/// Interval between two runs of the reaper.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Open session, registered by its token.
+#[derive(Clone)]
+struct Entry {
+    _connection: Database,
+    _expires_at: Instant,
+    // Set, with the connection locked, once the transaction ended
+    _ended: Arc<AtomicBool>,
+}
+
+/// Sessions open on a database. Clones of the database share them.
+#[derive(Default)]
+pub(crate) struct Sessions {
+    _entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Sessions {
+    /// Begins a transaction on `connection`, registering it as a session
+    /// expiring at `expires_at`, unless `max_sessions` are already open.
+    pub fn open(&self, connection: Database, expires_at: Instant, max_sessions: usize) -> Result<(String, Arc<AtomicBool>)> {
+        let mut entries = self.entries()?;
+
+        if entries.len() >= max_sessions {
+            return Err(error::Error::new(
+                error::ErrorKind::QuotaExceeded,
+                format!("{} session(s) already open (`sessions.max`)", entries.len())
+            ));
+        }
+
+        {
+            let mut lock = connection.lock_initialized()?;
+            tx::transaction(&mut lock, Action::Begin)?;
+        }
+
+        let token = token();
+        let ended = Arc::new(AtomicBool::new(false));
+
+        entries.insert(token.clone(), Entry {
+            _connection: connection,
+            _expires_at: expires_at,
+            _ended: ended.clone()
+        });
+        Ok((token, ended))
+    }
+
+    /// Returns the connection of the session `token`, rolling it back when
+    /// it expired.
+    fn get(&self, token: &str, now: Instant) -> Result<Entry> {
+        let entry = self.entries()?.get(token).cloned();
+
+        match entry {
+            Some(ref entry) if entry._expires_at > now => Ok(entry.clone()),
+            Some(_) => {
+                self.end(token, Action::Rollback)?;
+                Err(expired())
+            },
+            None => Err(expired())
+        }
+    }
+
+    /// Ends the transaction of the session `token` and closes its
+    /// connection.
+    pub fn end(&self, token: &str, action: Action) -> Result<()> {
+        let entry = self.entries()?.remove(token).ok_or_else(expired)?;
+        let mut lock = entry._connection.lock_initialized()?;
+
+        entry._ended.store(true, Ordering::SeqCst);
+        let result = tx::transaction(&mut lock, action);
+
+        if let (Err(err), Action::Commit) = (&result, action) {
+            log_redacted!(log::Level::Error, "Failed to commit the session, rolling it back: {}", err);
+            let _ = tx::transaction(&mut lock, Action::Rollback);
+        }
+        result
+    }
+
+    /// Rolls back the sessions expired at `now`, returning how many were.
+    pub fn reap(&self, now: Instant) -> usize {
+        let expired = match self.entries() {
+            Ok(entries) => entries.iter()
+                .filter(|(_, entry)| entry._expires_at <= now)
+                .map(|(token, _)| token.clone())
+                .collect::<Vec<_>>(),
+            Err(_err) => return 0
+        };
+
+        let mut reaped = 0;
+        for token in expired {
+            match self.end(&token, Action::Rollback) {
+                Ok(()) => {
+                    log_redacted!(log::Level::Warn, "Rolled back an expired session.");
+                    reaped += 1;
+                },
+                Err(err) => log_redacted!(log::Level::Error, "Failed to roll back an expired session: {}", err)
+            }
+        }
+        reaped
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().map(|entries| entries.len()).unwrap_or(0)
+    }
+
+    fn entries(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Entry>>> {
+        self._entries.lock().map_err(|_err| error::Error::new(
+            error::ErrorKind::Other, "sessions got poisoned"
+        ))
+    }
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Sessions")
+            .field("open", &self.len())
+            .finish()
+    }
+}
+
+/// Spawns the thread rolling back the expired sessions, until the sessions
+/// are dropped.
+pub(crate) fn start_reaper(sessions: &Arc<Sessions>, clock: SharedClock) {
+    let sessions: Weak<Sessions> = Arc::downgrade(sessions);
+
+    thread::spawn(move || loop {
+        thread::sleep(REAP_INTERVAL);
+
+        match sessions.upgrade() {
+            Some(sessions) => sessions.reap(clock.now()),
+            None => break
+        };
+    });
+}
+
+/// Random token of 256 bits, hex-encoded.
+fn token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn expired() -> error::Error {
+    error::Error::new(error::ErrorKind::SessionExpired, "unknown or expired session")
+}
+
+/// Handle on an open session.
+pub struct Session {
+    _database: Database,
+    _sessions: Arc<Sessions>,
+    _token: String,
+    _entry: Entry,
+}
+
+impl Session {
+    pub(crate) fn open(database: &Database, sessions: &Arc<Sessions>, connection: Database, ttl: Duration, max_sessions: usize)
+        -> Result<Self>
+    {
+        let expires_at = database.now() + ttl;
+        let (token, ended) = sessions.open(connection.clone(), expires_at, max_sessions)?;
+
+        Ok(Self {
+            _database: database.clone(),
+            _sessions: sessions.clone(),
+            _token: token,
+            _entry: Entry {
+                _connection: connection,
+                _expires_at: expires_at,
+                _ended: ended
+            }
+        })
+    }
+
+    pub(crate) fn find(database: &Database, sessions: &Arc<Sessions>, token: &str) -> Result<Self> {
+        let entry = sessions.get(token, database.now())?;
+
+        Ok(Self {
+            _database: database.clone(),
+            _sessions: sessions.clone(),
+            _token: token.to_owned(),
+            _entry: entry
+        })
+    }
+
+    /// Token the next requests find the session with.
+    pub fn token(&self) -> &str {
+        &self._token
+    }
+
+    /// Time the session is rolled back at, unless ended before.
+    pub fn expires_at(&self) -> Instant {
+        self._entry._expires_at
+    }
+
+    /// Executes the closure matching the backend inside the transaction of
+    /// the session.
+    ///
+    /// The interaction goes through the layers of the database, e.g. its
+    /// statistics and route quotas.
+    #[track_caller]
+    pub fn run<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+
+        if self._database.now() >= self._entry._expires_at {
+            let _ = self._sessions.end(&self._token, Action::Rollback);
+            return Err(expired().with_location(caller));
+        }
+
+        let mut lock = self._entry._connection.lock_initialized()?;
+        // Ended (e.g. reaped) while waiting for the connection
+        if self._entry._ended.load(Ordering::SeqCst) {
+            return Err(expired().with_location(caller));
+        }
+
+        self._database.layered(caller, |context| {
+            let value = database::run_on(&mut lock, context, mysql_f, pg_f, sqlite_f);
+
+            tx::rollback_idle(&mut lock, 1, caller);
+            value
+        }).map(|outcome| outcome.value)
+    }
+
+    /// Commits the transaction and closes the session.
+    pub fn commit(self) -> Result<()> {
+        self._sessions.end(&self._token, Action::Commit)
+    }
+
+    /// Rolls the transaction back and closes the session.
+    pub fn rollback(self) -> Result<()> {
+        self._sessions.end(&self._token, Action::Rollback)
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Session")
+            .field("token", &"***")
+            .field("expires_at", &self._entry._expires_at)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, TransactionManager},
+        sqlite::SqliteConnection
+    };
+    use std::{
+        sync::Arc,
+        time::Duration
+    };
+    use crate::error::ErrorKind;
+    use crate::Database;
+    use super::{Session, Sessions};
+
+    fn connection() -> Database {
+        Database::from_connection(SqliteConnection::establish(":memory:").unwrap())
+    }
+
+    #[test]
+    fn holds_the_transaction_across_handles() {
+        let database = connection();
+        let sessions = Arc::new(Sessions::default());
+        let session = Session::open(&database, &sessions, connection(), Duration::from_secs(60), 1).unwrap();
+        let token = session.token().to_owned();
+        assert_eq!(token.len(), 64);
+
+        session.run::<_, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| conn.execute("CREATE TABLE t (id INTEGER)")
+        ).unwrap();
+        drop(session);
+
+        let err = Session::open(&database, &sessions, connection(), Duration::from_secs(60), 1).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+
+        let session = Session::find(&database, &sessions, &token).unwrap();
+        let depth = session.run::<_, diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| Ok(conn.transaction_manager().get_transaction_depth())
+        ).unwrap();
+        assert_eq!(depth, 1);
+
+        session.commit().unwrap();
+        assert_eq!(sessions.len(), 0);
+        assert_eq!(Session::find(&database, &sessions, &token).err().unwrap().kind(), ErrorKind::SessionExpired);
+    }
+
+    #[test]
+    fn reaps_expired_sessions() {
+        let database = connection();
+        let sessions = Arc::new(Sessions::default());
+        let session = Session::open(&database, &sessions, connection(), Duration::from_secs(60), 4).unwrap();
+
+        assert_eq!(sessions.reap(database.now()), 0);
+        assert_eq!(sessions.reap(database.now() + Duration::from_secs(60)), 1);
+
+        let err = session.run::<(), diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Ok(())
+        ).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::SessionExpired);
+    }
+}
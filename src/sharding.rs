@@ -0,0 +1,106 @@
+//! Routing of interactions to shards.
+//!
+//! With the `shards` configuration key listing the URLs of N databases,
+//! `Database::interact_on_shard` executes an interaction on the shard
+//! selected for a key by a [`ShardRouter`], and
+//! `Database::interact_all_shards` on every shard.
+//!
+//! Each shard has its own connection, statistics and circuit breaker, and
+//! is established on first use.
+//!
+//! [`ShardRouter`]: trait.ShardRouter.html
+
+use std::{
+    fmt,
+    sync::Arc
+};
+
+/// Selects the shard holding the data of a key.
+pub trait ShardRouter: Send + Sync + 'static
+{
+    /// Index, below `shards`, of the shard of `key`.
+    fn route(&self, key: &[u8], shards: usize) -> usize;
+}
+
+/// Default router: FNV-1a hash of the key, modulo the number of shards.
+///
+/// The hash does not depend on the Rust version nor on the platform, so
+/// keys keep their shard across upgrades (as long as the number of shards
+/// does not change).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashRouter;
+
+impl HashRouter
+{
+    pub fn hash(key: &[u8]) -> u64
+    {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        key.iter().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        })
+    }
+}
+
+impl ShardRouter for HashRouter
+{
+    fn route(&self, key: &[u8], shards: usize) -> usize
+    {
+        (HashRouter::hash(key) % shards as u64) as usize
+    }
+}
+
+/// Router of a database.
+#[derive(Clone)]
+pub(crate) struct Router(Arc<dyn ShardRouter>);
+
+impl Router
+{
+    pub fn new<R: ShardRouter>(router: R) -> Self
+    {
+        Router(Arc::new(router))
+    }
+
+    pub fn route(&self, key: &[u8], shards: usize) -> usize
+    {
+        self.0.route(key, shards)
+    }
+}
+
+impl Default for Router
+{
+    fn default() -> Self
+    {
+        Router::new(HashRouter)
+    }
+}
+
+impl fmt::Debug for Router
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.write_str("Router")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HashRouter, ShardRouter};
+
+    #[test]
+    fn fnv1a() {
+        assert_eq!(HashRouter::hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(HashRouter::hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn routes_within_shards() {
+        for key in &["alice", "bob", "carol", "dave"] {
+            let shard = HashRouter.route(key.as_bytes(), 3);
+
+            assert!(shard < 3);
+            assert_eq!(HashRouter.route(key.as_bytes(), 3), shard);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Simulated databases for contract tests, with the `testing` feature.
+//!
+//! A simulated database has no connection: the statements it is asked to
+//! execute through `Database::execute_sql` and `Database::upsert` are
+//! translated for the simulated backend and recorded, so that tests can
+//! assert which statements a handler executes without any database engine
+//! installed:
+//!
+//! ```rust,ignore
+//! let database = Database::simulated(Backend::Pg);
+//! database.simulation().unwrap().push_result(Ok(1));
+//!
+//! rename_user(&database, 42, "alice")?;
+//!
+//! assert!(database.simulation().unwrap().executed("UPDATE users SET name = 'alice'"));
+//! ```
+//!
+//! Statements are answered with the queued results, in order, then with
+//! `Ok(0)`. Closures given to `interact` can't run without a connection:
+//! they fail, and so do the helpers built on them.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Mutex
+};
+
+use crate::redaction::log_redacted;
+use crate::Backend;
+use crate::error;
+use crate::Result;
+
+/// Statements recorded by a simulated database, and their results.
+pub struct Simulation
+{
+    _backend: Backend,
+    _statements: Mutex<Vec<String>>,
+    _results: Mutex<VecDeque<Result<usize>>>,
+}
+
+impl Simulation
+{
+    pub fn new(backend: Backend) -> Self
+    {
+        Self {
+            _backend: backend,
+            _statements: Mutex::new(Vec::new()),
+            _results: Mutex::new(VecDeque::new())
+        }
+    }
+
+    /// Backend the statements are translated for.
+    pub fn backend(&self) -> Backend
+    {
+        self._backend
+    }
+
+    /// Queues the result of the next statement: a number of affected rows or
+    /// an error.
+    pub fn push_result(&self, result: Result<usize>)
+    {
+        if let Ok(mut results) = self._results.lock() {
+            results.push_back(result);
+        }
+    }
+
+    /// Statements recorded so far, in execution order.
+    pub fn statements(&self) -> Vec<String>
+    {
+        self._statements.lock().map(|statements| statements.clone()).unwrap_or_default()
+    }
+
+    /// Returns the statements recorded so far, forgetting them.
+    pub fn take_statements(&self) -> Vec<String>
+    {
+        self._statements.lock().map(|mut statements| statements.drain(..).collect()).unwrap_or_default()
+    }
+
+    /// Returns whether a recorded statement contains `fragment`, whitespace
+    /// runs being compared as single spaces.
+    pub fn executed(&self, fragment: &str) -> bool
+    {
+        let fragment = normalize(fragment);
+
+        self.statements().iter().any(|statement| normalize(statement).contains(&fragment))
+    }
+
+    /// Records `sql`, returning the next queued result.
+    pub(crate) fn execute(&self, sql: String) -> Result<usize>
+    {
+        log_redacted!(log::Level::Debug, "Simulated statement: {}", sql);
+
+        self._statements.lock()
+            .map_err(|_err| error::Error::new(error::ErrorKind::Other, "simulation got poisoned"))?
+            .push(sql);
+
+        self._results.lock()
+            .map_err(|_err| error::Error::new(error::ErrorKind::Other, "simulation got poisoned"))?
+            .pop_front()
+            .unwrap_or(Ok(0))
+    }
+}
+
+impl fmt::Debug for Simulation
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.debug_struct("Simulation")
+            .field("backend", &self._backend)
+            .field("statements", &self.statements().len())
+            .finish()
+    }
+}
+
+fn normalize(sql: &str) -> String
+{
+    sql.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Error of the closures given to a simulated database.
+pub(crate) fn closures_unsupported() -> error::Error
+{
+    error::Error::new(
+        error::ErrorKind::Other,
+        "closures can't run against a simulated database, use `execute_sql` or `upsert`"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Database};
+    use crate::error::{Error, ErrorKind};
+    use crate::helpers::SqlValue;
+
+    #[test]
+    fn records_translated_statements() {
+        let database = Database::simulated(Backend::Mysql);
+        let simulation = database.simulation().unwrap();
+
+        simulation.push_result(Ok(3));
+        simulation.push_result(Err(Error::new(ErrorKind::Other, "simulated failure")));
+
+        assert_eq!(database.backend(), Some(Backend::Mysql));
+        assert_eq!(database.execute_sql("DELETE FROM tokens").unwrap(), 3);
+        assert!(database.upsert("users", &["id"], &[("id", SqlValue::Integer(1))]).is_err());
+        assert_eq!(database.execute_sql("DELETE FROM codes").unwrap(), 0);
+
+        assert!(simulation.executed("DELETE   FROM tokens"));
+        assert!(simulation.executed("INSERT INTO `users`"));
+        assert_eq!(simulation.take_statements().len(), 3);
+        assert!(simulation.statements().is_empty());
+        assert_eq!(database.stats().interactions, 3);
+    }
+
+    #[test]
+    fn closures_fail() {
+        let database = Database::simulated(Backend::Sqlite);
+
+        let result = database.interact::<(), diesel::result::Error, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| unreachable!()
+        );
+        assert!(result.is_err());
+    }
+}
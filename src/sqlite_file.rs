@@ -0,0 +1,127 @@
+//! Detection of the replacement of a SQLite database file.
+//!
+//! Deployments shipping read-only snapshots replace the file (e.g. by
+//! renaming the new one over it): an open connection keeps reading the old,
+//! unlinked, file. The identity of the file (device and inode on Unix) is
+//! recorded when the connection is established, and compared before each
+//! interaction; the connection is reopened once it changed.
+
+use std::{
+    fs,
+    path::{
+        Path,
+        PathBuf
+    }
+};
+
+/// Identity of a file, changing when another file takes its path.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileIdentity {
+    _device: u64,
+    _inode: u64,
+}
+
+#[cfg(unix)]
+fn identity(path: &Path) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).ok().map(|metadata| FileIdentity {
+        _device: metadata.dev(),
+        _inode: metadata.ino()
+    })
+}
+
+/// Without inodes, the creation time tells files apart.
+#[cfg(not(unix))]
+fn identity(path: &Path) -> Option<FileIdentity> {
+    use std::time::UNIX_EPOCH;
+
+    let created = fs::metadata(path).ok()?.created().ok()?;
+    let elapsed = created.duration_since(UNIX_EPOCH).ok()?;
+
+    Some(FileIdentity {
+        _device: elapsed.as_secs(),
+        _inode: u64::from(elapsed.subsec_nanos())
+    })
+}
+
+/// Returns whether the SQLite database at `path` is held in memory.
+pub(crate) fn in_memory(path: &str) -> bool {
+    path.is_empty() || path == ":memory:" || path.starts_with("file::memory:")
+}
+
+/// Database file of a SQLite connection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct WatchedFile {
+    _path: PathBuf,
+    _identity: FileIdentity,
+}
+
+impl WatchedFile {
+    /// Watches the database file at `path`. In-memory databases and missing
+    /// files are not watched.
+    pub fn new(path: &str) -> Option<Self> {
+        if in_memory(path) {
+            return None;
+        }
+
+        let path = PathBuf::from(path);
+
+        identity(&path).map(|identity| Self {
+            _path: path,
+            _identity: identity
+        })
+    }
+
+    /// Returns whether another file took the path of the watched one.
+    ///
+    /// A missing file, e.g. in the middle of its replacement, is not
+    /// reported: reopening it would create an empty database.
+    pub fn replaced(&self) -> bool {
+        match identity(&self._path) {
+            Some(identity) => identity != self._identity,
+            None => false
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self._path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use super::WatchedFile;
+
+    #[test]
+    fn in_memory_databases_are_not_watched() {
+        assert_eq!(WatchedFile::new(":memory:"), None);
+        assert_eq!(WatchedFile::new(""), None);
+    }
+
+    #[test]
+    fn detects_replacement() {
+        let directory = env::temp_dir().join(format!("rocket-diesel-sqlite-file-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("snapshot.db");
+        let next = directory.join("snapshot.db.next");
+
+        fs::write(&path, b"v1").unwrap();
+        let watched = WatchedFile::new(path.to_str().unwrap()).unwrap();
+        assert!(!watched.replaced());
+
+        // Written in place: same file
+        fs::write(&path, b"v1 updated").unwrap();
+        assert!(!watched.replaced());
+
+        fs::write(&next, b"v2").unwrap();
+        fs::rename(&next, &path).unwrap();
+        assert!(watched.replaced());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watched.replaced());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
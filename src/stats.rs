@@ -0,0 +1,669 @@
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering
+        },
+        RwLock
+    },
+    time::Duration
+};
+
+/// Number of route templates with a breakdown of their interactions; the
+/// interactions of the routes beyond are counted under `OVERFLOW_ROUTE`.
+pub const MAX_ROUTES: usize = 64;
+
+/// Route of the interactions of the routes beyond `MAX_ROUTES`.
+pub const OVERFLOW_ROUTE: &str = "<other>";
+
+static ROUTES: RouteSlots = RouteSlots::new(MAX_ROUTES);
+
+/// Counter alone on its cache line, so that threads updating different
+/// counters do not invalidate each other's caches.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Counter(AtomicU64);
+
+impl Deref for Counter {
+    type Target = AtomicU64;
+
+    fn deref(&self) -> &AtomicU64 {
+        &self.0
+    }
+}
+
+fn micros(duration: Duration) -> u64
+{
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+/// Kind of statement executed by an interaction.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Operation
+{
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// Other statements, and closures given to `interact` which are not
+    /// labeled with `interact_as`.
+    Unknown,
+}
+
+impl Operation
+{
+    const ALL: [Operation; 5] = [
+        Operation::Select,
+        Operation::Insert,
+        Operation::Update,
+        Operation::Delete,
+        Operation::Unknown,
+    ];
+
+    /// Classifies `sql` by its first keyword, skipping leading comments
+    /// (e.g. query tags).
+    pub fn from_sql(sql: &str) -> Self
+    {
+        let mut rest = sql.trim_start();
+
+        loop {
+            if rest.starts_with("--") {
+                rest = rest.find('\n').map_or("", |end| &rest[end..]).trim_start();
+            } else if rest.starts_with("/*") {
+                rest = rest.find("*/").map_or("", |end| &rest[end + 2..]).trim_start();
+            } else {
+                break;
+            }
+        }
+
+        let keyword: String = rest.chars()
+            .take_while(char::is_ascii_alphabetic)
+            .collect::<String>()
+            .to_uppercase();
+
+        match keyword.as_str() {
+            "SELECT"                => Operation::Select,
+            "INSERT" | "REPLACE"    => Operation::Insert,
+            "UPDATE"                => Operation::Update,
+            "DELETE"                => Operation::Delete,
+            _                       => Operation::Unknown,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str
+    {
+        match self {
+            Operation::Select   => "select",
+            Operation::Insert   => "insert",
+            Operation::Update   => "update",
+            Operation::Delete   => "delete",
+            Operation::Unknown  => "unknown",
+        }
+    }
+
+    fn index(self) -> usize
+    {
+        self as usize
+    }
+}
+
+impl fmt::Display for Operation
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Upper bounds, in microseconds, of the buckets of the latency histogram.
+const LATENCY_BOUNDS: [u64; 10] = [
+    1_000, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000
+];
+
+/// Upper bounds, in rows, of the buckets of the result size histogram.
+const ROWS_BOUNDS: [u64; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+/// Distribution of observed values in fixed buckets.
+#[derive(Debug)]
+struct Histogram
+{
+    _bounds: &'static [u64],
+    // One bucket per bound, then the overflow bucket
+    _buckets: Vec<Counter>,
+    _sum: Counter,
+}
+
+impl Histogram
+{
+    fn new(bounds: &'static [u64]) -> Self
+    {
+        Self {
+            _bounds: bounds,
+            _buckets: (0..=bounds.len()).map(|_| Counter::default()).collect(),
+            _sum: Counter::default()
+        }
+    }
+
+    fn observe(&self, value: u64)
+    {
+        let index = self._bounds.iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self._bounds.len());
+
+        self._buckets[index].fetch_add(1, Ordering::Relaxed);
+        self._sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot
+    {
+        let mut count = 0;
+        let buckets = self._bounds.iter()
+            .zip(self._buckets.iter())
+            .map(|(bound, bucket)| {
+                count += bucket.load(Ordering::Relaxed);
+                (*bound, count)
+            })
+            .collect();
+
+        HistogramSnapshot {
+            buckets,
+            count: count + self._buckets[self._bounds.len()].load(Ordering::Relaxed),
+            sum: self._sum.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Point-in-time copy of a histogram, in the cumulative form of
+/// Prometheus.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HistogramSnapshot
+{
+    /// Upper bound of each bucket, with the number of observed values lower
+    /// than or equal to it.
+    pub buckets: Vec<(u64, u64)>,
+    /// Number of observed values.
+    pub count: u64,
+    /// Sum of the observed values.
+    pub sum: u64,
+}
+
+/// Counters of the interactions of an operation.
+#[derive(Debug, Default)]
+struct OperationCounters
+{
+    _interactions: Counter,
+    _errors: Counter,
+    _total_micros: Counter,
+}
+
+/// Counters of the interactions of an operation on a route, without the
+/// padding of `Counter` to keep the table of the routes small.
+#[derive(Debug, Default)]
+struct RouteCounters
+{
+    _interactions: AtomicU64,
+    _errors: AtomicU64,
+    _total_micros: AtomicU64,
+}
+
+/// Slots of the route templates in the per-route breakdown, given in the
+/// order routes are first seen, up to a capacity.
+#[derive(Debug)]
+struct RouteSlots
+{
+    _capacity: usize,
+    _routes: RwLock<Vec<String>>,
+}
+
+impl RouteSlots
+{
+    const fn new(capacity: usize) -> Self
+    {
+        Self {
+            _capacity: capacity,
+            _routes: RwLock::new(Vec::new())
+        }
+    }
+
+    /// Slot of `route`, registered on first use: the capacity once every
+    /// slot is taken.
+    fn slot(&self, route: &str) -> usize
+    {
+        if let Ok(routes) = self._routes.read() {
+            if let Some(slot) = routes.iter().position(|known| known == route) {
+                return slot;
+            }
+        }
+
+        match self._routes.write() {
+            Ok(mut routes) => match routes.iter().position(|known| known == route) {
+                Some(slot) => slot,
+                None if routes.len() < self._capacity => {
+                    routes.push(route.to_owned());
+                    routes.len() - 1
+                },
+                None => self._capacity
+            },
+            Err(_) => self._capacity
+        }
+    }
+
+    /// Route of each slot, the overflow one last.
+    fn routes(&self) -> Vec<String>
+    {
+        let mut routes = self._routes.read().map(|routes| routes.clone()).unwrap_or_default();
+
+        routes.resize(self._capacity, String::new());
+        routes.push(OVERFLOW_ROUTE.to_owned());
+        routes
+    }
+}
+
+/// Slot of the route template `route` in the per-route breakdown of the
+/// statistics, resolved once per request rather than per interaction.
+pub(crate) fn route_slot(route: &str) -> usize
+{
+    ROUTES.slot(route)
+}
+
+/// Statistics of the interactions of an operation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OperationStats
+{
+    pub operation: Operation,
+    /// Route the interactions served, `None` for the totals of the
+    /// operation over every route (and outside of requests).
+    pub route: Option<String>,
+    pub interactions: u64,
+    pub errors: u64,
+    pub average_latency: Duration,
+}
+
+impl OperationStats
+{
+    fn new(operation: Operation, route: Option<String>, interactions: u64, errors: u64, total_micros: u64) -> Self
+    {
+        Self {
+            operation,
+            route,
+            interactions,
+            errors,
+            average_latency: match interactions {
+                0 => Duration::from_secs(0),
+                _ => Duration::from_micros(total_micros / interactions)
+            }
+        }
+    }
+}
+
+/// Counters updated by every interaction with the database.
+///
+/// Updates are relaxed atomic additions: recording never locks, and costs a
+/// few uncontended instructions when nothing reads the counters.
+#[derive(Debug)]
+pub(crate) struct Stats
+{
+    _interactions: Counter,
+    _errors: Counter,
+    _total_micros: Counter,
+    _lock_contentions: Counter,
+    _operations: [OperationCounters; 5],
+    // Per-route breakdown, indexed by route slot then operation
+    _routes: Vec<[RouteCounters; 5]>,
+    _latency: Histogram,
+    _result_rows: Histogram,
+}
+
+impl Default for Stats
+{
+    fn default() -> Self
+    {
+        Self {
+            _interactions: Counter::default(),
+            _errors: Counter::default(),
+            _total_micros: Counter::default(),
+            _lock_contentions: Counter::default(),
+            _operations: Default::default(),
+            _routes: (0..=MAX_ROUTES).map(|_| Default::default()).collect(),
+            _latency: Histogram::new(&LATENCY_BOUNDS),
+            _result_rows: Histogram::new(&ROWS_BOUNDS)
+        }
+    }
+}
+
+impl Stats
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Records a completed interaction and how long its execution took.
+    pub fn record_interaction(&self, duration: Duration, failed: bool)
+    {
+        let micros = micros(duration);
+
+        self._interactions.fetch_add(1, Ordering::Relaxed);
+        self._total_micros.fetch_add(micros, Ordering::Relaxed);
+        self._latency.observe(micros);
+        if failed {
+            self._errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the number of rows of a result checked against the result
+    /// limit.
+    pub fn record_result_rows(&self, rows: usize)
+    {
+        self._result_rows.observe(rows as u64);
+    }
+
+    /// Distribution of the execution times of the interactions, in
+    /// microseconds.
+    pub fn latency_histogram(&self) -> HistogramSnapshot
+    {
+        self._latency.snapshot()
+    }
+
+    /// Distribution of the number of rows of the results.
+    pub fn result_rows_histogram(&self) -> HistogramSnapshot
+    {
+        self._result_rows.snapshot()
+    }
+
+    /// Records an interaction which failed before executing anything.
+    pub fn record_error(&self)
+    {
+        self._errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a completed interaction executing `operation`, on the route
+    /// of slot `route` (see `route_slot`) when serving a routed request.
+    pub fn record_operation(&self, operation: Operation, route: Option<usize>, duration: Duration, failed: bool)
+    {
+        let counters = &self._operations[operation.index()];
+        let micros = micros(duration);
+
+        counters._interactions.fetch_add(1, Ordering::Relaxed);
+        counters._total_micros.fetch_add(micros, Ordering::Relaxed);
+        if failed {
+            counters._errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(counters) = route.and_then(|slot| self._routes.get(slot)) {
+            let counters = &counters[operation.index()];
+
+            counters._interactions.fetch_add(1, Ordering::Relaxed);
+            counters._total_micros.fetch_add(micros, Ordering::Relaxed);
+            if failed {
+                counters._errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Statistics of every operation executed, in total then per route.
+    pub fn operations(&self) -> Vec<OperationStats>
+    {
+        let mut operations: Vec<OperationStats> = Operation::ALL.iter()
+            .map(|&operation| {
+                let counters = &self._operations[operation.index()];
+
+                OperationStats::new(
+                    operation,
+                    None,
+                    counters._interactions.load(Ordering::Relaxed),
+                    counters._errors.load(Ordering::Relaxed),
+                    counters._total_micros.load(Ordering::Relaxed)
+                )
+            })
+            .filter(|stats| stats.interactions > 0)
+            .collect();
+
+        let mut per_route: Vec<OperationStats> = ROUTES.routes().into_iter()
+            .zip(&self._routes)
+            .flat_map(|(route, counters)| Operation::ALL.iter().map(move |&operation| {
+                let counters = &counters[operation.index()];
+
+                OperationStats::new(
+                    operation,
+                    Some(route.clone()),
+                    counters._interactions.load(Ordering::Relaxed),
+                    counters._errors.load(Ordering::Relaxed),
+                    counters._total_micros.load(Ordering::Relaxed)
+                )
+            }))
+            .filter(|stats| stats.interactions > 0)
+            .collect();
+
+        per_route.sort_by(|a, b| (&a.route, a.operation).cmp(&(&b.route, b.operation)));
+        operations.extend(per_route);
+
+        operations
+    }
+
+    /// Records a caller which had to wait for the connection lock.
+    pub fn record_lock_contention(&self)
+    {
+        self._lock_contentions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot
+    {
+        let interactions = self._interactions.load(Ordering::Relaxed);
+        let total_micros = self._total_micros.load(Ordering::Relaxed);
+
+        StatsSnapshot {
+            interactions,
+            errors: self._errors.load(Ordering::Relaxed),
+            average_latency: match interactions {
+                0 => Duration::from_secs(0),
+                _ => Duration::from_micros(total_micros / interactions)
+            },
+            lock_contentions: self._lock_contentions.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Point-in-time copy of the database statistics.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StatsSnapshot
+{
+    /// Number of closures executed against the database.
+    pub interactions: u64,
+    /// Number of interactions which failed.
+    pub errors: u64,
+    /// Average execution time of an interaction.
+    pub average_latency: Duration,
+    /// Number of interactions which had to wait for the connection.
+    pub lock_contentions: u64,
+}
+
+impl fmt::Display for StatsSnapshot
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            fmt,
+            "queries={} errors={} avg_latency={:?} lock_contentions={}",
+            self.interactions, self.errors, self.average_latency, self.lock_contentions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{route_slot, HistogramSnapshot, Operation, OperationStats, RouteSlots, Stats, StatsSnapshot, OVERFLOW_ROUTE};
+
+    #[test]
+    fn empty_snapshot() {
+        assert_eq!(Stats::new().snapshot(), StatsSnapshot::default());
+    }
+
+    #[test]
+    fn snapshot() {
+        let stats = Stats::new();
+
+        stats.record_interaction(Duration::from_millis(10), false);
+        stats.record_interaction(Duration::from_millis(30), true);
+        stats.record_error();
+        stats.record_lock_contention();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.interactions, 2);
+        assert_eq!(snapshot.errors, 2);
+        assert_eq!(snapshot.average_latency, Duration::from_millis(20));
+        assert_eq!(snapshot.lock_contentions, 1);
+    }
+
+    #[test]
+    fn histograms() {
+        let stats = Stats::new();
+
+        stats.record_interaction(Duration::from_micros(800), false);
+        stats.record_interaction(Duration::from_millis(7), false);
+        stats.record_interaction(Duration::from_secs(5), false);
+        stats.record_result_rows(10);
+
+        let latency = stats.latency_histogram();
+        assert_eq!(&latency.buckets[..3], &[(1_000, 1), (5_000, 1), (10_000, 2)]);
+        assert_eq!(latency.buckets.last(), Some(&(2_500_000, 2)));
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.sum, 5_007_800);
+
+        assert_eq!(stats.result_rows_histogram(), HistogramSnapshot {
+            buckets: vec![(1, 0), (10, 1), (100, 1), (1_000, 1), (10_000, 1), (100_000, 1)],
+            count: 1,
+            sum: 10
+        });
+    }
+
+    #[test]
+    fn classifies_statements() {
+        assert_eq!(Operation::from_sql("  select 1"), Operation::Select);
+        assert_eq!(Operation::from_sql("/* route:/users */ UPDATE users SET a = 1"), Operation::Update);
+        assert_eq!(Operation::from_sql("-- comment\nDELETE FROM users"), Operation::Delete);
+        assert_eq!(Operation::from_sql("REPLACE INTO users VALUES (1)"), Operation::Insert);
+        assert_eq!(Operation::from_sql("CREATE TABLE users (id INTEGER)"), Operation::Unknown);
+    }
+
+    #[test]
+    fn operations() {
+        let stats = Stats::new();
+
+        let users = route_slot("/users");
+
+        stats.record_operation(Operation::Select, Some(users), Duration::from_millis(10), false);
+        stats.record_operation(Operation::Select, None, Duration::from_millis(20), true);
+        stats.record_operation(Operation::Insert, Some(users), Duration::from_millis(4), false);
+
+        assert_eq!(stats.operations(), vec![
+            OperationStats {
+                operation: Operation::Select,
+                route: None,
+                interactions: 2,
+                errors: 1,
+                average_latency: Duration::from_millis(15)
+            },
+            OperationStats {
+                operation: Operation::Insert,
+                route: None,
+                interactions: 1,
+                errors: 0,
+                average_latency: Duration::from_millis(4)
+            },
+            OperationStats {
+                operation: Operation::Select,
+                route: Some("/users".to_owned()),
+                interactions: 1,
+                errors: 0,
+                average_latency: Duration::from_millis(10)
+            },
+            OperationStats {
+                operation: Operation::Insert,
+                route: Some("/users".to_owned()),
+                interactions: 1,
+                errors: 0,
+                average_latency: Duration::from_millis(4)
+            },
+        ]);
+    }
+
+    #[test]
+    fn route_slots_overflow() {
+        let slots = RouteSlots::new(2);
+
+        assert_eq!(slots.slot("/users/<id>"), 0);
+        assert_eq!(slots.slot("/orders"), 1);
+        assert_eq!(slots.slot("/users/<id>"), 0);
+        assert_eq!(slots.slot("/reports"), 2);
+        assert_eq!(slots.routes(), vec!["/users/<id>", "/orders", OVERFLOW_ROUTE]);
+    }
+
+    #[test]
+    fn display() {
+        let snapshot = StatsSnapshot {
+            interactions: 3,
+            errors: 1,
+            average_latency: Duration::from_millis(5),
+            lock_contentions: 0
+        };
+
+        assert_eq!(
+            snapshot.to_string(),
+            "queries=3 errors=1 avg_latency=5ms lock_contentions=0"
+        );
+    }
+}
+
+/// Overhead of recording interactions, alone and while other threads record
+/// theirs (`cargo bench --features nightly stats`).
+#[cfg(all(test, feature = "nightly"))]
+mod benches {
+    extern crate test;
+
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc
+        },
+        thread,
+        time::Duration
+    };
+    use self::test::Bencher;
+    use super::Stats;
+
+    const CONTENDING_THREADS: usize = 3;
+
+    #[bench]
+    fn record_interaction(bencher: &mut Bencher) {
+        let stats = Stats::new();
+
+        bencher.iter(|| stats.record_interaction(Duration::from_micros(250), false));
+    }
+
+    #[bench]
+    fn record_interaction_contended(bencher: &mut Bencher) {
+        let stats = Arc::new(Stats::new());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let contenders: Vec<_> = (0..CONTENDING_THREADS).map(|_| {
+            let stats = stats.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || while !stop.load(Ordering::Relaxed) {
+                stats.record_interaction(Duration::from_micros(250), false);
+                stats.record_lock_contention();
+            })
+        }).collect();
+
+        bencher.iter(|| stats.record_interaction(Duration::from_micros(250), false));
+
+        stop.store(true, Ordering::Relaxed);
+        for contender in contenders {
+            contender.join().unwrap();
+        }
+    }
+}
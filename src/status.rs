@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// Consecutive failures to read the configuration after which they are
+/// logged as warnings.
+pub(crate) const WARN_AFTER: u32 = 3;
+
+/// Consecutive failures to read the configuration after which they are
+/// logged as errors.
+pub(crate) const ERROR_AFTER: u32 = 10;
+
+/// State of the configuration of the database, telling a misconfigured
+/// application from an unreachable database.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigurationStatus {
+    /// No request read the configuration yet.
+    Pending,
+    /// The configuration was read and is valid.
+    Loaded,
+    /// The configuration was read but is invalid (e.g. missing `url`).
+    Invalid {
+        error: String,
+    },
+    /// Reading the configuration failed `failures` consecutive times.
+    /// Attempts are spaced by a growing delay.
+    Missing {
+        failures: u32,
+        error: String,
+    },
+}
+
+impl ConfigurationStatus {
+    /// Returns whether the application is misconfigured.
+    pub fn is_misconfigured(&self) -> bool {
+        match self {
+            ConfigurationStatus::Invalid { .. } | ConfigurationStatus::Missing { .. } => true,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for ConfigurationStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigurationStatus::Pending => fmt.write_str("pending"),
+            ConfigurationStatus::Loaded => fmt.write_str("loaded"),
+            ConfigurationStatus::Invalid { error } => write!(fmt, "invalid: {}", error),
+            ConfigurationStatus::Missing { failures, error } => {
+                write!(fmt, "missing after {} attempt(s): {}", failures, error)
+            }
+        }
+    }
+}
+
+/// Level logging the `failures`-th consecutive failure to read the
+/// configuration.
+pub(crate) fn log_level(failures: u32) -> log::Level {
+    if failures >= ERROR_AFTER {
+        log::Level::Error
+    } else if failures >= WARN_AFTER {
+        log::Level::Warn
+    } else {
+        log::Level::Debug
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{log_level, ConfigurationStatus, ERROR_AFTER, WARN_AFTER};
+
+    #[test]
+    fn escalates_log_level() {
+        assert_eq!(log_level(1), log::Level::Debug);
+        assert_eq!(log_level(WARN_AFTER - 1), log::Level::Debug);
+        assert_eq!(log_level(WARN_AFTER), log::Level::Warn);
+        assert_eq!(log_level(ERROR_AFTER), log::Level::Error);
+        assert_eq!(log_level(u32::max_value()), log::Level::Error);
+    }
+
+    #[test]
+    fn misconfigured() {
+        assert!(!ConfigurationStatus::Pending.is_misconfigured());
+        assert!(!ConfigurationStatus::Loaded.is_misconfigured());
+        assert!(ConfigurationStatus::Missing { failures: 1, error: String::new() }.is_misconfigured());
+    }
+}
@@ -0,0 +1,125 @@
+//! Handling of databases running out of storage.
+//!
+//! Embedded and edge deployments often have a tight storage budget. The
+//! `sqlite_max_page_count` configuration key caps the size of SQLite
+//! databases (`PRAGMA max_page_count`), so that they fill up before the
+//! disk does. Writes failing because the database or its disk is full
+//! (`SQLITE_FULL`, a full MySQL table, Postgres running out of disk space)
+//! fail with `StorageFull`, then run the cleanup registered with
+//! `Database::on_storage_full`:
+//!
+//! ```rust,ignore
+//! database.on_storage_full(|database| {
+//!     database.execute_sql("DELETE FROM events WHERE created_at < date('now', '-7 days')")?;
+//!     database.execute_sql("VACUUM")?;
+//!     Ok(())
+//! });
+//! ```
+//!
+//! The cleanup runs once the connection is released and is not run again
+//! by the writes failing while it runs. The failed write is not retried.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering
+        },
+        Arc,
+        RwLock
+    }
+};
+
+use crate::redaction::log_redacted;
+use crate::Database;
+use crate::Result;
+
+type CleanupFn = dyn Fn(&Database) -> Result<()> + Send + Sync;
+
+/// Cleanup run when the storage of a database is full. Clones share it.
+#[derive(Clone, Default)]
+pub(crate) struct StorageFullHook {
+    _cleanup: Arc<RwLock<Option<Arc<CleanupFn>>>>,
+    _running: Arc<AtomicBool>,
+}
+
+impl StorageFullHook {
+    /// Registers `cleanup`, replacing the one registered.
+    pub fn register<F>(&self, cleanup: F)
+        where F: Fn(&Database) -> Result<()> + Send + Sync + 'static
+    {
+        if let Ok(mut registered) = self._cleanup.write() {
+            *registered = Some(Arc::new(cleanup));
+        }
+    }
+
+    /// Runs the cleanup on `database`, unless it is already running.
+    pub fn run(&self, database: &Database) {
+        let cleanup = match self._cleanup.read().ok().and_then(|cleanup| cleanup.clone()) {
+            Some(cleanup) => cleanup,
+            None => return
+        };
+
+        if self._running.swap(true, Ordering::AcqRel) {
+            return ;
+        }
+        match cleanup(database) {
+            Ok(()) => log_redacted!(log::Level::Info, "Storage cleanup succeeded."),
+            Err(err) => log_redacted!(log::Level::Error, "Storage cleanup failed: {}", err)
+        }
+        self._running.store(false, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for StorageFullHook {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registered = self._cleanup.read().map_or(false, |cleanup| cleanup.is_some());
+
+        fmt.debug_struct("StorageFullHook")
+            .field("registered", &registered)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc
+    };
+    use crate::Database;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn full_databases_run_the_cleanup() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE events (payload BLOB); PRAGMA max_page_count = 16;").unwrap();
+        let database = Database::from_connection(conn);
+        let cleanups = Arc::new(AtomicUsize::new(0));
+
+        database.on_storage_full({
+            let cleanups = cleanups.clone();
+
+            move |database| {
+                cleanups.fetch_add(1, Ordering::SeqCst);
+                // Fails as well without running the cleanup again
+                database.execute_sql("INSERT INTO events VALUES (zeroblob(1048576))")?;
+                Ok(())
+            }
+        });
+
+        let err = database.execute_sql("INSERT INTO events VALUES (zeroblob(1048576))").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StorageFull);
+        assert!(err.is_storage_full());
+        assert_eq!(cleanups.load(Ordering::SeqCst), 1);
+
+        // Other errors don't run it
+        assert!(database.execute_sql("INSERT INTO missing VALUES (1)").is_err());
+        assert_eq!(cleanups.load(Ordering::SeqCst), 1);
+    }
+}
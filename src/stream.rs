@@ -0,0 +1,353 @@
+//! Connection leases of long-lived streaming responses, such as
+//! server-sent events.
+//!
+//! ```rust,ignore
+//! #[get("/events")]
+//! fn events(database: State<'_, Database>) -> EventStream<impl FnMut(&StreamLease) -> Result<StreamEvent>> {
+//!     let mut last_id = 0;
+//!
+//!     EventStream::new(database.inner(), move |lease| {
+//!         let events = lease.interact(/* events after last_id */)?;
+//!         // ...
+//!     }).with_keep_alive(Duration::from_secs(15))
+//! }
+//! ```
+//!
+//! The database holds a single connection: a lease locks it for each
+//! interaction of the producer only, so the stream does not keep other
+//! requests waiting. While the producer is idle, the stream sends an SSE
+//! comment every `keep_alive` and pings the database, keeping both the
+//! client and the connection (e.g. in serverless mode) alive.
+//!
+//! Rocket notices that the client went away when writing to it: the
+//! response is then dropped, which releases the lease. The keep-alive
+//! interval thus bounds how long a lease outlives its client, and
+//! `with_max_duration` how long any stream lasts.
+
+use rocket::{
+    http::ContentType,
+    request::Request,
+    response::{
+        self,
+        Responder,
+        Response
+    }
+};
+
+use std::{
+    error::Error,
+    io::{
+        self,
+        Read
+    },
+    thread,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::redaction::log_redacted;
+use crate::Database;
+use crate::InteractOutcome;
+use crate::Result;
+
+/// Interval between two keep-alive comments while the producer is idle.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Interval between two calls of an idle producer.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Comment sent to keep the stream alive.
+const KEEP_ALIVE_COMMENT: &[u8] = b": keep-alive\n\n";
+
+/// What the producer of a stream has to send.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StreamEvent
+{
+    /// Bytes to send as is, e.g. a complete SSE event.
+    Data(Vec<u8>),
+    /// Nothing to send yet: the producer is called again after the poll
+    /// interval.
+    Idle,
+    /// The stream is complete.
+    End,
+}
+
+impl StreamEvent
+{
+    /// SSE event carrying `data`, one `data:` field per line.
+    pub fn sse(event: Option<&str>, data: &str) -> Self
+    {
+        let mut bytes = String::new();
+
+        if let Some(event) = event {
+            bytes.push_str("event: ");
+            bytes.push_str(event);
+            bytes.push('\n');
+        }
+        for line in data.split('\n') {
+            bytes.push_str("data: ");
+            bytes.push_str(line);
+            bytes.push('\n');
+        }
+        bytes.push('\n');
+
+        StreamEvent::Data(bytes.into_bytes())
+    }
+}
+
+/// Connection of a database leased by a stream, released when dropped.
+#[derive(Debug)]
+pub struct StreamLease
+{
+    _database: Database,
+}
+
+impl StreamLease
+{
+    pub fn new(database: &Database) -> Self
+    {
+        database.stream_leased();
+
+        Self {
+            _database: database.clone()
+        }
+    }
+
+    /// Database the lease shares its connection with.
+    pub fn database(&self) -> &Database
+    {
+        &self._database
+    }
+
+    /// Same as `Database::interact`.
+    #[track_caller]
+    pub fn interact<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        self._database.interact(mysql_f, pg_f, sqlite_f)
+    }
+
+    /// Same as `Database::interact_outcome`.
+    #[track_caller]
+    pub fn interact_outcome<T, E, MysqlF, PgF, SqliteF>(&self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF)
+        -> Result<InteractOutcome<T>>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        self._database.interact_outcome(mysql_f, pg_f, sqlite_f)
+    }
+
+    /// Checks that the connection is alive.
+    pub fn ping(&self) -> Result<()>
+    {
+        self._database.execute_sql("SELECT 1").map(|_| ())
+    }
+}
+
+impl Drop for StreamLease
+{
+    fn drop(&mut self)
+    {
+        self._database.stream_released();
+    }
+}
+
+/// Streaming response reading its body from a producer holding a lease.
+pub struct EventStream<F>
+{
+    _lease: StreamLease,
+    _producer: F,
+    _keep_alive: Duration,
+    _poll_interval: Duration,
+    _max_duration: Option<Duration>,
+    _started: Instant,
+    _last_sent: Instant,
+    _pending: Vec<u8>,
+    _position: usize,
+    _done: bool,
+}
+
+impl<F> EventStream<F>
+    where F: FnMut(&StreamLease) -> Result<StreamEvent>
+{
+    /// Stream leasing the connection of `database` and sending what
+    /// `producer` returns, until it returns `StreamEvent::End` or fails.
+    pub fn new(database: &Database, producer: F) -> Self
+    {
+        let now = database.now();
+
+        Self {
+            _lease: StreamLease::new(database),
+            _producer: producer,
+            _keep_alive: DEFAULT_KEEP_ALIVE,
+            _poll_interval: DEFAULT_POLL_INTERVAL,
+            _max_duration: None,
+            _started: now,
+            _last_sent: now,
+            _pending: Vec::new(),
+            _position: 0,
+            _done: false
+        }
+    }
+
+    /// Sends a keep-alive comment and pings the database after `keep_alive`
+    /// without data.
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self
+    {
+        self._keep_alive = keep_alive;
+        self
+    }
+
+    /// Calls an idle producer every `poll_interval`.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self
+    {
+        self._poll_interval = poll_interval;
+        self
+    }
+
+    /// Ends the stream after `max_duration`, whatever the producer returns.
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self
+    {
+        self._max_duration = Some(max_duration);
+        self
+    }
+
+    /// Lease of the stream.
+    pub fn lease(&self) -> &StreamLease
+    {
+        &self._lease
+    }
+
+    /// Fills the pending bytes, returning `false` once the stream is done.
+    fn fill(&mut self) -> bool
+    {
+        while !self._done && self._position >= self._pending.len() {
+            let now = self._lease.database().now();
+
+            if let Some(max_duration) = self._max_duration {
+                if now.duration_since(self._started) >= max_duration {
+                    log_redacted!(log::Level::Debug, "Ending stream after {:?}.", max_duration);
+                    self._done = true;
+                    break;
+                }
+            }
+
+            match (self._producer)(&self._lease) {
+                Ok(StreamEvent::Data(bytes)) => self.send(bytes, now),
+                Ok(StreamEvent::End) => self._done = true,
+                Ok(StreamEvent::Idle) if now.duration_since(self._last_sent) >= self._keep_alive => {
+                    match self._lease.ping() {
+                        Ok(()) => self.send(KEEP_ALIVE_COMMENT.to_vec(), now),
+                        Err(err) => {
+                            log_redacted!(log::Level::Warn, "Ending stream, the database connection is lost: {}", err);
+                            self._done = true;
+                        }
+                    }
+                },
+                Ok(StreamEvent::Idle) => thread::sleep(self._poll_interval),
+                Err(err) => {
+                    log_redacted!(log::Level::Warn, "Ending stream, its producer failed: {}", err);
+                    self._done = true;
+                }
+            }
+        }
+
+        self._position < self._pending.len()
+    }
+
+    fn send(&mut self, bytes: Vec<u8>, now: Instant)
+    {
+        self._pending = bytes;
+        self._position = 0;
+        self._last_sent = now;
+    }
+}
+
+impl<F> Read for EventStream<F>
+    where F: FnMut(&StreamLease) -> Result<StreamEvent>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        if buf.is_empty() || !self.fill() {
+            return Ok(0);
+        }
+
+        let pending = &self._pending[self._position..];
+        let len = pending.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&pending[..len]);
+        self._position += len;
+        Ok(len)
+    }
+}
+
+impl<'r, F> Responder<'r> for EventStream<F>
+    where F: FnMut(&StreamLease) -> Result<StreamEvent> + 'r
+{
+    fn respond_to(self, _request: &Request<'_>) -> response::Result<'r>
+    {
+        Response::build()
+            .header(ContentType::new("text", "event-stream"))
+            .raw_header("Cache-Control", "no-cache")
+            .streamed_body(self)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use std::{
+        io::Read,
+        time::Duration
+    };
+    use crate::Database;
+    use super::{EventStream, StreamEvent};
+
+    #[test]
+    fn formats_sse_events() {
+        assert_eq!(
+            StreamEvent::sse(Some("user"), "a\nb"),
+            StreamEvent::Data(b"event: user\ndata: a\ndata: b\n\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn streams_until_end_and_releases_the_lease() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let mut events = vec![StreamEvent::End, StreamEvent::sse(None, "2"), StreamEvent::Idle, StreamEvent::sse(None, "1")];
+
+        let mut stream = EventStream::new(&database, move |_lease| Ok(events.pop().unwrap()))
+            .with_keep_alive(Duration::from_secs(0))
+            .with_poll_interval(Duration::from_millis(0));
+        assert_eq!(database.stream_leases(), 1);
+
+        let mut body = String::new();
+        stream.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "data: 1\n\n: keep-alive\n\ndata: 2\n\n");
+
+        drop(stream);
+        assert_eq!(database.stream_leases(), 0);
+    }
+
+    #[test]
+    fn ends_after_max_duration() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let mut stream = EventStream::new(&database, |_lease| Ok(StreamEvent::sse(None, "tick")))
+            .with_max_duration(Duration::from_secs(0));
+
+        let mut body = Vec::new();
+        stream.read_to_end(&mut body).unwrap();
+        assert!(body.is_empty());
+    }
+}
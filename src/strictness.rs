@@ -0,0 +1,55 @@
+use crate::error;
+
+/// How a database reacts to its misuse: interacting before the fairing was
+/// attached, a configuration without `url` or with an unsupported scheme.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strictness
+{
+    /// Misuse fails the interaction with a typed error.
+    Lenient,
+    /// Misuse panics with a precise message in debug builds, so that it is
+    /// noticed during development, and fails with a typed error in release
+    /// builds.
+    Strict,
+}
+
+impl Strictness
+{
+    /// Panics with `err` when misuse must panic in this build, returns it
+    /// otherwise.
+    pub(crate) fn misuse(self, err: error::Error) -> error::Error
+    {
+        if self == Strictness::Strict && cfg!(debug_assertions) {
+            panic!("rocket-diesel misuse (`Strictness::Strict`): {}", err);
+        }
+        err
+    }
+}
+
+impl Default for Strictness
+{
+    fn default() -> Self
+    {
+        Strictness::Lenient
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{Error, ErrorKind};
+    use super::Strictness;
+
+    #[test]
+    fn lenient_returns_the_error() {
+        let err = Strictness::Lenient.misuse(Error::new(ErrorKind::NotAttached, "not attached"));
+
+        assert_eq!(err.kind(), ErrorKind::NotAttached);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "rocket-diesel misuse")]
+    fn strict_panics_in_debug_builds() {
+        Strictness::Strict.misuse(Error::new(ErrorKind::NotAttached, "not attached"));
+    }
+}
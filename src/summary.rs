@@ -0,0 +1,179 @@
+//! Database cost of each request, reported in response headers.
+//!
+//! ```rust,ignore
+//! rocket::ignite()
+//!     .attach(ConfigurationsFairing::new())
+//!     .attach(DieselDatabase::new())
+//!     .attach(SummaryHeaders::new())
+//! ```
+//!
+//! The `SummaryHeaders` fairing counts the interactions of each request and
+//! their execution time, then adds them to its response, e.g.
+//! `X-DB-Queries: 4` and `X-DB-Time: 12ms`, so that the cost of a page shows
+//! in the network tab of the browser's dev tools. It is only enabled in
+//! debug builds unless told otherwise: the headers tell clients about the
+//! internals of the application.
+//!
+//! Rocket handles a request on a single worker thread: the counter is kept
+//! in a thread local, and interactions done on other threads (e.g. with an
+//! `OwnedConn` moved to a background thread) are not counted.
+
+use rocket::{
+    data::Data,
+    fairing::{
+        Fairing,
+        Info,
+        Kind
+    },
+    request::Request,
+    response::Response
+};
+
+use std::{
+    cell::Cell,
+    time::Duration
+};
+
+/// Default header carrying the number of interactions.
+pub const QUERIES_HEADER: &str = "X-DB-Queries";
+
+/// Default header carrying the execution time of the interactions.
+pub const TIME_HEADER: &str = "X-DB-Time";
+
+thread_local! {
+    static CURRENT: Cell<Option<RequestSummary>> = Cell::new(None);
+}
+
+/// Interactions of the request handled by the current thread.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RequestSummary {
+    /// Number of interactions.
+    pub queries: u64,
+    /// Execution time of the interactions, lock wait excluded.
+    pub time: Duration,
+}
+
+/// Starts counting the interactions of the current thread.
+pub(crate) fn begin() {
+    CURRENT.with(|current| current.set(Some(RequestSummary::default())));
+}
+
+/// Counts an interaction which took `duration`, if counting.
+pub(crate) fn record(duration: Duration) {
+    CURRENT.with(|current| if let Some(mut summary) = current.get() {
+        summary.queries += 1;
+        summary.time += duration;
+        current.set(Some(summary));
+    });
+}
+
+/// Stops counting, returning the interactions counted.
+pub(crate) fn end() -> Option<RequestSummary> {
+    CURRENT.with(|current| current.replace(None))
+}
+
+/// Returns the interactions counted so far for the request handled by the
+/// current thread, `None` without the `SummaryHeaders` fairing.
+pub fn current() -> Option<RequestSummary> {
+    CURRENT.with(Cell::get)
+}
+
+/// Fairing adding the database summary of each request to its response.
+#[derive(Clone, Debug)]
+pub struct SummaryHeaders {
+    _enabled: bool,
+    _queries_header: String,
+    _time_header: String,
+}
+
+impl SummaryHeaders {
+    /// Fairing enabled in debug builds, with the default headers.
+    pub fn new() -> Self {
+        Self {
+            _enabled: cfg!(debug_assertions),
+            _queries_header: QUERIES_HEADER.to_owned(),
+            _time_header: TIME_HEADER.to_owned()
+        }
+    }
+
+    /// Enables or disables the fairing, e.g. in release builds of a staging
+    /// environment.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self._enabled = enabled;
+        self
+    }
+
+    /// Sets the names of the headers.
+    pub fn with_headers(mut self, queries_header: &str, time_header: &str) -> Self {
+        self._queries_header = queries_header.to_owned();
+        self._time_header = time_header.to_owned();
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self._enabled
+    }
+}
+
+impl Default for SummaryHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats `duration` in milliseconds, e.g. `12ms` or `0.35ms`.
+fn millis(duration: Duration) -> String {
+    let micros = duration.as_micros();
+
+    match micros % 1000 {
+        0 => format!("{}ms", micros / 1000),
+        _ => format!("{:.2}ms", micros as f64 / 1000.0)
+    }
+}
+
+impl Fairing for SummaryHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Diesel request summary headers",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    fn on_request(&self, _request: &mut Request<'_>, _data: &Data) {
+        if self._enabled {
+            begin();
+        }
+    }
+
+    fn on_response(&self, _request: &Request<'_>, response: &mut Response<'_>) {
+        if let Some(summary) = end() {
+            response.set_raw_header(self._queries_header.clone(), summary.queries.to_string());
+            response.set_raw_header(self._time_header.clone(), millis(summary.time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{begin, current, end, millis, record, RequestSummary};
+
+    #[test]
+    fn counts_between_begin_and_end() {
+        record(Duration::from_millis(5));
+        assert_eq!(current(), None);
+
+        begin();
+        record(Duration::from_millis(5));
+        record(Duration::from_millis(7));
+        assert_eq!(end(), Some(RequestSummary { queries: 2, time: Duration::from_millis(12) }));
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn formats_milliseconds() {
+        assert_eq!(millis(Duration::from_millis(12)), "12ms");
+        assert_eq!(millis(Duration::from_micros(350)), "0.35ms");
+        assert_eq!(millis(Duration::from_secs(0)), "0ms");
+    }
+}
@@ -0,0 +1,190 @@
+//! Support bundle: a report on the state of the database to attach to bug
+//! reports and incidents.
+//!
+//! `Database::support_bundle` gathers the redacted settings, the state of
+//! the initialization and of the circuit breaker, the last error, the
+//! statistics, the server version and the migration status. `to_json`
+//! renders it. `support::routes()` serves it at `/support-bundle`,
+//! protected by the `metrics.token` like the `/metrics` route.
+
+use rocket::{
+    Data,
+    handler,
+    http::{
+        ContentType,
+        Method
+    },
+    Outcome,
+    request::Request,
+    response::content::Content,
+    Route
+};
+
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH
+};
+
+use crate::metrics;
+use crate::CircuitState;
+use crate::ConfigurationStatus;
+use crate::Database;
+use crate::MigrationPolicy;
+use crate::ServerVersion;
+use crate::StatsSnapshot;
+
+/// Migration settings and how many migrations are pending.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MigrationStatus
+{
+    pub policy: MigrationPolicy,
+    pub directory: String,
+    /// Number of pending migrations, `None` while not connected.
+    pub pending: Option<usize>,
+    /// Why the pending migrations could not be counted.
+    pub error: Option<String>,
+}
+
+/// Report on the state of a database. Secrets (passwords, tokens, keys)
+/// are never included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SupportBundle
+{
+    pub generated_at: SystemTime,
+    /// Version of rocket-diesel.
+    pub crate_version: &'static str,
+    pub configuration: ConfigurationStatus,
+    /// Redacted settings, as `(key, value)`, empty without a valid
+    /// configuration.
+    pub settings: Vec<(&'static str, String)>,
+    pub initialized: bool,
+    pub circuit: CircuitState,
+    pub last_init_error: Option<String>,
+    pub stats: StatsSnapshot,
+    /// Number of interactions waiting for their turn (`pool.fair`).
+    pub waiting: usize,
+    pub server_version: Option<ServerVersion>,
+    /// `None` without a valid configuration.
+    pub migrations: Option<MigrationStatus>,
+}
+
+impl SupportBundle
+{
+    /// Renders the bundle as a JSON object.
+    pub fn to_json(&self) -> String
+    {
+        let generated_at = self.generated_at.duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let settings = self.settings.iter()
+            .map(|(key, value)| format!("{}:{}", string(key), string(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let migrations = match self.migrations {
+            None => "null".to_owned(),
+            Some(ref migrations) => format!(
+                "{{\"policy\":{},\"directory\":{},\"pending\":{},\"error\":{}}}",
+                string(migrations.policy.as_str()),
+                string(&migrations.directory),
+                migrations.pending.map_or("null".to_owned(), |pending| pending.to_string()),
+                optional_string(migrations.error.as_ref())
+            )
+        };
+
+        format!(
+            concat!(
+                "{{\"generated_at\":{},\"crate_version\":{},\"configuration\":{},\"settings\":{{{}}},",
+                "\"initialized\":{},\"circuit\":{},\"last_init_error\":{},",
+                "\"stats\":{{\"interactions\":{},\"errors\":{},\"average_latency_micros\":{},\"lock_contentions\":{}}},",
+                "\"waiting\":{},\"server_version\":{},\"migrations\":{}}}"
+            ),
+            generated_at,
+            string(self.crate_version),
+            string(&self.configuration.to_string()),
+            settings,
+            self.initialized,
+            string(&self.circuit.to_string()),
+            optional_string(self.last_init_error.as_ref()),
+            self.stats.interactions,
+            self.stats.errors,
+            self.stats.average_latency.as_micros(),
+            self.stats.lock_contentions,
+            self.waiting,
+            optional_string(self.server_version.map(|version| version.to_string()).as_ref()),
+            migrations
+        )
+    }
+}
+
+/// Routes to mount to serve the support bundle.
+pub fn routes() -> Vec<Route>
+{
+    vec![Route::new(Method::Get, "/support-bundle", support_bundle)]
+}
+
+fn support_bundle<'r>(request: &'r Request<'_>, _data: Data) -> handler::Outcome<'r>
+{
+    match metrics::authorized_database(request) {
+        Ok(database) => handler::Outcome::from(
+            request, Content(ContentType::JSON, database.support_bundle().to_json())
+        ),
+        Err(status) => Outcome::Failure(status)
+    }
+}
+
+/// Renders `value` as a JSON string.
+pub(crate) fn string(value: &str) -> String
+{
+    let mut json = String::with_capacity(value.len() + 2);
+
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn optional_string(value: Option<&String>) -> String
+{
+    value.map_or("null".to_owned(), |value| string(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use crate::{ConfigurationStatus, Database};
+    use super::string;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+
+    #[test]
+    fn bundle_of_connection() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let bundle = database.support_bundle();
+
+        assert_eq!(bundle.configuration, ConfigurationStatus::Pending);
+        assert!(bundle.initialized);
+        assert!(bundle.settings.is_empty());
+        assert!(bundle.migrations.is_none());
+
+        let json = bundle.to_json();
+        assert!(json.starts_with("{\"generated_at\":"));
+        assert!(json.contains(",\"initialized\":true,\"circuit\":\"closed\",\"last_init_error\":null,"));
+        assert!(json.ends_with(",\"server_version\":null,\"migrations\":null}"));
+    }
+}
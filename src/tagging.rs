@@ -0,0 +1,309 @@
+//! Tagging of statements with the request executing them.
+//!
+//! When enabled, statements executed by the helpers are prefixed with a
+//! comment like `/* route:/users/<id> request:abc */`, so that slow query
+//! logs and APM tools can attribute them to Rocket routes.
+//!
+//! Rocket handles a request (fairings included) on a single worker thread,
+//! so the tag of the current request is kept in a thread local set by the
+//! fairing when the request comes in and cleared when the response leaves.
+//!
+//! Request fairings run before routing: the fairing only tags the request
+//! id (and client IP address). The route template (`/users/<id>`, never
+//! the path with its values) is added once a route matched, by the request
+//! guards of the crate: `QueryTag`, `Tx` and `OwnedConn`. Handlers using
+//! none of them can take `_tag: QueryTag` for their statements to carry
+//! their route.
+//!
+//! Work done outside of the request's thread (e.g. with an `OwnedConn`)
+//! takes the tag explicitly: `QueryTag` is a request guard, and
+//! `Database::interact_with_request` makes it the tag of the interaction.
+//! The client IP address is only part of the tag with the
+//! `query_tagging_client_ip` configuration key.
+
+use rocket::{
+    Outcome,
+    request::{
+        self,
+        FromRequest,
+        Request
+    },
+    State
+};
+
+use std::{
+    cell::{
+        Cell,
+        RefCell
+    },
+    net::IpAddr
+};
+
+use crate::{
+    stats,
+    Database
+};
+
+thread_local! {
+    static CURRENT: RefCell<Option<QueryTag>> = RefCell::new(None);
+    // Path of the request, matched against the route quotas
+    static PATH: RefCell<Option<String>> = RefCell::new(None);
+    // Slot of the route of the current tag in the per-route statistics
+    static ROUTE_SLOT: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// Header carrying the request identifier.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Attribution of statements to the request executing them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueryTag {
+    pub route: Option<String>,
+    pub request_id: Option<String>,
+    pub client_ip: Option<IpAddr>,
+}
+
+impl QueryTag {
+    /// Builds the tag of `request`: the template of its route and its
+    /// `X-Request-Id` header.
+    ///
+    /// The route is `None` until one matched, e.g. in request fairings.
+    pub fn from_request(request: &Request<'_>) -> Self {
+        Self {
+            route: request.route().map(|route| route.uri.path().to_owned()),
+            request_id: request.headers().get_one(REQUEST_ID_HEADER).map(str::to_owned),
+            client_ip: None
+        }
+    }
+
+    /// Same as `from_request`, with the client IP address of `request`.
+    pub fn with_client_ip(request: &Request<'_>) -> Self {
+        Self {
+            client_ip: request.client_ip(),
+            ..Self::from_request(request)
+        }
+    }
+
+    /// Returns whether the tag carries no information.
+    pub fn is_empty(&self) -> bool {
+        self.route.is_none() && self.request_id.is_none() && self.client_ip.is_none()
+    }
+
+    /// Renders the tag as an SQL comment.
+    pub fn comment(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let Some(ref route) = self.route {
+            parts.push(format!("route:{}", sanitize(route)));
+        }
+        if let Some(ref request_id) = self.request_id {
+            parts.push(format!("request:{}", sanitize(request_id)));
+        }
+        if let Some(client_ip) = self.client_ip {
+            parts.push(format!("client:{}", client_ip));
+        }
+
+        format!("/* {} */", parts.join(" "))
+    }
+
+    /// Prefixes `sql` with the comment of the tag, if not empty.
+    pub fn apply(&self, sql: &str) -> String {
+        if self.is_empty() {
+            return sql.to_owned();
+        }
+
+        format!("{} {}", self.comment(), sql)
+    }
+}
+
+/// Tag of the request, with its client IP address if configured by
+/// `query_tagging_client_ip`. Also adds the route to the tag of the current
+/// thread.
+impl<'a, 'r> FromRequest<'a, 'r> for QueryTag {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        set_route(request);
+
+        let client_ip = match request.guard::<State<'_, Database>>() {
+            Outcome::Success(database) => database.tags_client_ip(),
+            _ => false
+        };
+
+        Outcome::Success(match client_ip {
+            true => QueryTag::with_client_ip(request),
+            false => QueryTag::from_request(request)
+        })
+    }
+}
+
+/// Prevents values from closing the comment early.
+fn sanitize(value: &str) -> String {
+    value.replace("*/", "* /")
+}
+
+/// Sets the tag of the request handled by the current thread.
+pub(crate) fn set_current(tag: Option<QueryTag>) {
+    replace_current(tag);
+}
+
+/// Sets the tag of the current thread and the slot of its route, returning
+/// the previous tag.
+fn replace_current(tag: Option<QueryTag>) -> Option<QueryTag> {
+    let slot = tag.as_ref().and_then(|tag| tag.route.as_ref()).map(|route| stats::route_slot(route));
+
+    ROUTE_SLOT.with(|current| current.set(slot));
+    CURRENT.with(|current| current.replace(tag))
+}
+
+/// Returns the tag of the request handled by the current thread.
+pub fn current() -> Option<QueryTag> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Adds the template of the route `request` matched to the tag of the
+/// current thread, from a request guard (routing is done by then).
+pub(crate) fn set_route(request: &Request<'_>) {
+    if let Some(route) = request.route() {
+        CURRENT.with(|current| if let Some(tag) = current.borrow_mut().as_mut() {
+            let template = route.uri.path();
+
+            if tag.route.as_ref().map(String::as_str) != Some(template) {
+                ROUTE_SLOT.with(|slot| slot.set(Some(stats::route_slot(template))));
+                tag.route = Some(template.to_owned());
+            }
+        });
+    }
+}
+
+/// Returns the slot of the route of the current tag in the per-route
+/// statistics, resolved when the route was set.
+pub(crate) fn current_route_slot() -> Option<usize> {
+    ROUTE_SLOT.with(Cell::get)
+}
+
+/// Sets the path of the request handled by the current thread.
+pub(crate) fn set_path(path: Option<String>) {
+    PATH.with(|current| *current.borrow_mut() = path);
+}
+
+/// Returns the path of the request handled by the current thread.
+pub(crate) fn current_path() -> Option<String> {
+    PATH.with(|current| current.borrow().clone())
+}
+
+/// Tag set on the current thread until dropped, the previous one being
+/// restored then.
+pub(crate) struct ScopedTag(Option<QueryTag>);
+
+impl ScopedTag {
+    pub fn new(tag: QueryTag) -> Self {
+        ScopedTag(replace_current(Some(tag)))
+    }
+}
+
+impl Drop for ScopedTag {
+    fn drop(&mut self) {
+        set_current(self.0.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection as _,
+        sqlite::SqliteConnection
+    };
+    use rocket::{
+        config::{Config, Environment},
+        data::Data,
+        handler,
+        http::Method,
+        local::Client,
+        Request,
+        Route
+    };
+    use crate::Database;
+    use super::{current, set_current, QueryTag, ScopedTag};
+
+    /// Answers the route of the tag of the request, once `QueryTag` ran.
+    fn tagged_route<'r>(request: &'r Request<'_>, _data: Data) -> handler::Outcome<'r> {
+        let before = current().and_then(|tag| tag.route);
+        let _tag = request.guard::<QueryTag>();
+        let after = current().and_then(|tag| tag.route);
+
+        handler::Outcome::from(request, format!("{:?} {:?}", before, after))
+    }
+
+    #[test]
+    fn apply() {
+        let tag = QueryTag {
+            route: Some("/users/<id>".to_owned()),
+            request_id: Some("abc".to_owned()),
+            client_ip: None
+        };
+
+        assert_eq!(
+            tag.apply("SELECT 1"),
+            "/* route:/users/<id> request:abc */ SELECT 1"
+        );
+
+        let tag = QueryTag { client_ip: Some("10.0.0.1".parse().unwrap()), ..tag };
+        assert_eq!(
+            tag.apply("SELECT 1"),
+            "/* route:/users/<id> request:abc client:10.0.0.1 */ SELECT 1"
+        );
+        assert_eq!(QueryTag::default().apply("SELECT 1"), "SELECT 1");
+    }
+
+    #[test]
+    fn sanitize() {
+        let tag = QueryTag {
+            route: None,
+            request_id: Some("*/ DROP TABLE users; /*".to_owned()),
+            client_ip: None
+        };
+
+        assert_eq!(tag.comment(), "/* request:* / DROP TABLE users; /* */");
+    }
+
+    #[test]
+    fn current_is_thread_local() {
+        let tag = QueryTag { route: Some("/".to_owned()), request_id: None, client_ip: None };
+
+        set_current(Some(tag.clone()));
+        assert_eq!(current(), Some(tag));
+        assert_eq!(std::thread::spawn(current).join().unwrap(), None);
+
+        set_current(None);
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn scoped_tag_restores_previous() {
+        let outer = QueryTag { route: Some("/outer".to_owned()), ..QueryTag::default() };
+        let inner = QueryTag { route: Some("/inner".to_owned()), ..QueryTag::default() };
+
+        set_current(Some(outer.clone()));
+        {
+            let _scoped = ScopedTag::new(inner.clone());
+            assert_eq!(current(), Some(inner));
+        }
+        assert_eq!(current(), Some(outer));
+
+        set_current(None);
+    }
+
+    #[test]
+    fn tags_the_matched_route() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let rocket = rocket::custom(Config::new(Environment::Development))
+            .mount("/users", vec![Route::new(Method::Get, "/<id>", tagged_route)])
+            .attach(database);
+        let client = Client::new(rocket).unwrap();
+
+        let mut response = client.get("/users/42").dispatch();
+        assert_eq!(response.body_string(), Some("None Some(\"/users/<id>\")".to_owned()));
+        assert_eq!(current(), None);
+    }
+}
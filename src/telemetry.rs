@@ -0,0 +1,137 @@
+//! OpenTelemetry database semantic conventions.
+//!
+//! With the `tracing` feature, every interaction is wrapped in a
+//! `db.interaction` span carrying the attributes below, so traces exported
+//! through an OpenTelemetry subscriber need no custom mapping.
+
+use crate::Backend;
+use crate::Settings;
+
+/// Attributes describing the database, following the OpenTelemetry
+/// semantic conventions.
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct DbAttributes {
+    /// `db.system`
+    pub system: Option<&'static str>,
+    /// `db.name`
+    pub name: Option<String>,
+    /// `net.peer.name`
+    pub peer_name: Option<String>,
+    /// `net.peer.port`
+    pub peer_port: Option<u16>,
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+impl DbAttributes {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let url = settings.url();
+        let backend = Backend::from_scheme(url.scheme());
+
+        let name = match backend {
+            Some(Backend::Sqlite) => Some(url.path().to_owned()),
+            _ => url.path_segments()
+                .and_then(|mut segments| segments.next())
+                .map(str::to_owned)
+        };
+
+        Self {
+            system: backend.map(|backend| match backend {
+                Backend::Mysql  => "mysql",
+                Backend::Pg     => "postgresql",
+                Backend::Sqlite => "sqlite",
+            }),
+            name: name.filter(|name| !name.is_empty()),
+            peer_name: url.host_str()
+                .filter(|host| !host.is_empty())
+                .map(str::to_owned),
+            peer_port: url.port()
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) use self::layer::TracingLayer;
+
+#[cfg(feature = "tracing")]
+mod layer {
+    use tracing::field;
+
+    use crate::layer::{
+        InteractContext,
+        InteractLayer,
+        Next
+    };
+    use crate::Result;
+    use super::DbAttributes;
+
+    /// Wraps interactions in `db.interaction` spans.
+    pub(crate) struct TracingLayer;
+
+    impl InteractLayer for TracingLayer {
+        fn call(&self, context: &mut InteractContext, next: Next<'_>) -> Result<()> {
+            let attributes = context.settings()
+                .map(DbAttributes::from_settings)
+                .unwrap_or_default();
+            let span = tracing::info_span!(
+                "db.interaction",
+                db.system = field::Empty,
+                db.name = field::Empty,
+                db.operation = field::Empty,
+                net.peer.name = field::Empty,
+                net.peer.port = field::Empty,
+                code.filepath = context.caller().file(),
+                code.lineno = u64::from(context.caller().line()),
+                otel.status_code = field::Empty
+            );
+
+            if let Some(system) = attributes.system {
+                span.record("db.system", &system);
+            }
+            if let Some(ref name) = attributes.name {
+                span.record("db.name", &name.as_str());
+            }
+            if let Some(ref peer_name) = attributes.peer_name {
+                span.record("net.peer.name", &peer_name.as_str());
+            }
+            if let Some(peer_port) = attributes.peer_port {
+                span.record("net.peer.port", &u64::from(peer_port));
+            }
+
+            let result = span.in_scope(|| next.run(context));
+
+            if result.is_err() {
+                span.record("otel.status_code", &"ERROR");
+            }
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Settings;
+    use super::DbAttributes;
+
+    #[test]
+    fn server_attributes() {
+        let settings = Settings::new("postgres://user@db.local:5433/rocket".to_owned()).unwrap();
+
+        assert_eq!(DbAttributes::from_settings(&settings), DbAttributes {
+            system: Some("postgresql"),
+            name: Some("rocket".to_owned()),
+            peer_name: Some("db.local".to_owned()),
+            peer_port: Some(5433)
+        });
+    }
+
+    #[test]
+    fn sqlite_attributes() {
+        let settings = Settings::new("sqlite:///var/lib/app.db".to_owned()).unwrap();
+        let attributes = DbAttributes::from_settings(&settings);
+
+        assert_eq!(attributes.system, Some("sqlite"));
+        assert_eq!(attributes.name, Some("/var/lib/app.db".to_owned()));
+        assert_eq!(attributes.peer_name, None);
+    }
+}
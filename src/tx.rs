@@ -0,0 +1,578 @@
+//! Request guard running a whole handler in a transaction.
+//!
+//! ```rust,ignore
+//! #[post("/users/<id>/rename/<name>")]
+//! fn rename(mut tx: Tx<'_>, id: i32, name: String) -> Result<(), Error> {
+//!     tx.run(|conn| /* update */, |conn| /* update */, |conn| /* update */)?;
+//!     tx.run(|conn| /* audit */, |conn| /* audit */, |conn| /* audit */)?;
+//!     tx.commit()
+//! }
+//! ```
+//!
+//! The transaction begins when the guard is acquired and only commits when
+//! the handler calls `commit`: a guard dropped without it (the handler
+//! returning early with an error, or panicking) rolls the transaction back.
+//! `commit` rolls back too, and fails, when a `run` failed or `rollback`
+//! was called.
+//!
+//! The guard holds the connection for its whole lifetime: the handler must
+//! not call `Database::interact` (nor acquire another `Tx`) while it is
+//! alive, or it deadlocks.
+//!
+//! Steps which may hit deadlocks (e.g. in long multi-step business
+//! transactions) run with `attempt` instead of `run`: the step is wrapped in
+//! a savepoint, rolled back to it and retried when it fails with a
+//! transient error, without restarting the whole transaction.
+//!
+//! Side effects which must only happen once the changes are visible (e.g.
+//! sending emails, publishing events) are scheduled with `after_commit`:
+//! they run once the transaction committed and the connection is released,
+//! and are dropped when it is rolled back.
+
+use diesel::connection::TransactionManager;
+
+use rocket::{
+    http::Status,
+    Outcome,
+    request::{
+        self,
+        FromRequest,
+        Request
+    },
+    State
+};
+
+use std::{
+    error::Error,
+    fmt,
+    panic::Location
+};
+
+use crate::redaction::log_redacted;
+use crate::database;
+use crate::retry;
+use crate::Database;
+use crate::error;
+use crate::locked_connection::ConnectionMut;
+use crate::LockedConnection;
+use crate::Result;
+use crate::tagging;
+
+/// Times `Tx::attempt` runs a step failing with transient errors.
+const ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Action {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+fn apply<Conn>(conn: &Conn, action: Action) -> diesel::QueryResult<()>
+    where Conn: diesel::Connection
+{
+    let manager = conn.transaction_manager();
+
+    match action {
+        Action::Begin       => manager.begin_transaction(conn),
+        Action::Commit      => manager.commit_transaction(conn),
+        Action::Rollback    => manager.rollback_transaction(conn),
+    }
+}
+
+/// Rolls back the transactions of `conn` nested deeper than `depth`,
+/// returning how many were rolled back.
+fn rollback_beyond<Conn>(conn: &Conn, depth: u32) -> diesel::QueryResult<u32>
+    where Conn: diesel::Connection
+{
+    let manager = conn.transaction_manager();
+    let mut rolled_back = 0;
+
+    while manager.get_transaction_depth() > depth {
+        manager.rollback_transaction(conn)?;
+        rolled_back += 1;
+    }
+
+    Ok(rolled_back)
+}
+
+/// Rolls back the transactions an interaction left open on the locked
+/// connection, beyond the `depth` expected once it returned, before the
+/// connection is released.
+pub(crate) fn rollback_idle(lock: &mut LockedConnection<'_>, depth: u32, caller: &Location<'_>) {
+    let result = rollback_beyond_lock(lock, depth);
+    let route = tagging::current().and_then(|tag| tag.route)
+        .unwrap_or_else(|| "<none>".to_owned());
+
+    match result {
+        Ok(0) => {},
+        Ok(rolled_back) => log_redacted!(log::Level::Warn,
+            "Rolled back {} transaction(s) left open by the interaction at {} (route {}).",
+            rolled_back, caller, route
+        ),
+        Err(err) => log_redacted!(log::Level::Error,
+            "Failed to roll back a transaction left open by the interaction at {} (route {}): {}",
+            caller, route, err
+        )
+    }
+}
+
+/// Rolls back the transactions of the locked connection nested deeper than
+/// `depth`.
+fn rollback_beyond_lock(lock: &mut LockedConnection<'_>, depth: u32) -> diesel::QueryResult<u32> {
+    match lock.conn_mut() {
+        ConnectionMut::Unknown => Ok(0),
+        ConnectionMut::Mysql(conn) => rollback_beyond::<diesel::MysqlConnection>(conn, depth),
+        ConnectionMut::Pg(conn) => rollback_beyond::<diesel::PgConnection>(conn, depth),
+        ConnectionMut::Sqlite(conn) => rollback_beyond::<diesel::SqliteConnection>(conn, depth),
+    }
+}
+
+/// Applies `action` to the transaction of the locked connection.
+pub(crate) fn transaction(lock: &mut LockedConnection<'_>, action: Action) -> Result<()> {
+    let result = match lock.conn_mut() {
+        ConnectionMut::Unknown => return Err(error::Error::new(
+            error::ErrorKind::Other, "database is not ready"
+        )),
+        ConnectionMut::Mysql(conn) => apply::<diesel::MysqlConnection>(conn, action),
+        ConnectionMut::Pg(conn) => apply::<diesel::PgConnection>(conn, action),
+        ConnectionMut::Sqlite(conn) => apply::<diesel::SqliteConnection>(conn, action),
+    };
+
+    result.map_err(error::Error::from)
+}
+
+type Callback = Box<dyn FnOnce()>;
+
+/// Transaction spanning the handler which acquired the guard.
+pub struct Tx<'r> {
+    _database: &'r Database,
+    // `None` once the transaction ended
+    _lock: Option<LockedConnection<'r>>,
+    _rollback: bool,
+    _after_commit: Vec<Callback>,
+    _after_rollback: Vec<Callback>,
+}
+
+impl<'r> Tx<'r> {
+    /// Locks the connection of `database` and begins a transaction.
+    pub fn begin(database: &'r Database) -> Result<Self> {
+        let mut lock = database.lock_initialized()?;
+        transaction(&mut lock, Action::Begin)?;
+
+        Ok(Self {
+            _database: database,
+            _lock: Some(lock),
+            _rollback: false,
+            _after_commit: Vec::new(),
+            _after_rollback: Vec::new()
+        })
+    }
+
+    /// Schedules `callback` to run once the transaction committed.
+    pub fn after_commit<F>(&mut self, callback: F)
+        where F: FnOnce() + 'static
+    {
+        self._after_commit.push(Box::new(callback));
+    }
+
+    /// Schedules `callback` to run once the transaction was rolled back.
+    pub fn after_rollback<F>(&mut self, callback: F)
+        where F: FnOnce() + 'static
+    {
+        self._after_rollback.push(Box::new(callback));
+    }
+
+    /// Executes the closure matching the backend inside the transaction.
+    ///
+    /// A failure marks the transaction for rollback.
+    #[track_caller]
+    pub fn run<T, E, MysqlF, PgF, SqliteF>(&mut self, mysql_f: MysqlF, pg_f: PgF, sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnOnce(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnOnce(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnOnce(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+        let database = self._database;
+        let lock = self._lock.as_mut().ok_or_else(|| error::Error::new(
+            error::ErrorKind::Other, "transaction already ended"
+        ))?;
+
+        let result = database.layered(caller, |context| {
+            let value = database::run_on(lock, context, mysql_f, pg_f, sqlite_f);
+
+            rollback_idle(lock, 1, caller);
+            value
+        });
+        database.touch();
+
+        if result.is_err() {
+            self._rollback = true;
+        }
+        result.map(|outcome| outcome.value)
+    }
+
+    /// Executes the closure matching the backend in a savepoint of the
+    /// transaction, retrying it up to three times in total when it
+    /// fails with a transient error (`Error::is_transient`, e.g. a
+    /// deadlock). Changes of a failed attempt are rolled back to the
+    /// savepoint, those of the previous steps are kept.
+    ///
+    /// Retries are charged to the `retry_budget` of the request. A failure
+    /// of the last attempt marks the transaction for rollback.
+    #[track_caller]
+    pub fn attempt<T, E, MysqlF, PgF, SqliteF>(&mut self, mut mysql_f: MysqlF, mut pg_f: PgF, mut sqlite_f: SqliteF) -> Result<T>
+        where E: From<diesel::result::Error> + Error + Send + Sync + 'static,
+              MysqlF: FnMut(&mut diesel::mysql::MysqlConnection) -> std::result::Result<T, E>,
+              PgF: FnMut(&mut diesel::pg::PgConnection) -> std::result::Result<T, E>,
+              SqliteF: FnMut(&mut diesel::sqlite::SqliteConnection) -> std::result::Result<T, E>,
+    {
+        let caller = Location::caller();
+        let database = self._database;
+        let budget = database.settings().ok().and_then(|settings| settings.retry_budget());
+        let mut attempts = 0;
+
+        let result = loop {
+            let lock = self._lock.as_mut().ok_or_else(|| error::Error::new(
+                error::ErrorKind::Other, "transaction already ended"
+            ))?;
+            let started = database.clock().now();
+            attempts += 1;
+
+            let result = transaction(lock, Action::Begin).and_then(|_| database.layered(caller, |context| {
+                let value = database::run_on(
+                    lock, context, |conn| mysql_f(conn), |conn| pg_f(conn), |conn| sqlite_f(conn)
+                );
+
+                rollback_idle(lock, 2, caller);
+                value
+            }));
+            database.touch();
+
+            let result = result.and_then(|outcome| transaction(lock, Action::Commit).map(|_| outcome));
+
+            if result.is_err() {
+                // Rolls back to the savepoint, unless it was not created
+                if let Err(err) = rollback_beyond_lock(lock, 1) {
+                    log_redacted!(log::Level::Error, "Failed to roll back to the savepoint of the step at {}: {}", caller, err);
+                }
+            }
+            if attempts > 1 {
+                retry::spend(database.clock().now().duration_since(started));
+            }
+
+            match result {
+                Err(ref err) if err.is_transient() && attempts < ATTEMPTS && retry::allows(budget) => {
+                    log_redacted!(log::Level::Warn,
+                        "Retrying the step at {} from its savepoint (attempt {}/{}): {}",
+                        caller, attempts + 1, ATTEMPTS, err
+                    );
+                },
+                result => break result
+            }
+        };
+
+        if result.is_err() {
+            self._rollback = true;
+        }
+        result.map(|outcome| outcome.value)
+    }
+
+    /// Commits the transaction, then runs the `after_commit` callbacks.
+    ///
+    /// A transaction marked for rollback (by a failed step or `rollback`)
+    /// is rolled back instead, and an error returned.
+    pub fn commit(mut self) -> Result<()> {
+        match self._rollback {
+            true => {
+                self.end(Action::Rollback)?;
+                Err(error::Error::new(
+                    error::ErrorKind::Other, "transaction was marked for rollback"
+                ))
+            },
+            false => self.end(Action::Commit)
+        }
+    }
+
+    /// Marks the transaction for rollback: `commit` rolls it back instead
+    /// of committing it.
+    pub fn rollback(&mut self) {
+        self._rollback = true;
+    }
+
+    /// Returns whether the transaction will be rolled back.
+    pub fn is_rollback(&self) -> bool {
+        self._rollback
+    }
+}
+
+impl<'r> fmt::Debug for Tx<'r> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Tx")
+            .field("lock", &self._lock)
+            .field("rollback", &self._rollback)
+            .field("after_commit", &self._after_commit.len())
+            .field("after_rollback", &self._after_rollback.len())
+            .finish()
+    }
+}
+
+impl<'r> Tx<'r> {
+    /// Ends the transaction with `action`, then runs the callbacks matching
+    /// the outcome once the connection is released.
+    fn end(&mut self, action: Action) -> Result<()> {
+        let mut lock = match self._lock.take() {
+            Some(lock) => lock,
+            None => return Ok(())
+        };
+        let mut outcome = action;
+        let result = transaction(&mut lock, action);
+
+        if let Err(ref err) = result {
+            log_redacted!(log::Level::Error, "Failed to end the transaction ({:?}): {}", action, err);
+
+            // A failed commit leaves the transaction open
+            if let Action::Commit = action {
+                let _ = transaction(&mut lock, Action::Rollback);
+                outcome = Action::Rollback;
+            }
+        }
+
+        // Releases the connection: callbacks may use the database
+        drop(lock);
+
+        let callbacks = match outcome {
+            Action::Commit => self._after_commit.drain(..),
+            _ => self._after_rollback.drain(..)
+        };
+        for callback in callbacks {
+            callback();
+        }
+        result
+    }
+}
+
+/// Rolls back a transaction which was not committed.
+impl<'r> Drop for Tx<'r> {
+    fn drop(&mut self) {
+        let _ = self.end(Action::Rollback);
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Tx<'r> {
+    type Error = error::Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        tagging::set_route(request);
+
+        let database = match request.guard::<State<'r, Database>>() {
+            Outcome::Success(database) => database.inner(),
+            _ => return Outcome::Failure((
+                Status::InternalServerError,
+                error::Error::new(error::ErrorKind::Other, "database fairing is not attached")
+            ))
+        };
+
+        match Tx::begin(database) {
+            Ok(tx) => Outcome::Success(tx),
+            Err(err) => Outcome::Failure((Status::ServiceUnavailable, err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, TransactionManager},
+        query_dsl::RunQueryDsl,
+        result::{DatabaseErrorKind, Error as DieselError},
+        sqlite::SqliteConnection
+    };
+    use std::{
+        cell::RefCell,
+        rc::Rc
+    };
+    use crate::Database;
+    use super::{rollback_beyond, Tx};
+
+    fn execute(database: &Database, sql: &str) -> usize {
+        database.interact_any::<_, DieselError, _>(
+            |conn| diesel::sql_query(sql).execute(&*conn.as_sqlite().unwrap())
+        ).unwrap()
+    }
+
+    /// Schedules callbacks recording `name` and their outcome in `events`,
+    /// the commit one checking the connection is released.
+    fn schedule(tx: &mut Tx<'_>, database: &Database, events: &Rc<RefCell<Vec<String>>>, name: &str) {
+        let (database, committed, name_commit) = (database.clone(), events.clone(), name.to_owned());
+        tx.after_commit(move || {
+            execute(&database, "DELETE FROM parents");
+            committed.borrow_mut().push(format!("{} committed", name_commit));
+        });
+
+        let (rolled_back, name) = (events.clone(), name.to_owned());
+        tx.after_rollback(move || rolled_back.borrow_mut().push(format!("{} rolled back", name)));
+    }
+
+    #[test]
+    fn rolls_back_idle_transactions() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        let manager = conn.transaction_manager();
+
+        manager.begin_transaction(&conn).unwrap();
+        manager.begin_transaction(&conn).unwrap();
+
+        assert_eq!(rollback_beyond(&conn, 1).unwrap(), 1);
+        assert_eq!(manager.get_transaction_depth(), 1);
+        assert_eq!(rollback_beyond(&conn, 1).unwrap(), 0);
+        assert_eq!(rollback_beyond(&conn, 0).unwrap(), 1);
+        assert_eq!(manager.get_transaction_depth(), 0);
+    }
+
+    #[test]
+    fn retries_steps_from_their_savepoint() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let delete_all = |database: &Database| database.interact::<_, DieselError, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query("DELETE FROM steps").execute(&*conn)
+        ).unwrap();
+
+        {
+            let mut tx = Tx::begin(&database).unwrap();
+            tx.run::<_, DieselError, _, _, _>(
+                |_conn| unreachable!(),
+                |_conn| unreachable!(),
+                |conn| diesel::sql_query("CREATE TABLE steps (id INTEGER)").execute(&*conn)
+            ).unwrap();
+
+            let mut attempts = 0;
+            tx.attempt::<_, DieselError, _, _, _>(
+                |_conn| unreachable!(),
+                |_conn| unreachable!(),
+                |conn| {
+                    attempts += 1;
+                    diesel::sql_query("INSERT INTO steps VALUES (1)").execute(&*conn)?;
+
+                    match attempts {
+                        1 => Err(DieselError::DatabaseError(
+                            DatabaseErrorKind::SerializationFailure, Box::new("deadlock detected".to_owned())
+                        )),
+                        _ => Ok(())
+                    }
+                }
+            ).unwrap();
+
+            assert_eq!(attempts, 2);
+            assert!(!tx.is_rollback());
+            tx.commit().unwrap();
+        }
+        assert_eq!(delete_all(&database), 1);
+
+        {
+            let mut tx = Tx::begin(&database).unwrap();
+            let mut attempts = 0;
+            let result = tx.attempt::<(), DieselError, _, _, _>(
+                |_conn| unreachable!(),
+                |_conn| unreachable!(),
+                |_conn| {
+                    attempts += 1;
+                    Err(DieselError::NotFound)
+                }
+            );
+
+            assert!(result.is_err());
+            assert_eq!(attempts, 1);
+            assert!(tx.is_rollback());
+            assert!(tx.commit().is_err());
+        }
+    }
+
+    #[test]
+    fn commits_only_when_asked() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let insert = |tx: &mut Tx<'_>, id: i32| tx.run::<_, DieselError, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query(format!("INSERT INTO items VALUES ({})", id)).execute(&*conn)
+        ).unwrap();
+        let delete_all = |database: &Database| database.interact::<_, DieselError, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query("DELETE FROM items").execute(&*conn)
+        ).unwrap();
+
+        database.interact::<_, DieselError, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query("CREATE TABLE items (id INTEGER)").execute(&*conn)
+        ).unwrap();
+
+        let mut tx = Tx::begin(&database).unwrap();
+        insert(&mut tx, 1);
+        tx.commit().unwrap();
+        assert_eq!(delete_all(&database), 1);
+
+        // Dropped without committing, e.g. by a handler returning early
+        {
+            let mut tx = Tx::begin(&database).unwrap();
+            insert(&mut tx, 2);
+        }
+        assert_eq!(delete_all(&database), 0);
+
+        let mut tx = Tx::begin(&database).unwrap();
+        insert(&mut tx, 3);
+        tx.rollback();
+        assert!(tx.commit().is_err());
+        assert_eq!(delete_all(&database), 0);
+    }
+
+    #[test]
+    fn runs_the_callbacks_of_the_outcome() {
+        let database = Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+        let events = Rc::new(RefCell::new(Vec::new()));
+        execute(&database, "PRAGMA foreign_keys = ON");
+        execute(&database, "CREATE TABLE parents (id INTEGER PRIMARY KEY)");
+        execute(&database, "CREATE TABLE children (parent INTEGER REFERENCES parents (id) DEFERRABLE INITIALLY DEFERRED)");
+
+        // After the commit, with the connection released
+        let mut tx = Tx::begin(&database).unwrap();
+        schedule(&mut tx, &database, &events, "commit");
+        tx.commit().unwrap();
+
+        // Rolled back with `rollback`
+        let mut tx = Tx::begin(&database).unwrap();
+        schedule(&mut tx, &database, &events, "rollback");
+        tx.rollback();
+        assert!(tx.commit().is_err());
+
+        // Rolled back after a failed step
+        let mut tx = Tx::begin(&database).unwrap();
+        schedule(&mut tx, &database, &events, "failed step");
+        assert!(tx.run::<(), DieselError, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |_conn| Err(DieselError::NotFound)
+        ).is_err());
+        drop(tx);
+
+        // The deferred foreign key fails the commit
+        let mut tx = Tx::begin(&database).unwrap();
+        schedule(&mut tx, &database, &events, "failed commit");
+        tx.run::<_, DieselError, _, _, _>(
+            |_conn| unreachable!(),
+            |_conn| unreachable!(),
+            |conn| diesel::sql_query("INSERT INTO children VALUES (1)").execute(&*conn)
+        ).unwrap();
+        assert!(tx.commit().is_err());
+
+        assert_eq!(*events.borrow(), vec![
+            "commit committed",
+            "rollback rolled back",
+            "failed step rolled back",
+            "failed commit rolled back"
+        ]);
+        assert_eq!(execute(&database, "DELETE FROM children"), 0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Version of the database server.
+//!
+//! The version is queried once connected, exposed by
+//! `Database::server_version`, and compared with the `min_version` of the
+//! backend (e.g. `min_version.pg = "12"`): initialization fails with
+//! `UnsupportedVersion` when the server is older than the application
+//! supports.
+
+use diesel::{
+    query_dsl::RunQueryDsl,
+    result::QueryResult,
+    sql_types::Text
+};
+
+use std::{
+    fmt,
+    str::FromStr
+};
+
+use crate::error;
+use crate::Result;
+
+/// `major.minor.patch` version of a server, omitted parts being `0`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ServerVersion
+{
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ServerVersion
+{
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self
+    {
+        Self { major, minor, patch }
+    }
+}
+
+impl fmt::Display for ServerVersion
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(fmt, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Parses the leading version of strings reported by servers, e.g.
+/// `8.0.23-log` (MySQL) or `12.4 (Debian 12.4-1.pgdg100+1)` (Postgres).
+impl FromStr for ServerVersion
+{
+    type Err = error::Error;
+
+    fn from_str(version: &str) -> Result<Self>
+    {
+        let leading = version.trim()
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .unwrap_or("");
+        let mut parts = leading.split('.').filter(|part| !part.is_empty()).map(str::parse::<u32>);
+
+        let major = match parts.next() {
+            Some(Ok(major)) => major,
+            _ => return Err(error::Error::new(
+                error::ErrorKind::FormatError,
+                format!("invalid server version `{}`", version)
+            ))
+        };
+        let minor = parts.next().and_then(|part| part.ok()).unwrap_or(0);
+        let patch = parts.next().and_then(|part| part.ok()).unwrap_or(0);
+
+        Ok(ServerVersion::new(major, minor, patch))
+    }
+}
+
+#[derive(QueryableByName)]
+struct VersionRow
+{
+    #[sql_type = "Text"]
+    version: String,
+}
+
+/// Backend-specific query of the server version.
+pub trait VersionConnection: diesel::Connection
+{
+    fn server_version(&self) -> QueryResult<String>;
+}
+
+macro_rules! impl_version_connection {
+    ($connection:ty, $select:expr) => {
+        impl VersionConnection for $connection
+        {
+            fn server_version(&self) -> QueryResult<String>
+            {
+                diesel::sql_query($select)
+                    .get_result::<VersionRow>(self)
+                    .map(|row| row.version)
+            }
+        }
+    };
+}
+
+impl_version_connection!(diesel::MysqlConnection, "SELECT VERSION() AS version");
+impl_version_connection!(diesel::PgConnection, "SELECT current_setting('server_version') AS version");
+impl_version_connection!(diesel::SqliteConnection, "SELECT sqlite_version() AS version");
+
+/// Queries the version of the server of `conn`, failing when it is older
+/// than `min_version`.
+pub(crate) fn check<C: VersionConnection>(conn: &C, min_version: Option<ServerVersion>) -> Result<ServerVersion>
+{
+    let version = conn.server_version()?.parse::<ServerVersion>()?;
+
+    match min_version {
+        Some(min_version) if version < min_version => Err(error::Error::new(
+            error::ErrorKind::UnsupportedVersion,
+            format!("server version {} is older than the required `min_version` {}", version, min_version)
+        )),
+        _ => Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use crate::error::ErrorKind;
+    use super::{check, ServerVersion};
+
+    #[test]
+    fn parses_reported_versions() {
+        assert_eq!("8.0.23-log".parse::<ServerVersion>().unwrap(), ServerVersion::new(8, 0, 23));
+        assert_eq!("12.4 (Debian 12.4-1.pgdg100+1)".parse::<ServerVersion>().unwrap(), ServerVersion::new(12, 4, 0));
+        assert_eq!("10.5.8-MariaDB".parse::<ServerVersion>().unwrap(), ServerVersion::new(10, 5, 8));
+        assert_eq!("13beta2".parse::<ServerVersion>().unwrap(), ServerVersion::new(13, 0, 0));
+        assert!("unknown".parse::<ServerVersion>().is_err());
+    }
+
+    #[test]
+    fn orders_versions() {
+        assert!(ServerVersion::new(9, 6, 20) < ServerVersion::new(12, 0, 0));
+        assert!(ServerVersion::new(3, 31, 1) > ServerVersion::new(3, 8, 0));
+    }
+
+    #[test]
+    fn enforces_min_version() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+
+        let version = check(&conn, Some(ServerVersion::new(3, 0, 0))).unwrap();
+        assert_eq!(version.major, 3);
+
+        let err = check(&conn, Some(ServerVersion::new(99, 0, 0))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnsupportedVersion);
+    }
+}
@@ -0,0 +1,336 @@
+//! Modification counters of tables, for conditional `GET` requests.
+//!
+//! ```rust,ignore
+//! database.install_version_triggers("users")?;
+//!
+//! #[get("/users")]
+//! fn users(database: State<'_, Database>) -> Result<Versioned<Json<Vec<User>>>, Error> {
+//!     let version = database.table_version("users")?;
+//!
+//!     // Answers `304 Not Modified` without loading the users when the
+//!     // client's copy is current
+//!     Ok(Versioned::new(version, || Json(load_users(&database))))
+//! }
+//! ```
+//!
+//! Each table has a version in the `_rocket_diesel_table_versions` table
+//! (created by the bootstrap step), incremented on every change: by the
+//! triggers `Database::install_version_triggers` installs, or explicitly
+//! with `Database::bump_table_version` (e.g. for tables changed by bulk
+//! loads skipping triggers). Tables never changed are at version `0`.
+//!
+//! The `ETag` of a table is its name and version; its `Last-Modified` date
+//! the time of its last change, to the second. `If-Modified-Since` is
+//! compared with the date the client received, as most clients send it
+//! back unchanged.
+
+use diesel::sql_types::BigInt;
+
+use rocket::{
+    http::{
+        Header,
+        Status
+    },
+    request::Request,
+    response::{
+        self,
+        Responder,
+        Response
+    }
+};
+
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH
+};
+
+use crate::Backend;
+use crate::helpers::{
+    quote_identifier,
+    quote_literal
+};
+
+/// Name of the table versions table.
+pub const TABLE: &str = "_rocket_diesel_table_versions";
+
+#[derive(QueryableByName)]
+pub(crate) struct VersionRow
+{
+    #[sql_type = "BigInt"]
+    pub version: i64,
+    #[sql_type = "BigInt"]
+    pub modified_at: i64,
+}
+
+/// Version of a table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableVersion
+{
+    pub table: String,
+    /// Number of changes, `0` when the table never changed.
+    pub version: i64,
+    /// Time of the last change, the Unix epoch when the table never
+    /// changed.
+    pub modified_at: SystemTime,
+}
+
+impl TableVersion
+{
+    pub(crate) fn new(table: &str, row: Option<VersionRow>) -> Self
+    {
+        let (version, modified_at) = row.map_or((0, 0), |row| (row.version, row.modified_at));
+
+        Self {
+            table: table.to_owned(),
+            version,
+            modified_at: UNIX_EPOCH + Duration::from_secs(modified_at.max(0) as u64)
+        }
+    }
+
+    /// Value of the `ETag` header, e.g. `"users-42"`.
+    pub fn etag(&self) -> String
+    {
+        format!("\"{}-{}\"", self.table, self.version)
+    }
+
+    /// Value of the `Last-Modified` header, e.g.
+    /// `Sun, 06 Nov 1994 08:49:37 GMT`.
+    pub fn last_modified(&self) -> String
+    {
+        http_date(self.modified_at)
+    }
+
+    /// Returns whether the copy of the client of `request` is current: its
+    /// `If-None-Match` lists the `ETag` (or `*`), or, without
+    /// `If-None-Match`, its `If-Modified-Since` is the `Last-Modified` date.
+    pub fn is_fresh(&self, request: &Request<'_>) -> bool
+    {
+        let headers = request.headers();
+
+        match headers.get_one("If-None-Match") {
+            Some(tags) => {
+                let etag = self.etag();
+
+                tags.split(',')
+                    .map(|tag| tag.trim().trim_start_matches("W/"))
+                    .any(|tag| tag == "*" || tag == etag)
+            },
+            None => headers.get_one("If-Modified-Since")
+                .map_or(false, |since| since.trim() == self.last_modified())
+        }
+    }
+}
+
+/// Response answering `304 Not Modified` when the client's copy of a table
+/// is current, and the response of the closure otherwise, with the `ETag`
+/// and `Last-Modified` headers of the table.
+pub struct Versioned<F>
+{
+    _version: TableVersion,
+    _respond: F,
+}
+
+impl<F> Versioned<F>
+{
+    /// Response of `respond`, only called when the client's copy at
+    /// `version` is not current.
+    pub fn new(version: TableVersion, respond: F) -> Self
+    {
+        Self {
+            _version: version,
+            _respond: respond
+        }
+    }
+}
+
+impl<'r, F, R> Responder<'r> for Versioned<F>
+    where F: FnOnce() -> R,
+          R: Responder<'r>
+{
+    fn respond_to(self, request: &Request<'_>) -> response::Result<'r>
+    {
+        let Versioned { _version: version, _respond: respond } = self;
+        let etag = Header::new("ETag", version.etag());
+        let last_modified = Header::new("Last-Modified", version.last_modified());
+
+        if version.is_fresh(request) {
+            return Response::build()
+                .status(Status::NotModified)
+                .header(etag)
+                .header(last_modified)
+                .ok();
+        }
+
+        Response::build_from(respond().respond_to(request)?)
+            .header(etag)
+            .header(last_modified)
+            .ok()
+    }
+}
+
+/// Statement incrementing the version of `table`.
+pub(crate) fn bump_sql(backend: Backend, table: &str) -> String
+{
+    bump_sql_of(backend, &quote_literal(backend, table))
+}
+
+/// Statement incrementing the version of the table named by the SQL
+/// expression `table`.
+fn bump_sql_of(backend: Backend, table: &str) -> String
+{
+    match backend {
+        Backend::Mysql => format!(
+            "INSERT INTO {} (table_name, version, modified_at) VALUES ({}, 1, UNIX_TIMESTAMP()) \
+             ON DUPLICATE KEY UPDATE version = version + 1, modified_at = VALUES(modified_at)",
+            TABLE, table
+        ),
+        Backend::Pg => format!(
+            "INSERT INTO {0} (table_name, version, modified_at) \
+             VALUES ({1}, 1, CAST(EXTRACT(EPOCH FROM now()) AS BIGINT)) \
+             ON CONFLICT (table_name) DO UPDATE SET version = {0}.version + 1, modified_at = EXCLUDED.modified_at",
+            TABLE, table
+        ),
+        // Without `ON CONFLICT`, missing before SQLite 3.24
+        Backend::Sqlite => format!(
+            "INSERT OR IGNORE INTO {0} (table_name, version, modified_at) VALUES ({1}, 0, 0); \
+             UPDATE {0} SET version = version + 1, modified_at = CAST(strftime('%s', 'now') AS INTEGER) \
+             WHERE table_name = {1};",
+            TABLE, table
+        ),
+    }
+}
+
+/// Statement reading the version of `table`.
+pub(crate) fn select_sql(backend: Backend, table: &str) -> String
+{
+    format!(
+        "SELECT version, modified_at FROM {} WHERE table_name = {}",
+        TABLE, quote_literal(backend, table)
+    )
+}
+
+/// Statements installing, in place of those already installed, the
+/// triggers incrementing the version of `table` on every change.
+pub(crate) fn trigger_sql(backend: Backend, table: &str) -> Vec<String>
+{
+    let quoted = quote_identifier(backend, table);
+    let bump = bump_sql(backend, table);
+
+    match backend {
+        Backend::Pg => vec![
+            format!(
+                "CREATE OR REPLACE FUNCTION _rocket_diesel_bump_table_version() RETURNS trigger AS $$ \
+                 BEGIN {} ; RETURN NULL; END $$ LANGUAGE plpgsql",
+                bump_sql_of(backend, "TG_TABLE_NAME")
+            ),
+            format!("DROP TRIGGER IF EXISTS _rocket_diesel_version ON {}", quoted),
+            format!(
+                "CREATE TRIGGER _rocket_diesel_version AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON {} \
+                 FOR EACH STATEMENT EXECUTE PROCEDURE _rocket_diesel_bump_table_version()",
+                quoted
+            ),
+        ],
+        Backend::Mysql | Backend::Sqlite => ["insert", "update", "delete"].iter().flat_map(|event| {
+            let trigger = quote_identifier(backend, &format!("_rocket_diesel_version_{}_{}", table, event));
+
+            vec![
+                format!("DROP TRIGGER IF EXISTS {}", trigger),
+                match backend {
+                    Backend::Mysql => format!(
+                        "CREATE TRIGGER {} AFTER {} ON {} FOR EACH ROW {}",
+                        trigger, event.to_uppercase(), quoted, bump
+                    ),
+                    _ => format!(
+                        "CREATE TRIGGER {} AFTER {} ON {} BEGIN {} END",
+                        trigger, event.to_uppercase(), quoted, bump
+                    ),
+                }
+            ]
+        }).collect()
+    }
+}
+
+/// Formats `time` as an HTTP date (RFC 7231 IMF-fixdate).
+pub fn http_date(time: SystemTime) -> String
+{
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = (seconds / 86400) as i64;
+    let time_of_day = seconds % 86400;
+
+    // Civil date of a day count (Howard Hinnant's `civil_from_days`)
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize], day, MONTHS[(month - 1) as usize], year,
+        time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::{bootstrap, Backend, Database};
+    use super::{http_date, trigger_sql, TableVersion, VersionRow};
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(UNIX_EPOCH + Duration::from_secs(784_111_777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(UNIX_EPOCH + Duration::from_secs(951_782_400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn etags() {
+        let version = TableVersion::new("users", Some(VersionRow { version: 42, modified_at: 784_111_777 }));
+
+        assert_eq!(version.etag(), "\"users-42\"");
+        assert_eq!(version.last_modified(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(TableVersion::new("users", None).version, 0);
+    }
+
+    #[test]
+    fn pg_triggers_bump_the_changed_table() {
+        let statements = trigger_sql(Backend::Pg, "users");
+
+        assert_eq!(statements.len(), 3);
+        assert!(statements[0].contains("VALUES (TG_TABLE_NAME, 1,"));
+    }
+
+    #[test]
+    fn triggers_bump_versions() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        bootstrap::run(&conn, Backend::Sqlite).unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER)").unwrap();
+        let database = Database::from_connection(conn);
+
+        assert_eq!(database.table_version("users").unwrap().version, 0);
+
+        database.install_version_triggers("users").unwrap();
+        database.install_version_triggers("users").unwrap();
+        database.execute_sql("INSERT INTO users VALUES (1)").unwrap();
+        database.execute_sql("UPDATE users SET id = 2").unwrap();
+        assert_eq!(database.table_version("users").unwrap().version, 2);
+
+        database.bump_table_version("users").unwrap();
+        let version = database.table_version("users").unwrap();
+        assert_eq!(version.version, 3);
+        assert!(version.modified_at > UNIX_EPOCH);
+    }
+}
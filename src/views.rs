@@ -0,0 +1,285 @@
+//! Materialized views refreshed on a schedule.
+//!
+//! ```rust,ignore
+//! database.register_view(
+//!     View::new("daily_sales", "SELECT day, SUM(amount) AS total FROM sales GROUP BY day")
+//!         .every(Duration::from_secs(600))
+//! );
+//! database.refresh_view("daily_sales")?;
+//! ```
+//!
+//! On Postgres, the view is a materialized view created by the migrations
+//! and refreshed with `REFRESH MATERIALIZED VIEW`. MySQL and SQLite have no
+//! materialized views: the view is emulated by a summary table of the same
+//! name, also created by the migrations, whose rows are replaced by those
+//! of the query of the view in a transaction.
+//!
+//! Views refreshed `every` period are refreshed by a job of
+//! `Database::schedule_exclusive`, on a single instance of the fleet. Their
+//! last refresh is reported by `Database::view_statuses`, the metrics, and
+//! the `/health` route through a warning check (`view:<name>`) failing
+//! when the last refresh failed or is older than two periods.
+
+use std::{
+    sync::{
+        Arc,
+        RwLock
+    },
+    time::{
+        Duration,
+        SystemTime
+    }
+};
+
+use crate::Backend;
+use crate::error;
+use crate::helpers;
+use crate::Result;
+
+/// Materialized view, or summary table emulating one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct View
+{
+    _name: String,
+    _query: String,
+    _concurrently: bool,
+    _every: Option<Duration>,
+}
+
+impl View
+{
+    /// View `name` holding the rows of `query`.
+    pub fn new(name: &str, query: &str) -> Self
+    {
+        Self {
+            _name: name.to_owned(),
+            _query: query.to_owned(),
+            _concurrently: false,
+            _every: None
+        }
+    }
+
+    /// Refreshes the Postgres view without locking out its readers, which
+    /// requires a unique index on the view.
+    pub fn concurrently(mut self) -> Self
+    {
+        self._concurrently = true;
+        self
+    }
+
+    /// Refreshes the view at the start of every period of `every`.
+    pub fn every(mut self, every: Duration) -> Self
+    {
+        self._every = Some(every);
+        self
+    }
+
+    pub fn name(&self) -> &str
+    {
+        &self._name
+    }
+
+    pub fn refresh_every(&self) -> Option<Duration>
+    {
+        self._every
+    }
+
+    /// Statements refreshing the view on `backend`, and whether they run in
+    /// a transaction.
+    pub fn refresh_sql(&self, backend: Backend) -> (String, bool)
+    {
+        let name = helpers::quote_identifier(backend, &self._name);
+
+        match backend {
+            // `CONCURRENTLY` can't run in a transaction block
+            Backend::Pg => (
+                format!(
+                    "REFRESH MATERIALIZED VIEW {}{}",
+                    if self._concurrently { "CONCURRENTLY " } else { "" }, name
+                ),
+                false
+            ),
+            Backend::Mysql | Backend::Sqlite => (
+                format!("DELETE FROM {}; INSERT INTO {} {}", name, name, self._query),
+                true
+            )
+        }
+    }
+}
+
+/// Last refresh of a registered view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewStatus
+{
+    pub name: String,
+    pub every: Option<Duration>,
+    /// End of the last successful refresh.
+    pub last_refresh: Option<SystemTime>,
+    /// Duration of the last successful refresh.
+    pub last_duration: Option<Duration>,
+    /// Why the last refresh failed, `None` when it succeeded.
+    pub error: Option<String>,
+}
+
+impl ViewStatus
+{
+    /// Returns why the view is stale at `now`: its last refresh failed, or
+    /// is older than two periods.
+    pub fn stale(&self, now: SystemTime) -> Option<String>
+    {
+        if let Some(ref error) = self.error {
+            return Some(format!("last refresh failed: {}", error));
+        }
+
+        let every = self.every?;
+        let age = self.last_refresh
+            .map(|last_refresh| now.duration_since(last_refresh).unwrap_or_default());
+
+        match age {
+            Some(age) if age <= every * 2 => None,
+            Some(age) => Some(format!("last refreshed {}s ago", age.as_secs())),
+            None => Some("never refreshed".to_owned())
+        }
+    }
+}
+
+/// Views registered on a database. Clones share them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Views
+{
+    _views: Arc<RwLock<Vec<(View, ViewStatus)>>>,
+}
+
+impl Views
+{
+    /// Registers `view`, replacing the view registered with that name.
+    pub fn register(&self, view: View)
+    {
+        if let Ok(mut views) = self._views.write() {
+            let status = ViewStatus {
+                name: view._name.clone(),
+                every: view._every,
+                last_refresh: None,
+                last_duration: None,
+                error: None
+            };
+
+            views.retain(|(registered, _status)| registered._name != view._name);
+            views.push((view, status));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<View>
+    {
+        self._views.read().ok()
+            .and_then(|views| views.iter()
+                .find(|(view, _status)| view._name == name)
+                .map(|(view, _status)| view.clone()))
+            .ok_or_else(|| error::Error::new(
+                error::ErrorKind::MissingValue,
+                format!("no view `{}` registered", name)
+            ))
+    }
+
+    /// Records the outcome of a refresh of `name` ending at `now`.
+    pub fn record(&self, name: &str, now: SystemTime, result: &Result<Duration>)
+    {
+        if let Ok(mut views) = self._views.write() {
+            if let Some((_view, status)) = views.iter_mut().find(|(view, _status)| view._name == name) {
+                match result {
+                    Ok(duration) => {
+                        status.last_refresh = Some(now);
+                        status.last_duration = Some(*duration);
+                        status.error = None;
+                    },
+                    Err(err) => status.error = Some(err.to_string())
+                }
+            }
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<ViewStatus>
+    {
+        self._views.read()
+            .map(|views| views.iter().map(|(_view, status)| status.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn status(&self, name: &str) -> Option<ViewStatus>
+    {
+        self.statuses().into_iter().find(|status| status.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::{Connection, SimpleConnection},
+        sqlite::SqliteConnection
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::{Backend, Database};
+    use crate::error::{Error, ErrorKind};
+    use super::{View, Views};
+
+    #[test]
+    fn refresh_statements() {
+        let view = View::new("daily_sales", "SELECT day, SUM(amount) FROM sales GROUP BY day");
+
+        assert_eq!(view.refresh_sql(Backend::Pg), ("REFRESH MATERIALIZED VIEW \"daily_sales\"".to_owned(), false));
+        assert_eq!(
+            view.clone().concurrently().refresh_sql(Backend::Pg).0,
+            "REFRESH MATERIALIZED VIEW CONCURRENTLY \"daily_sales\""
+        );
+        assert_eq!(
+            view.refresh_sql(Backend::Mysql),
+            (
+                "DELETE FROM `daily_sales`; INSERT INTO `daily_sales` SELECT day, SUM(amount) FROM sales GROUP BY day"
+                    .to_owned(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn staleness() {
+        let views = Views::default();
+        let every = Duration::from_secs(60);
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        views.register(View::new("daily_sales", "SELECT 1").every(every));
+        assert_eq!(views.status("daily_sales").unwrap().stale(now).unwrap(), "never refreshed");
+
+        views.record("daily_sales", now, &Ok(Duration::from_millis(5)));
+        assert_eq!(views.status("daily_sales").unwrap().stale(now + every), None);
+        assert!(views.status("daily_sales").unwrap().stale(now + every * 3).is_some());
+
+        views.record("daily_sales", now, &Err(Error::new(ErrorKind::Other, "relation does not exist")));
+        assert!(views.status("daily_sales").unwrap().stale(now).unwrap().contains("relation does not exist"));
+        assert!(views.get("weekly_sales").is_err());
+    }
+
+    #[test]
+    fn refreshes_summary_tables() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "CREATE TABLE sales (day INTEGER, amount INTEGER);
+             INSERT INTO sales VALUES (1, 10), (1, 5), (2, 7);
+             CREATE TABLE daily_sales (day INTEGER, total INTEGER);"
+        ).unwrap();
+        let database = Database::from_connection(conn);
+
+        assert!(database.refresh_view("daily_sales").is_err());
+
+        database.register_view(View::new("daily_sales", "SELECT day, SUM(amount) FROM sales GROUP BY day"));
+        database.refresh_view("daily_sales").unwrap();
+        database.refresh_view("daily_sales").unwrap();
+
+        let rows = database.execute_sql("DELETE FROM daily_sales").unwrap();
+        assert_eq!(rows, 2);
+
+        let status = &database.view_statuses()[0];
+        assert!(status.last_refresh.is_some());
+        assert_eq!(status.error, None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Establishment of many connections at warm-up, a few at a time.
+//!
+//! After a deploy, every instance connecting to every named database and
+//! shard at once hits the database servers with a burst of connects (TLS
+//! handshakes, authentication, backend forks). A [`WarmUp`] establishes
+//! the connections of several databases with a bounded parallelism,
+//! logging its progress:
+//!
+//! ```rust,ignore
+//! let results = WarmUp::new(4)
+//!     .with_database("app", app.clone())
+//!     .with_database("analytics", analytics.clone())
+//!     .run();
+//! ```
+//!
+//! `Database::warm_up` does the same for a database and its shards, with
+//! the parallelism of the `warm_up_parallelism` configuration key.
+//! Connections already established are only touched.
+//!
+//! [`WarmUp`]: struct.WarmUp.html
+
+use std::{
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering
+        },
+        Mutex
+    },
+    thread,
+    time::Instant
+};
+
+use crate::redaction::log_redacted;
+use crate::Database;
+use crate::Result;
+
+/// Default number of connections established concurrently.
+pub const DEFAULT_PARALLELISM: usize = 4;
+
+/// Named databases whose connections are established together.
+#[derive(Clone, Debug)]
+pub struct WarmUp
+{
+    _databases: Vec<(String, Database)>,
+    _parallelism: usize,
+}
+
+impl WarmUp
+{
+    /// Warm-up establishing at most `parallelism` connections at a time
+    /// (at least one).
+    pub fn new(parallelism: usize) -> Self
+    {
+        Self {
+            _databases: Vec::new(),
+            _parallelism: parallelism.max(1)
+        }
+    }
+
+    pub fn with_database(mut self, name: &str, database: Database) -> Self
+    {
+        self._databases.push((name.to_owned(), database));
+        self
+    }
+
+    /// Establishes the connections, returning the outcome for each
+    /// database in the order they were added.
+    pub fn run(&self) -> Vec<(String, Result<()>)>
+    {
+        let start = Instant::now();
+        let total = self._databases.len();
+        let done = AtomicUsize::new(0);
+
+        log_redacted!(
+            log::Level::Info, "Warming up {} database(s), {} at a time...", total, self._parallelism
+        );
+
+        let results = run_limited(&self._databases, self._parallelism, |(name, database)| {
+            let connect = Instant::now();
+            let result = database.prewarm();
+            let done = done.fetch_add(1, Ordering::AcqRel) + 1;
+
+            match result {
+                Ok(()) => log_redacted!(
+                    log::Level::Info, "Warmed up `{}` in {:?} ({}/{}).", name, connect.elapsed(), done, total
+                ),
+                Err(ref err) => log_redacted!(
+                    log::Level::Warn, "Failed to warm up `{}` ({}/{}): {}", name, done, total, err
+                )
+            }
+            result
+        });
+
+        log_redacted!(log::Level::Info, "Warmed up {} database(s) in {:?}.", total, start.elapsed());
+
+        self._databases.iter()
+            .map(|(name, _database)| name.clone())
+            .zip(results)
+            .collect()
+    }
+}
+
+/// Calls `f` on each item, on at most `parallelism` threads, returning the
+/// results in the order of the items.
+fn run_limited<T, R, F>(items: &[T], parallelism: usize, f: F) -> Vec<R>
+    where T: Sync,
+          R: Send,
+          F: Fn(&T) -> R + Sync
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_item| None).collect());
+
+    thread::scope(|scope| {
+        for _worker in 0..parallelism.max(1).min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::AcqRel);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => break
+                };
+                let result = f(item);
+
+                if let Ok(mut results) = results.lock() {
+                    results[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results.into_inner()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|result| result.expect("every item is run"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        connection::Connection,
+        sqlite::SqliteConnection
+    };
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration
+    };
+    use crate::Database;
+    use super::{run_limited, WarmUp};
+
+    #[test]
+    fn limits_the_parallelism() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..12).collect();
+
+        let results = run_limited(&items, 3, |item| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+            item * 2
+        });
+
+        assert_eq!(results, items.iter().map(|item| item * 2).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn warms_up_named_databases() {
+        let database = || Database::from_connection(SqliteConnection::establish(":memory:").unwrap());
+
+        let results = WarmUp::new(0)
+            .with_database("app", database())
+            .with_database("analytics", database())
+            .run();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "app");
+        assert_eq!(results[1].0, "analytics");
+        assert!(results.iter().all(|(_name, result)| result.is_ok()));
+    }
+}